@@ -0,0 +1,257 @@
+//! `no_std` definitions of the i.MX6 MMDC register map and the profiling counter
+//! math, usable from bare-metal code as well as from the Linux tool on top of it
+
+#![no_std]
+
+pub mod soc;
+pub mod units;
+
+use core::time::Duration;
+use units::{Accesses, Bandwidth, Bytes, Cycles, MegabytesPerSecond, Percent};
+
+/// Length of the register block mapping of one MMDC port
+pub const MMDC_MAP_LENGTH: usize = 0x4000;
+
+/// MADPCR0 profiling control bits
+pub const DBG_EN: u32 = 0x1;
+pub const DBG_RST: u32 = 0x2;
+pub const PRF_FRZ: u32 = 0x4;
+pub const CYC_OVF: u32 = 0x8;
+
+pub const AXI_IPU1: u32 = 0x3FE70004;
+pub const AXI_IPU2_6Q: u32 = 0x3FE70005;
+pub const AXI_GPU3D_6DL: u32 = 0x003F0002;
+pub const AXI_GPU3D_6Q: u32 = 0x003E0002;
+pub const AXI_GPU2D2_6DL: u32 = 0x003F0003;
+pub const AXI_GPU2D1_6DL: u32 = 0x003F000A;
+pub const AXI_GPU2D_6Q: u32 = 0x003E000B;
+pub const AXI_GPU2D_6SL: u32 = 0x0017000F;
+pub const AXI_VPU_6DL: u32 = 0x003F000B;
+pub const AXI_VPU_6Q: u32 = 0x003F0013;
+pub const AXI_OPENVG_6Q: u32 = 0x003F0022;
+pub const AXI_OPENVG_6SL: u32 = 0x001F0017;
+pub const AXI_ARM: u32 = 0x00060000;
+pub const AXI_PCIE: u32 = 0x303F001B;
+pub const AXI_SATA: u32 = 0x3FFF00E3;
+pub const AXI_DEFAULT: u32 = 0x00000000;
+
+/// i.MX6QP prefetch engines in front of the display path, all four PRE engines share the
+/// filter. Not verified on hardware yet, cross-check with --madpcr1 before relying on it.
+pub const AXI_PRE_6QP: u32 = 0x3FC70007;
+/// The PRG engines forward the reads of the IPU they serve under its ID
+pub const AXI_PRG0_6QP: u32 = AXI_IPU1;
+pub const AXI_PRG1_6QP: u32 = AXI_IPU2_6Q;
+
+pub const MMDC_P0_IPS_BASE_ADDR: u64 = 0x021B0000;
+pub const MMDC_P1_IPS_BASE_ADDR: u64 = 0x021B4000;
+pub const VF610_DDRMC_BASE_ADDR: u64 = 0x400AE000;
+/// PL310 L2 cache controller of the Cortex-A9 parts
+pub const PL310_BASE_ADDR: u64 = 0x00A02000;
+
+/// Extracts a `width` bits wide field starting at bit `shift`
+pub fn bits(value: u32, shift: u32, width: u32) -> u32 {
+    (value >> shift) & ((1u64 << width) - 1) as u32
+}
+
+/// Register block of one MMDC port as laid out from its IPS base address
+#[repr(C)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct MMDC {
+    pub mdctl: u32,
+    pub mdpdc: u32,
+    pub mdotc: u32,
+    pub mdcfg0: u32,
+    pub mdcfg1: u32,
+    pub mdcfg2: u32,
+    pub mdmisc: u32,
+    pub mdscr: u32,
+    pub mdref: u32,
+    pub mdwcc: u32,
+    pub mdrcc: u32,
+    pub mdrwd: u32,
+    pub mdor: u32,
+    pub mdmrr: u32,
+    pub mdcfg3lp: u32,
+    pub mdmr4: u32,
+    pub mdasp: u32,
+
+    pub adopt_base_offset_fill: [u32; 239],
+    pub maarcr: u32,
+    pub mapsr: u32,
+    pub maexidr0: u32,
+    pub maexidr1: u32,
+    pub madpcr0: u32,
+    pub madpcr1: u32,
+    pub madpsr0: u32,
+    pub madpsr1: u32,
+    pub madpsr2: u32,
+    pub madpsr3: u32,
+    pub madpsr4: u32,
+    pub madpsr5: u32,
+    pub masbs0: u32,
+    pub masbs1: u32,
+    pub ma_reserved1: u32,
+    pub ma_reserved2: u32,
+    pub magenp: u32,
+
+    pub phy_base_offset_fill: [u32; 239],
+    pub mpzqhwctrl: u32,
+    pub mpzqswctrl: u32,
+    pub mpwlgcr: u32,
+    pub mpwldectrl0: u32,
+    pub mpwldectrl1: u32,
+    pub mpwldlst: u32,
+    pub mpodtctrl: u32,
+    pub mpredqby0dl: u32,
+    pub mpredqby1dl: u32,
+    pub mpredqby2dl: u32,
+    pub mpredqby3dl: u32,
+    pub mpwrdqby0dl: u32,
+    pub mpwrdqby1dl: u32,
+    pub mpwrdqby2dl: u32,
+    pub mpwrdqby3dl: u32,
+    pub mpdgctrl0: u32,
+    pub mpdgctrl1: u32,
+    pub mpdgdlst: u32,
+    pub mprddlctl: u32,
+    pub mprddlst: u32,
+    pub mpwrdlctl: u32,
+    pub mpwrdlst: u32,
+    pub mpsdctrl: u32,
+    pub mpzqlp2ctl: u32,
+    pub mprddlhwctl: u32,
+    pub mpwrdlhwctl: u32,
+    pub mprddlhwst0: u32,
+    pub mprddlhwst1: u32,
+    pub mpwrdlhwst0: u32,
+    pub mpwrdlhwst1: u32,
+    pub mpwlhwerr: u32,
+    pub mpdghwst0: u32,
+    pub mpdghwst1: u32,
+    pub mpdghwst2: u32,
+    pub mpdghwst3: u32,
+    pub mppdcmpr1: u32,
+    pub mppdcmpr2: u32,
+    pub mpswdar: u32,
+    pub mpswdrdr0: u32,
+    pub mpswdrdr1: u32,
+    pub mpswdrdr2: u32,
+    pub mpswdrdr3: u32,
+    pub mpswdrdr4: u32,
+    pub mpswdrdr5: u32,
+    pub mpswdrdr6: u32,
+    pub mpswdrdr7: u32,
+    pub mpmur: u32,
+    pub mpwrcadl: u32,
+    pub mpdccr: u32,
+    pub mpbc: u32,
+}
+
+/// The six profiling counters MADPSR0..MADPSR5
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Counters {
+    pub total_cycles: Cycles,
+    pub busy_cycles: Cycles,
+    pub read_accesses: Accesses,
+    pub write_accesses: Accesses,
+    pub read_bytes: Bytes,
+    pub write_bytes: Bytes,
+}
+
+impl Counters {
+    /// Reads the counters, they have to be frozen with PRF_FRZ beforehand
+    pub fn from_registers(mmdc: &MMDC) -> Counters {
+        // volatile, the hardware updates the registers behind the compiler's back
+        let read = |register: &u32| unsafe { core::ptr::read_volatile(register) };
+        Counters::from_raw([
+            read(&mmdc.madpsr0),
+            read(&mmdc.madpsr1),
+            read(&mmdc.madpsr2),
+            read(&mmdc.madpsr3),
+            read(&mmdc.madpsr4),
+            read(&mmdc.madpsr5),
+        ])
+    }
+
+    pub fn from_raw(raw: [u32; 6]) -> Counters {
+        Counters {
+            total_cycles: Cycles(raw[0]),
+            busy_cycles: Cycles(raw[1]),
+            read_accesses: Accesses(raw[2]),
+            write_accesses: Accesses(raw[3]),
+            read_bytes: Bytes(raw[4]),
+            write_bytes: Bytes(raw[5]),
+        }
+    }
+
+    pub fn raw(&self) -> [u32; 6] {
+        [
+            self.total_cycles.get(),
+            self.busy_cycles.get(),
+            self.read_accesses.get(),
+            self.write_accesses.get(),
+            self.read_bytes.get(),
+            self.write_bytes.get(),
+        ]
+    }
+
+    fn transferred(&self) -> f32 {
+        self.read_bytes.get() as f32 + self.write_bytes.get() as f32
+    }
+
+    /// Transferred bytes relative to what the bus could move in the busy cycles
+    pub fn utilization(&self, bus_width: u32) -> Percent {
+        if self.busy_cycles.get() == 0 {
+            return Percent(0);
+        }
+        let bytes_per_beat = (bus_width / 8) as f32;
+        let capacity = self.busy_cycles.get() as f32 * 2_f32 * bytes_per_beat;
+        Percent((self.transferred() / capacity * 100_f32) as u32)
+    }
+
+    /// Busy cycles relative to all cycles
+    pub fn data_load(&self) -> Percent {
+        Percent::of(self.busy_cycles, self.total_cycles)
+    }
+
+    /// Average bytes per access
+    pub fn access_utilization(&self) -> Bytes {
+        let accesses = self.read_accesses.get() as f32 + self.write_accesses.get() as f32;
+        if accesses == 0_f32 {
+            return Bytes(0);
+        }
+        Bytes((self.transferred() / accesses) as u32)
+    }
+
+    pub fn avg_read_burstsize(&self) -> Bytes {
+        Bytes(
+            self.read_bytes
+                .get()
+                .checked_div(self.read_accesses.get())
+                .unwrap_or(0),
+        )
+    }
+
+    pub fn avg_write_burstsize(&self) -> Bytes {
+        Bytes(
+            self.write_bytes
+                .get()
+                .checked_div(self.write_accesses.get())
+                .unwrap_or(0),
+        )
+    }
+
+    /// Read, write and total bandwidth over the measured time, whole milliseconds of it
+    pub fn bandwidth(&self, time: Duration) -> Bandwidth {
+        let milliseconds = time.as_millis() as f32;
+        let per_second = |bytes: f32| bytes * 1000_f32 / (1024_f32 * 1024_f32 * milliseconds);
+        let read = per_second(self.read_bytes.get() as f32);
+        let write = per_second(self.write_bytes.get() as f32);
+        Bandwidth {
+            read: MegabytesPerSecond(read),
+            write: MegabytesPerSecond(write),
+            total: MegabytesPerSecond(read + write),
+        }
+    }
+}
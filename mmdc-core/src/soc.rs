@@ -0,0 +1,216 @@
+//! Supported SoCs with the keys they are detected by
+
+use crate::{
+    AXI_ARM, AXI_GPU2D1_6DL, AXI_GPU2D2_6DL, AXI_GPU2D_6Q, AXI_GPU2D_6SL, AXI_GPU3D_6DL,
+    AXI_GPU3D_6Q, AXI_IPU1, AXI_IPU2_6Q, AXI_OPENVG_6Q, AXI_OPENVG_6SL, AXI_PCIE, AXI_PRE_6QP,
+    AXI_PRG0_6QP, AXI_PRG1_6QP, AXI_SATA, AXI_VPU_6DL, AXI_VPU_6Q, MMDC_P0_IPS_BASE_ADDR,
+    MMDC_P1_IPS_BASE_ADDR, PL310_BASE_ADDR, VF610_DDRMC_BASE_ADDR,
+};
+
+/// Bus master selectable through its MADPCR1 AXI ID filter
+pub struct Master {
+    pub name: &'static str,
+    pub filter: u32,
+}
+
+/// DDR controller type, everything but the MMDC is only detected and refused so far
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Controller {
+    Mmdc,
+    /// Vybrid DDRMC, its counters are not laid out like the MMDC profiling registers
+    Ddrmc,
+}
+
+pub struct SocProfile {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Device tree root compatible strings
+    pub compatible: &'static [&'static str],
+    /// `soc_id` as reported in /sys/devices/soc0
+    pub soc_ids: &'static [&'static str],
+    /// MXC CPU type, upper bits of the /proc/cpuinfo revision on vendor kernels
+    pub cpu_types: &'static [u32],
+    /// Lowest silicon revision (low 12 bits of the cpuinfo revision) of the matching CPU
+    /// types, telling apart parts which share their CPU type
+    pub min_revision: u32,
+    pub controller: Controller,
+    /// Base addresses of the MMDC ports, the second one is only used in
+    /// 2-channel LPDDR2 configurations
+    pub base_addresses: &'static [u64],
+    /// Base address of the PL310 L2 cache controller, the Cortex-A7 parts integrate
+    /// their L2 cache without one
+    pub l2_cache_base: Option<u64>,
+    /// Possible data bus widths in bits, the actual one is read from MDCTL
+    pub bus_widths: &'static [u32],
+    pub masters: &'static [Master],
+}
+
+const fn master(name: &'static str, filter: u32) -> Master {
+    Master { name, filter }
+}
+
+const ARM: Master = master("arm", AXI_ARM);
+
+/// Ordered so more specific profiles sharing a CPU type come first
+pub static SOCS: &[SocProfile] = &[
+    SocProfile {
+        name: "imx6qp",
+        description: "i.MX6 QuadPlus/DualPlus",
+        compatible: &["fsl,imx6qp"],
+        soc_ids: &["i.MX6QP"],
+        // reported as i.MX6Q revision 2.0 and up
+        cpu_types: &[0x63],
+        min_revision: 0x20,
+        controller: Controller::Mmdc,
+        base_addresses: &[MMDC_P0_IPS_BASE_ADDR, MMDC_P1_IPS_BASE_ADDR],
+        l2_cache_base: Some(PL310_BASE_ADDR),
+        bus_widths: &[16, 32, 64],
+        masters: &[
+            ARM,
+            master("ipu1", AXI_IPU1),
+            master("ipu2", AXI_IPU2_6Q),
+            master("pre", AXI_PRE_6QP),
+            master("prg0", AXI_PRG0_6QP),
+            master("prg1", AXI_PRG1_6QP),
+            master("gpu3d", AXI_GPU3D_6Q),
+            master("gpu2d", AXI_GPU2D_6Q),
+            master("vpu", AXI_VPU_6Q),
+            master("openvg", AXI_OPENVG_6Q),
+            master("pcie", AXI_PCIE),
+            master("sata", AXI_SATA),
+        ],
+    },
+    SocProfile {
+        name: "imx6q",
+        description: "i.MX6 Quad/Dual",
+        compatible: &["fsl,imx6q"],
+        soc_ids: &["i.MX6Q"],
+        cpu_types: &[0x63],
+        min_revision: 0,
+        controller: Controller::Mmdc,
+        base_addresses: &[MMDC_P0_IPS_BASE_ADDR, MMDC_P1_IPS_BASE_ADDR],
+        l2_cache_base: Some(PL310_BASE_ADDR),
+        bus_widths: &[16, 32, 64],
+        masters: &[
+            ARM,
+            master("ipu1", AXI_IPU1),
+            master("ipu2", AXI_IPU2_6Q),
+            master("gpu3d", AXI_GPU3D_6Q),
+            master("gpu2d", AXI_GPU2D_6Q),
+            master("vpu", AXI_VPU_6Q),
+            master("openvg", AXI_OPENVG_6Q),
+            master("pcie", AXI_PCIE),
+            master("sata", AXI_SATA),
+        ],
+    },
+    SocProfile {
+        name: "imx6dl",
+        description: "i.MX6 DualLite/Solo",
+        compatible: &["fsl,imx6dl"],
+        soc_ids: &["i.MX6DL"],
+        cpu_types: &[0x61],
+        min_revision: 0,
+        controller: Controller::Mmdc,
+        base_addresses: &[MMDC_P0_IPS_BASE_ADDR, MMDC_P1_IPS_BASE_ADDR],
+        l2_cache_base: Some(PL310_BASE_ADDR),
+        bus_widths: &[16, 32, 64],
+        masters: &[
+            ARM,
+            master("ipu1", AXI_IPU1),
+            master("gpu3d", AXI_GPU3D_6DL),
+            master("gpu2d1", AXI_GPU2D1_6DL),
+            master("gpu2d2", AXI_GPU2D2_6DL),
+            master("vpu", AXI_VPU_6DL),
+            master("pcie", AXI_PCIE),
+        ],
+    },
+    SocProfile {
+        name: "imx6sl",
+        description: "i.MX6 SoloLite",
+        compatible: &["fsl,imx6sl"],
+        soc_ids: &["i.MX6SL"],
+        cpu_types: &[0x60],
+        min_revision: 0,
+        controller: Controller::Mmdc,
+        base_addresses: &[MMDC_P0_IPS_BASE_ADDR, MMDC_P1_IPS_BASE_ADDR],
+        l2_cache_base: Some(PL310_BASE_ADDR),
+        bus_widths: &[16, 32],
+        masters: &[
+            ARM,
+            master("gpu2d", AXI_GPU2D_6SL),
+            master("openvg", AXI_OPENVG_6SL),
+        ],
+    },
+    SocProfile {
+        name: "imx6sll",
+        description: "i.MX6 SLL",
+        compatible: &["fsl,imx6sll"],
+        soc_ids: &["i.MX6SLL"],
+        cpu_types: &[0x67],
+        min_revision: 0,
+        controller: Controller::Mmdc,
+        base_addresses: &[MMDC_P0_IPS_BASE_ADDR],
+        l2_cache_base: Some(PL310_BASE_ADDR),
+        bus_widths: &[32],
+        // no GPU. The EPDC, PXP and LCDIF IDs differ from the other parts and no
+        // verified table of them is at hand, they are only available through --madpcr1
+        // until they are measured on hardware.
+        masters: &[ARM],
+    },
+    SocProfile {
+        name: "imx6sx",
+        description: "i.MX6 SoloX",
+        compatible: &["fsl,imx6sx"],
+        soc_ids: &["i.MX6SX"],
+        cpu_types: &[0x62],
+        min_revision: 0,
+        controller: Controller::Mmdc,
+        base_addresses: &[MMDC_P0_IPS_BASE_ADDR],
+        l2_cache_base: Some(PL310_BASE_ADDR),
+        bus_widths: &[16, 32],
+        masters: &[],
+    },
+    SocProfile {
+        name: "imx6ul",
+        description: "i.MX6 UltraLite",
+        compatible: &["fsl,imx6ul"],
+        soc_ids: &["i.MX6UL"],
+        cpu_types: &[0x64],
+        min_revision: 0,
+        controller: Controller::Mmdc,
+        base_addresses: &[MMDC_P0_IPS_BASE_ADDR],
+        l2_cache_base: None,
+        bus_widths: &[16],
+        masters: &[],
+    },
+    SocProfile {
+        name: "imx6ull",
+        description: "i.MX6 ULL",
+        compatible: &["fsl,imx6ull"],
+        soc_ids: &["i.MX6ULL"],
+        cpu_types: &[0x65],
+        min_revision: 0,
+        controller: Controller::Mmdc,
+        base_addresses: &[MMDC_P0_IPS_BASE_ADDR],
+        l2_cache_base: None,
+        bus_widths: &[16],
+        masters: &[],
+    },
+    SocProfile {
+        name: "vf610",
+        description: "Vybrid VF610",
+        compatible: &["fsl,vf610"],
+        soc_ids: &["VF610"],
+        cpu_types: &[],
+        min_revision: 0,
+        controller: Controller::Ddrmc,
+        base_addresses: &[VF610_DDRMC_BASE_ADDR],
+        l2_cache_base: None,
+        bus_widths: &[16],
+        masters: &[],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static SocProfile> {
+    SOCS.iter().find(|soc| soc.name == name)
+}
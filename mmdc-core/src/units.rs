@@ -0,0 +1,91 @@
+//! Newtypes for the quantities of the counter math so bytes, beats and cycles can't be
+//! mixed up silently, converting between them takes the bus width or time explicitly
+
+use core::time::Duration;
+
+macro_rules! unit {
+    ($(#[$doc:meta])* $name:ident($inner:ty)) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub struct $name(pub $inner);
+
+        impl $name {
+            pub fn get(self) -> $inner {
+                self.0
+            }
+        }
+    };
+}
+
+unit!(
+    /// Transferred data
+    Bytes(u32)
+);
+unit!(
+    /// Data bus transfers, two per DDR clock cycle
+    Beats(u32)
+);
+unit!(
+    /// DDR clock cycles
+    Cycles(u32)
+);
+unit!(
+    /// AXI read or write accesses, bursts of one or more beats
+    Accesses(u32)
+);
+unit!(
+    /// Bandwidth in MiB/s, the unit of all bandwidths the tool reports
+    MegabytesPerSecond(f32)
+);
+unit!(
+    /// Ratio in whole percent
+    Percent(u32)
+);
+
+impl Bytes {
+    /// Beats needed to move the bytes over a bus `bus_width` bits wide
+    pub fn beats(self, bus_width: u32) -> Beats {
+        Beats(self.0 / (bus_width / 8).max(1))
+    }
+
+    /// Rate of the bytes transferred over `time`, zero for an empty duration
+    pub fn per(self, time: Duration) -> MegabytesPerSecond {
+        let seconds = time.as_secs_f32();
+        if seconds == 0_f32 {
+            return MegabytesPerSecond(0_f32);
+        }
+        MegabytesPerSecond(self.0 as f32 / (1024_f32 * 1024_f32) / seconds)
+    }
+}
+
+impl Beats {
+    /// Bytes the beats move over a bus `bus_width` bits wide, `None` if they don't fit
+    pub fn bytes(self, bus_width: u32) -> Option<Bytes> {
+        self.0.checked_mul(bus_width / 8).map(Bytes)
+    }
+
+    /// DDR clock cycles the beats occupy at double data rate
+    pub fn cycles(self) -> Cycles {
+        Cycles(self.0 / 2)
+    }
+}
+
+impl Percent {
+    /// `part` relative to `whole`, zero if `whole` is
+    pub fn of(part: Cycles, whole: Cycles) -> Percent {
+        if whole.0 == 0 {
+            return Percent(0);
+        }
+        Percent((part.0 as f32 / whole.0 as f32 * 100_f32) as u32)
+    }
+}
+
+/// Read, write and combined bandwidth of an interval
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bandwidth {
+    pub read: MegabytesPerSecond,
+    pub write: MegabytesPerSecond,
+    pub total: MegabytesPerSecond,
+}
@@ -0,0 +1,73 @@
+//! Aggregation server merging the samples streamed from many targets with --stream into
+//! one dataset, each record tagged with the board it came from
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::json::{self, escape};
+
+type Dataset = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Prepends the board key to a JSON object line
+fn tag(line: &str, board: &str) -> Option<String> {
+    let rest = line.trim().strip_prefix('{')?;
+    let separator = if rest.starts_with('}') { "" } else { "," };
+    Some(format!(
+        "{{\"board\":{}{}{}",
+        escape(board),
+        separator,
+        rest
+    ))
+}
+
+fn merge_stream(stream: TcpStream, dataset: &Dataset) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    // targets without --board-id are told apart by their address
+    let mut board = peer.ip().to_string();
+    eprintln!("{} connected", peer);
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let fields = json::parse_flat(&line);
+        // the metadata line carries the board already
+        let tagged = match fields.iter().find(|(key, _)| key == "board") {
+            Some((_, id)) => {
+                board = id.clone();
+                Some(line)
+            }
+            None => tag(&line, &board),
+        };
+        if let Some(tagged) = tagged {
+            let mut dataset = dataset.lock().unwrap();
+            writeln!(dataset, "{}", tagged)?;
+            dataset.flush()?;
+        }
+    }
+    eprintln!("{} ({}) disconnected", peer, board);
+    Ok(())
+}
+
+/// Accepts targets on `port` and writes their metadata and sample lines to `output` or
+/// stdout, in the order they arrive
+pub fn run(port: u16, output: Option<&Path>) -> io::Result<()> {
+    let dataset: Box<dyn Write + Send> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let dataset: Dataset = Arc::new(Mutex::new(dataset));
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    eprintln!("Aggregating targets on port {}", port);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let dataset = dataset.clone();
+        thread::spawn(move || {
+            if let Err(e) = merge_stream(stream, &dataset) {
+                eprintln!("Error receiving samples: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
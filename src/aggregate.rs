@@ -0,0 +1,88 @@
+//! Combines the windows of --report-every into one record
+
+use crate::{Counters, MMDCProfileResult};
+
+/// Spread of the windows behind an aggregated record
+#[derive(Clone, Copy, Default)]
+pub struct Range {
+    pub min_mbps: f32,
+    pub max_mbps: f32,
+    pub min_utilization: u32,
+    pub max_utilization: u32,
+}
+
+pub struct Aggregator {
+    every: u32,
+    bus_width: u32,
+    windows: u32,
+    sums: [u64; 6],
+    time: u64,
+    range: Range,
+    flags: u32,
+}
+
+impl Aggregator {
+    pub fn new(every: u32, bus_width: u32) -> Aggregator {
+        Aggregator {
+            every,
+            bus_width,
+            windows: 0,
+            sums: [0; 6],
+            time: 0,
+            range: Range::default(),
+            flags: 0,
+        }
+    }
+
+    /// Adds a window, returns the record of the mean window with the spread once `every`
+    /// windows are collected. The counters are averaged as their sum would overflow.
+    pub fn add(
+        &mut self,
+        results: &MMDCProfileResult,
+        time: u32,
+    ) -> Option<(MMDCProfileResult, u32)> {
+        if self.every <= 1 {
+            return Some((results.clone(), time));
+        }
+        let total = results.bandwidth(time).2;
+        if self.windows == 0 {
+            self.range = Range {
+                min_mbps: total,
+                max_mbps: total,
+                min_utilization: results.utilization,
+                max_utilization: results.utilization,
+            };
+        } else {
+            self.range.min_mbps = self.range.min_mbps.min(total);
+            self.range.max_mbps = self.range.max_mbps.max(total);
+            self.range.min_utilization = self.range.min_utilization.min(results.utilization);
+            self.range.max_utilization = self.range.max_utilization.max(results.utilization);
+        }
+        for (sum, counter) in self.sums.iter_mut().zip(results.raw_counters.iter()) {
+            *sum += *counter as u64;
+        }
+        self.time += time as u64;
+        self.flags |= results.flags;
+        self.windows += 1;
+        if self.windows < self.every {
+            return None;
+        }
+
+        let mut mean = [0; 6];
+        for (mean, sum) in mean.iter_mut().zip(self.sums.iter()) {
+            *mean = (*sum / self.windows as u64) as u32;
+        }
+        let mut aggregated =
+            MMDCProfileResult::from_counters(&Counters::from_raw(mean), self.bus_width);
+        aggregated.master = results.master;
+        aggregated.range = Some(self.range);
+        aggregated.flags = self.flags;
+        aggregated.boottime_ms = results.boottime_ms;
+        let time = (self.time / self.windows as u64) as u32;
+        self.windows = 0;
+        self.sums = [0; 6];
+        self.time = 0;
+        self.flags = 0;
+        Some((aggregated, time))
+    }
+}
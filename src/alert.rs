@@ -0,0 +1,142 @@
+//! Utilization threshold alerts with hysteresis and rate limiting, so transient spikes
+//! don't flood the logs or trigger storms of alerts
+
+use std::time::{Duration, Instant};
+
+pub struct AlertPolicy {
+    /// Utilization in percent which raises the alert
+    pub threshold: u32,
+    /// Percent below the threshold the utilization has to drop to clear the alert
+    pub hysteresis: u32,
+    /// Number of successive samples which have to cross before the state changes
+    pub consecutive: u32,
+    /// Least time between two raised alerts
+    pub min_interval: Duration,
+}
+
+pub struct Alert {
+    policy: AlertPolicy,
+    raised: bool,
+    streak: u32,
+    last_raised: Option<Instant>,
+}
+
+impl Alert {
+    pub fn new(policy: AlertPolicy) -> Alert {
+        Alert {
+            policy,
+            raised: false,
+            streak: 0,
+            last_raised: None,
+        }
+    }
+
+    /// Feeds a sample, returns the new state when the alert is raised or cleared. An alert
+    /// held back by the rate limit is not raised at all, so it is not cleared either.
+    pub fn update(&mut self, utilization: u32) -> Option<bool> {
+        let crossing = match self.raised {
+            false => utilization >= self.policy.threshold,
+            true => utilization < self.policy.threshold.saturating_sub(self.policy.hysteresis),
+        };
+        if !crossing {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < self.policy.consecutive {
+            return None;
+        }
+        self.streak = 0;
+        if !self.raised {
+            let limited = self
+                .last_raised
+                .is_some_and(|last| last.elapsed() < self.policy.min_interval);
+            if limited {
+                return None;
+            }
+            self.last_raised = Some(Instant::now());
+        }
+        self.raised = !self.raised;
+        Some(self.raised)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn alert(hysteresis: u32, consecutive: u32, min_interval: Duration) -> Alert {
+        Alert::new(AlertPolicy {
+            threshold: 80,
+            hysteresis,
+            consecutive,
+            min_interval,
+        })
+    }
+
+    fn feed(alert: &mut Alert, utilizations: &[u32]) -> Vec<Option<bool>> {
+        utilizations.iter().map(|u| alert.update(*u)).collect()
+    }
+
+    #[test]
+    fn raised_at_the_threshold_and_cleared_below_the_hysteresis() {
+        let mut alert = alert(10, 1, Duration::ZERO);
+        assert_eq!(
+            feed(&mut alert, &[79, 80, 90, 75, 70, 69, 79, 80]),
+            [
+                None,
+                Some(true),
+                None,
+                None,
+                None,
+                Some(false),
+                None,
+                Some(true)
+            ]
+        );
+    }
+
+    #[test]
+    fn without_hysteresis_it_clears_just_below_the_threshold() {
+        let mut alert = alert(0, 1, Duration::ZERO);
+        assert_eq!(
+            feed(&mut alert, &[80, 80, 79]),
+            [Some(true), None, Some(false)]
+        );
+    }
+
+    #[test]
+    fn interrupted_streaks_start_over() {
+        let mut alert = alert(10, 3, Duration::ZERO);
+        assert_eq!(
+            feed(&mut alert, &[85, 85, 50, 85, 85, 85]),
+            [None, None, None, None, None, Some(true)]
+        );
+        // clearing needs the streak as well
+        assert_eq!(
+            feed(&mut alert, &[60, 60, 75, 60, 60, 60]),
+            [None, None, None, None, None, Some(false)]
+        );
+    }
+
+    #[test]
+    fn raising_again_is_rate_limited() {
+        let mut alert = alert(0, 1, Duration::from_secs(3600));
+        assert_eq!(
+            feed(&mut alert, &[90, 10, 90, 10, 90]),
+            [Some(true), Some(false), None, None, None]
+        );
+    }
+
+    #[test]
+    fn raised_again_after_the_interval() {
+        let mut alert = alert(0, 1, Duration::from_millis(50));
+        assert_eq!(
+            feed(&mut alert, &[90, 10, 90]),
+            [Some(true), Some(false), None]
+        );
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(alert.update(90), Some(true));
+    }
+}
@@ -0,0 +1,36 @@
+//! Application context attached to the samples of a profiler, e.g. the name of the running
+//! test case or the frame number, so recorded DDR data can be related to what caused it
+
+use std::sync::{Arc, Mutex};
+
+/// Key value pairs attached to every following sample. Clones share the pairs, so another
+/// thread can annotate while `MmdcProfiler::run` blocks.
+#[derive(Clone, Debug, Default)]
+pub struct Annotations {
+    entries: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl Annotations {
+    /// Adds the pair or replaces the value of `key`
+    pub fn set(&self, key: &str, value: impl ToString) {
+        let mut entries = self.entries.lock().unwrap();
+        let value = value.to_string();
+        match entries.iter_mut().find(|(name, _)| name == key) {
+            Some((_, current)) => *current = value,
+            None => entries.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().retain(|(name, _)| name != key);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Current pairs in the order they were first set
+    pub fn snapshot(&self) -> Vec<(String, String)> {
+        self.entries.lock().unwrap().clone()
+    }
+}
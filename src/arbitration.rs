@@ -0,0 +1,114 @@
+//! Decoding of MAARCR, the arbitration and reordering policy of the MMDC core
+
+use crate::bits;
+use std::fmt;
+
+/// MAARCR fields, see the AXI reordering control register of the i.MX6 reference manual
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arbitration {
+    /// ARCR_GUARD, times an access may be bypassed before it gets the highest priority
+    pub guard: u32,
+    /// ARCR_DYN_MAX, highest dynamic priority an aging access can reach
+    pub dyn_max: u32,
+    /// ARCR_DYN_JMP, dynamic priority gained each time an access is bypassed
+    pub dyn_jmp: u32,
+    /// ARCR_ACC_HIT, weight of back-to-back accesses of the same kind
+    pub acc_hit: u32,
+    /// ARCR_PAG_HIT, weight of accesses hitting an open page
+    pub pag_hit: u32,
+    /// ARCR_RCH_EN, the real-time channel bypasses the reordering
+    pub rch_en: bool,
+    /// ARCR_EXC_ERR_EN, exclusive access errors are reported
+    pub exc_err_en: bool,
+    /// ARCR_SEC_ERR_EN, security violations are reported
+    pub sec_err_en: bool,
+    /// ARCR_SEC_ERR_LOCK, ARCR_SEC_ERR_EN is locked until reset
+    pub sec_err_lock: bool,
+}
+
+impl Arbitration {
+    pub fn from_register(maarcr: u32) -> Arbitration {
+        Arbitration {
+            guard: bits(maarcr, 0, 4),
+            dyn_max: bits(maarcr, 4, 4),
+            dyn_jmp: bits(maarcr, 8, 4),
+            acc_hit: bits(maarcr, 16, 3),
+            pag_hit: bits(maarcr, 20, 3),
+            rch_en: bits(maarcr, 24, 1) == 1,
+            exc_err_en: bits(maarcr, 28, 1) == 1,
+            sec_err_en: bits(maarcr, 30, 1) == 1,
+            sec_err_lock: bits(maarcr, 31, 1) == 1,
+        }
+    }
+}
+
+impl fmt::Display for Arbitration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "  Page hit weight: {}, access hit weight: {}",
+            self.pag_hit, self.acc_hit
+        )?;
+        writeln!(
+            f,
+            "  Dynamic priority: +{} per bypass, up to {}",
+            self.dyn_jmp, self.dyn_max
+        )?;
+        match self.guard {
+            0 => writeln!(f, "  Guard: disabled")?,
+            guard => writeln!(f, "  Guard: highest priority after {} bypasses", guard)?,
+        }
+        writeln!(f, "  Real-time channel: {}", self.rch_en)?;
+        write!(
+            f,
+            "  Error reporting: exclusive {}, security {}{}",
+            self.exc_err_en,
+            self.sec_err_en,
+            if self.sec_err_lock { " (locked)" } else { "" }
+        )
+    }
+}
+
+/// MAARCR bits of the weight and priority fields, the error reporting bits are left alone
+/// when applying a named policy
+pub const POLICY_MASK: u32 = 0x0077_0FFF;
+
+/// Named arbitration policies as MAARCR values of the POLICY_MASK fields
+pub const POLICIES: [(&str, u32, &str); 4] = [
+    ("default", 0x0042_01F0, "Reset value"),
+    (
+        "throughput",
+        0x0074_01F0,
+        "Strongly prefers open pages and runs of the same access kind",
+    ),
+    (
+        "latency",
+        0x0021_04F2,
+        "Ages bypassed accesses quickly and guards them after two bypasses",
+    ),
+    (
+        "in-order",
+        0x0000_0FF1,
+        "No hit weights, every bypassed access gets the highest priority",
+    ),
+];
+
+/// MAARCR value of a named policy applied to `current`, or of a hex value such as 514201F0
+pub fn resolve(setting: &str, current: u32) -> Result<u32, String> {
+    if let Some((_, policy, _)) = POLICIES.iter().find(|(name, _, _)| *name == setting) {
+        return Ok(current & !POLICY_MASK | policy);
+    }
+    u32::from_str_radix(setting.trim_start_matches("0x"), 16).map_err(|_| {
+        let names: Vec<&str> = POLICIES.iter().map(|(name, _, _)| *name).collect();
+        format!(
+            "{} is neither a hex MAARCR value nor a policy: {}",
+            setting,
+            names.join(", ")
+        )
+    })
+}
+
+pub fn print_arbitration(maarcr: u32) {
+    println!("Arbitration (MAARCR 0x{:08X}):", maarcr);
+    println!("{}", Arbitration::from_register(maarcr));
+}
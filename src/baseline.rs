@@ -0,0 +1,113 @@
+//! Run summaries stored as a baseline and the regression gate comparing later runs
+//! against them, e.g. in hardware-in-the-loop CI:
+//!
+//! ```text
+//! r-mmdc -c 60 --save-baseline baseline.json
+//! r-mmdc -c 60 --baseline baseline.json --max-regression 10%
+//! ```
+//!
+//! The same workload causing more DDR traffic or load than before counts as regression.
+
+use crate::json::{self, JsonObject};
+use crate::summary::Summary;
+use crate::SCHEMA_VERSION;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const DEFAULT_MAX_REGRESSION: f32 = 10_f32;
+
+/// Compared statistics, in the order of the report
+const METRICS: [&str; 3] = ["mean_total_mbps", "p95_total_mbps", "mean_utilization"];
+
+pub struct Baseline {
+    samples: u32,
+    values: Vec<(String, f64)>,
+}
+
+impl Baseline {
+    pub fn from_summary(summary: &Summary) -> Baseline {
+        let values = [
+            summary.mean_total_bandwidth(),
+            summary.total_bandwidth.quantile(95),
+            summary.mean_utilization(),
+        ];
+        Baseline {
+            samples: summary.samples(),
+            values: METRICS
+                .iter()
+                .zip(values.iter())
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Baseline> {
+        let fields = json::parse_flat(&fs::read_to_string(path)?);
+        let value = |key: &str| {
+            fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .and_then(|(_, value)| value.parse::<f64>().ok())
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{} missing", key))
+                })
+        };
+        let mut values = Vec::new();
+        for name in METRICS.iter() {
+            values.push((name.to_string(), value(name)?));
+        }
+        Ok(Baseline {
+            samples: value("samples")? as u32,
+            values,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let object = JsonObject::new()
+            .number("schema_version", SCHEMA_VERSION)
+            .number("samples", self.samples);
+        let object = self.values.iter().fold(object, |object, (name, value)| {
+            object.number(name, format!("{:.2}", value))
+        });
+        fs::write(path, format!("{}\n", object.build()))
+    }
+
+    /// Writes the comparison of `current` with the baseline, returns whether no statistic
+    /// grew by more than `max_regression` percent
+    pub fn compare(
+        &self,
+        current: &Baseline,
+        max_regression: f32,
+        out: &mut dyn Write,
+    ) -> io::Result<bool> {
+        writeln!(
+            out,
+            "Baseline comparison, {} against {} baseline samples:",
+            current.samples, self.samples
+        )?;
+        let mut passed = true;
+        for ((name, baseline), (_, value)) in self.values.iter().zip(current.values.iter()) {
+            let change = match *baseline {
+                baseline if baseline > 0_f64 => (value - baseline) / baseline * 100_f64,
+                _ => 0_f64,
+            };
+            let regressed = change > max_regression as f64;
+            passed &= !regressed;
+            writeln!(
+                out,
+                "{:<18} {:>10.2} -> {:>10.2} {:>+7.1}%{}",
+                name,
+                baseline,
+                value,
+                change,
+                if regressed { "  REGRESSION" } else { "" }
+            )?;
+        }
+        match passed {
+            true => writeln!(out, "PASS: within {}%", max_regression)?,
+            false => writeln!(out, "FAIL: regressed by more than {}%", max_regression)?,
+        }
+        Ok(passed)
+    }
+}
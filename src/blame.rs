@@ -0,0 +1,89 @@
+//! Suspects of a saturation. When the utilization crosses the threshold, a quick scan
+//! of the masters right after the saturated record names the ones with the most traffic,
+//! the counters can only watch one master at a time.
+
+use crate::alert::{Alert, AlertPolicy};
+use crate::schedule::Fixed;
+use crate::top::{self, MasterLoad};
+use crate::{select_master, MMDCProfileResult, ALL_MASTERS, MMDC};
+use mmdc_core::soc::Master;
+use std::time::Duration;
+
+/// Masters named in a report
+const SUSPECTS: usize = 3;
+
+pub struct Blame {
+    alert: Alert,
+    masters: &'static [Master],
+    scheduler: Fixed,
+    overhead: Duration,
+}
+
+impl Blame {
+    pub fn new(
+        policy: AlertPolicy,
+        masters: &'static [Master],
+        window: Duration,
+        overhead: Duration,
+    ) -> Blame {
+        Blame {
+            alert: Alert::new(policy),
+            masters,
+            scheduler: Fixed::new(window),
+            overhead,
+        }
+    }
+
+    /// Scans the masters when the record raises the alert and returns the top contributors,
+    /// e.g. `gpu3d 812.30MB/s (61%), vpu 201.50MB/s (15%)`. The filter of the run is
+    /// selected again afterwards.
+    pub fn check(
+        &mut self,
+        mmdc: &mut MMDC,
+        bus_width: u32,
+        results: &MMDCProfileResult,
+    ) -> Option<String> {
+        if self.alert.update(results.utilization) != Some(true) {
+            return None;
+        }
+        let filter = mmdc.madpcr1;
+        let mut reference = [MasterLoad::new(&ALL_MASTERS)];
+        let mut loads: Vec<MasterLoad> = self.masters.iter().map(MasterLoad::new).collect();
+        top::scan(
+            mmdc,
+            bus_width,
+            self.overhead,
+            &mut self.scheduler,
+            &mut reference,
+        );
+        top::scan(
+            mmdc,
+            bus_width,
+            self.overhead,
+            &mut self.scheduler,
+            &mut loads,
+        );
+        select_master(mmdc, filter);
+
+        let all = reference[0].bandwidth().2;
+        loads.sort_by(|a, b| b.bandwidth().2.total_cmp(&a.bandwidth().2));
+        let suspects: Vec<String> = loads
+            .iter()
+            .take(SUSPECTS)
+            .filter(|load| load.bandwidth().2 > 0_f32)
+            .map(|load| {
+                let total = load.bandwidth().2;
+                let share = if all > 0_f32 {
+                    total * 100_f32 / all
+                } else {
+                    0_f32
+                };
+                format!("{} {:.2}MB/s ({:.0}%)", load.master.name, total, share)
+            })
+            .collect();
+        match suspects.is_empty() {
+            true => Some("no traffic during the scan".to_string()),
+            false => Some(suspects.join(", ")),
+        }
+    }
+}
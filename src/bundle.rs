@@ -0,0 +1,192 @@
+//! Support bundle, a gzipped tar archive of everything asked for on a bug report about
+//! odd numbers: the system identification, a dump of the register block, a short
+//! measurement and the run metadata
+
+use crate::gzip::GzipWriter;
+use crate::metadata::RunMetadata;
+use crate::{
+    apply_options, detect_soc, do_measuring_cylce, get_tick_count, measure_overhead,
+    print_profiling_results, schedule, Format, Opt, DT_COMPATIBLE_PATH, MMDC,
+};
+use mmdc_core::soc::SocProfile;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::mem::size_of;
+use std::path::Path;
+use std::ptr;
+
+/// Top level directory of the archive
+const DIRECTORY: &str = "r-mmdc-support";
+
+/// Files of the system identification, added as they are
+const SYSTEM_FILES: [(&str, &str); 3] = [
+    ("cpuinfo.txt", "/proc/cpuinfo"),
+    ("kernel.txt", "/proc/version"),
+    ("cmdline.txt", "/proc/cmdline"),
+];
+
+/// Device tree and SoC bus attributes summarized in device-tree.txt
+const DEVICE_TREE_FILES: [&str; 6] = [
+    "/proc/device-tree/model",
+    DT_COMPATIBLE_PATH,
+    "/sys/devices/soc0/machine",
+    "/sys/devices/soc0/family",
+    "/sys/devices/soc0/soc_id",
+    "/sys/devices/soc0/revision",
+];
+
+const BLOCK_SIZE: usize = 512;
+
+/// Cargo features the binary was built with
+fn features() -> Vec<&'static str> {
+    let features = [
+        ("compress", cfg!(feature = "compress")),
+        ("network", cfg!(feature = "network")),
+        ("dbus", cfg!(feature = "dbus")),
+        ("shm", cfg!(feature = "shm")),
+        ("syslog", cfg!(feature = "syslog")),
+        ("perf", cfg!(feature = "perf")),
+        ("fuse", cfg!(feature = "fuse")),
+        ("serde", cfg!(feature = "serde")),
+    ];
+    features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Writer of an uncompressed ustar archive of regular files
+struct TarWriter<W: Write> {
+    inner: W,
+    mtime: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn new(inner: W) -> TarWriter<W> {
+        TarWriter {
+            inner,
+            mtime: (get_tick_count() / 1000) as u64,
+        }
+    }
+
+    fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let path = format!("{}/{}", DIRECTORY, name);
+        let mut header = [0_u8; BLOCK_SIZE];
+        let mut field = |offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value);
+        };
+        field(0, path.as_bytes());
+        field(100, b"0000644\0");
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", data.len()).as_bytes());
+        field(136, format!("{:011o}\0", self.mtime).as_bytes());
+        // the checksum is summed with its own field filled with spaces
+        field(148, b"        ");
+        field(156, b"0");
+        field(257, b"ustar\0");
+        field(263, b"00");
+        let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        self.inner.write_all(&header)?;
+        self.inner.write_all(data)?;
+        let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.inner.write_all(&vec![0_u8; padding])
+    }
+
+    /// Two zero blocks end the archive
+    fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0_u8; 2 * BLOCK_SIZE])?;
+        Ok(self.inner)
+    }
+}
+
+/// Content of a file, or why it couldn't be read, the bundle is still useful without it
+fn read_or_reason(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| format!("unavailable: {}\n", e).into_bytes())
+}
+
+fn device_tree_summary() -> String {
+    let mut summary = String::new();
+    for path in DEVICE_TREE_FILES.iter() {
+        let value = match fs::read(path) {
+            // device tree strings are NUL terminated and lists NUL separated
+            Ok(value) => String::from_utf8_lossy(&value)
+                .split('\0')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .collect::<Vec<&str>>()
+                .join(", "),
+            Err(e) => format!("unavailable: {}", e),
+        };
+        summary.push_str(&format!("{}: {}\n", path, value));
+    }
+    summary
+}
+
+/// Every word of the mapped register block with its offset
+fn register_dump(mmdc: &MMDC) -> String {
+    let words = mmdc as *const MMDC as *const u32;
+    (0..size_of::<MMDC>() / 4)
+        .map(|index| {
+            let value = unsafe { ptr::read_volatile(words.add(index)) };
+            format!("0x{:03X}: 0x{:08X}\n", index * 4, value)
+        })
+        .collect()
+}
+
+fn metadata_text(run_metadata: &RunMetadata, opt: &Opt) -> String {
+    let mut text = String::new();
+    let arguments: Vec<String> = std::env::args().collect();
+    text.push_str(&format!("command_line={}\n", arguments.join(" ")));
+    text.push_str(&format!("features={}\n", features().join(",")));
+    let detection = match detect_soc() {
+        Ok((soc, source)) => format!("{} from {}", soc.name, source),
+        Err(e) => e.to_string(),
+    };
+    text.push_str(&format!("soc_detection={}\n", detection));
+    text.push_str(&format!(
+        "soc_option={}\n",
+        opt.soc.as_deref().unwrap_or("")
+    ));
+    for (key, value) in run_metadata.entries() {
+        text.push_str(&format!("{}={}\n", key, value));
+    }
+    text
+}
+
+/// Writes the bundle to `path`, measuring `samples` windows of the sleep time
+pub fn create(
+    path: &Path,
+    mmdc: &mut MMDC,
+    opt: &Opt,
+    soc: Option<&'static SocProfile>,
+    bus_width: u32,
+    samples: u32,
+) -> io::Result<()> {
+    // dumped before the measurement changes the profiling registers
+    let registers = register_dump(mmdc);
+    let overhead = measure_overhead(mmdc, bus_width);
+    let run_metadata = RunMetadata::collect(mmdc, opt, soc, bus_width, overhead);
+
+    apply_options(mmdc, opt);
+    let mut measurement = Vec::new();
+    let mut scheduler = schedule::from_opt(opt);
+    for _ in 0..samples {
+        let (results, time) = do_measuring_cylce(mmdc, bus_width, overhead, scheduler.as_mut());
+        print_profiling_results(&mut measurement, &results, time, opt, Format::Jsonl)?;
+    }
+
+    let gzip = GzipWriter::new(BufWriter::new(File::create(path)?))?;
+    let mut tar = TarWriter::new(gzip);
+    tar.append("metadata.txt", metadata_text(&run_metadata, opt).as_bytes())?;
+    for (name, source) in SYSTEM_FILES.iter() {
+        tar.append(name, &read_or_reason(source))?;
+    }
+    tar.append("device-tree.txt", device_tree_summary().as_bytes())?;
+    tar.append("registers.txt", registers.as_bytes())?;
+    tar.append("samples.jsonl", &measurement)?;
+    tar.finish()?.try_finish()
+}
@@ -0,0 +1,238 @@
+use crate::{bits, MMDC};
+
+const BYTE_LANES: u32 = 4;
+
+/// Delay line settings are given in 1/256 of a DDR clock cycle
+const DELAY_TAPS_PER_CYCLE: f32 = 256_f32;
+
+struct GatingLane {
+    half_cycles: u32,
+    abs_offset: u32,
+}
+
+impl GatingLane {
+    /// Half cycles in the upper four and taps in the lower seven bits, the layout of the
+    /// MPDGHWSTn limits
+    fn from_bits(value: u32) -> GatingLane {
+        GatingLane {
+            half_cycles: bits(value, 7, 4),
+            abs_offset: bits(value, 0, 7),
+        }
+    }
+
+    fn cycles(&self) -> f32 {
+        self.half_cycles as f32 * 0.5 + self.abs_offset as f32 / DELAY_TAPS_PER_CYCLE
+    }
+}
+
+/// Lower and upper boundary of the DQS gating window found by the hardware calibration
+fn gating_window(hwst: u32) -> (GatingLane, GatingLane) {
+    (
+        GatingLane::from_bits(bits(hwst, 0, 11)),
+        GatingLane::from_bits(bits(hwst, 16, 11)),
+    )
+}
+
+struct WriteLevelingLane {
+    cycle_delay: u32,
+    half_cycle: u32,
+    abs_offset: u32,
+    hw_error: bool,
+    hw_result: u32,
+}
+
+impl WriteLevelingLane {
+    fn cycles(&self) -> f32 {
+        self.cycle_delay as f32
+            + self.half_cycle as f32 * 0.5
+            + self.abs_offset as f32 / DELAY_TAPS_PER_CYCLE
+    }
+}
+
+fn delay_line_lanes(reg: u32) -> Vec<u32> {
+    (0..BYTE_LANES).map(|lane| bits(reg, lane * 8, 7)).collect()
+}
+
+fn gating_lanes(dgctrl0: u32, dgctrl1: u32) -> Vec<GatingLane> {
+    [dgctrl0, dgctrl1]
+        .iter()
+        .flat_map(|reg| {
+            (0..2).map(move |half| GatingLane {
+                half_cycles: bits(*reg, 8 + half * 16, 4),
+                abs_offset: bits(*reg, half * 16, 7),
+            })
+        })
+        .collect()
+}
+
+fn write_leveling_lanes(mmdc: &MMDC) -> Vec<WriteLevelingLane> {
+    [mmdc.mpwldectrl0, mmdc.mpwldectrl1]
+        .iter()
+        .flat_map(|reg| (0..2).map(move |half| (*reg, half * 16)))
+        .enumerate()
+        .map(|(lane, (reg, shift))| WriteLevelingLane {
+            cycle_delay: bits(reg, 9 + shift, 2),
+            half_cycle: bits(reg, 8 + shift, 1),
+            abs_offset: bits(reg, shift, 7),
+            hw_error: bits(mmdc.mpwlgcr, 8 + lane as u32, 1) == 1,
+            hw_result: bits(mmdc.mpwlhwerr, lane as u32 * 8, 8),
+        })
+        .collect()
+}
+
+fn print_delay_line(name: &str, reg: u32) {
+    println!("{} (0x{:08X}):", name, reg);
+    for (lane, taps) in delay_line_lanes(reg).iter().enumerate() {
+        println!(
+            "  Byte lane {}: {} taps ({:.3} cycles)",
+            lane,
+            taps,
+            *taps as f32 / DELAY_TAPS_PER_CYCLE
+        );
+    }
+}
+
+fn print_gating(mmdc: &MMDC) {
+    println!(
+        "DQS gating (MPDGCTRL0 0x{:08X}, MPDGCTRL1 0x{:08X}):",
+        mmdc.mpdgctrl0, mmdc.mpdgctrl1
+    );
+    println!(
+        "  Gating disabled: {}, HW gating enabled: {}, extended upper boundary: {}",
+        bits(mmdc.mpdgctrl0, 29, 1) == 1,
+        bits(mmdc.mpdgctrl0, 28, 1) == 1,
+        bits(mmdc.mpdgctrl0, 23, 1) == 1
+    );
+    let status = [
+        mmdc.mpdghwst0,
+        mmdc.mpdghwst1,
+        mmdc.mpdghwst2,
+        mmdc.mpdghwst3,
+    ];
+    for (lane, (gating, hwst)) in gating_lanes(mmdc.mpdgctrl0, mmdc.mpdgctrl1)
+        .iter()
+        .zip(status.iter())
+        .enumerate()
+    {
+        let (low, up) = gating_window(*hwst);
+        println!(
+            "  Byte lane {}: {} half cycles + {} taps ({:.3} cycles), HW window {:.3}..{:.3} cycles (MPDGHWST{} 0x{:08X})",
+            lane,
+            gating.half_cycles,
+            gating.abs_offset,
+            gating.cycles(),
+            low.cycles(),
+            up.cycles(),
+            lane,
+            hwst
+        );
+    }
+    if bits(mmdc.mpdgctrl0, 12, 1) == 1 {
+        println!("  WARNING: hardware DQS gating calibration failed");
+    }
+}
+
+/// Termination of the ODTn_INT_RES fields, in ohms, 0 is disabled
+const ODT_RESISTANCES: [u32; 8] = [0, 120, 60, 40, 30, 24, 20, 17];
+
+fn odt_lanes(odtctrl: u32) -> Vec<u32> {
+    (0..BYTE_LANES)
+        .map(|lane| ODT_RESISTANCES[bits(odtctrl, 4 + lane * 4, 3) as usize])
+        .collect()
+}
+
+fn print_odt(mmdc: &MMDC) {
+    println!("ODT (MPODTCTRL 0x{:08X}):", mmdc.mpodtctrl);
+    println!(
+        "  DRAM ODT pin on writes: active CS {}, passive CS {}",
+        bits(mmdc.mpodtctrl, 1, 1) == 1,
+        bits(mmdc.mpodtctrl, 0, 1) == 1
+    );
+    println!(
+        "  DRAM ODT pin on reads: active CS {}, passive CS {}",
+        bits(mmdc.mpodtctrl, 3, 1) == 1,
+        bits(mmdc.mpodtctrl, 2, 1) == 1
+    );
+    for (lane, ohms) in odt_lanes(mmdc.mpodtctrl).iter().enumerate() {
+        match ohms {
+            0 => println!("  Byte lane {}: read termination disabled", lane),
+            ohms => println!("  Byte lane {}: read termination {} ohms", lane, ohms),
+        }
+    }
+}
+
+fn print_write_leveling(mmdc: &MMDC) {
+    println!(
+        "Write leveling (MPWLGCR 0x{:08X}, MPWLDECTRL0 0x{:08X}, MPWLDECTRL1 0x{:08X}, MPWLHWERR 0x{:08X}):",
+        mmdc.mpwlgcr, mmdc.mpwldectrl0, mmdc.mpwldectrl1, mmdc.mpwlhwerr
+    );
+    println!(
+        "  HW leveling enabled: {}, SW leveling enabled: {}",
+        bits(mmdc.mpwlgcr, 0, 1) == 1,
+        bits(mmdc.mpwlgcr, 1, 1) == 1
+    );
+    let lanes = write_leveling_lanes(mmdc);
+    for (lane, leveling) in lanes.iter().enumerate() {
+        println!(
+            "  Byte lane {}: {} cycles + {} half cycle + {} taps ({:.3} cycles), HW result 0x{:02X}{}",
+            lane,
+            leveling.cycle_delay,
+            leveling.half_cycle,
+            leveling.abs_offset,
+            leveling.cycles(),
+            leveling.hw_result,
+            if leveling.hw_error { " ERROR" } else { "" }
+        );
+    }
+    if lanes.iter().any(|lane| lane.hw_error) {
+        println!("  WARNING: hardware write leveling failed on at least one byte lane");
+    }
+}
+
+pub fn print_calibration(mmdc: &MMDC) {
+    println!("MMDC calibration values:");
+    println!("***********************");
+    print_delay_line("Read delay line MPRDDLCTL", mmdc.mprddlctl);
+    print_delay_line("Write delay line MPWRDLCTL", mmdc.mpwrdlctl);
+    print_gating(mmdc);
+    print_write_leveling(mmdc);
+    print_odt(mmdc);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gating_half_cycles_are_four_bits_wide() {
+        // DG_HC_DEL 0xF and DG_DL_ABS_OFFSET 0x7F in both halves of both registers
+        let lanes = gating_lanes(0x0F7F_0F7F, 0x0A01_0305);
+        let decoded: Vec<(u32, u32)> = lanes
+            .iter()
+            .map(|lane| (lane.half_cycles, lane.abs_offset))
+            .collect();
+        assert_eq!(decoded, [(15, 127), (15, 127), (3, 5), (10, 1)]);
+        assert_eq!(lanes[0].cycles(), 7.5 + 127_f32 / 256_f32);
+    }
+
+    #[test]
+    fn gating_flags_don_t_leak_into_the_lanes() {
+        // DG_DIS, HW_DG_EN and DG_EXT_UP set, all delays zero
+        let lanes = gating_lanes(0x3000_1000, 0);
+        assert!(lanes
+            .iter()
+            .all(|lane| lane.half_cycles == 0 && lane.abs_offset == 0));
+    }
+
+    #[test]
+    fn hardware_gating_window() {
+        let (low, up) = gating_window(0x0283_0105);
+        assert_eq!((low.half_cycles, low.abs_offset), (2, 5));
+        assert_eq!((up.half_cycles, up.abs_offset), (5, 3));
+    }
+
+    #[test]
+    fn delay_lines_are_seven_bits_per_lane() {
+        assert_eq!(delay_line_lanes(0xC0FF_8040), [0x40, 0, 0x7F, 0x40]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Drops the records of --emit-on-change which hardly differ from the last written one
+
+use crate::MMDCProfileResult;
+
+pub struct ChangeFilter {
+    /// Bandwidth change in MB/s a record needs to be written
+    delta: f32,
+    /// Read, write and total bandwidth of the last written record per master
+    last: Vec<(Option<&'static str>, [f32; 3])>,
+}
+
+impl ChangeFilter {
+    pub fn new(delta: f32) -> ChangeFilter {
+        ChangeFilter {
+            delta,
+            last: Vec::new(),
+        }
+    }
+
+    /// Whether the read, write or total bandwidth moved by the delta or more since the
+    /// last written record of the same master. Flagged records are always written.
+    pub fn should_emit(&mut self, results: &MMDCProfileResult, time: u32) -> bool {
+        let (read, write, total) = results.bandwidth(time);
+        let current = [read, write, total];
+        let delta = self.delta;
+        let last = self
+            .last
+            .iter_mut()
+            .find(|(master, _)| *master == results.master);
+        let changed = match &last {
+            Some((_, last)) => {
+                results.flags != 0
+                    || last
+                        .iter()
+                        .zip(current.iter())
+                        .any(|(last, current)| (current - last).abs() >= delta)
+            }
+            None => true,
+        };
+        if changed {
+            match last {
+                Some((_, last)) => *last = current,
+                None => self.last.push((results.master, current)),
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(master: Option<&'static str>, read: u32, write: u32) -> MMDCProfileResult {
+        MMDCProfileResult {
+            raw_counters: [0, 0, 0, 0, read * 1024 * 1024, write * 1024 * 1024],
+            master,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn first_record_is_written() {
+        let mut filter = ChangeFilter::new(5.0);
+        assert!(filter.should_emit(&results(None, 0, 0), 1000));
+    }
+
+    #[test]
+    fn small_changes_are_dropped() {
+        let mut filter = ChangeFilter::new(5.0);
+        assert!(filter.should_emit(&results(None, 100, 0), 1000));
+        assert!(!filter.should_emit(&results(None, 104, 0), 1000));
+        assert!(!filter.should_emit(&results(None, 96, 0), 1000));
+        assert!(filter.should_emit(&results(None, 95, 0), 1000));
+    }
+
+    #[test]
+    fn drift_is_compared_to_the_last_written_record() {
+        let mut filter = ChangeFilter::new(5.0);
+        assert!(filter.should_emit(&results(None, 100, 0), 1000));
+        assert!(!filter.should_emit(&results(None, 103, 0), 1000));
+        assert!(filter.should_emit(&results(None, 106, 0), 1000));
+        assert!(!filter.should_emit(&results(None, 109, 0), 1000));
+    }
+
+    #[test]
+    fn write_bandwidth_alone_counts() {
+        let mut filter = ChangeFilter::new(5.0);
+        assert!(filter.should_emit(&results(None, 100, 0), 1000));
+        assert!(!filter.should_emit(&results(None, 100, 4), 1000));
+        assert!(filter.should_emit(&results(None, 100, 5), 1000));
+    }
+
+    #[test]
+    fn flagged_records_are_written() {
+        let mut filter = ChangeFilter::new(5.0);
+        assert!(filter.should_emit(&results(None, 100, 0), 1000));
+        let mut flagged = results(None, 100, 0);
+        flagged.flags = 1;
+        assert!(filter.should_emit(&flagged, 1000));
+    }
+
+    #[test]
+    fn masters_are_compared_separately() {
+        let mut filter = ChangeFilter::new(5.0);
+        assert!(filter.should_emit(&results(Some("arm"), 100, 0), 1000));
+        assert!(filter.should_emit(&results(Some("gpu"), 0, 0), 1000));
+        assert!(!filter.should_emit(&results(Some("arm"), 101, 0), 1000));
+        assert!(!filter.should_emit(&results(Some("gpu"), 1, 0), 1000));
+        assert!(filter.should_emit(&results(Some("gpu"), 100, 0), 1000));
+    }
+}
@@ -0,0 +1,226 @@
+//! INI style configuration file with named measurement presets
+//!
+//! ```text
+//! [preset.gpu-load]
+//! master = gpu3d
+//! sleeptime = 100
+//! cycles = 50
+//! format = csv
+//! output = /var/log/gpu-load.csv
+//! ```
+//!
+//! Keys are the long option names, `true`/`false` toggle flags and repeated keys give
+//! repeated options.
+//!
+//! The `[metrics]` section adds derived columns to every record:
+//!
+//! ```text
+//! [metrics]
+//! write_ratio = write_bytes / (read_bytes + write_bytes)
+//! ```
+//!
+//! Every `[sink.NAME]` section adds an output the records are sent to next to the main
+//! one, see the sink module for the keys:
+//!
+//! ```text
+//! [sink.archive]
+//! type = file
+//! path = /var/log/mmdc.jsonl
+//! format = jsonl
+//! ```
+//!
+//! The `[groups]` section names sets of masters, which `--group` rotates through and
+//! `top` and the summary roll up:
+//!
+//! ```text
+//! [groups]
+//! graphics = gpu3d + gpu2d + ipu1
+//! media = vpu + ipu2
+//! ```
+
+use crate::expr::Expr;
+use crate::METRIC_VARIABLES;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const DEFAULT_PATH: &str = "/etc/r-mmdc.conf";
+
+const PRESET_PREFIX: &str = "preset.";
+const METRICS_SECTION: &str = "metrics";
+const SINK_PREFIX: &str = "sink.";
+const GROUPS_SECTION: &str = "groups";
+
+enum Section {
+    Preset,
+    Metrics,
+    Sink,
+    Groups,
+    Other,
+}
+
+pub struct Preset {
+    pub name: String,
+    entries: Vec<(String, String)>,
+}
+
+impl Preset {
+    /// Command line arguments of the preset, leaving out options that are in `given` already
+    pub fn args(&self, given: &[String]) -> Vec<String> {
+        let mut args = Vec::new();
+        for (key, value) in self.entries.iter() {
+            let option = format!("--{}", key);
+            let overridden = given
+                .iter()
+                .any(|arg| *arg == option || arg.starts_with(&format!("{}=", option)));
+            if overridden {
+                continue;
+            }
+            match value.as_str() {
+                "true" => args.push(option),
+                "false" => {}
+                value => {
+                    args.push(option);
+                    args.push(value.to_string());
+                }
+            }
+        }
+        args
+    }
+}
+
+pub struct SinkConfig {
+    pub name: String,
+    entries: Vec<(String, String)>,
+}
+
+impl SinkConfig {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Named set of masters, their bandwidth adds up to the one of the group
+#[derive(Clone)]
+pub struct MasterGroup {
+    pub name: String,
+    pub masters: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub presets: Vec<Preset>,
+    /// Derived metrics in the order of their columns
+    pub metrics: Vec<(String, Expr)>,
+    pub sinks: Vec<SinkConfig>,
+    pub groups: Vec<MasterGroup>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Config> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Loads the file at `path`, a missing file at the default path gives an empty config
+    pub fn load_or_default(path: &Path) -> io::Result<Config> {
+        if path == Path::new(DEFAULT_PATH) && !path.exists() {
+            return Ok(Config::default());
+        }
+        Config::load(path)
+    }
+
+    pub fn parse(content: &str) -> io::Result<Config> {
+        let mut config = Config::default();
+        // entries of unknown sections are skipped
+        let mut section = Section::Other;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim();
+                section = if let Some(preset) = name.strip_prefix(PRESET_PREFIX) {
+                    config.presets.push(Preset {
+                        name: preset.to_string(),
+                        entries: Vec::new(),
+                    });
+                    Section::Preset
+                } else if let Some(sink) = name.strip_prefix(SINK_PREFIX) {
+                    config.sinks.push(SinkConfig {
+                        name: sink.to_string(),
+                        entries: Vec::new(),
+                    });
+                    Section::Sink
+                } else if name == METRICS_SECTION {
+                    Section::Metrics
+                } else if name == GROUPS_SECTION {
+                    Section::Groups
+                } else {
+                    Section::Other
+                };
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(position) => (line[..position].trim(), line[position + 1..].trim()),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: expected key = value", number + 1),
+                    ))
+                }
+            };
+            match (&section, config.presets.last_mut()) {
+                (Section::Preset, Some(preset)) => preset
+                    .entries
+                    .push((key.to_string(), value.trim_matches('"').to_string())),
+                (Section::Metrics, _) => {
+                    let expr = Expr::parse(value, &METRIC_VARIABLES).map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("line {}: {}", number + 1, e),
+                        )
+                    })?;
+                    config.metrics.push((key.to_string(), expr));
+                }
+                (Section::Sink, _) => {
+                    if let Some(sink) = config.sinks.last_mut() {
+                        sink.entries
+                            .push((key.to_string(), value.trim_matches('"').to_string()));
+                    }
+                }
+                (Section::Groups, _) => {
+                    let masters: Vec<String> = value
+                        .split(['+', ','])
+                        .map(str::trim)
+                        .filter(|master| !master.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                    if masters.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("line {}: group {} has no masters", number + 1, key),
+                        ));
+                    }
+                    config.groups.push(MasterGroup {
+                        name: key.to_string(),
+                        masters,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn preset(&self, name: &str) -> Option<&Preset> {
+        self.presets.iter().find(|preset| preset.name == name)
+    }
+
+    pub fn group(&self, name: &str) -> Option<&MasterGroup> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+}
@@ -0,0 +1,263 @@
+//! Gates the measurement on external start/stop commands and names its phases
+
+use crate::output::{create_fifo, nix_to_io};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{SigSet, Signal};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct GateState {
+    running: bool,
+    /// Number of the current or last start/stop segment, counting from 1
+    segment: u32,
+}
+
+/// Start/stop state shared with the thread receiving the commands
+#[derive(Clone, Default)]
+pub struct Gate {
+    state: Arc<(Mutex<GateState>, Condvar)>,
+    /// Phases named by "marker <name>" lines of the control FIFO
+    markers: Markers,
+}
+
+impl Gate {
+    /// Idles until "start" is written to the named pipe at `path`, measures until "stop"
+    pub fn from_fifo(path: &Path) -> io::Result<Gate> {
+        create_fifo(path)?;
+        let gate = Gate::default();
+        let receiver = gate.clone();
+        let path = path.to_path_buf();
+        thread::spawn(move || receiver.receive_fifo(&path));
+        Ok(gate)
+    }
+
+    /// Measures while the sysfs GPIO `line` is high, starting on the rising and stopping on
+    /// the falling edge
+    pub fn from_gpio(line: u32) -> io::Result<Gate> {
+        let directory = PathBuf::from(format!("/sys/class/gpio/gpio{}", line));
+        if !directory.exists() {
+            fs::write("/sys/class/gpio/export", line.to_string())?;
+        }
+        fs::write(directory.join("direction"), "in")?;
+        fs::write(directory.join("edge"), "both")?;
+        let value = File::open(directory.join("value"))?;
+        let gate = Gate::default();
+        let receiver = gate.clone();
+        thread::spawn(move || {
+            if let Err(e) = receiver.receive_gpio(value) {
+                eprintln!("Error reading GPIO {}: {}", line, e);
+            }
+        });
+        Ok(gate)
+    }
+
+    fn receive_gpio(&self, mut value: File) -> io::Result<()> {
+        let mut level = [0; 1];
+        loop {
+            value.seek(SeekFrom::Start(0))?;
+            value.read_exact(&mut level)?;
+            if level[0] == b'1' {
+                self.start();
+            } else {
+                self.stop();
+            }
+            // sysfs signals edges as priority data
+            let mut fds = [PollFd::new(value.as_raw_fd(), PollFlags::POLLPRI)];
+            poll(&mut fds, -1).map_err(nix_to_io)?;
+        }
+    }
+
+    /// Starts on SIGUSR1 and stops on SIGUSR2, which `block_signals` has to have blocked
+    pub fn from_signals() -> Gate {
+        let signals = signal_set(&GATE_SIGNALS);
+        let gate = Gate::default();
+        let receiver = gate.clone();
+        thread::spawn(move || loop {
+            match signals.wait() {
+                Ok(Signal::SIGUSR1) => receiver.start(),
+                Ok(_) => receiver.stop(),
+                Err(e) => {
+                    eprintln!("Error waiting for signals: {}", e);
+                    return;
+                }
+            }
+        });
+        gate
+    }
+
+    fn receive_fifo(&self, path: &Path) {
+        receive_lines(path, |command| match command {
+            "start" => self.start(),
+            "stop" => self.stop(),
+            "" => {}
+            command => match command.strip_prefix("marker ") {
+                Some(name) => self.markers.set(name.trim()),
+                None => eprintln!("WARNING: unknown control command {}", command),
+            },
+        });
+    }
+
+    pub fn markers(&self) -> Markers {
+        self.markers.clone()
+    }
+
+    pub fn start(&self) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        if !state.running {
+            state.running = true;
+            state.segment += 1;
+        }
+        condvar.notify_all();
+    }
+
+    pub fn stop(&self) {
+        self.state.0.lock().unwrap().running = false;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.0.lock().unwrap().running
+    }
+
+    pub fn segment(&self) -> u32 {
+        self.state.0.lock().unwrap().segment
+    }
+
+    /// Blocks until the next start while stopped
+    pub fn wait_until_running(&self) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        while !state.running {
+            state = condvar.wait(state).unwrap();
+        }
+    }
+}
+
+const TERMINATION_SIGNALS: [Signal; 3] = [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP];
+
+const GATE_SIGNALS: [Signal; 2] = [Signal::SIGUSR1, Signal::SIGUSR2];
+
+fn signal_set(signals: &[Signal]) -> SigSet {
+    let mut set = SigSet::empty();
+    for signal in signals {
+        set.add(*signal);
+    }
+    set
+}
+
+/// Blocks the termination signals, and those of the `--signals` gate if `gate`, so only
+/// the threads waiting for them receive them. The threads inherit the signal mask, so this
+/// has to be called before any thread is spawned.
+pub fn block_signals(gate: bool) -> io::Result<()> {
+    let mut signals = TERMINATION_SIGNALS.to_vec();
+    if gate {
+        signals.extend_from_slice(&GATE_SIGNALS);
+    }
+    signal_set(&signals).thread_block().map_err(nix_to_io)
+}
+
+/// Time in which repeated termination signals count once
+const REPEAT_WINDOW: Duration = Duration::from_millis(500);
+
+/// Termination signals of a run. The first one asks the run to stop after its current
+/// window, so the outputs, summaries and sinks are finished on the normal path, a second one
+/// ends the process right away. Signals within `REPEAT_WINDOW` of the first count as the
+/// first, `timeout` for one sends it to the process and to its process group.
+#[derive(Clone, Default)]
+pub struct Termination {
+    /// Number of the received signal, 0 while none was received
+    signal: Arc<AtomicI32>,
+}
+
+impl Termination {
+    /// Waits for SIGINT, SIGTERM and SIGHUP, which `block_signals` has to have blocked.
+    /// `on_exit` runs before the process exits on the second signal.
+    pub fn catch(on_exit: impl FnOnce() + Send + 'static) -> Termination {
+        let signals = signal_set(&TERMINATION_SIGNALS);
+        let termination = Termination::default();
+        let received = termination.signal.clone();
+        thread::spawn(move || {
+            if let Ok(first) = signals.wait() {
+                received.store(first as i32, Ordering::SeqCst);
+            }
+            let first = Instant::now();
+            // the run may be blocked, e.g. by a stopped gate, and never get to stop
+            while let Ok(second) = signals.wait() {
+                if first.elapsed() >= REPEAT_WINDOW {
+                    on_exit();
+                    std::process::exit(128 + second as i32);
+                }
+            }
+        });
+        termination
+    }
+
+    /// The termination signal received so far, the run should stop
+    pub fn signal(&self) -> Option<i32> {
+        match self.signal.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+}
+
+/// Name of the current phase of the measurement, e.g. boot, video-playback or idle
+#[derive(Clone, Default)]
+pub struct Markers {
+    phase: Arc<Mutex<Option<String>>>,
+}
+
+impl Markers {
+    /// Starts the phase named by each line written to the named pipe at `path`
+    pub fn from_fifo(path: &Path) -> io::Result<Markers> {
+        create_fifo(path)?;
+        let markers = Markers::default();
+        let receiver = markers.clone();
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            receive_lines(&path, |name| {
+                if !name.is_empty() {
+                    receiver.set(name);
+                }
+            })
+        });
+        Ok(markers)
+    }
+
+    pub fn set(&self, name: &str) {
+        *self.phase.lock().unwrap() = Some(name.to_string());
+    }
+
+    /// None until the first marker
+    pub fn phase(&self) -> Option<String> {
+        self.phase.lock().unwrap().clone()
+    }
+}
+
+/// Hands the trimmed lines of the named pipe at `path` to `handle`, reopening it after the
+/// writers have gone
+fn receive_lines(path: &Path, mut handle: impl FnMut(&str)) {
+    loop {
+        // blocks until a writer opens the pipe and returns once the last one closes it
+        let pipe = match File::open(path) {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                eprintln!("Error opening control FIFO {}: {}", path.display(), e);
+                return;
+            }
+        };
+        for line in BufReader::new(pipe).lines() {
+            match line {
+                Ok(line) => handle(line.trim()),
+                Err(e) => eprintln!("Error reading control FIFO: {}", e),
+            }
+        }
+    }
+}
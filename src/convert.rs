@@ -0,0 +1,274 @@
+//! Conversion between the recorded formats: CSV outputs, JSON lines sessions of `record`
+//! and binary traces of `--trace`. The input format is detected from the content.
+//!
+//! CSV records carry no flags and, unless written with `--timestamps`, no timestamps,
+//! which then count up from zero by the window lengths. The metrics and system columns of
+//! the records are not converted, only the counters they are derived from.
+
+use crate::trace::{self, TraceReader, TraceRecord, TraceWriter};
+use crate::{
+    json, master_names, metadata, print_record_at, schema, Format, Opt, FLAG_NAMES, SCHEMA_VERSION,
+};
+use r_mmdc::SampleSink;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+pub const FORMATS: [&str; 3] = ["csv", "jsonl", "binary"];
+
+/// Bus width of captures whose metadata doesn't give it, the one of the i.MX6Q
+const DEFAULT_BUS_WIDTH: u32 = 64;
+
+/// Wall-clock timestamps of the CSV records are in ms since the epoch, far above this
+const MIN_TIMESTAMP_MS: u64 = 1_000_000_000_000;
+
+/// Output format named by the file extension
+pub fn format_of(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()? {
+        "csv" => Some("csv"),
+        "jsonl" | "json" => Some("jsonl"),
+        "mtrace" => Some("binary"),
+        _ => None,
+    }
+}
+
+enum Target<'o> {
+    Csv(BufWriter<File>, &'o Opt),
+    Jsonl(BufWriter<File>),
+    Binary(TraceWriter<BufWriter<File>>),
+}
+
+/// Writes the converted capture, the metadata goes ahead of the first record
+struct Converter<'o> {
+    target: Target<'o>,
+    bus_width: Option<u32>,
+    metadata: Vec<(String, String)>,
+    started: bool,
+    records: u64,
+}
+
+impl Converter<'_> {
+    fn metadata(&mut self, key: &str, value: &str) -> io::Result<()> {
+        if key == "bus_width" && self.bus_width.is_none() {
+            self.bus_width = value.parse().ok();
+        }
+        if key == "schema_version" {
+            // version 1 captures carry none, the converted one has the current version
+            return schema::supported(value.parse().unwrap_or(1)).map_err(invalid);
+        }
+        self.metadata.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        self.started = true;
+        let entries: Vec<(&str, String)> = Some(("schema_version", SCHEMA_VERSION.to_string()))
+            .into_iter()
+            .chain(
+                self.metadata
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.clone())),
+            )
+            .collect();
+        match &mut self.target {
+            Target::Csv(out, _) => metadata::write_csv(&entries, out),
+            Target::Jsonl(out) => writeln!(out, "{}", metadata::to_json(&entries)),
+            Target::Binary(writer) => writer.on_metadata(&entries),
+        }
+    }
+
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if !self.started {
+            self.start()?;
+        }
+        self.records += 1;
+        let bus_width = self.bus_width.unwrap_or(DEFAULT_BUS_WIDTH);
+        match &mut self.target {
+            Target::Csv(out, opt) => {
+                let results = record.results(bus_width);
+                print_record_at(
+                    out,
+                    &results,
+                    record.time,
+                    record.timestamp_ms,
+                    opt,
+                    Format::Csv,
+                )
+            }
+            Target::Jsonl(out) => {
+                let results = record.results(bus_width);
+                writeln!(out, "{}", results.to_json(record.time, record.timestamp_ms))
+            }
+            Target::Binary(writer) => writer.append(record, bus_width),
+        }
+    }
+
+    fn finish(mut self) -> io::Result<u64> {
+        if !self.started {
+            self.start()?;
+        }
+        match &mut self.target {
+            Target::Csv(out, _) | Target::Jsonl(out) => out.flush()?,
+            Target::Binary(writer) => writer.finish()?,
+        }
+        Ok(self.records)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Flags of the comma separated names of a JSON record
+fn flags(names: &str) -> u32 {
+    names
+        .split(',')
+        .filter_map(|name| FLAG_NAMES.iter().find(|(_, flag_name)| *flag_name == name))
+        .fold(0, |flags, (flag, _)| flags | flag)
+}
+
+fn read_trace(path: &Path, converter: &mut Converter) -> io::Result<()> {
+    let mut reader = TraceReader::open(path)?;
+    if converter.bus_width.is_none() {
+        converter.bus_width = Some(reader.bus_width);
+    }
+    for (key, value) in json::parse_flat(&reader.metadata) {
+        if key != "record" {
+            converter.metadata(&key, &value)?;
+        }
+    }
+    let mut result = Ok(());
+    reader.for_each_between(0, u64::MAX, &mut |record| {
+        if result.is_ok() {
+            result = converter.record(record);
+        }
+    })?;
+    result
+}
+
+fn read_jsonl(path: &Path, converter: &mut Converter) -> io::Result<()> {
+    for line in BufReader::new(File::open(path)?).lines() {
+        let mut fields = json::parse_flat(&line?);
+        schema::upgrade(&mut fields, converter.records).map_err(invalid)?;
+        let value = |key: &str| {
+            fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        if value("record") == Some("metadata") {
+            for (key, value) in fields.iter().filter(|(key, _)| key != "record") {
+                converter.metadata(key, value)?;
+            }
+            continue;
+        }
+        let number = |key: &str| value(key).and_then(|value| value.parse::<u64>().ok());
+        let time = match number("time_ms") {
+            Some(time) => time as u32,
+            None => continue,
+        };
+        let mut raw = [0; 6];
+        let counters = [
+            "total_cycles",
+            "busy_cycles",
+            "read_accesses",
+            "write_accesses",
+            "read_bytes",
+            "write_bytes",
+        ];
+        for (counter, key) in raw.iter_mut().zip(counters.iter()) {
+            *counter = number(key).unwrap_or_default() as u32;
+        }
+        converter.record(&TraceRecord {
+            sequence: number("seq").unwrap_or(converter.records),
+            timestamp_ms: number("timestamp_ms").unwrap_or_default(),
+            time,
+            raw,
+            flags: value("flags").map_or(0, flags),
+            master: value("master").map(str::to_string),
+        })?;
+    }
+    Ok(())
+}
+
+fn read_csv(path: &Path, converter: &mut Converter) -> io::Result<()> {
+    let mut elapsed = 0_u64;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if let Some(comment) = line.strip_prefix("# ") {
+            // metadata ahead of the records, summaries and warm-up rows after them
+            if let (false, Some((key, value))) = (converter.started, comment.split_once('=')) {
+                converter.metadata(key, value)?;
+            }
+            continue;
+        }
+        let mut fields: Vec<&str> = line.split(';').collect();
+        // rows of rotated masters lead with the channel and master
+        let master = match fields.first() {
+            Some(channel) if channel.starts_with('p') && fields.len() > 1 => {
+                let master = fields[1].to_string();
+                fields.drain(..2);
+                Some(master)
+            }
+            _ => None,
+        };
+        let number = |index: usize| {
+            fields
+                .get(index)
+                .and_then(|field| field.parse::<u64>().ok())
+        };
+        let time = match number(0) {
+            Some(time) => time as u32,
+            None => continue,
+        };
+        let mut raw = [0; 6];
+        for (index, counter) in raw.iter_mut().enumerate() {
+            *counter = number(index + 1).unwrap_or_default() as u32;
+        }
+        elapsed += time as u64;
+        // --timestamps appends the wall clock, boot time and sequence number
+        let clock = fields.len().checked_sub(3).and_then(|index| {
+            let timestamp = number(index).filter(|ms| *ms >= MIN_TIMESTAMP_MS)?;
+            Some((timestamp, number(index + 2)?))
+        });
+        let (timestamp_ms, sequence) = clock.unwrap_or((elapsed, converter.records));
+        converter.record(&TraceRecord {
+            sequence,
+            timestamp_ms,
+            time,
+            raw,
+            flags: 0,
+            master: master.filter(|master| master_names().contains(&master.as_str())),
+        })?;
+    }
+    Ok(())
+}
+
+/// Converts the capture at `input` to `format`, returns the number of records
+pub fn run(input: &Path, output: &Path, format: &str, opt: &Opt) -> io::Result<u64> {
+    let out = BufWriter::new(File::create(output)?);
+    let target = match format {
+        "csv" => Target::Csv(out, opt),
+        "jsonl" => Target::Jsonl(out),
+        _ => Target::Binary(TraceWriter::new(out)),
+    };
+    let mut converter = Converter {
+        target,
+        bus_width: opt.bus_width,
+        metadata: Vec::new(),
+        started: false,
+        records: 0,
+    };
+    if trace::is_trace(input)? {
+        read_trace(input, &mut converter)?;
+    } else {
+        let mut first = String::new();
+        BufReader::new(File::open(input)?).read_line(&mut first)?;
+        if first.trim_start().starts_with('{') {
+            read_jsonl(input, &mut converter)?;
+        } else {
+            read_csv(input, &mut converter)?;
+        }
+    }
+    converter.finish()
+}
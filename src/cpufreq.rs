@@ -0,0 +1,64 @@
+//! Current frequency of each cpufreq policy, i.e. each CPU cluster
+
+use crate::schema::Column;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const CPUFREQ_DIR: &str = "/sys/devices/system/cpu/cpufreq";
+
+pub struct CpuFreq {
+    /// Column name and scaling_cur_freq file of each policy
+    policies: Vec<(String, PathBuf)>,
+}
+
+impl CpuFreq {
+    pub fn open() -> io::Result<CpuFreq> {
+        let mut policies: Vec<(u32, PathBuf)> = fs::read_dir(CPUFREQ_DIR)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let policy = name.strip_prefix("policy")?.parse().ok()?;
+                Some((policy, entry.path().join("scaling_cur_freq")))
+            })
+            .collect();
+        if policies.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no cpufreq policies, is CONFIG_CPU_FREQ enabled?",
+            ));
+        }
+        policies.sort();
+        Ok(CpuFreq {
+            policies: policies
+                .into_iter()
+                .map(|(policy, path)| (format!("cpufreq{}_mhz", policy), path))
+                .collect(),
+        })
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        self.policies
+            .iter()
+            .map(|(name, _)| Column {
+                name: name.clone(),
+                unit: "MHz",
+                description: "Frequency of the CPU cluster at the end of the window",
+            })
+            .collect()
+    }
+
+    /// Frequencies in MHz, zero for policies that can't be read, e.g. while offline
+    pub fn read(&self) -> Vec<(String, i64)> {
+        self.policies
+            .iter()
+            .map(|(name, path)| {
+                let khz: u64 = fs::read_to_string(path)
+                    .ok()
+                    .and_then(|value| value.trim().parse().ok())
+                    .unwrap_or_default();
+                (name.clone(), (khz / 1000) as i64)
+            })
+            .collect()
+    }
+}
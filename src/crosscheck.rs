@@ -0,0 +1,83 @@
+//! Validates the /dev/mem register path against the kernel's perf driver
+
+use crate::{do_measuring_cylce, measure_overhead, schedule, Opt, MMDC};
+use r_mmdc::perf::PerfCounters;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const COUNTERS: [&str; 6] = [
+    "total cycles",
+    "busy cycles",
+    "read accesses",
+    "write accesses",
+    "read bytes",
+    "write bytes",
+];
+
+/// Relative difference in percent of the larger rate
+fn divergence(a: f64, b: f64) -> f64 {
+    let larger = a.max(b);
+    if larger > 0_f64 {
+        (a - b).abs() / larger * 100_f64
+    } else {
+        0_f64
+    }
+}
+
+/// Alternates /dev/mem and perf windows of the sampling length, both use the same
+/// hardware counters so they can't run at the very same time, and compares the
+/// per second rates. Returns false if any counter diverges beyond the tolerance.
+pub fn run(
+    mmdc: &mut MMDC,
+    opt: &Opt,
+    bus_width: u32,
+    rounds: u32,
+    tolerance: f64,
+) -> io::Result<bool> {
+    let mut perf = PerfCounters::open(opt.madpcr1.unwrap_or_default())?;
+    let overhead = measure_overhead(mmdc, bus_width);
+    let mut scheduler = schedule::from_opt(opt);
+
+    let mut devmem_counts = [0_f64; 6];
+    let mut perf_counts = [0_f64; 6];
+    let (mut devmem_time, mut perf_time) = (0_f64, 0_f64);
+    for _ in 0..rounds {
+        let (results, time) = do_measuring_cylce(mmdc, bus_width, overhead, scheduler.as_mut());
+        for (sum, counter) in devmem_counts.iter_mut().zip(results.raw_counters.iter()) {
+            *sum += *counter as f64;
+        }
+        devmem_time += time as f64 / 1000_f64;
+
+        let start_time = Instant::now();
+        perf.start()?;
+        thread::sleep(Duration::from_millis(opt.sleeptime));
+        perf.stop()?;
+        perf_time += start_time.elapsed().as_secs_f64();
+        for (sum, counter) in perf_counts.iter_mut().zip(perf.read()?.iter()) {
+            *sum += *counter as f64;
+        }
+    }
+
+    println!(
+        "{:<16}{:>16}{:>16}{:>12}",
+        "Counter", "/dev/mem (1/s)", "perf (1/s)", "Divergence"
+    );
+    let mut passed = true;
+    for (index, name) in COUNTERS.iter().enumerate() {
+        let devmem_rate = devmem_counts[index] / devmem_time;
+        let perf_rate = perf_counts[index] / perf_time;
+        let divergence = divergence(devmem_rate, perf_rate);
+        let diverged = divergence > tolerance;
+        passed &= !diverged;
+        println!(
+            "{:<16}{:>16.0}{:>16.0}{:>11.1}%{}",
+            name,
+            devmem_rate,
+            perf_rate,
+            divergence,
+            if diverged { " DIVERGED" } else { "" }
+        );
+    }
+    Ok(passed)
+}
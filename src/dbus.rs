@@ -0,0 +1,985 @@
+//! Minimal D-Bus service exposing the latest sample to other processes.
+//!
+//! The object `/org/rmmdc/Profiler` implements `org.rmmdc.Profiler1` with the read only
+//! properties `Running` (b), `Timestamp` (t), `MeasureTime`, `TotalCycles`, `BusyCycles`,
+//! `ReadAccesses`, `WriteAccesses`, `ReadBytes`, `WriteBytes`, `Utilization`, `DataLoad`
+//! and `AccessUtilization` (u), the methods `Start()` and `Stop()` and the signal
+//! `ThresholdCrossed(u utilization, b above)`. Changes of the properties are announced with
+//! `org.freedesktop.DBus.Properties.PropertiesChanged`.
+//!
+//! Owning the name on the system bus requires installing `dbus/org.rmmdc.Profiler1.conf`
+//! to `/etc/dbus-1/system.d/`. It lets everyone read the properties and receive the
+//! signals, but only root and the `rmmdc` group call `Start()` and `Stop()`.
+
+use crate::alert::{Alert, AlertPolicy};
+use crate::summary::Summary;
+use crate::{get_tick_count, MMDCProfileResult, Record};
+use r_mmdc::SampleSink;
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+const BUS_NAME: &str = "org.rmmdc.Profiler1";
+const OBJECT_PATH: &str = "/org/rmmdc/Profiler";
+const INTERFACE: &str = "org.rmmdc.Profiler1";
+const SYSTEM_BUS_PATH: &str = "/var/run/dbus/system_bus_socket";
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.rmmdc.Profiler1">
+    <method name="Start"/>
+    <method name="Stop"/>
+    <signal name="ThresholdCrossed">
+      <arg name="utilization" type="u"/>
+      <arg name="above" type="b"/>
+    </signal>
+    <property name="Running" type="b" access="read"/>
+    <property name="Timestamp" type="t" access="read"/>
+    <property name="MeasureTime" type="u" access="read"/>
+    <property name="TotalCycles" type="u" access="read"/>
+    <property name="BusyCycles" type="u" access="read"/>
+    <property name="ReadAccesses" type="u" access="read"/>
+    <property name="WriteAccesses" type="u" access="read"/>
+    <property name="ReadBytes" type="u" access="read"/>
+    <property name="WriteBytes" type="u" access="read"/>
+    <property name="Utilization" type="u" access="read"/>
+    <property name="DataLoad" type="u" access="read"/>
+    <property name="AccessUtilization" type="u" access="read"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface" type="s"/>
+      <arg name="changed_properties" type="a{sv}"/>
+      <arg name="invalidated_properties" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Byte(u8),
+    Bool(bool),
+    U32(u32),
+    U64(u64),
+    Str(String),
+    ObjectPath(String),
+    Signature(String),
+    Variant(Box<Value>),
+    Array(String, Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Struct(Vec<Value>),
+}
+
+impl Value {
+    fn signature(&self) -> String {
+        match self {
+            Value::Byte(_) => "y".to_string(),
+            Value::Bool(_) => "b".to_string(),
+            Value::U32(_) => "u".to_string(),
+            Value::U64(_) => "t".to_string(),
+            Value::Str(_) => "s".to_string(),
+            Value::ObjectPath(_) => "o".to_string(),
+            Value::Signature(_) => "g".to_string(),
+            Value::Variant(_) => "v".to_string(),
+            Value::Array(element, _) => format!("a{}", element),
+            Value::DictEntry(key, value) => format!("{{{}{}}}", key.signature(), value.signature()),
+            Value::Struct(fields) => format!(
+                "({})",
+                fields.iter().map(Value::signature).collect::<String>()
+            ),
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) | Value::ObjectPath(s) | Value::Signature(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+fn alignment(signature: &str) -> usize {
+    match signature.as_bytes().first() {
+        Some(b'b') | Some(b'u') | Some(b'i') | Some(b's') | Some(b'o') | Some(b'a') => 4,
+        Some(b't') | Some(b'x') | Some(b'd') | Some(b'(') | Some(b'{') => 8,
+        _ => 1,
+    }
+}
+
+/// Splits a signature into its complete types
+fn split_signature(signature: &str) -> Result<Vec<String>, io::Error> {
+    let bytes = signature.as_bytes();
+    let mut types = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        let start = position;
+        while bytes[position] == b'a' {
+            position += 1;
+            if position == bytes.len() {
+                return Err(protocol_error("incomplete array signature"));
+            }
+        }
+        if bytes[position] == b'(' || bytes[position] == b'{' {
+            let mut depth = 0;
+            loop {
+                match bytes.get(position) {
+                    Some(b'(') | Some(b'{') => depth += 1,
+                    Some(b')') | Some(b'}') => depth -= 1,
+                    Some(_) => {}
+                    None => return Err(protocol_error("unbalanced signature")),
+                }
+                position += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+        } else {
+            position += 1;
+        }
+        types.push(signature[start..position].to_string());
+    }
+    Ok(types)
+}
+
+#[derive(Default)]
+struct Marshaller {
+    buf: Vec<u8>,
+}
+
+impl Marshaller {
+    fn pad(&mut self, alignment: usize) {
+        while !self.buf.len().is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write(&mut self, value: &Value) {
+        match value {
+            Value::Byte(byte) => self.buf.push(*byte),
+            Value::Bool(flag) => self.u32(*flag as u32),
+            Value::U32(number) => self.u32(*number),
+            Value::U64(number) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&number.to_le_bytes());
+            }
+            Value::Str(s) | Value::ObjectPath(s) => {
+                self.u32(s.len() as u32);
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+            }
+            Value::Signature(s) => {
+                self.buf.push(s.len() as u8);
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+            }
+            Value::Variant(inner) => {
+                self.write(&Value::Signature(inner.signature()));
+                self.write(inner);
+            }
+            Value::Array(element, items) => {
+                self.u32(0);
+                let length_position = self.buf.len() - 4;
+                self.pad(alignment(element));
+                let start = self.buf.len();
+                for item in items {
+                    self.write(item);
+                }
+                let length = (self.buf.len() - start) as u32;
+                self.buf[length_position..length_position + 4]
+                    .copy_from_slice(&length.to_le_bytes());
+            }
+            Value::DictEntry(key, value) => {
+                self.pad(8);
+                self.write(key);
+                self.write(value);
+            }
+            Value::Struct(fields) => {
+                self.pad(8);
+                for field in fields {
+                    self.write(field);
+                }
+            }
+        }
+    }
+}
+
+struct Unmarshaller<'a> {
+    buf: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Unmarshaller<'a> {
+    fn align(&mut self, alignment: usize) {
+        self.position = self.position.div_ceil(alignment) * alignment;
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], io::Error> {
+        let bytes = self
+            .buf
+            .get(self.position..self.position + length)
+            .ok_or_else(|| protocol_error("message truncated"))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, io::Error> {
+        self.align(4);
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self, length: usize) -> Result<String, io::Error> {
+        let bytes = self.take(length + 1)?;
+        Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+
+    fn read(&mut self, signature: &str) -> Result<Value, io::Error> {
+        match signature.as_bytes().first() {
+            Some(b'y') => Ok(Value::Byte(self.take(1)?[0])),
+            Some(b'b') => Ok(Value::Bool(self.u32()? != 0)),
+            Some(b'u') | Some(b'i') => Ok(Value::U32(self.u32()?)),
+            Some(b't') | Some(b'x') => {
+                self.align(8);
+                let bytes = self.take(8)?;
+                let mut value = [0u8; 8];
+                value.copy_from_slice(bytes);
+                Ok(Value::U64(u64::from_le_bytes(value)))
+            }
+            Some(b's') => {
+                let length = self.u32()? as usize;
+                Ok(Value::Str(self.string(length)?))
+            }
+            Some(b'o') => {
+                let length = self.u32()? as usize;
+                Ok(Value::ObjectPath(self.string(length)?))
+            }
+            Some(b'g') => {
+                let length = self.take(1)?[0] as usize;
+                Ok(Value::Signature(self.string(length)?))
+            }
+            Some(b'v') => {
+                let length = self.take(1)?[0] as usize;
+                let inner = self.string(length)?;
+                Ok(Value::Variant(Box::new(self.read(&inner)?)))
+            }
+            Some(b'a') => {
+                let element = &signature[1..];
+                let length = self.u32()? as usize;
+                self.align(alignment(element));
+                let end = self.position + length;
+                let mut items = Vec::new();
+                while self.position < end {
+                    items.push(self.read(element)?);
+                }
+                Ok(Value::Array(element.to_string(), items))
+            }
+            Some(b'(') => {
+                self.align(8);
+                let fields = split_signature(&signature[1..signature.len() - 1])?
+                    .iter()
+                    .map(|field| self.read(field))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Struct(fields))
+            }
+            Some(b'{') => {
+                self.align(8);
+                let types = split_signature(&signature[1..signature.len() - 1])?;
+                if types.len() != 2 {
+                    return Err(protocol_error("invalid dict entry"));
+                }
+                let key = self.read(&types[0])?;
+                let value = self.read(&types[1])?;
+                Ok(Value::DictEntry(Box::new(key), Box::new(value)))
+            }
+            _ => Err(protocol_error("unsupported type in message")),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    body: Vec<Value>,
+}
+
+impl Message {
+    fn method_call(destination: &str, path: &str, interface: &str, member: &str) -> Message {
+        Message {
+            kind: METHOD_CALL,
+            path: Some(path.to_string()),
+            interface: Some(interface.to_string()),
+            member: Some(member.to_string()),
+            destination: Some(destination.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn reply_to(call: &Message, body: Vec<Value>) -> Message {
+        Message {
+            kind: METHOD_RETURN,
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            body,
+            ..Default::default()
+        }
+    }
+
+    fn error_to(call: &Message, name: &str, text: &str) -> Message {
+        Message {
+            kind: ERROR,
+            error_name: Some(name.to_string()),
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            body: vec![Value::Str(text.to_string())],
+            ..Default::default()
+        }
+    }
+
+    fn signature(&self) -> String {
+        self.body.iter().map(Value::signature).collect()
+    }
+
+    fn encode(&self, serial: u32) -> Vec<u8> {
+        let mut body = Marshaller::default();
+        for value in &self.body {
+            body.write(value);
+        }
+
+        let mut fields = Vec::new();
+        let mut field = |code: u8, value: Value| {
+            fields.push(Value::Struct(vec![
+                Value::Byte(code),
+                Value::Variant(Box::new(value)),
+            ]))
+        };
+        if let Some(path) = &self.path {
+            field(1, Value::ObjectPath(path.clone()));
+        }
+        if let Some(interface) = &self.interface {
+            field(2, Value::Str(interface.clone()));
+        }
+        if let Some(member) = &self.member {
+            field(3, Value::Str(member.clone()));
+        }
+        if let Some(error_name) = &self.error_name {
+            field(4, Value::Str(error_name.clone()));
+        }
+        if let Some(reply_serial) = self.reply_serial {
+            field(5, Value::U32(reply_serial));
+        }
+        if let Some(destination) = &self.destination {
+            field(6, Value::Str(destination.clone()));
+        }
+        if !self.body.is_empty() {
+            field(8, Value::Signature(self.signature()));
+        }
+
+        let mut message = Marshaller::default();
+        message
+            .buf
+            .extend_from_slice(&[b'l', self.kind, self.flags, 1]);
+        message.u32(body.buf.len() as u32);
+        message.u32(serial);
+        message.write(&Value::Array("(yv)".to_string(), fields));
+        message.pad(8);
+        message.buf.extend_from_slice(&body.buf);
+        message.buf
+    }
+
+    fn read_from(stream: &mut impl Read) -> io::Result<Message> {
+        let mut fixed = [0u8; 16];
+        stream.read_exact(&mut fixed)?;
+        if fixed[0] != b'l' {
+            return Err(protocol_error("only little endian messages are supported"));
+        }
+        let body_length = u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]) as usize;
+        let fields_length =
+            u32::from_le_bytes([fixed[12], fixed[13], fixed[14], fixed[15]]) as usize;
+        let header_length = (16 + fields_length).div_ceil(8) * 8;
+        let mut buf = fixed.to_vec();
+        buf.resize(header_length + body_length, 0);
+        stream.read_exact(&mut buf[16..])?;
+
+        let mut reader = Unmarshaller {
+            buf: &buf,
+            position: 8,
+        };
+        let mut message = Message {
+            kind: fixed[1],
+            flags: fixed[2],
+            serial: reader.u32()?,
+            ..Default::default()
+        };
+        let mut signature = String::new();
+        if let Value::Array(_, fields) = reader.read("a(yv)")? {
+            for field in fields {
+                if let Value::Struct(parts) = field {
+                    if let (Value::Byte(code), Value::Variant(value)) = (&parts[0], &parts[1]) {
+                        let text = value.as_str().map(str::to_string);
+                        match code {
+                            1 => message.path = text,
+                            2 => message.interface = text,
+                            3 => message.member = text,
+                            4 => message.error_name = text,
+                            5 => {
+                                if let Value::U32(serial) = **value {
+                                    message.reply_serial = Some(serial);
+                                }
+                            }
+                            6 => message.destination = text,
+                            7 => message.sender = text,
+                            8 => signature = text.unwrap_or_default(),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        reader.position = header_length;
+        for field in split_signature(&signature)? {
+            message.body.push(reader.read(&field)?);
+        }
+        Ok(message)
+    }
+}
+
+struct Connection {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Connection {
+    fn send(&mut self, message: &Message) -> io::Result<u32> {
+        self.serial += 1;
+        self.stream.write_all(&message.encode(self.serial))?;
+        Ok(self.serial)
+    }
+}
+
+fn bus_socket(bus: &str) -> io::Result<UnixStream> {
+    if bus == "system" {
+        return UnixStream::connect(SYSTEM_BUS_PATH);
+    }
+    let address = env::var("DBUS_SESSION_BUS_ADDRESS")
+        .map_err(|_| protocol_error("DBUS_SESSION_BUS_ADDRESS is not set"))?;
+    for part in address.split(';') {
+        for option in part.trim_start_matches("unix:").split(',') {
+            if let Some(path) = option.strip_prefix("path=") {
+                return UnixStream::connect(path);
+            }
+            if let Some(name) = option.strip_prefix("abstract=") {
+                use std::os::linux::net::SocketAddrExt;
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                return UnixStream::connect_addr(&address);
+            }
+        }
+    }
+    Err(protocol_error("unsupported session bus address"))
+}
+
+fn authenticate(stream: &mut UnixStream) -> io::Result<()> {
+    let uid = nix::unistd::getuid().to_string();
+    let hex_uid: String = uid.bytes().map(|byte| format!("{:02x}", byte)).collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex_uid).as_bytes())?;
+
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    if !line.starts_with(b"OK ") {
+        return Err(protocol_error("D-Bus authentication rejected"));
+    }
+    stream.write_all(b"BEGIN\r\n")
+}
+
+/// Sends a method call and waits for its reply, ignoring everything received meanwhile
+fn call(connection: &mut Connection, message: &Message) -> io::Result<Message> {
+    let serial = connection.send(message)?;
+    loop {
+        let reply = Message::read_from(&mut connection.stream)?;
+        if reply.reply_serial == Some(serial) {
+            if reply.kind == ERROR {
+                return Err(protocol_error(
+                    reply.error_name.as_deref().unwrap_or("D-Bus error"),
+                ));
+            }
+            return Ok(reply);
+        }
+    }
+}
+
+#[derive(Default)]
+struct ServiceState {
+    running: bool,
+    timestamp: u64,
+    time: u32,
+    latest: Option<MMDCProfileResult>,
+}
+
+impl ServiceState {
+    /// The properties which differ from `previous`
+    fn changed_since(&self, previous: &ServiceState) -> Vec<(&'static str, Value)> {
+        let previous = previous.properties();
+        self.properties()
+            .into_iter()
+            .zip(previous)
+            .filter(|(current, previous)| current != previous)
+            .map(|(current, _)| current)
+            .collect()
+    }
+
+    fn properties(&self) -> Vec<(&'static str, Value)> {
+        let latest = self.latest.clone().unwrap_or_default();
+        vec![
+            ("Running", Value::Bool(self.running)),
+            ("Timestamp", Value::U64(self.timestamp)),
+            ("MeasureTime", Value::U32(self.time)),
+            ("TotalCycles", Value::U32(latest.total_cycles)),
+            ("BusyCycles", Value::U32(latest.busy_cycles)),
+            ("ReadAccesses", Value::U32(latest.read_accesses)),
+            ("WriteAccesses", Value::U32(latest.write_accesses)),
+            ("ReadBytes", Value::U32(latest.read_bytes)),
+            ("WriteBytes", Value::U32(latest.write_bytes)),
+            ("Utilization", Value::U32(latest.utilization)),
+            ("DataLoad", Value::U32(latest.data_load)),
+            ("AccessUtilization", Value::U32(latest.access_utilization)),
+        ]
+    }
+}
+
+type SharedState = Arc<(Mutex<ServiceState>, Condvar)>;
+
+fn property_entries(properties: Vec<(&'static str, Value)>) -> Value {
+    let entries = properties
+        .into_iter()
+        .map(|(name, value)| {
+            Value::DictEntry(
+                Box::new(Value::Str(name.to_string())),
+                Box::new(Value::Variant(Box::new(value))),
+            )
+        })
+        .collect();
+    Value::Array("{sv}".to_string(), entries)
+}
+
+fn properties_changed(changed: Vec<(&'static str, Value)>) -> Message {
+    Message {
+        kind: SIGNAL,
+        path: Some(OBJECT_PATH.to_string()),
+        interface: Some("org.freedesktop.DBus.Properties".to_string()),
+        member: Some("PropertiesChanged".to_string()),
+        body: vec![
+            Value::Str(INTERFACE.to_string()),
+            property_entries(changed),
+            Value::Array("s".to_string(), Vec::new()),
+        ],
+        ..Default::default()
+    }
+}
+
+/// Handle of the service, the clones share the connection and the state
+#[derive(Clone)]
+pub struct DbusService {
+    connection: Arc<Mutex<Connection>>,
+    state: SharedState,
+    alert: Option<Arc<Mutex<Alert>>>,
+}
+
+fn handle_call(call: &Message, state: &SharedState) -> Message {
+    let (lock, condvar) = &**state;
+    if call.path.as_deref() != Some(OBJECT_PATH) {
+        return Message::error_to(
+            call,
+            "org.freedesktop.DBus.Error.UnknownObject",
+            "unknown object path",
+        );
+    }
+    let argument = |index: usize| call.body.get(index).and_then(Value::as_str).unwrap_or("");
+    match (call.interface.as_deref(), call.member.as_deref()) {
+        (Some("org.freedesktop.DBus.Introspectable"), Some("Introspect")) => {
+            Message::reply_to(call, vec![Value::Str(INTROSPECTION.to_string())])
+        }
+        (Some("org.freedesktop.DBus.Peer"), Some("Ping")) => Message::reply_to(call, vec![]),
+        (Some("org.freedesktop.DBus.Properties"), Some("Get")) => {
+            let properties = lock.lock().unwrap().properties();
+            match properties
+                .into_iter()
+                .find(|(name, _)| *name == argument(1))
+            {
+                Some((_, value)) if argument(0) == INTERFACE => {
+                    Message::reply_to(call, vec![Value::Variant(Box::new(value))])
+                }
+                _ => Message::error_to(
+                    call,
+                    "org.freedesktop.DBus.Error.UnknownProperty",
+                    "unknown property",
+                ),
+            }
+        }
+        (Some("org.freedesktop.DBus.Properties"), Some("GetAll")) => {
+            let properties = match argument(0) {
+                INTERFACE => lock.lock().unwrap().properties(),
+                _ => Vec::new(),
+            };
+            Message::reply_to(call, vec![property_entries(properties)])
+        }
+        // the interface is required, the bus policy restricts the calls by it
+        (Some(INTERFACE), Some("Start")) => {
+            lock.lock().unwrap().running = true;
+            condvar.notify_all();
+            Message::reply_to(call, vec![])
+        }
+        (Some(INTERFACE), Some("Stop")) => {
+            lock.lock().unwrap().running = false;
+            Message::reply_to(call, vec![])
+        }
+        _ => Message::error_to(
+            call,
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            "unknown method",
+        ),
+    }
+}
+
+impl DbusService {
+    /// Connects to the `system` or `session` bus and serves requests from a background thread
+    pub fn start(bus: &str, alert: Option<AlertPolicy>) -> io::Result<DbusService> {
+        let mut stream = bus_socket(bus)?;
+        authenticate(&mut stream)?;
+        let mut connection = Connection { stream, serial: 0 };
+
+        let bus_path = "/org/freedesktop/DBus";
+        call(
+            &mut connection,
+            &Message::method_call(
+                "org.freedesktop.DBus",
+                bus_path,
+                "org.freedesktop.DBus",
+                "Hello",
+            ),
+        )?;
+        let mut request_name = Message::method_call(
+            "org.freedesktop.DBus",
+            bus_path,
+            "org.freedesktop.DBus",
+            "RequestName",
+        );
+        // DBUS_NAME_FLAG_DO_NOT_QUEUE
+        request_name.body = vec![Value::Str(BUS_NAME.to_string()), Value::U32(4)];
+        let reply = call(&mut connection, &request_name)?;
+        if let Some(Value::U32(1)) = reply.body.first() {
+        } else {
+            return Err(protocol_error("unable to acquire bus name"));
+        }
+
+        let mut reader = connection.stream.try_clone()?;
+        let connection = Arc::new(Mutex::new(connection));
+        let state: SharedState = Arc::new((
+            Mutex::new(ServiceState {
+                running: true,
+                ..Default::default()
+            }),
+            Condvar::new(),
+        ));
+
+        let thread_connection = connection.clone();
+        let thread_state = state.clone();
+        thread::spawn(move || {
+            while let Ok(message) = Message::read_from(&mut reader) {
+                if message.kind != METHOD_CALL {
+                    continue;
+                }
+                let running = thread_state.0.lock().unwrap().running;
+                let reply = handle_call(&message, &thread_state);
+                let mut connection = thread_connection.lock().unwrap();
+                if message.flags & NO_REPLY_EXPECTED == 0 {
+                    let _ = connection.send(&reply);
+                }
+                let now_running = thread_state.0.lock().unwrap().running;
+                if now_running != running {
+                    let changed = vec![("Running", Value::Bool(now_running))];
+                    let _ = connection.send(&properties_changed(changed));
+                }
+            }
+            eprintln!("D-Bus connection closed");
+        });
+
+        Ok(DbusService {
+            connection,
+            state,
+            alert: alert.map(|policy| Arc::new(Mutex::new(Alert::new(policy)))),
+        })
+    }
+
+    /// Blocks while a client has stopped the measurement
+    pub fn wait_until_running(&self) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        while !state.running {
+            state = condvar.wait(state).unwrap();
+        }
+    }
+
+    pub fn update(&self, result: &MMDCProfileResult, time: u32, timestamp: u64) {
+        let changed = {
+            let mut state = self.state.0.lock().unwrap();
+            let previous = ServiceState {
+                running: state.running,
+                timestamp: state.timestamp,
+                time: state.time,
+                latest: state.latest.take(),
+            };
+            state.latest = Some(result.clone());
+            state.time = time;
+            state.timestamp = timestamp;
+            state.changed_since(&previous)
+        };
+        if !changed.is_empty() {
+            if let Err(e) = self
+                .connection
+                .lock()
+                .unwrap()
+                .send(&properties_changed(changed))
+            {
+                eprintln!("Error emitting D-Bus signal: {}", e);
+            }
+        }
+        let crossed = self
+            .alert
+            .as_ref()
+            .and_then(|alert| alert.lock().unwrap().update(result.utilization));
+
+        if let Some(above) = crossed {
+            let signal = Message {
+                kind: SIGNAL,
+                path: Some(OBJECT_PATH.to_string()),
+                interface: Some(INTERFACE.to_string()),
+                member: Some("ThresholdCrossed".to_string()),
+                body: vec![Value::U32(result.utilization), Value::Bool(above)],
+                ..Default::default()
+            };
+            if let Err(e) = self.connection.lock().unwrap().send(&signal) {
+                eprintln!("Error emitting D-Bus signal: {}", e);
+            }
+        }
+    }
+}
+
+impl SampleSink<Record<'_>, Summary> for DbusService {
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        self.update(record.results, record.time, get_tick_count() as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Message {
+        Message::read_from(&mut &bytes[..]).unwrap()
+    }
+
+    fn properties_call(member: &str, arguments: &[&str]) -> Message {
+        let mut call = Message::method_call(
+            BUS_NAME,
+            OBJECT_PATH,
+            "org.freedesktop.DBus.Properties",
+            member,
+        );
+        call.body = arguments
+            .iter()
+            .map(|argument| Value::Str(argument.to_string()))
+            .collect();
+        call
+    }
+
+    fn state() -> SharedState {
+        let state = ServiceState {
+            running: true,
+            latest: Some(MMDCProfileResult {
+                utilization: 42,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        Arc::new((Mutex::new(state), Condvar::new()))
+    }
+
+    #[test]
+    fn header_layout() {
+        let mut call = Message::method_call(BUS_NAME, OBJECT_PATH, INTERFACE, "Stop");
+        call.flags = NO_REPLY_EXPECTED;
+        let bytes = call.encode(7);
+        assert_eq!(bytes[..4], [b'l', METHOD_CALL, NO_REPLY_EXPECTED, 1]);
+        // no body, serial 7
+        assert_eq!(bytes[4..12], [0, 0, 0, 0, 7, 0, 0, 0]);
+        let fields_length = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+        assert_eq!(bytes.len(), (16 + fields_length as usize).div_ceil(8) * 8);
+        // first field: code 1, variant of signature "o", the path padded to 4 bytes
+        assert_eq!(bytes[16..20], [1, 1, b'o', 0]);
+        assert_eq!(bytes[20..24], (OBJECT_PATH.len() as u32).to_le_bytes());
+        assert_eq!(bytes[24..24 + OBJECT_PATH.len()], *OBJECT_PATH.as_bytes());
+    }
+
+    #[test]
+    fn round_trip_of_nested_values() {
+        let mut message = Message::method_call(BUS_NAME, OBJECT_PATH, INTERFACE, "Test");
+        message.body = vec![
+            Value::Byte(3),
+            // aligned to 8 after a single byte
+            Value::U64(u64::MAX - 1),
+            Value::Bool(true),
+            Value::Array(
+                "{sv}".to_string(),
+                vec![
+                    Value::DictEntry(
+                        Box::new(Value::Str("a".to_string())),
+                        Box::new(Value::Variant(Box::new(Value::U32(1)))),
+                    ),
+                    Value::DictEntry(
+                        Box::new(Value::Str("bc".to_string())),
+                        Box::new(Value::Variant(Box::new(Value::U64(2)))),
+                    ),
+                ],
+            ),
+            Value::Struct(vec![Value::Byte(1), Value::Signature("as".to_string())]),
+            Value::Array("s".to_string(), Vec::new()),
+        ];
+        let decoded = decode(&message.encode(1));
+        assert_eq!(decoded.serial, 1);
+        assert_eq!(decoded.kind, METHOD_CALL);
+        assert_eq!(decoded.path.as_deref(), Some(OBJECT_PATH));
+        assert_eq!(decoded.member.as_deref(), Some("Test"));
+        assert_eq!(decoded.destination.as_deref(), Some(BUS_NAME));
+        assert_eq!(decoded.signature(), "ytba{sv}(yg)as");
+        assert_eq!(decoded.body, message.body);
+    }
+
+    #[test]
+    fn reply_and_error_round_trip() {
+        let call = decode(&properties_call("Get", &[INTERFACE, "Running"]).encode(5));
+        let reply = decode(&Message::reply_to(&call, vec![Value::U32(9)]).encode(6));
+        assert_eq!((reply.kind, reply.reply_serial), (METHOD_RETURN, Some(5)));
+        let error = decode(&Message::error_to(&call, "org.example.Error", "failed").encode(7));
+        assert_eq!(error.kind, ERROR);
+        assert_eq!(error.error_name.as_deref(), Some("org.example.Error"));
+        assert_eq!(error.body, [Value::Str("failed".to_string())]);
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let bytes = properties_call("Get", &[INTERFACE, "Running"]).encode(1);
+        let mut big_endian = bytes.clone();
+        big_endian[0] = b'B';
+        assert!(Message::read_from(&mut &big_endian[..]).is_err());
+        assert!(Message::read_from(&mut &bytes[..bytes.len() - 1]).is_err());
+        assert!(split_signature("a").is_err());
+        assert!(split_signature("(us").is_err());
+        assert_eq!(
+            split_signature("aa{sv}(ua(st))y").unwrap(),
+            ["aa{sv}", "(ua(st))", "y"]
+        );
+        let mut reader = Unmarshaller {
+            buf: &[1, 0, 0, 0, b'x'],
+            position: 0,
+        };
+        assert!(reader.read("s").is_err());
+    }
+
+    #[test]
+    fn properties_are_served() {
+        let state = state();
+        let reply = handle_call(&properties_call("Get", &[INTERFACE, "Utilization"]), &state);
+        assert_eq!(reply.body, [Value::Variant(Box::new(Value::U32(42)))]);
+        let reply = handle_call(&properties_call("Get", &[INTERFACE, "Missing"]), &state);
+        assert_eq!(reply.kind, ERROR);
+        let reply = handle_call(&properties_call("GetAll", &[INTERFACE]), &state);
+        match &reply.body[..] {
+            [Value::Array(_, entries)] => assert_eq!(entries.len(), 12),
+            body => panic!("unexpected body {:?}", body),
+        }
+    }
+
+    #[test]
+    fn stop_needs_the_interface() {
+        let state = state();
+        let mut stop = Message::method_call(BUS_NAME, OBJECT_PATH, INTERFACE, "Stop");
+        stop.interface = None;
+        assert_eq!(handle_call(&stop, &state).kind, ERROR);
+        assert!(state.0.lock().unwrap().running);
+        stop.interface = Some(INTERFACE.to_string());
+        assert_eq!(handle_call(&stop, &state).kind, METHOD_RETURN);
+        assert!(!state.0.lock().unwrap().running);
+    }
+
+    #[test]
+    fn changed_properties() {
+        let previous = ServiceState::default();
+        let current = ServiceState {
+            time: 1000,
+            latest: Some(MMDCProfileResult {
+                read_bytes: 64,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let changed = current.changed_since(&previous);
+        assert_eq!(
+            changed,
+            [
+                ("MeasureTime", Value::U32(1000)),
+                ("ReadBytes", Value::U32(64))
+            ]
+        );
+        let signal = decode(&properties_changed(changed).encode(2));
+        assert_eq!(signal.kind, SIGNAL);
+        assert_eq!(signal.member.as_deref(), Some("PropertiesChanged"));
+        assert_eq!(signal.signature(), "sa{sv}as");
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{bits, ProfilingError, MMDC};
+use std::fmt;
+use std::fs;
+
+const MB: u64 = 1024 * 1024;
+
+/// MDASP CS0_END has a resolution of 256Mbit
+const MDASP_RESOLUTION: u64 = 32 * MB;
+
+/// Start of the MMDC address space on i.MX6 parts
+const DDR_BASE_ADDR: u64 = 0x1000_0000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DdrType {
+    Ddr3,
+    Lpddr2,
+    Unknown(u32),
+}
+
+impl DdrType {
+    pub fn from_registers(mmdc: &MMDC) -> DdrType {
+        match bits(mmdc.mdmisc, 3, 2) {
+            0 => DdrType::Ddr3,
+            1 => DdrType::Lpddr2,
+            other => DdrType::Unknown(other),
+        }
+    }
+
+    /// Longest allowed average refresh interval in microseconds
+    fn max_refresh_interval(&self) -> Option<f32> {
+        match self {
+            DdrType::Ddr3 => Some(7.8),
+            DdrType::Lpddr2 => Some(3.9),
+            DdrType::Unknown(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for DdrType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DdrType::Ddr3 => write!(f, "DDR3"),
+            DdrType::Lpddr2 => write!(f, "LPDDR2"),
+            DdrType::Unknown(value) => write!(f, "unknown ({})", value),
+        }
+    }
+}
+
+pub fn bank_interleaving(mmdc: &MMDC) -> bool {
+    bits(mmdc.mdmisc, 12, 1) == 1
+}
+
+/// Burst length in beats, DDR3 always bursts with 8 beats
+pub fn burst_length(mmdc: &MMDC, ddr_type: DdrType) -> u32 {
+    if ddr_type == DdrType::Ddr3 || bits(mmdc.mdctl, 19, 1) == 1 {
+        8
+    } else {
+        4
+    }
+}
+
+/// Average refresh interval in microseconds as configured in MDREF
+fn refresh_interval(mmdc: &MMDC) -> Option<f32> {
+    let refreshes = (bits(mmdc.mdref, 11, 3) + 1) as f32;
+    match bits(mmdc.mdref, 14, 2) {
+        0 => Some(1000_f32 / 64_f32 / refreshes),
+        1 => Some(1000_f32 / 32_f32 / refreshes),
+        _ => None,
+    }
+}
+
+pub struct DdrGeometry {
+    pub rows: u32,
+    pub cols: u32,
+    pub banks: u32,
+    pub bus_width: u32,
+    pub chip_selects: Vec<u32>,
+}
+
+impl DdrGeometry {
+    pub fn from_registers(mmdc: &MMDC) -> DdrGeometry {
+        let cols = match bits(mmdc.mdctl, 20, 3) {
+            0 => 9,
+            1 => 10,
+            2 => 11,
+            3 => 8,
+            _ => 12,
+        };
+        let chip_selects = [(0, 31), (1, 30)]
+            .iter()
+            .filter(|(_, sde)| bits(mmdc.mdctl, *sde, 1) == 1)
+            .map(|(cs, _)| *cs)
+            .collect();
+
+        DdrGeometry {
+            rows: 11 + bits(mmdc.mdctl, 24, 3),
+            cols,
+            banks: if bits(mmdc.mdmisc, 5, 1) == 1 { 4 } else { 8 },
+            bus_width: 16 << bits(mmdc.mdctl, 16, 2),
+            chip_selects,
+        }
+    }
+
+    pub fn chip_select_size(&self) -> u64 {
+        (1u64 << (self.rows + self.cols)) * self.banks as u64 * (self.bus_width / 8) as u64
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.chip_select_size() * self.chip_selects.len() as u64
+    }
+}
+
+/// Size of CS0 as configured in the MDASP address map
+fn mdasp_cs0_size(mmdc: &MMDC) -> u64 {
+    ((bits(mmdc.mdasp, 0, 7) as u64 + 1) * MDASP_RESOLUTION).saturating_sub(DDR_BASE_ADDR)
+}
+
+fn get_mem_total() -> Result<u64, ProfilingError> {
+    let meminfo = match fs::read_to_string("/proc/meminfo") {
+        Ok(content) => content,
+        Err(_) => return Err(ProfilingError::new("Error reading /proc/meminfo")),
+    };
+    meminfo
+        .lines()
+        .find(|line| line.starts_with("MemTotal:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| ProfilingError::new("No MemTotal found in /proc/meminfo"))
+}
+
+pub fn print_ddr_type(mmdc: &MMDC) {
+    let ddr_type = DdrType::from_registers(mmdc);
+    let geometry = DdrGeometry::from_registers(mmdc);
+    println!("DDR type: {}", ddr_type);
+    if ddr_type == DdrType::Lpddr2 {
+        println!("LPDDR2 2-channel mode: {}", bits(mmdc.mdmisc, 2, 1) == 1);
+    }
+    println!("Bank interleaving: {}", bank_interleaving(mmdc));
+
+    let burst_length = burst_length(mmdc, ddr_type);
+    println!(
+        "Burst length: {} ({} bytes per burst)",
+        burst_length,
+        burst_length * geometry.bus_width / 8
+    );
+
+    match refresh_interval(mmdc) {
+        Some(interval) => {
+            println!("Average refresh interval: {:.2}us", interval);
+            if let Some(max_interval) = ddr_type.max_refresh_interval() {
+                if interval > max_interval {
+                    println!(
+                        "WARNING: refresh interval exceeds the {} maximum of {}us",
+                        ddr_type, max_interval
+                    );
+                }
+            }
+        }
+        None => println!("Refresh: REF_CNT based (MDREF 0x{:08X})", mmdc.mdref),
+    }
+}
+
+pub fn print_ddr_size(mmdc: &MMDC) {
+    let geometry = DdrGeometry::from_registers(mmdc);
+    println!(
+        "DDR geometry: {} rows, {} cols, {} banks, {}-bit bus",
+        geometry.rows, geometry.cols, geometry.banks, geometry.bus_width
+    );
+    for cs in &geometry.chip_selects {
+        println!("CS{} size: {} MB", cs, geometry.chip_select_size() / MB);
+    }
+    println!("Total DDR size: {} MB", geometry.total_size() / MB);
+
+    let cs0_size = mdasp_cs0_size(mmdc);
+    println!("MDASP CS0 size: {} MB", cs0_size / MB);
+    if geometry.chip_selects.contains(&0) && cs0_size != geometry.chip_select_size() {
+        println!("WARNING: MDASP address map does not match the CS0 geometry");
+    }
+
+    match get_mem_total() {
+        Ok(mem_total) => {
+            println!("Kernel MemTotal: {} MB", mem_total / MB);
+            if mem_total > geometry.total_size() {
+                println!("WARNING: kernel sees more memory than the MMDC is configured for");
+            } else if mem_total < geometry.total_size() / 2 {
+                println!("WARNING: kernel sees less than half of the configured DDR size");
+            }
+        }
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// Common clock framework entries of the MMDC core clock, newest kernels first
+const DDR_CLOCK_PATHS: [&str; 2] = [
+    "/sys/kernel/debug/clk/mmdc_ch0_axi/clk_rate",
+    "/sys/kernel/debug/clk/mmdc_ch0_axi_podf/clk_rate",
+];
+
+/// DDR clock assumed if it can neither be detected nor was given
+const DEFAULT_DDR_CLOCK_MHZ: u32 = 528;
+
+pub fn detect_ddr_clock() -> Result<u32, ProfilingError> {
+    DDR_CLOCK_PATHS
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|rate| rate.trim().parse::<u64>().ok())
+        .map(|hz| (hz / 1_000_000) as u32)
+        .find(|mhz| *mhz > 0)
+        .ok_or_else(|| ProfilingError::new("Unable to detect DDR clock from debugfs"))
+}
+
+/// DDR clock in MHz, either given by the user, detected or the default
+pub fn ddr_clock(configured: Option<u32>) -> u32 {
+    match configured {
+        Some(mhz) => mhz,
+        None => detect_ddr_clock().unwrap_or_else(|e| {
+            eprintln!("{}, assuming {}MHz", e, DEFAULT_DDR_CLOCK_MHZ);
+            DEFAULT_DDR_CLOCK_MHZ
+        }),
+    }
+}
+
+/// (name, register value, shift, width, clocks added to the raw field)
+const TIMINGS: [(&str, usize, u32, u32, u32); 16] = [
+    ("tCL", 0, 0, 4, 3),
+    ("tFAW", 0, 4, 5, 1),
+    ("tXPDLL", 0, 9, 4, 1),
+    ("tXP", 0, 13, 3, 1),
+    ("tXS", 0, 16, 8, 1),
+    ("tRFC", 0, 24, 8, 1),
+    ("tCWL", 1, 0, 3, 2),
+    ("tMRD", 1, 5, 4, 1),
+    ("tWR", 1, 9, 3, 1),
+    ("tRAS", 1, 16, 5, 1),
+    ("tRC", 1, 21, 5, 1),
+    ("tRP", 1, 26, 3, 1),
+    ("tRCD", 1, 29, 3, 1),
+    ("tRRD", 2, 0, 3, 1),
+    ("tWTR", 2, 3, 3, 1),
+    ("tRTP", 2, 6, 3, 1),
+];
+
+pub fn print_ddr_timings(mmdc: &MMDC, ddr_clock_mhz: u32) {
+    let registers = [mmdc.mdcfg0, mmdc.mdcfg1, mmdc.mdcfg2];
+    println!(
+        "DDR timings at {}MHz (MDCFG0 0x{:08X}, MDCFG1 0x{:08X}, MDCFG2 0x{:08X}):",
+        ddr_clock_mhz, mmdc.mdcfg0, mmdc.mdcfg1, mmdc.mdcfg2
+    );
+    for (name, register, shift, width, offset) in TIMINGS.iter() {
+        let clocks = bits(registers[*register], *shift, *width) + offset;
+        println!(
+            "  {}: {} clocks ({:.2}ns)",
+            name,
+            clocks,
+            clocks as f32 * 1000_f32 / ddr_clock_mhz as f32
+        );
+    }
+}
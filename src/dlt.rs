@@ -0,0 +1,132 @@
+//! Minimal AUTOSAR/COVESA DLT encoder sending verbose log messages over UDP
+
+use crate::summary::Summary;
+use crate::{clock_time, Record};
+use r_mmdc::SampleSink;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+// standard header type: extended header, ECU ID and timestamp present, version 1
+const HTYP_UEH: u8 = 0x01;
+const HTYP_WEID: u8 = 0x04;
+const HTYP_WTMS: u8 = 0x10;
+const HTYP_VERSION: u8 = 0x20;
+
+// verbose log message with level info
+const MSIN_LOG_INFO: u8 = 0x01 | (4 << 4);
+
+// argument type info: 32 bit unsigned or float with variable name and unit
+const TYPE_UINT32: u32 = 0x43;
+const TYPE_FLOAT32: u32 = 0x103;
+const TYPE_VARI: u32 = 0x800;
+
+/// Pads or truncates an application, context or ECU ID to its four bytes
+fn id(name: &str) -> [u8; 4] {
+    let mut id = [0; 4];
+    for (target, byte) in id.iter_mut().zip(name.bytes()) {
+        *target = byte;
+    }
+    id
+}
+
+/// Time since boot in 0.1ms units as used by the DLT timestamp
+fn uptime() -> u32 {
+    (clock_time(nix::libc::CLOCK_MONOTONIC).as_micros() / 100) as u32
+}
+
+struct Payload {
+    bytes: Vec<u8>,
+    arguments: u8,
+}
+
+impl Payload {
+    fn variable(&mut self, type_info: u32, name: &str, unit: &str, value: [u8; 4]) {
+        self.bytes
+            .extend_from_slice(&(type_info | TYPE_VARI).to_le_bytes());
+        self.bytes
+            .extend_from_slice(&(name.len() as u16 + 1).to_le_bytes());
+        self.bytes
+            .extend_from_slice(&(unit.len() as u16 + 1).to_le_bytes());
+        for text in [name, unit].iter() {
+            self.bytes.extend_from_slice(text.as_bytes());
+            self.bytes.push(0);
+        }
+        self.bytes.extend_from_slice(&value);
+        self.arguments += 1;
+    }
+
+    fn uint(&mut self, name: &str, unit: &str, value: u32) {
+        self.variable(TYPE_UINT32, name, unit, value.to_le_bytes());
+    }
+
+    fn float(&mut self, name: &str, unit: &str, value: f32) {
+        self.variable(TYPE_FLOAT32, name, unit, value.to_le_bytes());
+    }
+}
+
+pub struct DltSink {
+    socket: UdpSocket,
+    ecu: [u8; 4],
+    app: [u8; 4],
+    context: [u8; 4],
+    counter: u8,
+}
+
+impl DltSink {
+    /// Sends to a DLT viewer or receiver listening for UDP on the given address
+    pub fn connect(address: &str, ecu: &str, app: &str, context: &str) -> io::Result<DltSink> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not resolvable"))?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(address)?;
+        Ok(DltSink {
+            socket,
+            ecu: id(ecu),
+            app: id(app),
+            context: id(context),
+            counter: 0,
+        })
+    }
+
+    fn message(&mut self, payload: &Payload) -> Vec<u8> {
+        let length = 4 + 4 + 4 + 10 + payload.bytes.len();
+        let mut message = Vec::with_capacity(length);
+        message.push(HTYP_UEH | HTYP_WEID | HTYP_WTMS | HTYP_VERSION);
+        message.push(self.counter);
+        message.extend_from_slice(&(length as u16).to_be_bytes());
+        message.extend_from_slice(&self.ecu);
+        message.extend_from_slice(&uptime().to_be_bytes());
+        message.push(MSIN_LOG_INFO);
+        message.push(payload.arguments);
+        message.extend_from_slice(&self.app);
+        message.extend_from_slice(&self.context);
+        message.extend_from_slice(&payload.bytes);
+        self.counter = self.counter.wrapping_add(1);
+        message
+    }
+}
+
+impl SampleSink<Record<'_>, Summary> for DltSink {
+    /// Logs the sample as one verbose message with a named argument per value
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        let (result, time) = (record.results, record.time);
+        let (read, write, total) = result.bandwidth(time);
+        let mut payload = Payload {
+            bytes: Vec::new(),
+            arguments: 0,
+        };
+        payload.uint("time", "ms", time);
+        payload.float("read", "MB/s", read);
+        payload.float("write", "MB/s", write);
+        payload.float("total", "MB/s", total);
+        payload.uint("utilization", "%", result.utilization);
+        payload.uint("data_load", "%", result.data_load);
+        payload.uint("access_utilization", "bytes", result.access_utilization);
+        payload.uint("avg_read_burstsize", "bytes", result.avg_read_burstsize);
+        payload.uint("avg_write_burstsize", "bytes", result.avg_write_burstsize);
+        let message = self.message(&payload);
+        self.socket.send(&message).map(|_| ())
+    }
+}
@@ -0,0 +1,207 @@
+//! Bounded history of a whole run for GET /overview. Adjacent buckets are merged
+//! pairwise once all are in use, so a week-long run keeps its shape in a fixed amount
+//! of memory with min/avg/max per bucket.
+
+use crate::json::JsonObject;
+use crate::MMDCProfileResult;
+
+/// Buckets kept, the resolution halves whenever they are exhausted
+const BUCKETS: usize = 1024;
+
+/// Record of the sampling loop as condensed into the overview
+pub struct Point {
+    pub timestamp_ms: u64,
+    pub read_mbps: f32,
+    pub write_mbps: f32,
+    pub total_mbps: f32,
+    pub utilization: u32,
+}
+
+impl Point {
+    pub fn from_record(results: &MMDCProfileResult, time: u32, timestamp_ms: u64) -> Point {
+        let (read_mbps, write_mbps, total_mbps) = results.bandwidth(time);
+        Point {
+            timestamp_ms,
+            read_mbps,
+            write_mbps,
+            total_mbps,
+            utilization: results.utilization,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    start_ms: u64,
+    end_ms: u64,
+    samples: u32,
+    read_sum: f64,
+    write_sum: f64,
+    total_sum: f64,
+    min_total: f32,
+    max_total: f32,
+    max_utilization: u32,
+}
+
+impl Bucket {
+    fn new(point: &Point) -> Bucket {
+        Bucket {
+            start_ms: point.timestamp_ms,
+            end_ms: point.timestamp_ms,
+            samples: 1,
+            read_sum: point.read_mbps as f64,
+            write_sum: point.write_mbps as f64,
+            total_sum: point.total_mbps as f64,
+            min_total: point.total_mbps,
+            max_total: point.total_mbps,
+            max_utilization: point.utilization,
+        }
+    }
+
+    /// Extends the bucket by the later `other`
+    fn merge(&mut self, other: &Bucket) {
+        self.end_ms = other.end_ms;
+        self.samples += other.samples;
+        self.read_sum += other.read_sum;
+        self.write_sum += other.write_sum;
+        self.total_sum += other.total_sum;
+        self.min_total = self.min_total.min(other.min_total);
+        self.max_total = self.max_total.max(other.max_total);
+        self.max_utilization = self.max_utilization.max(other.max_utilization);
+    }
+
+    fn to_json(self) -> String {
+        let mean = |sum: f64| (sum / self.samples as f64) as f32;
+        JsonObject::new()
+            .number("start_ms", self.start_ms)
+            .number("end_ms", self.end_ms)
+            .number("samples", self.samples)
+            .float("read_mbps", mean(self.read_sum))
+            .float("write_mbps", mean(self.write_sum))
+            .float("total_mbps", mean(self.total_sum))
+            .float("min_total_mbps", self.min_total)
+            .float("max_total_mbps", self.max_total)
+            .number("max_utilization", self.max_utilization)
+            .build()
+    }
+}
+
+pub struct Overview {
+    buckets: Vec<Bucket>,
+    /// Records per bucket at the current resolution
+    width: u32,
+}
+
+impl Overview {
+    pub fn new() -> Overview {
+        Overview {
+            buckets: Vec::with_capacity(BUCKETS),
+            width: 1,
+        }
+    }
+
+    pub fn add(&mut self, point: &Point) {
+        let bucket = Bucket::new(point);
+        match self.buckets.last_mut() {
+            Some(last) if last.samples < self.width => last.merge(&bucket),
+            _ => self.buckets.push(bucket),
+        }
+        if self.buckets.len() > BUCKETS {
+            self.buckets = merged(&self.buckets, 2);
+            self.width *= 2;
+        }
+    }
+
+    /// JSON array of at most `points` buckets spanning the whole run
+    pub fn to_json(&self, points: usize) -> String {
+        let group = self.buckets.len().div_ceil(points.max(1)).max(1);
+        let buckets: Vec<String> = merged(&self.buckets, group)
+            .into_iter()
+            .map(Bucket::to_json)
+            .collect();
+        format!("[{}]", buckets.join(","))
+    }
+}
+
+/// Merges each `group` adjacent buckets into one
+fn merged(buckets: &[Bucket], group: usize) -> Vec<Bucket> {
+    buckets
+        .chunks(group)
+        .map(|chunk| {
+            let mut merged = chunk[0];
+            for bucket in &chunk[1..] {
+                merged.merge(bucket);
+            }
+            merged
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp_ms: u64, total_mbps: f32) -> Point {
+        Point {
+            timestamp_ms,
+            read_mbps: total_mbps,
+            write_mbps: 0.0,
+            total_mbps,
+            utilization: total_mbps as u32,
+        }
+    }
+
+    #[test]
+    fn buckets_stay_bounded_over_long_runs() {
+        let mut overview = Overview::new();
+        let records = BUCKETS as u64 * 10 + 3;
+        for i in 0..records {
+            overview.add(&point(i * 100, 1.0));
+        }
+        assert!(overview.buckets.len() <= BUCKETS);
+        assert_eq!(overview.width, 16);
+        let samples: u64 = overview.buckets.iter().map(|b| b.samples as u64).sum();
+        assert_eq!(samples, records);
+        assert_eq!(overview.buckets[0].start_ms, 0);
+        assert_eq!(overview.buckets.last().unwrap().end_ms, (records - 1) * 100);
+    }
+
+    #[test]
+    fn merging_keeps_the_order_of_the_buckets() {
+        let mut overview = Overview::new();
+        for i in 0..BUCKETS as u64 * 3 {
+            overview.add(&point(i, 1.0));
+        }
+        for pair in overview.buckets.windows(2) {
+            assert_eq!(pair[0].end_ms + 1, pair[1].start_ms);
+        }
+    }
+
+    #[test]
+    fn buckets_keep_min_mean_and_max() {
+        let mut overview = Overview::new();
+        for (i, total) in [10.0, 40.0, 20.0, 30.0].iter().enumerate() {
+            overview.add(&point(i as u64 * 1000, *total));
+        }
+        assert_eq!(
+            overview.to_json(1),
+            "[{\"start_ms\":0,\"end_ms\":3000,\"samples\":4,\"read_mbps\":25.00,\
+             \"write_mbps\":0.00,\"total_mbps\":25.00,\"min_total_mbps\":10.00,\
+             \"max_total_mbps\":40.00,\"max_utilization\":40}]"
+        );
+    }
+
+    #[test]
+    fn json_has_at_most_the_requested_points() {
+        let mut overview = Overview::new();
+        for i in 0..100 {
+            overview.add(&point(i, 1.0));
+        }
+        let count = |json: String| json.matches("start_ms").count();
+        assert_eq!(count(overview.to_json(10)), 10);
+        assert_eq!(count(overview.to_json(30)), 25);
+        assert_eq!(count(overview.to_json(1000)), 100);
+        assert_eq!(count(overview.to_json(0)), 1);
+        assert_eq!(Overview::new().to_json(10), "[]");
+    }
+}
@@ -0,0 +1,208 @@
+//! Arithmetic expressions over the counters of a sample, used for the derived metrics of
+//! the config file, e.g. `write_ratio = write_bytes / (read_bytes + write_bytes)`
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+pub enum Expr {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    variables: &'a [&'a str],
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().copied()
+    }
+
+    /// sum = product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(operator) = self.peek().filter(|c| *c == '+' || *c == '-') {
+            self.chars.next();
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    /// product = factor (('*' | '/') factor)*
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        while let Some(operator) = self.peek().filter(|c| *c == '*' || *c == '/') {
+            self.chars.next();
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+
+    /// factor = number | variable | '-' factor | '(' sum ')'
+    fn factor(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Expr::Negate(Box::new(self.factor()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let expr = self.sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(expr)
+                    }
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                number
+                    .parse()
+                    .map(Expr::Number)
+                    .map_err(|_| format!("invalid number {}", number))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                if !self.variables.contains(&name.as_str()) {
+                    return Err(format!(
+                        "unknown variable {}, available: {}",
+                        name,
+                        self.variables.join(", ")
+                    ));
+                }
+                Ok(Expr::Variable(name))
+            }
+            Some(c) => Err(format!("unexpected {}", c)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn take_while(&mut self, predicate: fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.chars.peek().copied().filter(|c| predicate(*c)) {
+            taken.push(c);
+            self.chars.next();
+        }
+        taken
+    }
+}
+
+impl Expr {
+    /// Parses `source`, referring only to the given variables
+    pub fn parse(source: &str, variables: &[&str]) -> Result<Expr, String> {
+        let mut parser = Parser {
+            chars: source.chars().peekable(),
+            variables,
+        };
+        let expr = parser.sum()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(c) => Err(format!("unexpected {}", c)),
+        }
+    }
+
+    /// Evaluates with `variable` giving the values, a division by zero yields NaN or infinity
+    pub fn eval(&self, variable: &dyn Fn(&str) -> f64) -> f64 {
+        match self {
+            Expr::Number(number) => *number,
+            Expr::Variable(name) => variable(name),
+            Expr::Negate(expr) => -expr.eval(variable),
+            Expr::Binary(left, operator, right) => {
+                let (left, right) = (left.eval(variable), right.eval(variable));
+                match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    _ => left / right,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARIABLES: [&str; 3] = ["read_bytes", "write_bytes", "time_ms"];
+
+    fn eval(source: &str) -> f64 {
+        let expr = Expr::parse(source, &VARIABLES).unwrap();
+        expr.eval(&|name| match name {
+            "read_bytes" => 300.0,
+            "write_bytes" => 100.0,
+            _ => 1000.0,
+        })
+    }
+
+    fn error(source: &str) -> String {
+        match Expr::parse(source, &VARIABLES) {
+            Ok(_) => panic!("{} parsed", source),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn products_bind_tighter_than_sums() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("2 * 3 + 1"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("1 + 6 / 3 - 1"), 2.0);
+    }
+
+    #[test]
+    fn operators_of_a_level_are_left_associative() {
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("24 / 4 / 2"), 3.0);
+        assert_eq!(eval("10 - (4 - 3)"), 9.0);
+    }
+
+    #[test]
+    fn negation_applies_to_the_factor() {
+        assert_eq!(eval("-2 * 3"), -6.0);
+        assert_eq!(eval("2 * -3"), -6.0);
+        assert_eq!(eval("--2"), 2.0);
+        assert_eq!(eval("-(1 + 2) + 4"), 1.0);
+        assert_eq!(eval("1 - -1"), 2.0);
+    }
+
+    #[test]
+    fn variables_and_whitespace() {
+        assert_eq!(eval("write_bytes / (read_bytes + write_bytes)"), 0.25);
+        assert_eq!(eval("  read_bytes*1000/time_ms "), 300.0);
+        assert_eq!(eval(".5 * 4"), 2.0);
+    }
+
+    #[test]
+    fn division_by_zero_is_not_an_error() {
+        assert!(eval("1 / 0").is_infinite());
+        assert!(eval("0 / 0").is_nan());
+    }
+
+    #[test]
+    fn errors_name_the_problem() {
+        assert_eq!(error("(1 + 2"), "missing )");
+        assert_eq!(error("1 +"), "unexpected end");
+        assert_eq!(error(""), "unexpected end");
+        assert_eq!(error("1 2"), "unexpected 2");
+        assert_eq!(error("1 + )"), "unexpected )");
+        assert_eq!(error("2 ^ 3"), "unexpected ^");
+        assert_eq!(error("1.2.3"), "invalid number 1.2.3");
+        assert_eq!(
+            error("reads + 1"),
+            "unknown variable reads, available: read_bytes, write_bytes, time_ms"
+        );
+    }
+}
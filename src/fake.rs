@@ -0,0 +1,61 @@
+//! Ordinary file laid out like an MMDC register block, so the CLI can be run against
+//! poked counter values without hardware, e.g. `r-mmdc --regs-file <path>`
+
+use crate::map::MmdcMap;
+use mmdc_core::{Counters, MMDC_MAP_LENGTH};
+use std::fs::OpenOptions;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+/// Registers are written through to the file, fields can be poked through `DerefMut`
+pub struct FakeRegisters {
+    path: PathBuf,
+    map: MmdcMap,
+}
+
+impl FakeRegisters {
+    /// Creates or truncates the file at `path` to a zeroed register block
+    pub fn create(path: &Path) -> io::Result<FakeRegisters> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(MMDC_MAP_LENGTH as u64)?;
+        Ok(FakeRegisters {
+            path: path.to_path_buf(),
+            map: MmdcMap::open_path(path, 0, MMDC_MAP_LENGTH)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sets MADPSR0..MADPSR5 as read after the freeze of a window
+    pub fn set_counters(&mut self, counters: &Counters) {
+        let raw = counters.raw();
+        self.map.madpsr0 = raw[0];
+        self.map.madpsr1 = raw[1];
+        self.map.madpsr2 = raw[2];
+        self.map.madpsr3 = raw[3];
+        self.map.madpsr4 = raw[4];
+        self.map.madpsr5 = raw[5];
+    }
+}
+
+impl Deref for FakeRegisters {
+    type Target = MmdcMap;
+
+    fn deref(&self) -> &MmdcMap {
+        &self.map
+    }
+}
+
+impl DerefMut for FakeRegisters {
+    fn deref_mut(&mut self) -> &mut MmdcMap {
+        &mut self.map
+    }
+}
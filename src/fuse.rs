@@ -0,0 +1,519 @@
+//! Minimal read only FUSE filesystem speaking the kernel protocol on /dev/fuse.
+//!
+//! The mount is a single directory of regular files, their contents are taken from a shared
+//! list when they are opened, so a reader sees the values of one window even if the list is
+//! updated while it reads. Mounting needs CAP_SYS_ADMIN, the files are readable by everyone.
+
+use crate::output::nix_to_io;
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const KERNEL_VERSION: u32 = 7;
+const KERNEL_MINOR_VERSION: u32 = 31;
+const MAX_WRITE: u32 = 4096;
+const BUFFER_SIZE: usize = 64 * 1024;
+
+const LOOKUP: u32 = 1;
+const FORGET: u32 = 2;
+const GETATTR: u32 = 3;
+const OPEN: u32 = 14;
+const READ: u32 = 15;
+const STATFS: u32 = 17;
+const RELEASE: u32 = 18;
+const FLUSH: u32 = 25;
+const INIT: u32 = 26;
+const OPENDIR: u32 = 27;
+const READDIR: u32 = 28;
+const RELEASEDIR: u32 = 29;
+const INTERRUPT: u32 = 36;
+const DESTROY: u32 = 38;
+const BATCH_FORGET: u32 = 42;
+
+const ROOT: u64 = 1;
+const IN_HEADER_LENGTH: usize = 40;
+const OUT_HEADER_LENGTH: usize = 16;
+/// Reads bypass the page cache, the contents change between opens
+const FOPEN_DIRECT_IO: u32 = 0x1;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFREG: u32 = 0o100_000;
+const DT_DIR: u32 = 4;
+const DT_REG: u32 = 8;
+/// Seconds the kernel may cache the names, the set of files never changes
+const ENTRY_VALID_S: u64 = 60;
+
+const ENOENT: i32 = 2;
+const EBADF: i32 = 9;
+const EACCES: i32 = 13;
+const ENOTDIR: i32 = 20;
+const ENOSYS: i32 = 38;
+const ENOTCONN: i32 = 107;
+
+/// Names and contents of the files of a mount
+pub type Files = Arc<Mutex<Vec<(String, String)>>>;
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    bytes
+        .get(offset..offset + 4)
+        .map_or(0, |field| u32::from_ne_bytes(field.try_into().unwrap()))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    bytes
+        .get(offset..offset + 8)
+        .map_or(0, |field| u64::from_ne_bytes(field.try_into().unwrap()))
+}
+
+/// Header of a request from the kernel
+#[derive(Debug, PartialEq)]
+struct Header {
+    opcode: u32,
+    unique: u64,
+    node: u64,
+}
+
+impl Header {
+    fn parse(request: &[u8]) -> Option<Header> {
+        if request.len() < IN_HEADER_LENGTH {
+            return None;
+        }
+        Some(Header {
+            opcode: u32_at(request, 4),
+            unique: u64_at(request, 8),
+            node: u64_at(request, 16),
+        })
+    }
+}
+
+fn reply(unique: u64, error: i32, body: &[u8]) -> Vec<u8> {
+    let length = (OUT_HEADER_LENGTH + body.len()) as u32;
+    let mut reply = Vec::with_capacity(length as usize);
+    reply.extend_from_slice(&length.to_ne_bytes());
+    reply.extend_from_slice(&(-error).to_ne_bytes());
+    reply.extend_from_slice(&unique.to_ne_bytes());
+    reply.extend_from_slice(body);
+    reply
+}
+
+/// `struct fuse_attr` of a node with the given mode and size
+fn attr(node: u64, mode: u32, size: u64) -> Vec<u8> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let mut attr = Vec::with_capacity(88);
+    for value in [node, size, size.div_ceil(512), now, now, now] {
+        attr.extend_from_slice(&value.to_ne_bytes());
+    }
+    let nlink = if mode & S_IFDIR != 0 { 2 } else { 1 };
+    // nanoseconds of the times, mode, nlink, uid, gid, rdev, blksize, flags
+    for value in [0, 0, 0, mode, nlink, 0, 0, 0, 512, 0] {
+        attr.extend_from_slice(&value.to_ne_bytes());
+    }
+    attr
+}
+
+/// `struct fuse_dirent`, padded to 8 bytes
+fn dirent(node: u64, next: u64, kind: u32, name: &str) -> Vec<u8> {
+    let mut entry = Vec::new();
+    entry.extend_from_slice(&node.to_ne_bytes());
+    entry.extend_from_slice(&next.to_ne_bytes());
+    entry.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+    entry.extend_from_slice(&kind.to_ne_bytes());
+    entry.extend_from_slice(name.as_bytes());
+    entry.resize(entry.len().next_multiple_of(8), 0);
+    entry
+}
+
+/// Answers the requests of the kernel, apart from the device I/O
+struct Filesystem {
+    files: Files,
+    /// Contents of the open files by handle
+    open: HashMap<u64, Vec<u8>>,
+    next_handle: u64,
+}
+
+impl Filesystem {
+    fn new(files: Files) -> Filesystem {
+        Filesystem {
+            files,
+            open: HashMap::new(),
+            next_handle: 1,
+        }
+    }
+
+    /// Index of the file of a node, nodes after the root are the files in order
+    fn file(&self, node: u64) -> Option<usize> {
+        let index = node.checked_sub(ROOT + 1)? as usize;
+        (index < self.files.lock().unwrap().len()).then_some(index)
+    }
+
+    fn contents(&self, index: usize) -> Vec<u8> {
+        self.files.lock().unwrap()[index].1.clone().into_bytes()
+    }
+
+    fn node_attr(&self, node: u64) -> Option<Vec<u8>> {
+        match node {
+            ROOT => Some(attr(ROOT, S_IFDIR | 0o555, 0)),
+            _ => {
+                let index = self.file(node)?;
+                Some(attr(
+                    node,
+                    S_IFREG | 0o444,
+                    self.contents(index).len() as u64,
+                ))
+            }
+        }
+    }
+
+    fn init(&self, body: &[u8]) -> Vec<u8> {
+        let mut init = Vec::with_capacity(64);
+        // major, minor, max_readahead as requested and no flags
+        for value in [KERNEL_VERSION, KERNEL_MINOR_VERSION, u32_at(body, 8), 0] {
+            init.extend_from_slice(&value.to_ne_bytes());
+        }
+        // max_background, congestion_threshold
+        init.extend_from_slice(&16_u16.to_ne_bytes());
+        init.extend_from_slice(&12_u16.to_ne_bytes());
+        // max_write, time_gran, max_pages and map_alignment, flags2 and unused
+        init.extend_from_slice(&MAX_WRITE.to_ne_bytes());
+        init.extend_from_slice(&1_u32.to_ne_bytes());
+        init.resize(64, 0);
+        init
+    }
+
+    fn lookup(&self, node: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        if node != ROOT {
+            return Err(ENOTDIR);
+        }
+        let name = body.split(|byte| *byte == 0).next().unwrap_or_default();
+        let index = self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|(file, _)| file.as_bytes() == name)
+            .ok_or(ENOENT)?;
+        let node = ROOT + 1 + index as u64;
+        let mut entry = Vec::with_capacity(128);
+        // node, generation, entry_valid, attr_valid and their nanoseconds
+        for value in [node, 0, ENTRY_VALID_S, 0] {
+            entry.extend_from_slice(&value.to_ne_bytes());
+        }
+        entry.extend_from_slice(&[0; 8]);
+        entry.extend(self.node_attr(node).ok_or(ENOENT)?);
+        Ok(entry)
+    }
+
+    fn getattr(&self, node: u64) -> Result<Vec<u8>, i32> {
+        // attr_valid of 0, the sizes follow the updates
+        let mut out = vec![0; 16];
+        out.extend(self.node_attr(node).ok_or(ENOENT)?);
+        Ok(out)
+    }
+
+    fn open(&mut self, node: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let index = self.file(node).ok_or(ENOENT)?;
+        if u32_at(body, 0) & 0x3 != 0 {
+            return Err(EACCES);
+        }
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.open.insert(handle, self.contents(index));
+        let mut out = handle.to_ne_bytes().to_vec();
+        out.extend_from_slice(&FOPEN_DIRECT_IO.to_ne_bytes());
+        out.extend_from_slice(&[0; 4]);
+        Ok(out)
+    }
+
+    fn read(&self, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let contents = self.open.get(&u64_at(body, 0)).ok_or(EBADF)?;
+        let offset = (u64_at(body, 8) as usize).min(contents.len());
+        let end = offset
+            .saturating_add(u32_at(body, 16) as usize)
+            .min(contents.len());
+        Ok(contents[offset..end].to_vec())
+    }
+
+    fn readdir(&self, node: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        if node != ROOT {
+            return Err(ENOTDIR);
+        }
+        let offset = u64_at(body, 8) as usize;
+        let size = u32_at(body, 16) as usize;
+        let files = self.files.lock().unwrap();
+        let entries = [(ROOT, DT_DIR, "."), (ROOT, DT_DIR, "..")]
+            .iter()
+            .copied()
+            .chain(
+                files
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (name, _))| (ROOT + 1 + index as u64, DT_REG, name.as_str())),
+            );
+        let mut out = Vec::new();
+        for (index, (node, kind, name)) in entries.enumerate().skip(offset) {
+            let entry = dirent(node, index as u64 + 1, kind, name);
+            if out.len() + entry.len() > size {
+                break;
+            }
+            out.extend(entry);
+        }
+        Ok(out)
+    }
+
+    fn statfs(&self) -> Vec<u8> {
+        let mut out = vec![0; 40];
+        // bsize, namelen, frsize, padding and spare
+        for value in [512_u32, 255, 512] {
+            out.extend_from_slice(&value.to_ne_bytes());
+        }
+        out.resize(80, 0);
+        out
+    }
+
+    /// Reply to a request, none for the ones the kernel expects no reply to
+    fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let header = Header::parse(request)?;
+        let body = &request[IN_HEADER_LENGTH..];
+        let result = match header.opcode {
+            FORGET | BATCH_FORGET | INTERRUPT => return None,
+            INIT => Ok(self.init(body)),
+            LOOKUP => self.lookup(header.node, body),
+            GETATTR => self.getattr(header.node),
+            OPEN => self.open(header.node, body),
+            READ => self.read(body),
+            RELEASE => {
+                self.open.remove(&u64_at(body, 0));
+                Ok(Vec::new())
+            }
+            OPENDIR if header.node == ROOT => Ok(vec![0; 16]),
+            OPENDIR => Err(ENOTDIR),
+            READDIR => self.readdir(header.node, body),
+            STATFS => Ok(self.statfs()),
+            FLUSH | RELEASEDIR | DESTROY => Ok(Vec::new()),
+            _ => Err(ENOSYS),
+        };
+        Some(match result {
+            Ok(body) => reply(header.unique, 0, &body),
+            Err(error) => reply(header.unique, error, &[]),
+        })
+    }
+}
+
+/// Serves the requests until the filesystem is unmounted
+fn serve(mut device: File, mut filesystem: Filesystem) {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let length = match device.read(&mut buffer) {
+            Ok(length) => length,
+            // interrupted requests and signals
+            Err(e) if matches!(e.raw_os_error(), Some(2) | Some(4) | Some(11)) => continue,
+            // ENODEV once unmounted
+            Err(_) => return,
+        };
+        if let Some(reply) = filesystem.handle(&buffer[..length]) {
+            // the request may have been interrupted meanwhile
+            let _ = device.write(&reply);
+        }
+        if Header::parse(&buffer[..length]).is_some_and(|header| header.opcode == DESTROY) {
+            return;
+        }
+    }
+}
+
+/// Detaches the mount at `path` of a process which ended without unmounting it, e.g. on a
+/// second termination signal
+pub fn remove_stale(path: &Path) {
+    let stale = fs::metadata(path).is_err_and(|e| e.raw_os_error() == Some(ENOTCONN));
+    if stale {
+        let _ = umount2(path, MntFlags::MNT_DETACH);
+    }
+}
+
+pub struct FuseMount {
+    path: PathBuf,
+}
+
+impl FuseMount {
+    /// Mounts `files` at the existing directory `path`
+    pub fn mount(path: &Path, files: Files) -> io::Result<FuseMount> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/fuse")?;
+        let options = format!(
+            "fd={},rootmode=40555,user_id=0,group_id=0,allow_other",
+            device.as_raw_fd()
+        );
+        mount(
+            Some("rmmdc"),
+            path,
+            Some("fuse.rmmdc"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RDONLY,
+            Some(options.as_str()),
+        )
+        .map_err(nix_to_io)?;
+        let filesystem = Filesystem::new(files);
+        thread::spawn(move || serve(device, filesystem));
+        Ok(FuseMount {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for FuseMount {
+    /// Detaches the mount, the thread serving it ends with it
+    fn drop(&mut self) {
+        if let Err(e) = umount2(&self.path, MntFlags::MNT_DETACH) {
+            eprintln!("Error unmounting {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(opcode: u32, node: u64, body: &[u8]) -> Vec<u8> {
+        let mut request = Vec::new();
+        request.extend_from_slice(&((IN_HEADER_LENGTH + body.len()) as u32).to_ne_bytes());
+        request.extend_from_slice(&opcode.to_ne_bytes());
+        request.extend_from_slice(&7_u64.to_ne_bytes());
+        request.extend_from_slice(&node.to_ne_bytes());
+        request.extend_from_slice(&[0; 16]);
+        request.extend_from_slice(body);
+        request
+    }
+
+    /// `struct fuse_read_in` of a handle, offset and size
+    fn read_in(handle: u64, offset: u64, size: u32) -> Vec<u8> {
+        let mut body = handle.to_ne_bytes().to_vec();
+        body.extend_from_slice(&offset.to_ne_bytes());
+        body.extend_from_slice(&size.to_ne_bytes());
+        body.resize(40, 0);
+        body
+    }
+
+    fn filesystem() -> (Files, Filesystem) {
+        let files: Files = Arc::new(Mutex::new(vec![
+            ("utilization".to_string(), "42\n".to_string()),
+            ("flags".to_string(), "\n".to_string()),
+        ]));
+        (files.clone(), Filesystem::new(files))
+    }
+
+    /// Error and body of a reply to request 7
+    fn parse(reply: Vec<u8>) -> (i32, Vec<u8>) {
+        assert_eq!(u32_at(&reply, 0) as usize, reply.len());
+        assert_eq!(u64_at(&reply, 8), 7);
+        let error = -(u32_at(&reply, 4) as i32);
+        (error, reply[OUT_HEADER_LENGTH..].to_vec())
+    }
+
+    fn call(filesystem: &mut Filesystem, opcode: u32, node: u64, body: &[u8]) -> (i32, Vec<u8>) {
+        parse(filesystem.handle(&request(opcode, node, body)).unwrap())
+    }
+
+    #[test]
+    fn init_reports_the_protocol_version() {
+        let (_, mut filesystem) = filesystem();
+        let mut body = 7_u32.to_ne_bytes().to_vec();
+        body.extend_from_slice(&40_u32.to_ne_bytes());
+        body.extend_from_slice(&131_072_u32.to_ne_bytes());
+        body.resize(64, 0);
+        let (error, init) = call(&mut filesystem, INIT, 0, &body);
+        assert_eq!(error, 0);
+        assert_eq!(init.len(), 64);
+        assert_eq!(u32_at(&init, 0), KERNEL_VERSION);
+        assert_eq!(u32_at(&init, 4), KERNEL_MINOR_VERSION);
+        assert_eq!(u32_at(&init, 8), 131_072);
+        assert_eq!(u32_at(&init, 20), MAX_WRITE);
+    }
+
+    #[test]
+    fn lookup_finds_the_files() {
+        let (_, mut filesystem) = filesystem();
+        let (error, entry) = call(&mut filesystem, LOOKUP, ROOT, b"flags\0");
+        assert_eq!(error, 0);
+        assert_eq!(entry.len(), 128);
+        assert_eq!(u64_at(&entry, 0), ROOT + 2);
+        // mode after the node, size, blocks, times and their nanoseconds
+        assert_eq!(u32_at(&entry, 40 + 60), S_IFREG | 0o444);
+        assert_eq!(call(&mut filesystem, LOOKUP, ROOT, b"seq\0").0, ENOENT);
+        assert_eq!(call(&mut filesystem, LOOKUP, ROOT + 1, b"x\0").0, ENOTDIR);
+    }
+
+    #[test]
+    fn getattr_reports_the_current_size() {
+        let (files, mut filesystem) = filesystem();
+        let (_, out) = call(&mut filesystem, GETATTR, ROOT + 1, &[0; 16]);
+        assert_eq!(out.len(), 104);
+        assert_eq!(u64_at(&out, 16 + 8), 3);
+        files.lock().unwrap()[0].1 = "100\n".to_string();
+        let (_, out) = call(&mut filesystem, GETATTR, ROOT + 1, &[0; 16]);
+        assert_eq!(u64_at(&out, 16 + 8), 4);
+        let (_, root) = call(&mut filesystem, GETATTR, ROOT, &[0; 16]);
+        assert_eq!(u32_at(&root, 16 + 60), S_IFDIR | 0o555);
+        assert_eq!(call(&mut filesystem, GETATTR, 9, &[0; 16]).0, ENOENT);
+    }
+
+    #[test]
+    fn open_files_keep_their_contents() {
+        let (files, mut filesystem) = filesystem();
+        let (error, out) = call(&mut filesystem, OPEN, ROOT + 1, &[0; 8]);
+        assert_eq!(error, 0);
+        let handle = u64_at(&out, 0);
+        assert_eq!(u32_at(&out, 8), FOPEN_DIRECT_IO);
+        files.lock().unwrap()[0].1 = "7\n".to_string();
+        let (_, data) = call(&mut filesystem, READ, ROOT + 1, &read_in(handle, 0, 1));
+        assert_eq!(data, b"4");
+        let (_, data) = call(&mut filesystem, READ, ROOT + 1, &read_in(handle, 1, 4096));
+        assert_eq!(data, b"2\n");
+        let (_, data) = call(&mut filesystem, READ, ROOT + 1, &read_in(handle, 9, 4096));
+        assert!(data.is_empty());
+        call(&mut filesystem, RELEASE, ROOT + 1, &read_in(handle, 0, 0));
+        let (error, _) = call(&mut filesystem, READ, ROOT + 1, &read_in(handle, 0, 1));
+        assert_eq!(error, EBADF);
+    }
+
+    #[test]
+    fn files_are_read_only() {
+        let (_, mut filesystem) = filesystem();
+        let write_only = 1_u32.to_ne_bytes();
+        assert_eq!(call(&mut filesystem, OPEN, ROOT + 1, &write_only).0, EACCES);
+    }
+
+    #[test]
+    fn readdir_lists_the_files_from_the_offset() {
+        let (_, mut filesystem) = filesystem();
+        let (_, out) = call(&mut filesystem, READDIR, ROOT, &read_in(0, 0, 4096));
+        let mut names = Vec::new();
+        let mut offset = 0;
+        while offset < out.len() {
+            let length = u32_at(&out, offset + 16) as usize;
+            names.push(String::from_utf8(out[offset + 24..offset + 24 + length].to_vec()).unwrap());
+            assert_eq!(u64_at(&out, offset + 8), names.len() as u64);
+            offset += (24 + length).next_multiple_of(8);
+        }
+        assert_eq!(names, [".", "..", "utilization", "flags"]);
+        let (_, rest) = call(&mut filesystem, READDIR, ROOT, &read_in(0, 3, 4096));
+        assert_eq!(rest, dirent(ROOT + 2, 4, DT_REG, "flags"));
+        let (_, none) = call(&mut filesystem, READDIR, ROOT, &read_in(0, 1, 8));
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn forget_has_no_reply_and_unknown_requests_fail() {
+        let (_, mut filesystem) = filesystem();
+        assert!(filesystem.handle(&request(FORGET, 2, &[0; 8])).is_none());
+        assert_eq!(call(&mut filesystem, 16, ROOT, &[]).0, ENOSYS);
+        assert!(filesystem.handle(&[0; 8]).is_none());
+    }
+}
@@ -0,0 +1,58 @@
+//! GPU load from the Vivante galcore driver's debugfs idle statistics
+//!
+//! Reading `gc/idle` prints the time the GPU spent in each power state since the previous
+//! read and starts a new period:
+//!
+//! ```text
+//! Start:   292561012 ns
+//! End:     3292680014 ns
+//! On:      3000119002 ns
+//! Off:     0 ns
+//! Idle:    0 ns
+//! Suspend: 0 ns
+//! ```
+
+use crate::schema::Column;
+use std::fs;
+use std::io;
+
+const IDLE_PATH: &str = "/sys/kernel/debug/gc/idle";
+
+pub struct GpuLoad;
+
+fn nanoseconds(statistics: &str, key: &str) -> Option<u64> {
+    statistics
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+        .and_then(|value| value.trim().trim_end_matches("ns").trim().parse().ok())
+}
+
+impl GpuLoad {
+    /// Checks for the statistics and starts their first period
+    pub fn open() -> io::Result<GpuLoad> {
+        fs::read_to_string(IDLE_PATH)?;
+        Ok(GpuLoad)
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        vec![Column {
+            name: "gpu_load".to_string(),
+            unit: "%",
+            description: "Time the GPU was on of the period since the previous record",
+        }]
+    }
+
+    /// Share of the time in the On state since the previous read, zero if unreadable
+    pub fn read(&self) -> Vec<(String, i64)> {
+        let statistics = fs::read_to_string(IDLE_PATH).unwrap_or_default();
+        let load = match (
+            nanoseconds(&statistics, "Start"),
+            nanoseconds(&statistics, "End"),
+            nanoseconds(&statistics, "On"),
+        ) {
+            (Some(start), Some(end), Some(on)) if end > start => on * 100 / (end - start),
+            _ => 0,
+        };
+        vec![("gpu_load".to_string(), load.min(100) as i64)]
+    }
+}
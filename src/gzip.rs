@@ -0,0 +1,547 @@
+//! Minimal gzip (RFC 1952) encoder compressing with LZ77 and the fixed deflate Huffman codes
+
+use std::io::{self, Write};
+
+const BLOCK_SIZE: usize = 64 * 1024;
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+const NO_POSITION: usize = usize::MAX;
+
+const END_OF_BLOCK: u32 = 256;
+
+const LENGTH_BASE: [u32; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u32; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (index, entry) in table.iter_mut().enumerate() {
+        let mut crc = index as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+/// Collects the deflate bit stream, least significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting with their most significant bit
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer = 0;
+            self.count = 0;
+        }
+    }
+
+    fn write_literal(&mut self, literal: u32) {
+        match literal {
+            0..=143 => self.write_code(0x30 + literal, 8),
+            144..=255 => self.write_code(0x190 + literal - 144, 9),
+            256..=279 => self.write_code(literal - 256, 7),
+            _ => self.write_code(0xC0 + literal - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: u32, distance: u32) {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|base| *base <= length)
+            .unwrap();
+        self.write_literal(257 + code as u32);
+        self.write_bits(length - LENGTH_BASE[code], LENGTH_EXTRA[code]);
+
+        let code = DISTANCE_BASE
+            .iter()
+            .rposition(|base| *base <= distance)
+            .unwrap();
+        self.write_code(code as u32, 5);
+        self.write_bits(distance - DISTANCE_BASE[code], DISTANCE_EXTRA[code]);
+    }
+}
+
+fn hash(data: &[u8], position: usize) -> usize {
+    let value = (data[position] as u32) << 16
+        | (data[position + 1] as u32) << 8
+        | data[position + 2] as u32;
+    (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+fn match_length(data: &[u8], candidate: usize, position: usize) -> usize {
+    let max_length = MAX_MATCH.min(data.len() - position);
+    (0..max_length)
+        .take_while(|offset| data[candidate + offset] == data[position + offset])
+        .count()
+}
+
+/// Most recent positions per hash of the next three bytes
+struct HashChains {
+    head: Vec<usize>,
+    previous: Vec<usize>,
+    /// Positions before this one are in the chains
+    inserted: usize,
+}
+
+impl HashChains {
+    fn new() -> HashChains {
+        HashChains {
+            head: vec![NO_POSITION; 1 << HASH_BITS],
+            previous: Vec::new(),
+            inserted: 0,
+        }
+    }
+
+    /// Inserts the positions before `end`, as far as the data has three bytes to hash
+    fn insert_until(&mut self, data: &[u8], end: usize) {
+        let end = end.min((data.len() + 1).saturating_sub(MIN_MATCH));
+        if self.previous.len() < end {
+            self.previous.resize(end, NO_POSITION);
+        }
+        while self.inserted < end {
+            let key = hash(data, self.inserted);
+            self.previous[self.inserted] = self.head[key];
+            self.head[key] = self.inserted;
+            self.inserted += 1;
+        }
+    }
+
+    /// Forgets the first `count` positions after they were removed from the data
+    fn slide(&mut self, count: usize) {
+        let shift = |position: &mut usize| {
+            *position = match *position {
+                position if position == NO_POSITION || position < count => NO_POSITION,
+                position => position - count,
+            }
+        };
+        self.head.iter_mut().for_each(shift);
+        self.previous.drain(..count.min(self.previous.len()));
+        self.previous.iter_mut().for_each(shift);
+        self.inserted = self.inserted.saturating_sub(count);
+    }
+}
+
+/// Gzip stream writer, the stream is finished on `try_finish` or when dropped
+pub struct GzipWriter<W: Write> {
+    inner: W,
+    finished: bool,
+    /// The compressed input matches may refer back to, at least the last WINDOW_SIZE bytes,
+    /// followed by the pending input from `start`. Kept across sync flushes, so records
+    /// flushed one by one still compress against the previous ones.
+    data: Vec<u8>,
+    start: usize,
+    chains: HashChains,
+    bits: BitWriter,
+    crc_table: [u32; 256],
+    crc: u32,
+    size: u32,
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<GzipWriter<W>> {
+        // magic, deflate, no flags, no mtime, no extra flags, unix
+        inner.write_all(&[0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 3])?;
+        Ok(GzipWriter {
+            inner,
+            finished: false,
+            data: Vec::with_capacity(2 * WINDOW_SIZE + BLOCK_SIZE),
+            start: 0,
+            chains: HashChains::new(),
+            bits: BitWriter::default(),
+            crc_table: crc32_table(),
+            crc: 0xFFFF_FFFF,
+            size: 0,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    fn compress_pending(&mut self, last: bool) {
+        if self.start >= 2 * WINDOW_SIZE {
+            let count = self.start - WINDOW_SIZE;
+            self.data.drain(..count);
+            self.chains.slide(count);
+            self.start -= count;
+        }
+        self.bits.write_bits(last as u32, 1);
+        self.bits.write_bits(1, 2);
+
+        let data = &self.data;
+        let mut position = self.start;
+        while position < data.len() {
+            self.chains.insert_until(data, position);
+            let (mut best_length, mut best_distance) = (0, 0);
+            if position + MIN_MATCH <= data.len() {
+                let mut candidate = self.chains.head[hash(data, position)];
+                let mut chain = 0;
+                while candidate != NO_POSITION
+                    && position - candidate <= WINDOW_SIZE
+                    && chain < MAX_CHAIN
+                {
+                    let length = match_length(data, candidate, position);
+                    if length > best_length {
+                        best_length = length;
+                        best_distance = position - candidate;
+                    }
+                    candidate = self.chains.previous[candidate];
+                    chain += 1;
+                }
+            }
+
+            if best_length >= MIN_MATCH {
+                self.bits
+                    .write_match(best_length as u32, best_distance as u32);
+                position += best_length;
+            } else {
+                self.bits.write_literal(data[position] as u32);
+                position += 1;
+            }
+        }
+        self.bits.write_literal(END_OF_BLOCK);
+        self.start = data.len();
+    }
+
+    fn write_out(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.bits.bytes)?;
+        self.bits.bytes.clear();
+        Ok(())
+    }
+
+    /// Completes the gzip stream, nothing may be written afterwards
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.compress_pending(true);
+        self.bits.align();
+        let crc = !self.crc;
+        self.bits.bytes.extend_from_slice(&crc.to_le_bytes());
+        self.bits.bytes.extend_from_slice(&self.size.to_le_bytes());
+        self.write_out()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.crc =
+                self.crc_table[((self.crc ^ *byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+        self.size = self.size.wrapping_add(buf.len() as u32);
+        self.data.extend_from_slice(buf);
+        if self.data.len() - self.start >= BLOCK_SIZE {
+            self.compress_pending(false);
+            self.write_out()?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Sync flush, everything written so far can be decompressed by the reader
+    fn flush(&mut self) -> io::Result<()> {
+        if self.start < self.data.len() {
+            self.compress_pending(false);
+        }
+        // empty stored block aligning the stream to a byte boundary
+        self.bits.write_bits(0, 3);
+        self.bits.align();
+        self.bits.bytes.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+        self.write_out()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for GzipWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.try_finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    /// Reads the bit stream least significant bit first, the counterpart of `BitWriter`
+    struct BitReader<'d> {
+        data: &'d [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, bit| {
+                let byte = self.data[self.position / 8];
+                let set = (byte >> (self.position % 8)) & 1;
+                self.position += 1;
+                value | (set as u32) << bit
+            })
+        }
+
+        /// Huffman codes come most significant bit first
+        fn code(&mut self, length: u32) -> u32 {
+            (0..length).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        fn align(&mut self) {
+            self.position = self.position.div_ceil(8) * 8;
+        }
+    }
+
+    /// Symbol of the fixed literal/length code
+    fn literal(reader: &mut BitReader) -> u32 {
+        let code = reader.code(7);
+        if code <= 0x17 {
+            return 256 + code;
+        }
+        let code = code << 1 | reader.bits(1);
+        match code {
+            0x30..=0xBF => code - 0x30,
+            0xC0..=0xC7 => 280 + code - 0xC0,
+            _ => 144 + (code << 1 | reader.bits(1)) - 0x190,
+        }
+    }
+
+    /// Inflates the stored and fixed Huffman blocks the writer emits, returns the data and
+    /// the remaining bytes after the final block. Flushed streams without one end with the
+    /// data.
+    fn inflate(data: &[u8]) -> (Vec<u8>, &[u8]) {
+        let mut reader = BitReader { data, position: 0 };
+        let mut output = Vec::new();
+        loop {
+            let last = reader.bits(1) == 1;
+            match reader.bits(2) {
+                0 => {
+                    reader.align();
+                    let length = reader.bits(16);
+                    assert_eq!(reader.bits(16), !length & 0xFFFF);
+                    for _ in 0..length {
+                        output.push(reader.bits(8) as u8);
+                    }
+                }
+                1 => loop {
+                    let symbol = literal(&mut reader);
+                    if symbol < 256 {
+                        output.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == END_OF_BLOCK {
+                        break;
+                    }
+                    let index = (symbol - 257) as usize;
+                    let length = LENGTH_BASE[index] + reader.bits(LENGTH_EXTRA[index]);
+                    let index = reader.code(5) as usize;
+                    let distance = DISTANCE_BASE[index] + reader.bits(DISTANCE_EXTRA[index]);
+                    let start = output.len() - distance as usize;
+                    for offset in 0..length as usize {
+                        output.push(output[start + offset]);
+                    }
+                },
+                block_type => panic!("unexpected block type {}", block_type),
+            }
+            if last || reader.position == data.len() * 8 {
+                break;
+            }
+        }
+        reader.align();
+        (output, &data[reader.position / 8..])
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let table = crc32_table();
+        !data.iter().fold(0xFFFF_FFFF, |crc, byte| {
+            table[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+        })
+    }
+
+    /// Checks the header and trailer and returns the decompressed member
+    fn gunzip(member: &[u8]) -> Vec<u8> {
+        assert_eq!(member[..4], [0x1F, 0x8B, 8, 0]);
+        let (data, trailer) = inflate(&member[10..]);
+        assert_eq!(trailer.len(), 8);
+        assert_eq!(trailer[..4], crc32(&data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        data
+    }
+
+    fn compress(chunks: &[&[u8]], flush: bool) -> Vec<u8> {
+        let mut writer = GzipWriter::new(Vec::new()).unwrap();
+        for chunk in chunks {
+            writer.write_all(chunk).unwrap();
+            if flush {
+                writer.flush().unwrap();
+            }
+        }
+        writer.try_finish().unwrap();
+        writer.get_ref().clone()
+    }
+
+    /// Records alike the CSV output, repetitive with long matches and bytes of every value
+    fn sample_data(length: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(length);
+        let mut state = 0x1234_5678_u32;
+        while data.len() < length {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            match state >> 30 {
+                0 => data.extend_from_slice(b"1000;264000000;13200;5000;2500;320000;160000\n"),
+                1 => data.extend_from_slice(&[0xAB; 300]),
+                _ => data.push((state >> 16) as u8),
+            }
+        }
+        data.truncate(length);
+        data
+    }
+
+    /// Decompresses with the gzip tool, the reference the members have to satisfy
+    fn gzip_decompress(member: &[u8]) -> Vec<u8> {
+        let mut child = Command::new("gzip")
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let member = member.to_vec();
+        let writer = thread::spawn(move || stdin.write_all(&member).unwrap());
+        let output = child.wait_with_output().unwrap();
+        writer.join().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    }
+
+    fn csv_rows(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|row| {
+                format!(
+                    "1000;{};13200;5000;2500;{};160000;32;32;{}.25;{}.50\n",
+                    264_000 + row % 7,
+                    320_000 + row % 13 * 64,
+                    row % 11,
+                    row % 5
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gzip_reads_the_members() {
+        let data = sample_data(3 * BLOCK_SIZE + 5000);
+        assert_eq!(gzip_decompress(&compress(&[&data], false)), data);
+        let chunks: Vec<&[u8]> = data.chunks(777).collect();
+        assert_eq!(gzip_decompress(&compress(&chunks, true)), data);
+        assert!(gzip_decompress(&compress(&[], true)).is_empty());
+    }
+
+    #[test]
+    fn flushed_records_compress_against_the_previous_ones() {
+        let rows = csv_rows(500);
+        let chunks: Vec<&[u8]> = rows.iter().map(Vec::as_slice).collect();
+        let flushed = compress(&chunks, true);
+        let unflushed = compress(&chunks, false);
+        assert_eq!(gzip_decompress(&flushed), rows.concat());
+        // each flush costs the block header, the end of block and the empty stored block
+        assert!(
+            flushed.len() < unflushed.len() + rows.len() * 8,
+            "{} flushed, {} unflushed",
+            flushed.len(),
+            unflushed.len()
+        );
+        assert!(flushed.len() * 4 < rows.concat().len());
+    }
+
+    #[test]
+    fn crc_of_the_check_string() {
+        let member = compress(&[b"123456789"], false);
+        assert_eq!(
+            member[member.len() - 8..][..4],
+            0xCBF4_3926_u32.to_le_bytes()
+        );
+        assert_eq!(gunzip(&member), b"123456789");
+    }
+
+    #[test]
+    fn empty_stream() {
+        assert!(gunzip(&compress(&[], false)).is_empty());
+    }
+
+    #[test]
+    fn round_trip_over_several_blocks() {
+        let data = sample_data(3 * BLOCK_SIZE + 123);
+        let member = compress(&[&data], false);
+        assert!(member.len() < data.len());
+        assert_eq!(gunzip(&member), data);
+    }
+
+    #[test]
+    fn all_literal_codes() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(gunzip(&compress(&[&data], false)), data);
+    }
+
+    #[test]
+    fn flushed_data_is_readable_before_the_trailer() {
+        let mut writer = GzipWriter::new(Vec::new()).unwrap();
+        writer.write_all(b"first record\n").unwrap();
+        writer.flush().unwrap();
+        // the sync flush ends in an empty stored block, the data so far inflates
+        let flushed = writer.get_ref().clone();
+        assert_eq!(flushed[flushed.len() - 4..], [0x00, 0x00, 0xFF, 0xFF]);
+        assert_eq!(inflate(&flushed[10..]).0, b"first record\n");
+        writer.write_all(b"second record\n").unwrap();
+        writer.try_finish().unwrap();
+        assert_eq!(gunzip(writer.get_ref()), b"first record\nsecond record\n");
+    }
+
+    #[test]
+    fn round_trip_with_flushes() {
+        let data = sample_data(BLOCK_SIZE + 5000);
+        let chunks: Vec<&[u8]> = data.chunks(777).collect();
+        assert_eq!(gunzip(&compress(&chunks, true)), data);
+    }
+}
@@ -0,0 +1,64 @@
+//! Detection of other agents rewriting the MMDC configuration during a run
+
+use crate::{MADPCR1_RESERVED, MMDC};
+use std::ptr;
+
+type Field = fn(&MMDC) -> &u32;
+
+/// Configuration registers which change the meaning of the counters if rewritten, e.g. by
+/// a busfreq transition or another tool
+const REGISTERS: [(&str, Field); 7] = [
+    ("MDCTL", |mmdc| &mmdc.mdctl),
+    ("MDMISC", |mmdc| &mmdc.mdmisc),
+    ("MDCFG0", |mmdc| &mmdc.mdcfg0),
+    ("MDCFG1", |mmdc| &mmdc.mdcfg1),
+    ("MDCFG2", |mmdc| &mmdc.mdcfg2),
+    ("MDREF", |mmdc| &mmdc.mdref),
+    ("MAARCR", |mmdc| &mmdc.maarcr),
+];
+
+fn read(mmdc: &MMDC, register: Field) -> u32 {
+    unsafe { ptr::read_volatile(register(mmdc)) }
+}
+
+/// Values of the configuration registers at the start of the run
+pub struct RegisterCheck {
+    values: Vec<u32>,
+}
+
+impl RegisterCheck {
+    pub fn new(mmdc: &MMDC) -> RegisterCheck {
+        RegisterCheck {
+            values: REGISTERS
+                .iter()
+                .map(|(_, register)| read(mmdc, *register))
+                .collect(),
+        }
+    }
+
+    /// Warns about every register changed since the previous check and about MADPCR1
+    /// not holding the filter this tool set. Returns whether anything changed.
+    pub fn check(&mut self, mmdc: &MMDC, filter: u32) -> bool {
+        let mut changed = false;
+        for ((name, register), value) in REGISTERS.iter().zip(self.values.iter_mut()) {
+            let current = read(mmdc, *register);
+            if current != *value {
+                eprintln!(
+                    "WARNING: {} changed from 0x{:08X} to 0x{:08X} during the run",
+                    name, value, current
+                );
+                *value = current;
+                changed = true;
+            }
+        }
+        let madpcr1 = unsafe { ptr::read_volatile(&mmdc.madpcr1) };
+        if madpcr1 & !MADPCR1_RESERVED != filter & !MADPCR1_RESERVED {
+            eprintln!(
+                "WARNING: MADPCR1 is 0x{:08X} instead of the selected filter 0x{:08X}, another agent rewrote it",
+                madpcr1, filter
+            );
+            changed = true;
+        }
+        changed
+    }
+}
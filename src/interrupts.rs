@@ -0,0 +1,113 @@
+//! Per window interrupt counts from /proc/interrupts, a cheap hint which DMA engines
+//! were active
+
+use crate::schema::Column;
+use std::fs;
+use std::io;
+
+const INTERRUPTS_PATH: &str = "/proc/interrupts";
+
+/// Line of /proc/interrupts: IRQ, total count over all CPUs and the remaining description
+/// with the controller and the action names
+fn parse(content: &str) -> Vec<(String, u64, String)> {
+    let mut lines = content.lines();
+    let cpus = lines
+        .next()
+        .map(|header| header.split_whitespace().count())
+        .unwrap_or_default();
+    lines
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let irq = tokens.next()?.strip_suffix(':')?.to_string();
+            let counts: Vec<u64> = tokens
+                .by_ref()
+                .take(cpus)
+                .map_while(|token| token.parse().ok())
+                .collect();
+            let description = tokens.collect::<Vec<&str>>().join(" ");
+            Some((irq, counts.iter().sum(), description))
+        })
+        .collect()
+}
+
+pub struct Interrupts {
+    /// Column name and IRQ of each selected interrupt
+    selected: Vec<(String, String)>,
+    previous: Vec<u64>,
+}
+
+impl Interrupts {
+    /// Selects interrupts by number, e.g. `45`, or by a part of their action name, e.g.
+    /// `ipu1` or `mmc0`
+    pub fn open(selectors: &[String]) -> io::Result<Interrupts> {
+        let lines = parse(&fs::read_to_string(INTERRUPTS_PATH)?);
+        let mut selected = Vec::new();
+        for selector in selectors {
+            let (irq, _, _) = lines
+                .iter()
+                .find(|(irq, _, _)| irq == selector)
+                .or_else(|| {
+                    lines
+                        .iter()
+                        .find(|(_, _, description)| description.contains(selector.as_str()))
+                })
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no interrupt {} in {}", selector, INTERRUPTS_PATH),
+                    )
+                })?;
+            let label: String = selector
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            selected.push((format!("irq_{}", label), irq.clone()));
+        }
+        let mut interrupts = Interrupts {
+            previous: vec![0; selected.len()],
+            selected,
+        };
+        interrupts.previous = interrupts.totals();
+        Ok(interrupts)
+    }
+
+    fn totals(&self) -> Vec<u64> {
+        let lines = parse(&fs::read_to_string(INTERRUPTS_PATH).unwrap_or_default());
+        self.selected
+            .iter()
+            .map(|(_, selected)| {
+                lines
+                    .iter()
+                    .find(|(irq, _, _)| irq == selected)
+                    .map(|(_, count, _)| *count)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        self.selected
+            .iter()
+            .map(|(name, _)| Column {
+                name: name.clone(),
+                unit: "interrupts",
+                description: "Interrupts raised since the previous record",
+            })
+            .collect()
+    }
+
+    /// Interrupts of each selected IRQ since the previous read
+    pub fn read(&mut self) -> Vec<(String, i64)> {
+        let totals = self.totals();
+        let deltas = self
+            .selected
+            .iter()
+            .zip(totals.iter().zip(self.previous.iter()))
+            .map(|((name, _), (total, previous))| {
+                (name.clone(), total.saturating_sub(*previous) as i64)
+            })
+            .collect();
+        self.previous = totals;
+        deltas
+    }
+}
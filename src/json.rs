@@ -0,0 +1,137 @@
+use std::fmt::Display;
+
+/// Builds a flat JSON object, keys keep their insertion order
+#[derive(Default)]
+pub struct JsonObject {
+    fields: Vec<(String, String)>,
+}
+
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl JsonObject {
+    pub fn new() -> JsonObject {
+        JsonObject::default()
+    }
+
+    pub fn string(mut self, key: &str, value: &str) -> JsonObject {
+        self.fields.push((key.to_string(), escape(value)));
+        self
+    }
+
+    pub fn boolean(mut self, key: &str, value: bool) -> JsonObject {
+        self.fields.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn number<T: Display>(mut self, key: &str, value: T) -> JsonObject {
+        self.fields.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn float(mut self, key: &str, value: f32) -> JsonObject {
+        let formatted = if value.is_finite() {
+            format!("{:.2}", value)
+        } else {
+            "null".to_string()
+        };
+        self.fields.push((key.to_string(), formatted));
+        self
+    }
+
+    /// One field per line, for reading rather than parsing line by line
+    pub fn build_pretty(&self) -> String {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("  {}: {}", escape(key), value))
+            .collect();
+        format!("{{\n{}\n}}", fields.join(",\n"))
+    }
+
+    pub fn build(&self) -> String {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{}:{}", escape(key), value))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('r') => value.push('\r'),
+                Some('t') => value.push('\t'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    if let Some(c) = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                        value.push(c);
+                    }
+                }
+                Some(c) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    value
+}
+
+/// Parses a flat JSON object into its key value pairs, values are returned unquoted
+pub fn parse_flat(line: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let mut chars = line.trim().trim_start_matches('{').chars().peekable();
+    loop {
+        while let Some(c) = chars.peek() {
+            if *c == '"' {
+                break;
+            }
+            chars.next();
+        }
+        if chars.next().is_none() {
+            break;
+        }
+        let key = parse_string(&mut chars);
+        chars.by_ref().find(|c| *c == ':');
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let value = if chars.peek() == Some(&'"') {
+            chars.next();
+            parse_string(&mut chars)
+        } else {
+            let mut value = String::new();
+            while let Some(c) = chars.peek() {
+                if *c == ',' || *c == '}' {
+                    break;
+                }
+                value.push(*c);
+                chars.next();
+            }
+            value.trim().to_string()
+        };
+        fields.push((key, value));
+    }
+    fields
+}
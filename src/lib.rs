@@ -0,0 +1,19 @@
+//! Library interface of r-mmdc for programmatic users of the MMDC profiling counters
+
+pub mod annotation;
+pub mod fake;
+pub mod map;
+#[cfg(feature = "perf")]
+pub mod perf;
+pub mod profiler;
+pub mod sample_sink;
+
+pub use annotation::Annotations;
+pub use fake::FakeRegisters;
+pub use map::MmdcMap;
+pub use mmdc_core::units::{
+    Accesses, Bandwidth, Beats, Bytes, Cycles, MegabytesPerSecond, Percent,
+};
+pub use mmdc_core::Counters;
+pub use profiler::{Backend, Channel, ConfigError, MmdcProfiler, ProfilerBuilder, Sample};
+pub use sample_sink::{RunTotals, SampleSink};
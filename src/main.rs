@@ -2,19 +2,93 @@ extern crate nix;
 extern crate regex;
 extern crate time;
 
-use nix::sys::mman::{MapFlags, ProtFlags, *};
+use nix::sys::mman::{msync, MsFlags};
 use regex::Regex;
 use std::error::Error;
 use std::fmt;
-use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
 use std::num::ParseIntError;
-use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::ptr;
 use std::thread;
-use std::time::SystemTime as stdtime;
+use std::time::{Duration, Instant};
+use structopt::clap::Shell;
 use structopt::StructOpt;
-use time::Time;
+
+use mmdc_core::soc::{Controller, Master, SocProfile};
+use mmdc_core::{bits, soc, Counters, MMDC};
+use mmdc_core::{
+    AXI_DEFAULT, CYC_OVF, DBG_EN, DBG_RST, MMDC_P0_IPS_BASE_ADDR, MMDC_P1_IPS_BASE_ADDR,
+    PL310_BASE_ADDR, PRF_FRZ,
+};
+use r_mmdc::{MmdcMap, SampleSink};
+
+#[cfg(feature = "network")]
+mod agg;
+mod aggregate;
+mod alert;
+mod arbitration;
+mod baseline;
+mod blame;
+#[cfg(feature = "compress")]
+mod bundle;
+mod calibration;
+mod change;
+mod config;
+mod control;
+mod convert;
+mod cpufreq;
+#[cfg(feature = "perf")]
+mod crosscheck;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod ddr;
+#[cfg(feature = "network")]
+mod dlt;
+#[cfg(feature = "network")]
+mod downsample;
+mod expr;
+#[cfg(feature = "fuse")]
+mod fuse;
+mod galcore;
+#[cfg(feature = "compress")]
+mod gzip;
+mod integrity;
+mod interrupts;
+mod json;
+mod measure;
+mod memory;
+mod metadata;
+mod output;
+mod pl310;
+#[cfg(feature = "perf")]
+mod pmu;
+mod power;
+mod ptest;
+mod schedule;
+mod schema;
+mod selftest;
+#[cfg(feature = "network")]
+mod server;
+#[cfg(feature = "shm")]
+mod shm;
+mod sink;
+mod statsdir;
+#[cfg(feature = "network")]
+mod stream;
+mod summary;
+mod sweep;
+#[cfg(feature = "syslog")]
+mod syslog;
+mod top;
+mod trace;
+mod tune;
+mod usdt;
+#[cfg(feature = "network")]
+mod view;
+#[cfg(feature = "network")]
+mod websocket;
 
 #[derive(Debug)]
 struct ProfilingError {
@@ -41,98 +115,7 @@ impl Error for ProfilingError {
     }
 }
 
-struct MMDC {
-    mdctl: u32,
-    mdpdc: u32,
-    mdotc: u32,
-    mdcfg0: u32,
-    mdcfg1: u32,
-    mdcfg2: u32,
-    mdmisc: u32,
-    mdscr: u32,
-    mdref: u32,
-    mdwcc: u32,
-    mdrcc: u32,
-    mdrwd: u32,
-    mdor: u32,
-    mdmrr: u32,
-    mdcfg3lp: u32,
-    mdmr4: u32,
-    mdasp: u32,
-
-    adopt_base_offset_fill: [u32; 239],
-    maarcr: u32,
-    mapsr: u32,
-    maexidr0: u32,
-    maexidr1: u32,
-    madpcr0: u32,
-    madpcr1: u32,
-    madpsr0: u32,
-    madpsr1: u32,
-    madpsr2: u32,
-    madpsr3: u32,
-    madpsr4: u32,
-    madpsr5: u32,
-    masbs0: u32,
-    masbs1: u32,
-    ma_reserved1: u32,
-    ma_reserved2: u32,
-    magenp: u32,
-
-    phy_base_offset_fill: [u32; 239],
-    mpzqhwctrl: u32,
-    mpzqswctrl: u32,
-    mpwlgcr: u32,
-    mpwldectrl0: u32,
-    mpwldectrl1: u32,
-    mpwldlst: u32,
-    mpodtctrl: u32,
-    mpredqby0dl: u32,
-    mpredqby1dl: u32,
-    mpredqby2dl: u32,
-    mpredqby3dl: u32,
-    mpwrdqby0dl: u32,
-    mpwrdqby1dl: u32,
-    mpwrdqby2dl: u32,
-    mpwrdqby3dl: u32,
-    mpdgctrl0: u32,
-    mpdgctrl1: u32,
-    mpdgdlst: u32,
-    mprddlctl: u32,
-    mprddlst: u32,
-    mpwrdlctl: u32,
-    mpwrdlst: u32,
-    mpsdctrl: u32,
-    mpzqlp2ctl: u32,
-    mprddlhwctl: u32,
-    mpwrdlhwctl: u32,
-    mprddlhwst0: u32,
-    mprddlhwst1: u32,
-    mpwrdlhwst0: u32,
-    mpwrdlhwst1: u32,
-    mpwlhwerr: u32,
-    mpdghwst0: u32,
-    mpdghwst1: u32,
-    mpdghwst2: u32,
-    mpdghwst3: u32,
-    mppdcmpr1: u32,
-    mppdcmpr2: u32,
-    mpswdar: u32,
-    mpswdrdr0: u32,
-    mpswdrdr1: u32,
-    mpswdrdr2: u32,
-    mpswdrdr3: u32,
-    mpswdrdr4: u32,
-    mpswdrdr5: u32,
-    mpswdrdr6: u32,
-    mpswdrdr7: u32,
-    mpmur: u32,
-    mpwrcadl: u32,
-    mpdccr: u32,
-    mpbc: u32,
-}
-
-#[derive(Default)]
+#[derive(Clone, Default)]
 struct MMDCProfileResult {
     total_cycles: u32,
     busy_cycles: u32,
@@ -140,113 +123,475 @@ struct MMDCProfileResult {
     write_accesses: u32,
     read_bytes: u32,
     write_bytes: u32,
+    /// Byte counts in beats of the bus width, not separately counted
+    read_beats: u32,
+    write_beats: u32,
+    raw_counters: [u32; 6],
+    bus_width: u32,
     data_load: u32,
     utilization: u32,
     access_utilization: u32,
     avg_write_burstsize: u32,
     avg_read_burstsize: u32,
+    master: Option<&'static str>,
+    warmup: bool,
+    range: Option<aggregate::Range>,
+    /// FLAG_* conditions which make the sample questionable
+    flags: u32,
+    /// Derived metrics of the config file
+    metrics: Vec<(String, f64)>,
+    /// Integer columns of the system sources, e.g. --cpufreq
+    columns: Vec<(String, i64)>,
+    /// Number of the record in the run, counting warm-up records as well
+    sequence: u64,
+    /// CLOCK_BOOTTIME at the end of the window, keeps counting while suspended
+    boottime_ms: u64,
+    /// Top contributors of a saturation, see --blame-threshold
+    suspects: Option<String>,
+    /// Phase of the last marker, see --marker-fifo
+    phase: Option<String>,
 }
 
-enum MMDCResultType {
-    Full,
-    Utilization,
+/// A record of the sampling loop as handed to the sinks
+struct Record<'a> {
+    results: &'a MMDCProfileResult,
+    /// Length of the window in ms
+    time: u32,
+    /// Written to the main output, false for the records held back by --emit-on-change
+    emitted: bool,
 }
 
-static AXI_IPU1: u32 = 0x3FE70004;
-static AXI_IPU2_6Q: u32 = 0x3FE70005;
-static AXI_GPU3D_6DL: u32 = 0x003F0002;
-static AXI_GPU3D_6Q: u32 = 0x003E0002;
-static AXI_GPU2D2_6DL: u32 = 0x003F0003;
-static AXI_GPU2D1_6DL: u32 = 0x003F000A;
-static AXI_GPU2D_6Q: u32 = 0x003E000B;
-static AXI_GPU2D_6SL: u32 = 0x0017000F;
-static AXI_VPU_6DL: u32 = 0x003F000B;
-static AXI_VPU_6Q: u32 = 0x003F0013;
-static AXI_OPENVG_6Q: u32 = 0x003F0022;
-static AXI_OPENVG_6SL: u32 = 0x001F0017;
-static AXI_ARM: u32 = 0x00060000;
-static AXI_PCIE: u32 = 0x303F001B;
-static AXI_SATA: u32 = 0x3FFF00E3;
-static AXI_DEFAULT: u32 = 0x00000000;
+/// Variables available to the derived metrics
+const METRIC_VARIABLES: [&str; 12] = [
+    "time_ms",
+    "total_cycles",
+    "busy_cycles",
+    "read_accesses",
+    "write_accesses",
+    "read_bytes",
+    "write_bytes",
+    "read_mbps",
+    "write_mbps",
+    "total_mbps",
+    "utilization",
+    "data_load",
+];
 
-static MMDC_P0_IPS_BASE_ADDR: i32 = 0x021B0000;
-static MMDC_P1_IPS_BASE_ADDR: i32 = 0x021B4000;
+/// Layout version of the structured records, bumped whenever fields are renamed, removed
+/// or change their meaning. Version 1 records predate the field and carry none.
+const SCHEMA_VERSION: u32 = 2;
 
-fn get_system_revision() -> Result<u32, ProfilingError> {
-    let mut f = match File::open("/proc/cpuinfo") {
-        Ok(file) => file,
-        Err(_) => return Err(ProfilingError::new("Error opening /proc/cpuinfo")),
-    };
+/// The cycle counter overflowed, the counts are incomplete
+const FLAG_OVERFLOW: u32 = 0x1;
+/// The DDR clock changed during or since the previous window
+const FLAG_FREQUENCY: u32 = 0x2;
+/// The window took considerably longer than requested
+const FLAG_DEADLINE: u32 = 0x4;
+/// The counters kept counting after PRF_FRZ
+const FLAG_FREEZE: u32 = 0x8;
+/// PRF_FRZ didn't read back set within FREEZE_TIMEOUT, the counters may not be latched
+const FLAG_LATCH: u32 = 0x10;
+/// The DDR ran at the low bus frequency of the busfreq driver during the window
+const FLAG_LOW_BUS: u32 = 0x20;
+/// Another agent rewrote MADPCR1 or the DDR configuration, found by --check-registers
+const FLAG_CONFIG: u32 = 0x40;
+/// A --blame-threshold scan followed the window, its time is in no record and no total
+const FLAG_SCAN: u32 = 0x80;
 
-    let mut buffer = [0_u8; 2048];
+const FLAG_NAMES: [(u32, &str); 8] = [
+    (FLAG_OVERFLOW, "overflow"),
+    (FLAG_FREQUENCY, "frequency"),
+    (FLAG_DEADLINE, "deadline"),
+    (FLAG_FREEZE, "freeze"),
+    (FLAG_LATCH, "latch"),
+    (FLAG_LOW_BUS, "lowbus"),
+    (FLAG_CONFIG, "config"),
+    (FLAG_SCAN, "scan"),
+];
 
-    match f.read(&mut buffer) {
-        Ok(rsize) => {
-            eprintln!("/proc/cpuinfo read size: {}", rsize);
-            if rsize == 0 || rsize == 2048 {
-                return Err(ProfilingError::new(
-                    "Error reading cpu info, no bytes read or buffer full",
-                ));
-            }
-            rsize
+impl MMDCProfileResult {
+    /// Bandwidth in MB/s the bus could move at the DDR clock measured by the cycle counter
+    fn peak_bandwidth(&self, time: u32) -> f32 {
+        let cycles_per_second = self.total_cycles as f32 * 1000_f32 / time.max(1) as f32;
+        cycles_per_second * 2_f32 * (self.bus_width / 8) as f32 / (1024_f32 * 1024_f32)
+    }
+
+    /// Read, write and total bandwidth in MB/s over the measured time in milliseconds
+    fn bandwidth(&self, time: u32) -> (f32, f32, f32) {
+        let bandwidth =
+            Counters::from_raw(self.raw_counters).bandwidth(Duration::from_millis(time as u64));
+        (
+            bandwidth.read.get(),
+            bandwidth.write.get(),
+            bandwidth.total.get(),
+        )
+    }
+
+    /// Value of one of the METRIC_VARIABLES
+    fn variable(&self, name: &str, time: u32) -> f64 {
+        let (read, write, total) = self.bandwidth(time);
+        match name {
+            "time_ms" => time as f64,
+            "total_cycles" => self.total_cycles as f64,
+            "busy_cycles" => self.busy_cycles as f64,
+            "read_accesses" => self.read_accesses as f64,
+            "write_accesses" => self.write_accesses as f64,
+            "read_bytes" => self.read_bytes as f64,
+            "write_bytes" => self.write_bytes as f64,
+            "read_mbps" => read as f64,
+            "write_mbps" => write as f64,
+            "total_mbps" => total as f64,
+            "utilization" => self.utilization as f64,
+            "data_load" => self.data_load as f64,
+            _ => f64::NAN,
         }
-        Err(_) => return Err(ProfilingError::new("Error reading cpu info")),
-    };
+    }
+
+    fn evaluate_metrics(&mut self, metrics: &[(String, expr::Expr)], time: u32) {
+        self.metrics = metrics
+            .iter()
+            .map(|(name, expr)| {
+                let value = expr.eval(&|variable| self.variable(variable, time));
+                (name.clone(), value)
+            })
+            .collect();
+    }
+
+    /// Names of the set flags separated by commas
+    fn flag_names(&self) -> String {
+        let names: Vec<&str> = FLAG_NAMES
+            .iter()
+            .filter(|(flag, _)| self.flags & flag != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        names.join(",")
+    }
+
+    fn from_counters(counters: &Counters, bus_width: u32) -> MMDCProfileResult {
+        MMDCProfileResult {
+            total_cycles: counters.total_cycles.get(),
+            busy_cycles: counters.busy_cycles.get(),
+            read_accesses: counters.read_accesses.get(),
+            write_accesses: counters.write_accesses.get(),
+            read_bytes: counters.read_bytes.get(),
+            write_bytes: counters.write_bytes.get(),
+            read_beats: counters.read_bytes.beats(bus_width).get(),
+            write_beats: counters.write_bytes.beats(bus_width).get(),
+            raw_counters: counters.raw(),
+            bus_width,
+            data_load: counters.data_load().get(),
+            utilization: counters.utilization(bus_width).get(),
+            access_utilization: counters.access_utilization().get(),
+            avg_write_burstsize: counters.avg_write_burstsize().get(),
+            avg_read_burstsize: counters.avg_read_burstsize().get(),
+            master: None,
+            warmup: false,
+            range: None,
+            flags: 0,
+            metrics: Vec::new(),
+            columns: Vec::new(),
+            sequence: 0,
+            boottime_ms: 0,
+            suspects: None,
+            phase: None,
+        }
+    }
+
+    fn to_json(&self, time: u32, timestamp: u64) -> String {
+        self.json_object(time, timestamp).build()
+    }
+
+    fn json_object(&self, time: u32, timestamp: u64) -> json::JsonObject {
+        let (read, write, total) = self.bandwidth(time);
+        let object = json::JsonObject::new().number("schema_version", SCHEMA_VERSION);
+        let object = match self.master {
+            Some(master) => object.string("master", master),
+            None => object,
+        };
+        let object = match self.warmup {
+            true => object.boolean("warmup", true),
+            false => object,
+        };
+        let object = match &self.suspects {
+            Some(suspects) => object.string("suspects", suspects),
+            None => object,
+        };
+        let object = match &self.phase {
+            Some(phase) => object.string("phase", phase),
+            None => object,
+        };
+        let object = object
+            .number("seq", self.sequence)
+            .number("timestamp_ms", timestamp)
+            .number("boottime_ms", self.boottime_ms)
+            .number("time_ms", time)
+            .number("total_cycles", self.total_cycles)
+            .number("busy_cycles", self.busy_cycles)
+            .number("read_accesses", self.read_accesses)
+            .number("write_accesses", self.write_accesses)
+            .number("read_bytes", self.read_bytes)
+            .number("write_bytes", self.write_bytes)
+            .number("avg_read_burstsize", self.avg_read_burstsize)
+            .number("avg_write_burstsize", self.avg_write_burstsize)
+            .float("read_mbps", read)
+            .float("write_mbps", write)
+            .float("total_mbps", total)
+            .number("utilization", self.utilization)
+            .number("data_load", self.data_load)
+            .number("access_utilization", self.access_utilization)
+            .string("flags", &self.flag_names());
+        let object = self.metrics.iter().fold(object, |object, (name, value)| {
+            object.float(name, *value as f32)
+        });
+        let object = self
+            .columns
+            .iter()
+            .fold(object, |object, (name, value)| object.number(name, value));
+        match self.range {
+            Some(range) => object
+                .float("min_total_mbps", range.min_mbps)
+                .float("max_total_mbps", range.max_mbps)
+                .number("min_utilization", range.min_utilization)
+                .number("max_utilization", range.max_utilization),
+            None => object,
+        }
+    }
 
-    let read_string = String::from_utf8_lossy(&buffer);
+    /// InfluxDB line protocol with a nanosecond timestamp
+    fn to_influx(&self, time: u32, channel: u32, timestamp: u64) -> String {
+        let (read, write, total) = self.bandwidth(time);
+        let mut tags = format!("mmdc,channel=p{}", channel);
+        if let Some(master) = self.master {
+            tags.push_str(&format!(",master={}", master));
+        }
+        if self.warmup {
+            tags.push_str(",warmup=true");
+        }
+        if let Some(phase) = &self.phase {
+            tags.push_str(&format!(",phase={}", phase));
+        }
+        let mut fields = vec![
+            format!("schema_version={}i", SCHEMA_VERSION),
+            format!("seq={}i", self.sequence),
+            format!("boottime_ms={}i", self.boottime_ms),
+            format!("time_ms={}i", time),
+            format!("total_cycles={}i", self.total_cycles),
+            format!("busy_cycles={}i", self.busy_cycles),
+            format!("read_accesses={}i", self.read_accesses),
+            format!("write_accesses={}i", self.write_accesses),
+            format!("read_bytes={}i", self.read_bytes),
+            format!("write_bytes={}i", self.write_bytes),
+            format!("read_mbps={:.2}", read),
+            format!("write_mbps={:.2}", write),
+            format!("total_mbps={:.2}", total),
+            format!("utilization={}i", self.utilization),
+            format!("data_load={}i", self.data_load),
+            format!("flags={}i", self.flags),
+        ];
+        for (name, value) in self.metrics.iter().filter(|(_, value)| value.is_finite()) {
+            fields.push(format!("{}={}", name, value));
+        }
+        for (name, value) in self.columns.iter() {
+            fields.push(format!("{}={}i", name, value));
+        }
+        format!(
+            "{} {} {}",
+            tags,
+            fields.join(","),
+            timestamp as u128 * 1_000_000
+        )
+    }
+}
+
+fn get_system_revision() -> Result<u32, ProfilingError> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo")
+        .map_err(|e| ProfilingError::new(&format!("Error reading /proc/cpuinfo: {}", e)))?;
     //find Revision: <something in string>
     let re = Regex::new(r"Revision\s*:\s*([a-fA-F0-9]+)").unwrap(); //lotso unwraping, it's like christmas
-    let revision_string = &(re.captures(&read_string).unwrap())[1];
-    let revision = u32::from_str_radix(revision_string, 16).unwrap();
-    eprintln!("CPU Revision is {:X?}", revision);
-
+    let revision_string = match re.captures(&cpuinfo) {
+        Some(captures) => captures[1].to_string(),
+        None => return Err(ProfilingError::new("No revision found in cpu info")),
+    };
+    let revision = match u32::from_str_radix(&revision_string, 16) {
+        Ok(revision) => revision,
+        Err(_) => return Err(ProfilingError::new("Invalid revision in cpu info")),
+    };
     if revision == 0u32 {
-        let mut sbuffer = [0_u8; 2048]; // just to be sure, prevent strange behaviour by buffer reusage
-        let mut soc_file = match File::open("TODO: /sys/devices/soc0/soc_id") {
-            Ok(file) => file,
-            Err(_) => {
-                return Err(ProfilingError::new(
-                    "Error opening /sys/devices/soc0/soc_id",
-                ))
-            }
-        };
+        return Err(ProfilingError::new("Zero revision in cpu info"));
+    }
+    Ok(revision)
+}
+
+const SOC_ID_PATH: &str = "/sys/devices/soc0/soc_id";
+const SOC_REVISION_PATH: &str = "/sys/devices/soc0/revision";
+const DT_COMPATIBLE_PATH: &str = "/proc/device-tree/compatible";
+
+/// Silicon revision as major.minor, from the SoC bus driver or else the low byte of the
+/// system revision in /proc/cpuinfo
+fn soc_revision() -> Option<String> {
+    if let Ok(revision) = std::fs::read_to_string(SOC_REVISION_PATH) {
+        return Some(revision.trim().to_string());
+    }
+    let revision = get_system_revision().ok()?;
+    Some(format!("{}.{}", (revision >> 4) & 0xF, revision & 0xF))
+}
+
+/// Looks up the SoC profile by the soc_id of the SoC bus driver
+fn detect_soc_by_id() -> Result<&'static SocProfile, ProfilingError> {
+    let soc_id = std::fs::read_to_string(SOC_ID_PATH)
+        .map_err(|e| ProfilingError::new(&format!("Error reading {}: {}", SOC_ID_PATH, e)))?;
+    let soc_id = soc_id.trim();
+    soc::SOCS
+        .iter()
+        .find(|soc| soc.soc_ids.contains(&soc_id))
+        .ok_or_else(|| ProfilingError::new(&format!("Unknown soc_id {}", soc_id)))
+}
+
+/// Looks up the SoC profile by the device tree root compatible strings, most specific first
+fn detect_soc_by_compatible() -> Result<&'static SocProfile, ProfilingError> {
+    let compatible = std::fs::read(DT_COMPATIBLE_PATH).map_err(|e| {
+        ProfilingError::new(&format!("Error reading {}: {}", DT_COMPATIBLE_PATH, e))
+    })?;
+    let compatible = String::from_utf8_lossy(&compatible);
+    let entries: Vec<&str> = compatible.split('\0').filter(|c| !c.is_empty()).collect();
+    entries
+        .iter()
+        .find_map(|entry| soc::SOCS.iter().find(|soc| soc.compatible.contains(entry)))
+        .ok_or_else(|| ProfilingError::new(&format!("Unknown compatible {}", entries.join(", "))))
+}
+
+/// Looks up the SoC profile by the MXC CPU type of the system revision
+fn detect_soc_by_revision() -> Result<&'static SocProfile, ProfilingError> {
+    let revision = get_system_revision()?;
+    let cpu_type = revision >> 12;
+    soc::SOCS
+        .iter()
+        .find(|soc| soc.cpu_types.contains(&cpu_type) && revision & 0xFFF >= soc.min_revision)
+        .ok_or_else(|| ProfilingError::new(&format!("Unknown CPU type 0x{:X}", cpu_type)))
+}
 
-        match soc_file.read(&mut sbuffer) {
-            Ok(rsize) => {
-                eprintln!("/sys/devices/soc0/soc_id read size: {}", rsize);
-                if rsize == 0 || rsize == 2048 {
-                    return Err(ProfilingError::new(
-                        "Error reading soc id, no bytes read or buffer full",
-                    ));
-                }
+type SocDetection = fn() -> Result<&'static SocProfile, ProfilingError>;
+
+/// Tries soc_id, the device tree and /proc/cpuinfo in this order, returns the SoC with the
+/// source it was detected from
+fn detect_soc() -> Result<(&'static SocProfile, &'static str), ProfilingError> {
+    let sources: [(&str, SocDetection); 3] = [
+        ("soc_id", detect_soc_by_id),
+        ("device tree", detect_soc_by_compatible),
+        ("cpuinfo", detect_soc_by_revision),
+    ];
+    let mut errors = Vec::new();
+    for (source, detect) in sources.iter() {
+        match detect() {
+            Ok(soc) => return Ok((soc, source)),
+            Err(e) => errors.push(format!("{}: {}", source, e)),
+        }
+    }
+    Err(ProfilingError::new(&format!(
+        "SoC detection failed ({})",
+        errors.join("; ")
+    )))
+}
+
+/// SoC given with --soc or detected, None if detection failed
+fn resolve_soc(opt: &Opt) -> Option<&'static SocProfile> {
+    match &opt.soc {
+        Some(name) => soc::find(name),
+        None => match detect_soc() {
+            Ok((soc, source)) => {
+                eprintln!("Detected {} from {}", soc.description, source);
+                Some(soc)
             }
-            Err(_) => return Err(ProfilingError::new("Error reading cpu info")),
-        };
-        let soc_id: String = String::from_utf8_lossy(&sbuffer).to_string();
-        eprintln!("Read soc id {}", soc_id);
-        return if soc_id.starts_with("i.MX6Q") {
-            Ok(0x63000u32)
-        } else if soc_id.starts_with("i.MX6DL") {
-            Ok(0x61000u32)
-        } else if soc_id.starts_with("i.MX6SL") {
-            Ok(0x60000u32)
-        } else {
-            Err(ProfilingError::new("Unknown soc id2"))
-        };
+            Err(e) => {
+                eprintln!("{}, falling back to i.MX6Q defaults", e);
+                None
+            }
+        },
     }
-    Err(ProfilingError::new("Unknown soc id"))
 }
 
-fn print_profiling_results(profiling_result: &MMDCProfileResult, time: u32, opt: &Opt) {
-    let avg_read: f32 =
-        profiling_result.write_bytes as f32 * 1000_f32 / (1024_f32 * 1024_f32 * time as f32);
-    let avg_write: f32 =
-        profiling_result.write_bytes as f32 * 1000_f32 / (1024_f32 * 1024_f32 * time as f32);
-    let total: f32 = (profiling_result.write_bytes as f32 + profiling_result.read_bytes as f32)
-        * 1000_f32
-        / (1024_f32 * 1024_f32 * time as f32);
-    if opt.formatted {
-        println!(
+/// Base address of the profiled channel, given with --base or taken from the SoC profile
+fn resolve_base(opt: &Opt, soc: Option<&SocProfile>) -> u64 {
+    if let Some(base) = opt.base {
+        return base;
+    }
+    let bases = soc.map_or(&[MMDC_P0_IPS_BASE_ADDR, MMDC_P1_IPS_BASE_ADDR][..], |soc| {
+        soc.base_addresses
+    });
+    match bases.get(opt.channel as usize) {
+        Some(base) => *base,
+        None => panic!(
+            "{} has {} MMDC channel(s), channel {} is not available",
+            soc.map_or("SoC", |soc| soc.description),
+            bases.len(),
+            opt.channel
+        ),
+    }
+}
+
+/// Bus width given with --bus-width or read from MDCTL, limited to what the SoC supports
+fn resolve_bus_width(mmdc: &MMDC, opt: &Opt, soc: Option<&SocProfile>) -> u32 {
+    if let Some(bus_width) = opt.bus_width {
+        return bus_width;
+    }
+    let bus_width = ddr::DdrGeometry::from_registers(mmdc).bus_width;
+    match soc {
+        Some(soc) if !soc.bus_widths.contains(&bus_width) => {
+            let supported = soc.bus_widths.iter().max().copied().unwrap_or(bus_width);
+            eprintln!(
+                "WARNING: MDCTL reports a {}-bit bus which the {} doesn't support, using {}-bit",
+                bus_width, soc.description, supported
+            );
+            supported
+        }
+        _ => bus_width,
+    }
+}
+
+fn print_profiling_results(
+    out: &mut dyn Write,
+    profiling_result: &MMDCProfileResult,
+    time: u32,
+    opt: &Opt,
+    format: Format,
+) -> io::Result<()> {
+    let timestamp = get_tick_count() as u64;
+    print_record_at(out, profiling_result, time, timestamp, opt, format)
+}
+
+/// Prints a record with the given wall-clock timestamp, e.g. a recorded one
+fn print_record_at(
+    out: &mut dyn Write,
+    profiling_result: &MMDCProfileResult,
+    time: u32,
+    timestamp: u64,
+    opt: &Opt,
+    format: Format,
+) -> io::Result<()> {
+    let (avg_read, avg_write, total) = profiling_result.bandwidth(time);
+    match format {
+        Format::Json => {
+            let object = profiling_result.json_object(time, timestamp);
+            return writeln!(out, "{}", object.build_pretty());
+        }
+        Format::Jsonl => return writeln!(out, "{}", profiling_result.to_json(time, timestamp)),
+        Format::Influx => {
+            let line = profiling_result.to_influx(time, opt.channel, timestamp);
+            return writeln!(out, "{}", line);
+        }
+        Format::Human | Format::Csv => {}
+    }
+    if format == Format::Csv {
+        // long format, rows of rotated masters are keyed by channel and master up front
+        // warm-up rows are commented out so they don't end up in the statistics
+        if profiling_result.warmup {
+            write!(out, "# warmup;")?;
+        }
+        if let Some(master) = profiling_result.master {
+            write!(out, "p{};{};", opt.channel, master)?;
+        }
+        write!(
+            out,
             "{};{};{};{};{};{};{};{};{};{:.2};{:.2};{:.2};{};{};{}",
             time,
             profiling_result.total_cycles,
@@ -263,68 +608,162 @@ fn print_profiling_results(profiling_result: &MMDCProfileResult, time: u32, opt:
             profiling_result.utilization,
             profiling_result.data_load,
             profiling_result.access_utilization
-        )
+        )?;
+        if opt.beats {
+            write!(
+                out,
+                ";{};{}",
+                profiling_result.read_beats, profiling_result.write_beats
+            )?;
+        }
+        if opt.raw {
+            for counter in profiling_result.raw_counters.iter() {
+                write!(out, ";{}", counter)?;
+            }
+        }
+        if opt.flags {
+            write!(out, ";{}", profiling_result.flag_names())?;
+        }
+        for (_, value) in profiling_result.metrics.iter() {
+            write!(out, ";{:.4}", value)?;
+        }
+        for (_, value) in profiling_result.columns.iter() {
+            write!(out, ";{}", value)?;
+        }
+        if let Some(range) = profiling_result.range {
+            write!(
+                out,
+                ";{:.2};{:.2};{};{}",
+                range.min_mbps, range.max_mbps, range.min_utilization, range.max_utilization
+            )?;
+        }
+        if opt.timestamps {
+            write!(
+                out,
+                ";{};{};{}",
+                timestamp, profiling_result.boottime_ms, profiling_result.sequence
+            )?;
+        }
+        writeln!(out)?;
     } else {
-        println!("MMDC new Profiling results:");
-        println!("***********************");
-        println!("Measure time: {}ms", time);
-        println!("Total cycles count: {}", profiling_result.total_cycles);
-        println!("Busy cycles count: {}", profiling_result.busy_cycles);
-        println!("Read accesses count: {}", profiling_result.read_accesses);
-        println!("Write accesses count: {}", profiling_result.write_accesses);
-        println!("Read bytes count: {}", profiling_result.read_bytes);
-        println!("Write bytes count: {}", profiling_result.write_bytes);
-        println!(
+        if profiling_result.warmup {
+            writeln!(out, "MMDC warm-up Profiling results:")?;
+        } else {
+            writeln!(out, "MMDC new Profiling results:")?;
+        }
+        writeln!(out, "***********************")?;
+        if let Some(master) = profiling_result.master {
+            writeln!(out, "Master: {}", master)?;
+        }
+        writeln!(out, "Measure time: {}ms", time)?;
+        writeln!(out, "Total cycles count: {}", profiling_result.total_cycles)?;
+        writeln!(out, "Busy cycles count: {}", profiling_result.busy_cycles)?;
+        writeln!(
+            out,
+            "Read accesses count: {}",
+            profiling_result.read_accesses
+        )?;
+        writeln!(
+            out,
+            "Write accesses count: {}",
+            profiling_result.write_accesses
+        )?;
+        writeln!(out, "Read bytes count: {}", profiling_result.read_bytes)?;
+        writeln!(out, "Write bytes count: {}", profiling_result.write_bytes)?;
+        if opt.beats {
+            writeln!(out, "Read beats count: {}", profiling_result.read_beats)?;
+            writeln!(out, "Write beats count: {}", profiling_result.write_beats)?;
+        }
+        writeln!(
+            out,
             "Avg. Read burst size: {}",
             profiling_result.avg_read_burstsize
-        );
-        println!(
+        )?;
+        writeln!(
+            out,
             "Avg. Write burst size: {}",
             profiling_result.avg_write_burstsize
-        );
+        )?;
 
-        println!(
-            "Read: {:.2} MB/s /  Write: {:.2} MB/s  Total: {:.2} MB/s",
-            avg_read, avg_write, total
-        );
-        println!("");
+        writeln!(
+            out,
+            "Read: {:.2} MB/s /  Write: {:.2} MB/s  Total: {:.2} MB/s {}",
+            avg_read,
+            avg_write,
+            total,
+            sparkline(total / profiling_result.peak_bandwidth(time))
+        )?;
+        writeln!(out)?;
 
-        println!("Utilization: {}", profiling_result.utilization);
-        println!("Bus Load: {}", profiling_result.data_load);
-        println!("Bytes Access: {}", profiling_result.access_utilization);
+        writeln!(out, "Utilization: {}", profiling_result.utilization)?;
+        writeln!(out, "Bus Load: {}", profiling_result.data_load)?;
+        writeln!(out, "Bytes Access: {}", profiling_result.access_utilization)?;
+        if profiling_result.flags != 0 {
+            writeln!(out, "Flags: {}", profiling_result.flag_names())?;
+        }
+        if let Some(suspects) = &profiling_result.suspects {
+            writeln!(out, "Suspects: {}", suspects)?;
+        }
+        if let Some(phase) = &profiling_result.phase {
+            writeln!(out, "Phase: {}", phase)?;
+        }
+        if profiling_result.flags & FLAG_LOW_BUS != 0 {
+            writeln!(
+                out,
+                "DDR clock: {:.0}MHz (low bus frequency)",
+                profiling_result.total_cycles as f32 / time as f32 / 1000_f32
+            )?;
+        }
+        for (name, value) in profiling_result.metrics.iter() {
+            writeln!(out, "{}: {:.4}", name, value)?;
+        }
+        for (name, value) in profiling_result.columns.iter() {
+            writeln!(out, "{}: {}", name, value)?;
+        }
+        if let Some(range) = profiling_result.range {
+            writeln!(
+                out,
+                "Total min/max: {:.2} MB/s / {:.2} MB/s",
+                range.min_mbps, range.max_mbps
+            )?;
+            writeln!(
+                out,
+                "Utilization min/max: {} / {}",
+                range.min_utilization, range.max_utilization
+            )?;
+        }
+
+        if opt.raw {
+            for (index, counter) in profiling_result.raw_counters.iter().enumerate() {
+                writeln!(out, "MADPSR{}: 0x{:08X}", index, counter)?;
+            }
+        }
     }
+    Ok(())
 }
 
-fn get_mmdc_profiling_results(mmdc: &MMDC) -> MMDCProfileResult {
-    let mut result = MMDCProfileResult::default();
-
-    result.total_cycles = mmdc.madpsr0;
-    result.busy_cycles = mmdc.madpsr1;
-    result.read_accesses = mmdc.madpsr2;
-    result.write_accesses = mmdc.madpsr3;
-    result.read_bytes = mmdc.madpsr4;
-    result.write_bytes = mmdc.madpsr5;
+/// Width of the bandwidth bar of the human output in characters
+const SPARKLINE_WIDTH: usize = 16;
 
-    if result.read_bytes != 0 || result.write_bytes != 0 {
-        result.utilization = ((result.read_bytes as f32 + result.write_bytes as f32)
-            / (result.busy_cycles as f32 * 16_f32)
-            * 100_f32) as u32;
-        result.data_load =
-            (result.busy_cycles as f32 / result.total_cycles as f32 * 100_f32) as u32;
-        result.access_utilization = ((result.read_bytes as f32 + result.write_bytes as f32)
-            / (result.read_accesses as f32 + result.write_accesses as f32))
-            as u32;
+/// Bar of `fraction` of the full width with eighth character resolution
+fn sparkline(fraction: f32) -> String {
+    const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let fraction = if fraction.is_finite() {
+        fraction.clamp(0_f32, 1_f32)
+    } else {
+        0_f32
+    };
+    let eighths = (fraction * (SPARKLINE_WIDTH * 8) as f32).round() as usize;
+    let mut bar = "█".repeat(eighths / 8);
+    match eighths % 8 {
+        0 => {}
+        partial => bar.push(PARTIAL[partial]),
     }
+    format!("|{:<width$}|", bar, width = SPARKLINE_WIDTH)
+}
 
-    if mmdc.madpsr3 > 0 {
-        result.avg_write_burstsize = mmdc.madpsr5 / mmdc.madpsr3;
-    } //no else branch needed, default 0
-
-    if mmdc.madpsr2 > 0 {
-        result.avg_read_burstsize = mmdc.madpsr4 / mmdc.madpsr2;
-    } //no else branch needed, default 0
-
-    result
+fn get_mmdc_profiling_results(mmdc: &MMDC, bus_width: u32) -> MMDCProfileResult {
+    MMDCProfileResult::from_counters(&Counters::from_registers(mmdc), bus_width)
 }
 
 fn get_tick_count() -> u128 {
@@ -335,7 +774,7 @@ fn get_tick_count() -> u128 {
 }
 
 fn clear_mmdc(mmdc: &mut MMDC) {
-    mmdc.madpcr0 = 0xA; // Reset counters and clear Overflow bit
+    mmdc.madpcr0 = DBG_RST | CYC_OVF; // Reset counters and clear Overflow bit
     unsafe {
         let _ = msync(&mut mmdc.madpcr0 as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
     }
@@ -343,19 +782,34 @@ fn clear_mmdc(mmdc: &mut MMDC) {
 
 fn start_mmdc_profiling(mmdc: &mut MMDC) {
     unsafe {
-        mmdc.madpcr0 = 0xA; // Reset counters and clear Overflow bit
+        mmdc.madpcr0 = DBG_RST | CYC_OVF; // Reset counters and clear Overflow bit
         let _ = msync(&mut mmdc.madpcr0 as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
 
-        mmdc.madpcr0 = 0x1; // Enable counters
+        mmdc.madpcr0 = DBG_EN; // Enable counters
         let _ = msync(&mut mmdc.madpcr0 as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
     }
 }
 
-fn load_mmdc_results(mmdc: &mut MMDC) {
-    mmdc.madpcr0 |= 0x4; //sets the PRF_FRZ bit to 1 in order to load the results into the registers
+/// Longest wait for PRF_FRZ to read back set after writing it
+const FREEZE_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Freezes the counters and returns whether the freeze latched, i.e. PRF_FRZ reads back
+/// set within FREEZE_TIMEOUT. On a heavily loaded bus the write may land late and the
+/// counters be read while still counting.
+fn load_mmdc_results(mmdc: &mut MMDC) -> bool {
+    mmdc.madpcr0 |= PRF_FRZ; //sets the PRF_FRZ bit to 1 in order to load the results into the registers
     unsafe {
         let _ = msync(&mut mmdc.madpcr0 as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
     }
+    let start = Instant::now();
+    // the hardware updates the register behind the compiler's back
+    while unsafe { ptr::read_volatile(&mmdc.madpcr0) } & PRF_FRZ == 0 {
+        if start.elapsed() > FREEZE_TIMEOUT {
+            return false;
+        }
+        std::hint::spin_loop();
+    }
+    true
 }
 
 fn stop_mmdc_profiling(mmdc: &mut MMDC) {
@@ -365,21 +819,531 @@ fn stop_mmdc_profiling(mmdc: &mut MMDC) {
     }
 }
 
-fn do_measuring_cylce(mmdc: &mut MMDC, opt: &Opt) {
-    clear_mmdc(mmdc);
-    let start_time = get_tick_count();
-    start_mmdc_profiling(mmdc);
-    thread::sleep(std::time::Duration::from_millis(opt.sleeptime));
-    load_mmdc_results(mmdc);
-    let results = get_mmdc_profiling_results(mmdc);
-    print_profiling_results(&results, (get_tick_count() - start_time) as u32, opt);
-    stop_mmdc_profiling(mmdc);
+/// Reads the given clock, CLOCK_MONOTONIC stops while suspended, CLOCK_BOOTTIME doesn't
+fn clock_time(clock: nix::libc::clockid_t) -> Duration {
+    let mut now = nix::libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        nix::libc::clock_gettime(clock, &mut now);
+    }
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
+
+/// Time spent suspended since boot
+fn suspended_time() -> Duration {
+    clock_time(nix::libc::CLOCK_BOOTTIME).saturating_sub(clock_time(nix::libc::CLOCK_MONOTONIC))
+}
+
+/// Suspends shorter than this are taken as clock jitter
+const SUSPEND_THRESHOLD: Duration = Duration::from_millis(10);
+
+/// Runs one measuring window of the --schedule policy, without its pauses, and hands its
+/// results back to the policy. Commands build the scheduler once, so the stateful policies
+/// carry on from one window to the next.
+fn do_measuring_cylce(
+    mmdc: &mut MMDC,
+    bus_width: u32,
+    overhead: Duration,
+    scheduler: &mut dyn schedule::Scheduler,
+) -> (MMDCProfileResult, u32) {
+    let (results, time) = measure_window(mmdc, bus_width, overhead, scheduler);
+    scheduler.observe(&results, time);
+    (results, time)
+}
+
+/// Runs one measuring window and returns its results along with the measured time.
+/// Windows the system was suspended in are discarded and measured again, the
+/// counters stop while suspended so their rates would be far off.
+fn measure_window(
+    mmdc: &mut MMDC,
+    bus_width: u32,
+    overhead: Duration,
+    scheduler: &mut dyn schedule::Scheduler,
+) -> (MMDCProfileResult, u32) {
+    loop {
+        let suspended_before = suspended_time();
+        clear_mmdc(mmdc);
+        let start_time = Instant::now();
+        start_mmdc_profiling(mmdc);
+        let window = scheduler.window();
+        thread::sleep(window);
+        let latched = load_mmdc_results(mmdc);
+        // the register traffic around the window is not part of the counted time
+        let elapsed = start_time.elapsed().saturating_sub(overhead);
+        let time = ((elapsed.as_micros() + 500) / 1000).max(1) as u32;
+        let mut results = get_mmdc_profiling_results(mmdc, bus_width);
+        results.boottime_ms = clock_time(nix::libc::CLOCK_BOOTTIME).as_millis() as u64;
+        // the registers are read again, volatile so the loads aren't folded into the first
+        if unsafe { ptr::read_volatile(&mmdc.madpcr0) } & CYC_OVF != 0 {
+            results.flags |= FLAG_OVERFLOW;
+        }
+        // frozen counters must not move anymore
+        if unsafe { ptr::read_volatile(&mmdc.madpsr0) } != results.total_cycles {
+            results.flags |= FLAG_FREEZE;
+        }
+        if elapsed > window + (window / 10).max(DEADLINE_SLACK) {
+            results.flags |= FLAG_DEADLINE;
+        }
+        if !latched {
+            warn_unlatched();
+            results.flags |= FLAG_LATCH;
+        }
+        stop_mmdc_profiling(mmdc);
+
+        let suspended = suspended_time().saturating_sub(suspended_before);
+        if suspended < SUSPEND_THRESHOLD {
+            return (results, time);
+        }
+        eprintln!(
+            "WARNING: system was suspended for {}ms during the window, discarding the sample",
+            suspended.as_millis()
+        );
+    }
+}
+
+/// Windows of `--gap-free`, the counters keep running from one window into the next
+/// so consecutive windows tile the timeline
+struct GapFreeWindows {
+    interval: Duration,
+    start: Instant,
+    deadline: Instant,
+    suspended_before: Duration,
+}
+
+impl GapFreeWindows {
+    fn arm(mmdc: &mut MMDC, opt: &Opt) -> GapFreeWindows {
+        let interval = Duration::from_millis(opt.sleeptime);
+        let suspended_before = suspended_time();
+        start_mmdc_profiling(mmdc);
+        let start = Instant::now();
+        let window = if schedule::name(opt) == "aligned" {
+            until_boundary(interval)
+        } else {
+            interval
+        };
+        GapFreeWindows {
+            interval,
+            start,
+            deadline: start + window,
+            suspended_before,
+        }
+    }
+
+    /// Ends the current window on its deadline and re-arms the counters right away, only
+    /// the traffic during the freeze, read and reset of the counters goes uncounted.
+    /// The deadlines follow a fixed schedule so the time spent on the output is absorbed
+    /// by the sleep of the next window.
+    fn next(&mut self, mmdc: &mut MMDC, opt: &Opt, bus_width: u32) -> (MMDCProfileResult, u32) {
+        loop {
+            if Instant::now() > self.deadline + self.interval {
+                // paused in between, e.g. by a gate, don't report a window spanning the pause
+                *self = GapFreeWindows::arm(mmdc, opt);
+            }
+            thread::sleep(self.deadline.saturating_duration_since(Instant::now()));
+            let latched = load_mmdc_results(mmdc);
+            let end = Instant::now();
+            let mut results = get_mmdc_profiling_results(mmdc, bus_width);
+            let overflow = unsafe { ptr::read_volatile(&mmdc.madpcr0) } & CYC_OVF != 0;
+            let frozen = unsafe { ptr::read_volatile(&mmdc.madpsr0) } == results.total_cycles;
+            start_mmdc_profiling(mmdc);
+            let suspended_after = suspended_time();
+
+            let elapsed = end - self.start;
+            let window = self.deadline - self.start;
+            self.start = end;
+            self.deadline += self.interval;
+            let suspended = suspended_after.saturating_sub(self.suspended_before);
+            self.suspended_before = suspended_after;
+
+            let time = ((elapsed.as_micros() + 500) / 1000).max(1) as u32;
+            results.boottime_ms = clock_time(nix::libc::CLOCK_BOOTTIME).as_millis() as u64;
+            if overflow {
+                results.flags |= FLAG_OVERFLOW;
+            }
+            if !frozen {
+                results.flags |= FLAG_FREEZE;
+            }
+            if elapsed > window + (window / 10).max(DEADLINE_SLACK) {
+                results.flags |= FLAG_DEADLINE;
+            }
+            if !latched {
+                warn_unlatched();
+                results.flags |= FLAG_LATCH;
+            }
+            if suspended < SUSPEND_THRESHOLD {
+                return (results, time);
+            }
+            eprintln!(
+                "WARNING: system was suspended for {}ms during the window, discarding the sample",
+                suspended.as_millis()
+            );
+        }
+    }
+}
+
+fn warn_unlatched() {
+    eprintln!(
+        "WARNING: PRF_FRZ didn't latch within {}us, the sample may be inconsistent",
+        FREEZE_TIMEOUT.as_micros()
+    );
+}
+
+/// Delay past the window after which a sample is flagged as late
+const DEADLINE_SLACK: Duration = Duration::from_millis(10);
+
+/// Change of the cycle rate against the previous window above which it's flagged, in percent
+const FREQUENCY_TOLERANCE: f32 = 5_f32;
+
+/// Flags a DDR frequency change by the total cycles per millisecond moving away from
+/// those of the previous window
+fn flag_frequency_change(results: &mut MMDCProfileResult, time: u32, previous_rate: &mut f32) {
+    let rate = results.total_cycles as f32 / time as f32;
+    if *previous_rate > 0_f32
+        && ((rate - *previous_rate) / *previous_rate * 100_f32).abs() > FREQUENCY_TOLERANCE
+    {
+        results.flags |= FLAG_FREQUENCY;
+    }
+    *previous_rate = rate;
+}
+
+/// Cycle rates below this share of the nominal DDR clock are the low bus frequency, e.g.
+/// 24 or 50 MHz instead of 400 or 528 MHz
+const LOW_BUS_RATIO: f32 = 0.25;
+
+/// Flags a window the DDR ran at the low bus frequency in. The clock is the one measured
+/// by the cycle counter, so the peak bandwidth of such windows is already scaled down.
+/// Without --ddr-clock or a detectable clock the fastest window of the run is nominal.
+fn flag_low_bus(results: &mut MMDCProfileResult, time: u32, nominal_rate: &mut f32) {
+    let rate = results.total_cycles as f32 / time as f32;
+    *nominal_rate = nominal_rate.max(rate);
+    if rate < *nominal_rate * LOW_BUS_RATIO {
+        results.flags |= FLAG_LOW_BUS;
+    }
+}
+
+/// Time left until the next wall-clock multiple of `interval`
+fn until_boundary(interval: Duration) -> Duration {
+    let now = clock_time(nix::libc::CLOCK_REALTIME).as_nanos();
+    let interval = interval.as_nanos().max(1);
+    Duration::from_nanos((interval - now % interval) as u64)
+}
+
+/// Rounds of the register sequence averaged by `measure_overhead`
+const OVERHEAD_ROUNDS: u32 = 16;
+
+/// Latency of the clear/start/freeze/read/stop register sequence of a measuring cycle
+fn measure_overhead(mmdc: &mut MMDC, bus_width: u32) -> Duration {
+    let start_time = Instant::now();
+    for _ in 0..OVERHEAD_ROUNDS {
+        clear_mmdc(mmdc);
+        start_mmdc_profiling(mmdc);
+        load_mmdc_results(mmdc);
+        get_mmdc_profiling_results(mmdc, bus_width);
+        stop_mmdc_profiling(mmdc);
+    }
+    start_time.elapsed() / OVERHEAD_ROUNDS
+}
+
+fn write_record(
+    out: &mut output::Output,
+    results: &MMDCProfileResult,
+    time: u32,
+    opt: &Opt,
+) -> io::Result<()> {
+    let record = Record {
+        results,
+        time,
+        emitted: true,
+    };
+    out.begin_batch()?;
+    sink::FormatEncoder::new(&mut *out, opt.output_format(), opt).on_sample(&record)?;
+    out.end_batch()?;
+    if !opt.no_flush {
+        out.flush()?;
+    }
+    if opt.also_human && opt.output_format() != Format::Human {
+        sink::FormatEncoder::new(io::stderr().lock(), Format::Human, opt).on_sample(&record)?;
+    }
+    Ok(())
+}
+
+fn open_cpufreq(opt: &Opt) -> Option<cpufreq::CpuFreq> {
+    if !opt.cpufreq {
+        return None;
+    }
+    Some(cpufreq::CpuFreq::open().unwrap_or_else(|e| panic!("couldn't read cpufreq: {}", e)))
+}
+
+fn open_gpu_load(opt: &Opt) -> Option<galcore::GpuLoad> {
+    if !opt.gpu_load {
+        return None;
+    }
+    Some(galcore::GpuLoad::open().unwrap_or_else(|e| {
+        panic!(
+            "couldn't read the galcore statistics, is debugfs mounted? {}",
+            e
+        )
+    }))
+}
+
+fn open_interrupts(opt: &Opt) -> Option<interrupts::Interrupts> {
+    if opt.irq.is_empty() {
+        return None;
+    }
+    Some(
+        interrupts::Interrupts::open(&opt.irq)
+            .unwrap_or_else(|e| panic!("couldn't select interrupts: {}", e)),
+    )
+}
+
+#[cfg(feature = "perf")]
+fn open_pmu(opt: &Opt) -> Option<pmu::CpuPmu> {
+    if !opt.pmu {
+        return None;
+    }
+    Some(pmu::CpuPmu::open().unwrap_or_else(|e| panic!("couldn't open the CPU PMU: {}", e)))
+}
+
+fn open_l2_events(opt: &Opt, soc: Option<&SocProfile>) -> Option<pl310::L2Events> {
+    if opt.l2.is_empty() {
+        return None;
+    }
+    let base = soc
+        .map_or(Some(PL310_BASE_ADDR), |soc| soc.l2_cache_base)
+        .unwrap_or_else(|| {
+            panic!(
+                "{} has no PL310 L2 cache controller",
+                soc.map_or("SoC", |soc| soc.description)
+            )
+        });
+    Some(
+        pl310::L2Events::open(base, &opt.l2)
+            .unwrap_or_else(|e| panic!("couldn't open the PL310 event counters: {}", e)),
+    )
+}
+
+fn open_memory_pressure(opt: &Opt) -> Option<memory::MemoryPressure> {
+    if !opt.mem_pressure {
+        return None;
+    }
+    Some(
+        memory::MemoryPressure::open()
+            .unwrap_or_else(|e| panic!("couldn't read the memory statistics: {}", e)),
+    )
+}
+
+/// Start/stop gate of --control-fifo, --gpio or --signals
+fn open_gate(opt: &Opt) -> Option<control::Gate> {
+    if let Some(path) = &opt.control_fifo {
+        let gate = control::Gate::from_fifo(path);
+        return Some(
+            gate.unwrap_or_else(|e| {
+                panic!("couldn't create control FIFO {}: {}", path.display(), e)
+            }),
+        );
+    }
+    if let Some(line) = opt.gpio {
+        let gate = control::Gate::from_gpio(line);
+        return Some(gate.unwrap_or_else(|e| panic!("couldn't set up GPIO {}: {}", line, e)));
+    }
+    if opt.signals {
+        return Some(control::Gate::from_signals());
+    }
+    None
+}
+
+/// Phase markers of --marker-fifo, or of the control FIFO of the gate
+fn open_markers(opt: &Opt, gate: Option<&control::Gate>) -> Option<control::Markers> {
+    if let Some(path) = &opt.marker_fifo {
+        let markers = control::Markers::from_fifo(path);
+        return Some(
+            markers.unwrap_or_else(|e| {
+                panic!("couldn't create marker FIFO {}: {}", path.display(), e)
+            }),
+        );
+    }
+    opt.control_fifo
+        .as_ref()
+        .and(gate)
+        .map(control::Gate::markers)
+}
+
+/// Marks the start of a phase in the output, JSON records carry it instead
+fn write_marker(
+    out: &mut output::Output,
+    phase: &str,
+    timestamp: u64,
+    opt: &Opt,
+) -> io::Result<()> {
+    match opt.output_format() {
+        Format::Csv => writeln!(out, "# marker={}", phase)?,
+        Format::Human => writeln!(out, "Marker {}", phase)?,
+        Format::Jsonl => {
+            let marker = json::JsonObject::new()
+                .string("record", "marker")
+                .string("phase", phase)
+                .number("timestamp_ms", timestamp);
+            writeln!(out, "{}", marker.build())?
+        }
+        Format::Json | Format::Influx => {}
+    }
+    Ok(())
+}
+
+/// Summary per phase, in the order the phases were first marked
+fn write_phase_summaries(
+    out: &mut output::Output,
+    phases: &[(String, summary::Summary)],
+    opt: &Opt,
+) -> io::Result<()> {
+    for (phase, summary) in phases {
+        write_summary(out, summary, Some(("phase", phase)), opt)?;
+    }
+    Ok(())
+}
+
+fn write_segment_summary(
+    out: &mut output::Output,
+    summary: &summary::Summary,
+    segment: u32,
+    opt: &Opt,
+) -> io::Result<()> {
+    let segment = segment.to_string();
+    write_summary(out, summary, Some(("segment", &segment)), opt)
+}
+
+/// Summary record in the output format, `label` names the segment or phase it covers
+fn write_summary(
+    out: &mut output::Output,
+    summary: &summary::Summary,
+    label: Option<(&str, &str)>,
+    opt: &Opt,
+) -> io::Result<()> {
+    sink::FormatEncoder::new(&mut *out, opt.output_format(), opt).write_summary(summary, label)?;
+    out.flush()
+}
+
+/// Bandwidth per --group, the sum of the mean bandwidths of its members
+fn write_group_rollup(
+    out: &mut output::Output,
+    rollup: &[(&str, (f32, f32, f32))],
+    opt: &Opt,
+) -> io::Result<()> {
+    sink::FormatEncoder::new(&mut *out, opt.output_format(), opt).write_group_rollup(rollup)?;
+    out.flush()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Human,
+    Csv,
+    /// One indented JSON object per record
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// InfluxDB line protocol
+    Influx,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<Format, String> {
+        match src {
+            "human" => Ok(Format::Human),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "influx" => Ok(Format::Influx),
+            _ => Err(format!("unknown format {}", src)),
+        }
+    }
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Human => "human",
+            Format::Csv => "csv",
+            Format::Json => "json",
+            Format::Jsonl => "jsonl",
+            Format::Influx => "influx",
+        }
+    }
+
+    /// Structured outputs start with the metadata record
+    fn has_metadata(self) -> bool {
+        matches!(self, Format::Csv | Format::Json | Format::Jsonl)
+    }
+}
+
+impl Opt {
+    fn output_format(&self) -> Format {
+        match (self.format, self.formatted) {
+            (Some(format), _) => format,
+            (None, true) => Format::Csv,
+            (None, false) => Format::Human,
+        }
+    }
+}
+
+fn parse_percent(src: &str) -> Result<f32, std::num::ParseFloatError> {
+    src.trim_end_matches('%').parse()
 }
 
 fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
     u32::from_str_radix(src, 16)
 }
 
+fn parse_soc(src: &str) -> Result<String, String> {
+    match soc::find(src) {
+        Some(soc) => Ok(soc.name.to_string()),
+        None => Err(format!("unknown SoC {}, see list-socs", src)),
+    }
+}
+
+/// Replay speed factor, anything but a positive finite number can't pace the records
+#[cfg(feature = "network")]
+fn parse_speed(src: &str) -> Result<f64, String> {
+    match src.parse::<f64>() {
+        Ok(speed) if speed > 0_f64 && speed.is_finite() => Ok(speed),
+        Ok(_) => Err(format!("speed {} must be a positive number", src)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Windows per record, there is nothing to report for 0
+fn parse_report_every(src: &str) -> Result<u32, String> {
+    match src.parse::<u32>() {
+        Ok(0) => Err(format!("window count {} must be at least 1", src)),
+        Ok(count) => Ok(count),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_base(src: &str) -> Result<u64, ParseIntError> {
+    u64::from_str_radix(src.trim_start_matches("0x"), 16)
+}
+
+fn parse_length(src: &str) -> Result<usize, ParseIntError> {
+    parse_size(src).map(|size| size as usize)
+}
+
+fn parse_size(src: &str) -> Result<u64, ParseIntError> {
+    if let Some(hex) = src.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16);
+    }
+    let (number, multiplier) = match src.chars().last() {
+        Some('K') | Some('k') => (&src[..src.len() - 1], 1024),
+        Some('M') | Some('m') => (&src[..src.len() - 1], 1024 * 1024),
+        Some('G') | Some('g') => (&src[..src.len() - 1], 1024 * 1024 * 1024),
+        _ => (src, 1),
+    };
+    number.parse::<u64>().map(|size| size * multiplier)
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "r-mmdc", about = "Rust port of the original mmdc tool", author = env!("CARGO_PKG_AUTHORS"))]
 struct Opt {
@@ -393,50 +1357,1106 @@ struct Opt {
     #[structopt(short = "c", long = "cycles", default_value = "1")]
     cycles: u32,
 
+    /// SoC
+    // SoC profile to use instead of the detected one, see list-socs
+    #[structopt(long = "soc", parse(try_from_str = parse_soc))]
+    soc: Option<String>,
+
+    /// Channel
+    // MMDC channel to profile, channel 1 only exists in 2-channel LPDDR2 configurations
+    #[structopt(long = "channel", default_value = "0", possible_values = &["0", "1"])]
+    channel: u32,
+
+    /// Base address
+    // Physical base address of the MMDC register block in HEX, taken from the SoC profile
+    // if not given, needs no page alignment
+    #[structopt(long = "base", parse(try_from_str = parse_base))]
+    base: Option<u64>,
+
+    /// Mapping length
+    // Length of the mapped register window, e.g. 0x4000 or 16K
+    #[structopt(long = "map-length", default_value = "0x4000", parse(try_from_str = parse_length))]
+    map_length: usize,
+
+    /// Align
+    // Starts and ends the windows on wall-clock multiples of the sleep time, e.g. whole seconds
+    #[structopt(long = "align", conflicts_with = "schedule")]
+    align: bool,
+
+    /// Schedule
+    // When windows start and end: back to back (fixed), on wall-clock multiples of the
+    // sleep time (aligned), one window per --duty-period (duty), or longer windows while
+    // below --activity-threshold (adaptive)
+    #[structopt(long = "schedule", possible_values = &schedule::SCHEDULES)]
+    schedule: Option<String>,
+
+    /// Duty period
+    // Period in ms of the duty schedule, each starting with a window of the sleep time
+    #[structopt(long = "duty-period", required_if("schedule", "duty"))]
+    duty_period: Option<u64>,
+
+    /// Activity threshold
+    // Total bandwidth in MB/s from which the adaptive schedule switches to windows of
+    // the sleep time
+    #[structopt(long = "activity-threshold", required_if("schedule", "adaptive"))]
+    activity_threshold: Option<f32>,
+
+    /// Idle sleep time
+    // Window length in ms of the adaptive schedule while the bandwidth stays below
+    // --activity-threshold
+    #[structopt(long = "idle-sleeptime", default_value = "10000")]
+    idle_sleeptime: u64,
+
+    /// Check registers
+    // Re-reads MADPCR1 and the DDR configuration registers every N windows and warns if
+    // another agent changed them, 0 disables the check
+    #[structopt(long = "check-registers", default_value = "100")]
+    check_registers: u32,
+
+    /// No power saving
+    // Disables the automatic power saving of MAPSR for the run, so self-refresh periods
+    // don't distort the utilization of a lightly loaded system. Restored on exit
+    #[structopt(long = "no-power-saving")]
+    no_power_saving: bool,
+
+    /// Gap-free
+    // Re-arms the counters right after reading them, so consecutive windows leave no
+    // blind gap for the output in between
+    #[structopt(long = "gap-free")]
+    gap_free: bool,
+
+    /// Report every
+    // Emits one record with the mean and the min/max of every N windows
+    #[structopt(long = "report-every", parse(try_from_str = parse_report_every))]
+    report_every: Option<u32>,
+
+    /// Emit on change
+    // Writes a record only if its read, write or total bandwidth differs by at least
+    // this many MB/s from the last written one, flagged records are always written
+    #[structopt(long = "emit-on-change")]
+    emit_on_change: Option<f32>,
+
+    /// Blame threshold
+    // Utilization in percent at which the masters are scanned for the top contributors,
+    // reported on stderr and with the record
+    #[structopt(long = "blame-threshold", conflicts_with = "gap-free")]
+    blame_threshold: Option<u32>,
+
+    /// Blame window
+    // Length of the scan window per master in ms, 20 if not given
+    #[structopt(long = "blame-window", requires = "blame-threshold")]
+    blame_window: Option<u64>,
+
+    /// Blame interval
+    // Least number of seconds between two scans, 10 if not given
+    #[structopt(long = "blame-interval", requires = "blame-threshold")]
+    blame_interval: Option<u64>,
+
+    /// Flags
+    // Adds a column with the validity flags to the CSV records: overflow, frequency,
+    // deadline, freeze, latch, lowbus, config and scan
+    #[structopt(long = "flags")]
+    flags: bool,
+
+    /// Timestamps
+    // Adds wall-clock and boot-time timestamp and sequence number columns to the CSV
+    // records, the structured formats always carry them
+    #[structopt(long = "timestamps")]
+    timestamps: bool,
+
+    /// CPU frequency
+    // Adds the frequency of each CPU cluster at the end of the window to the records
+    #[structopt(long = "cpufreq")]
+    cpufreq: bool,
+
+    /// GPU load
+    // Adds the share of each window the Vivante GPU was on, from the galcore debugfs
+    // statistics
+    #[structopt(long = "gpu-load")]
+    gpu_load: bool,
+
+    /// IRQ
+    // Adds the number of interrupts per window of an IRQ, given by number or a part of its
+    // name in /proc/interrupts, e.g. ipu1 or mmc0. Repeat for several IRQs
+    #[structopt(long = "irq", number_of_values = 1)]
+    irq: Vec<String>,
+
+    /// Memory pressure
+    // Adds the major faults, paged in and out data and the change of free and available
+    // memory per window
+    #[structopt(long = "mem-pressure")]
+    mem_pressure: bool,
+
+    /// L2
+    // Adds the count per window of a PL310 L2 cache event, e.g. co for evictions or drreq
+    // for data reads. Repeat for the second counter
+    #[structopt(
+        long = "l2",
+        number_of_values = 1,
+        max_values = 2,
+        possible_values = &pl310::EVENT_NAMES
+    )]
+    l2: Vec<String>,
+
+    #[cfg(feature = "perf")]
+    /// PMU
+    // Adds the CPU cycles, L2 refills and bus accesses of all CPUs per window from the
+    // Cortex-A PMU, the CPU's contribution to the DDR traffic
+    #[structopt(long = "pmu")]
+    pmu: bool,
+
+    /// Summary
+    // Prints statistics over all cycles at the end of the run
+    #[structopt(long = "summary")]
+    summary: bool,
+
+    /// Warm-up cycles
+    // Cycles to run and discard before the actual ones, e.g. while a workload ramps up
+    #[structopt(long = "warmup", default_value = "0")]
+    warmup: u32,
+
+    /// Show warm-up
+    // Prints the warm-up cycles, marked as such
+    #[structopt(long = "show-warmup")]
+    show_warmup: bool,
+
+    /// Master
+    // Profiles only the given master, see list-socs for the names. Repeat to rotate through
+    // several masters on successive cycles, each record is tagged with its master
+    #[structopt(
+        long = "master",
+        number_of_values = 1,
+        conflicts_with = "madpcr1",
+        possible_values = master_names()
+    )]
+    master: Vec<String>,
+
+    /// Group
+    // Rotates through the masters of a [groups] entry of the config file, repeat for several
+    // groups. The summary adds up the bandwidth per group
+    #[structopt(long = "group", number_of_values = 1, conflicts_with = "madpcr1")]
+    group: Vec<String>,
+
+    /// Register file
+    // Maps the registers from an ordinary file laid out like the MMDC block instead of
+    // /dev/mem, for testing without hardware
+    #[structopt(long = "regs-file", parse(from_os_str), conflicts_with = "base")]
+    regs_file: Option<PathBuf>,
+
     /// Custom madpcr1 location
     // Address to madpcr1 register in mapped memory in HEX
     #[structopt(short = "m", long = "madpcr1", parse(try_from_str = parse_hex))]
     madpcr1: Option<u32>,
 
     ///CSV Format
-    // Formats the output as a csv file
-    #[structopt(short = "f")]
+    // Deprecated alias for --format csv
+    #[structopt(short = "f", long = "formatted", conflicts_with = "format")]
     formatted: bool,
+
+    /// Format
+    // Output format of the records
+    #[structopt(long = "format", possible_values = &["human", "csv", "json", "jsonl", "influx"])]
+    format: Option<Format>,
+
+    /// Also human
+    // Prints the records human-readable to stderr in addition to the selected format
+    #[structopt(long = "also-human")]
+    also_human: bool,
+
+    /// Print schema
+    // Prints a JSON description of the fields the given options emit and exits
+    #[structopt(long = "print-schema")]
+    print_schema: bool,
+
+    /// DDR clock
+    // DDR clock in MHz, detected from debugfs if not given
+    #[structopt(long = "ddr-clock")]
+    ddr_clock: Option<u32>,
+
+    /// Bus width
+    // Width of the DDR data bus in bits, read from MDCTL if not given
+    #[structopt(long = "bus-width", possible_values = &["16", "32", "64"])]
+    bus_width: Option<u32>,
+
+    /// Show beats
+    // Additionally reports the byte counts as data beats of the bus width. The MMDC has no
+    // beat counter, the beats are the bytes divided by the bus width in bytes
+    #[structopt(long = "beats")]
+    beats: bool,
+
+    /// Raw counters
+    // Additionally reports the unconverted MADPSR0-5 counter registers
+    #[structopt(long = "raw")]
+    raw: bool,
+
+    /// Output file
+    // Writes the results to the given file instead of stdout
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Rotate size
+    // Rotates the output file once it exceeds the given size, e.g. 512K or 10M
+    #[structopt(long = "rotate-size", parse(try_from_str = parse_size), requires = "output")]
+    rotate_size: Option<u64>,
+
+    /// Rotate age
+    // Rotates the output file once it is older than the given amount of seconds
+    #[structopt(long = "rotate-age", requires = "output")]
+    rotate_age: Option<u64>,
+
+    /// Rotate keep
+    // Amount of rotated archives to keep next to the output file
+    #[structopt(long = "rotate-keep", default_value = "5")]
+    rotate_keep: u32,
+
+    /// Compress
+    // Writes the results gzip compressed
+    #[structopt(long = "compress")]
+    compress: bool,
+
+    /// Append
+    // Appends to the output file, records are written under an advisory file lock.
+    // Not combinable with --compress as concurrent writers would interleave gzip streams
+    #[structopt(long = "append", requires = "output", conflicts_with = "compress")]
+    append: bool,
+
+    /// No flush
+    // Buffers the output instead of flushing it after every record, trades latency
+    // of live consumers for throughput and a better compression ratio
+    #[structopt(long = "no-flush")]
+    no_flush: bool,
+
+    /// Control FIFO
+    // Named pipe, created if missing, taking "start" and "stop" lines. The measurement idles
+    // until started and a summary is written per start/stop segment
+    #[structopt(long = "control-fifo", parse(from_os_str))]
+    control_fifo: Option<PathBuf>,
+
+    /// Marker FIFO
+    // Named pipe, created if missing, taking the name of a phase per line, e.g.
+    // `echo video-playback > markers`, as do "marker <name>" lines of --control-fifo. The
+    // records carry the phase and a summary is written per phase
+    #[structopt(long = "marker-fifo", parse(from_os_str))]
+    marker_fifo: Option<PathBuf>,
+
+    /// Signals
+    // SIGUSR1 starts and SIGUSR2 stops a measurement segment, a summary is written per segment
+    #[structopt(long = "signals", conflicts_with_all = &["control-fifo", "gpio"])]
+    signals: bool,
+
+    /// GPIO
+    // Measures only while the given sysfs GPIO line is high, e.g. driven by external
+    // instrumentation. A summary is written per high segment
+    #[structopt(long = "gpio", conflicts_with = "control-fifo")]
+    gpio: Option<u32>,
+
+    /// FIFO
+    // Writes the results to the given named pipe, created if missing. Records are
+    // dropped while no reader is connected and the pipe is reopened once a reader returns
+    #[structopt(long = "fifo", parse(from_os_str), conflicts_with_all = &["output", "compress"])]
+    fifo: Option<PathBuf>,
+
+    #[cfg(feature = "shm")]
+    /// Shared memory
+    // Publishes the latest samples into the given POSIX shared memory segment, e.g. /r-mmdc
+    #[structopt(long = "shm")]
+    shm: Option<String>,
+
+    #[cfg(feature = "dbus")]
+    /// D-Bus
+    // Serves the latest sample as org.rmmdc.Profiler1 on the system or session bus
+    #[structopt(long = "dbus", possible_values = &["system", "session"])]
+    dbus: Option<String>,
+
+    #[cfg(feature = "dbus")]
+    /// D-Bus threshold
+    // Utilization in percent at which the ThresholdCrossed signal is emitted
+    #[structopt(long = "dbus-threshold", requires = "dbus")]
+    dbus_threshold: Option<u32>,
+
+    #[cfg(feature = "dbus")]
+    /// Alert hysteresis
+    // Percent below the threshold the utilization has to drop to clear the alert, 0 if not
+    // given
+    #[structopt(long = "alert-hysteresis", requires = "dbus-threshold")]
+    alert_hysteresis: Option<u32>,
+
+    #[cfg(feature = "dbus")]
+    /// Alert consecutive
+    // Number of successive samples which have to cross the threshold before the alert is
+    // raised or cleared, 1 if not given
+    #[structopt(long = "alert-consecutive", requires = "dbus-threshold")]
+    alert_consecutive: Option<u32>,
+
+    #[cfg(feature = "dbus")]
+    /// Alert interval
+    // Least number of seconds between two raised alerts, e.g. 60 for at most one a minute
+    #[structopt(long = "alert-interval", requires = "dbus-threshold")]
+    alert_interval: Option<u64>,
+
+    #[cfg(feature = "network")]
+    /// Listen
+    // Runs in server mode on the given address, e.g. 0.0.0.0:8080, pushing every sample
+    // as JSON to WebSocket clients connected to /ws and serving the REST API
+    // GET /sample, GET /history?n=<count>&seconds=<s>, GET /overview?points=<count>,
+    // GET /delta?client=<name> and POST /control {"action":"start|stop"}
+    #[structopt(long = "listen")]
+    listen: Option<String>,
+
+    #[cfg(feature = "network")]
+    /// History length
+    // Number of the latest samples the server keeps for GET /history
+    #[structopt(long = "history-length", default_value = "256")]
+    history_length: usize,
+
+    /// Trace
+    // Writes every record to an indexed binary trace as well, compact for long captures
+    // and seekable by `play --from`
+    #[structopt(long = "trace", parse(from_os_str))]
+    trace: Option<PathBuf>,
+
+    #[cfg(feature = "network")]
+    /// Stream
+    // Streams every sample as a JSON line to a host running `r-mmdc view`, e.g. 10.0.0.1:4000
+    #[structopt(long = "stream")]
+    stream: Option<String>,
+
+    /// Stats directory
+    // Keeps a file per value of the latest sample in this directory, e.g. /run/rmmdc, so
+    // scripts can cat the current utilization or read_mbps
+    #[structopt(long = "stats-dir", parse(from_os_str))]
+    stats_dir: Option<PathBuf>,
+
+    /// Board ID
+    // Identifies the board in the run metadata, e.g. for `r-mmdc agg`, the hostname if not
+    // given
+    #[structopt(long = "board-id")]
+    board_id: Option<String>,
+
+    #[cfg(feature = "network")]
+    /// DLT
+    // Logs every sample as a verbose DLT message over UDP to a viewer or receiver, e.g. 10.0.0.1:3490
+    #[structopt(long = "dlt")]
+    dlt: Option<String>,
+
+    #[cfg(feature = "network")]
+    /// DLT ECU ID
+    #[structopt(long = "dlt-ecu", default_value = "ECU1")]
+    dlt_ecu: String,
+
+    #[cfg(feature = "network")]
+    /// DLT application ID
+    #[structopt(long = "dlt-app", default_value = "MMDC")]
+    dlt_app: String,
+
+    #[cfg(feature = "network")]
+    /// DLT context ID
+    #[structopt(long = "dlt-context", default_value = "DDR")]
+    dlt_context: String,
+
+    #[cfg(feature = "syslog")]
+    /// Log
+    // Logs one line per sample to `syslog` or the systemd `journal` with structured fields
+    #[structopt(long = "log", possible_values = &["syslog", "journal"])]
+    log: Option<String>,
+
+    /// Config file
+    // Configuration file with the presets for the profile subcommand
+    #[structopt(long = "config", default_value = config::DEFAULT_PATH, parse(from_os_str))]
+    config: PathBuf,
+
+    /// Baseline
+    // Compares the summary of the run with a baseline saved by --save-baseline and fails
+    // if the bandwidth or utilization grew by more than --max-regression
+    #[structopt(long = "baseline", parse(from_os_str), global = true)]
+    baseline: Option<PathBuf>,
+
+    /// Maximum regression
+    // Allowed growth of the bandwidth and utilization over the baseline in percent, 10%
+    // if not given
+    #[structopt(long = "max-regression", parse(try_from_str = parse_percent), global = true)]
+    max_regression: Option<f32>,
+
+    /// Save baseline
+    // Saves the summary of the run as baseline for later runs with --baseline
+    #[structopt(long = "save-baseline", parse(from_os_str), global = true)]
+    save_baseline: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Prints the decoded per byte lane delay line, DQS gating and write leveling calibration
+    Calibration,
+    /// Prints the decoded DDR configuration of the MMDC
+    Info,
+    /// Runs a short measurement and prints ptest PASS/FAIL lines against the given expectations
+    Ptest {
+        /// Number of samples averaged for the checks
+        #[structopt(long = "samples", default_value = "5")]
+        samples: u32,
+        /// Minimum expected total bandwidth in MB/s
+        #[structopt(long = "min-bandwidth")]
+        min_bandwidth: Option<f32>,
+        /// Maximum expected total bandwidth in MB/s
+        #[structopt(long = "max-bandwidth")]
+        max_bandwidth: Option<f32>,
+        /// Minimum expected utilization in percent
+        #[structopt(long = "min-utilization")]
+        min_utilization: Option<u32>,
+        /// Maximum expected utilization in percent
+        #[structopt(long = "max-utilization")]
+        max_utilization: Option<u32>,
+    },
+    /// Verifies register access and that the profiling counters work
+    Selftest,
+    /// Measures with the options of a named preset from the config file
+    Profile {
+        /// Name of the [preset.<name>] section, long options given on the command line take precedence
+        #[structopt(long = "preset")]
+        preset: String,
+    },
+    /// Prints the supported SoCs with their detection keys, base addresses and masters
+    ListSocs,
+    /// Steps the read delay lines through a range and verifies memory patterns at every
+    /// value, recording the passing window per byte lane. Disturbs all DDR reads of the
+    /// system while it runs, the original delays are restored after every step
+    Sweep {
+        /// Confirms running the sweep on this system
+        #[structopt(long = "expert")]
+        expert: bool,
+        /// First delay line value
+        #[structopt(long = "start", default_value = "0")]
+        start: u32,
+        /// Last delay line value, at most 127
+        #[structopt(long = "end", default_value = "127")]
+        end: u32,
+        /// Distance of the delay values
+        #[structopt(long = "step", default_value = "4")]
+        step: u32,
+        /// Verification passes per delay value
+        #[structopt(long = "rounds", default_value = "1")]
+        rounds: u32,
+    },
+    /// Cycles rapidly through the masters of the SoC and shows them ranked by their
+    /// bandwidth, like top. The masters are measured one after the other, so the rates
+    /// are estimates of the load over a refresh period
+    Top {
+        /// Length of the window per master in ms
+        #[structopt(long = "window", default_value = "20")]
+        window: u64,
+        /// Time between updates of the ranking in ms
+        #[structopt(long = "refresh", default_value = "2000")]
+        refresh: u64,
+        /// Number of updates, runs until terminated if not given
+        #[structopt(long = "iterations")]
+        iterations: Option<u32>,
+    },
+    /// Applies an MMDC setting and measures the bandwidth before and after
+    Tune {
+        #[structopt(subcommand)]
+        target: TuneTarget,
+    },
+    #[cfg(feature = "perf")]
+    /// Compares the /dev/mem counters with the kernel's mmdc perf driver
+    Crosscheck {
+        /// Number of alternating /dev/mem and perf windows
+        #[structopt(long = "rounds", default_value = "5")]
+        rounds: u32,
+        /// Allowed divergence of the counter rates in percent
+        #[structopt(long = "tolerance", default_value = "10")]
+        tolerance: f64,
+    },
+    #[cfg(feature = "network")]
+    /// Receives and displays samples streamed from targets with --stream
+    View {
+        /// Port to listen on for streaming targets
+        port: u16,
+    },
+    #[cfg(feature = "compress")]
+    /// Collects the system identification, a register dump, a short measurement and the
+    /// run metadata into a .tar.gz archive to attach to a bug report
+    SupportBundle {
+        /// Archive to write, e.g. r-mmdc-support.tar.gz
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// Number of windows of the sleep time measured
+        #[structopt(long = "samples", default_value = "10")]
+        samples: u32,
+    },
+    /// Prints a shell completion script, e.g. for /etc/bash_completion.d/r-mmdc
+    Completions {
+        #[structopt(possible_values = &["bash", "zsh", "fish"])]
+        shell: Shell,
+    },
+    /// Measures like without a subcommand and records the run metadata and every sample
+    /// to a session file for `play`
+    Record {
+        /// Session file to write, JSON lines
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Converts a capture between CSV, JSON lines of `record` and a binary `--trace`
+    Convert {
+        /// CSV output, session file or trace to convert
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// File to write, the format defaults to the one of the extension
+        /// (.csv, .jsonl or .mtrace)
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+        /// Format to write
+        #[structopt(long = "to", possible_values = &convert::FORMATS)]
+        to: Option<String>,
+    },
+    #[cfg(feature = "network")]
+    /// Replays a session file of `record` or a `--trace` in the view display
+    Play {
+        /// Session file or trace to replay
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// Replay speed, e.g. 10 for ten times faster than recorded
+        #[structopt(long = "speed", default_value = "1", parse(try_from_str = parse_speed))]
+        speed: f64,
+        /// Seconds from the start of the capture to begin at
+        #[structopt(long = "from")]
+        from: Option<f64>,
+        /// Seconds from the start of the capture to end at
+        #[structopt(long = "to")]
+        to: Option<f64>,
+    },
+    #[cfg(feature = "network")]
+    /// Merges the samples streamed from many targets with --stream into one JSON lines
+    /// dataset, tagged with their --board-id
+    Agg {
+        /// Port to listen on for streaming targets
+        port: u16,
+        /// File to write the dataset to instead of stdout
+        #[structopt(long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum TuneTarget {
+    /// Applies a MAARCR arbitration policy, restored on exit unless --persist is given
+    Arbitration {
+        /// Named policy, default, throughput, latency or in-order, or a hex MAARCR value
+        setting: String,
+        #[structopt(flatten)]
+        experiment: ExperimentOpt,
+    },
+    /// Switches the MAPSR automatic power saving, restored on exit unless --persist is given
+    PowerSaving {
+        #[structopt(possible_values = &["on", "off"])]
+        state: String,
+        #[structopt(flatten)]
+        experiment: ExperimentOpt,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct ExperimentOpt {
+    /// Number of windows measured before and after the change
+    #[structopt(long = "samples", default_value = "5")]
+    samples: u32,
+    /// Only shows the decoded current and new setting
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// Keeps the new setting after exiting
+    #[structopt(long = "persist")]
+    persist: bool,
+}
+
+impl ExperimentOpt {
+    fn experiment(&self) -> tune::Experiment {
+        tune::Experiment {
+            samples: self.samples,
+            dry_run: self.dry_run,
+            persist: self.persist,
+        }
+    }
+}
+
+fn print_info(mmdc: &MMDC, opt: &Opt, soc: Option<&SocProfile>) {
+    println!("MMDC configuration:");
+    println!("***********************");
+    match soc {
+        Some(soc) => println!(
+            "SoC: {} ({}), channel {}",
+            soc.description, soc.name, opt.channel
+        ),
+        None => println!("SoC: unknown, channel {}", opt.channel),
+    }
+    ddr::print_ddr_type(mmdc);
+    ddr::print_ddr_size(mmdc);
+    ddr::print_ddr_timings(mmdc, ddr::ddr_clock(opt.ddr_clock));
+    arbitration::print_arbitration(mmdc.maarcr);
+    power::print_power_saving(mmdc.mapsr);
+}
+
+fn print_socs() {
+    for soc in soc::SOCS {
+        println!("{} ({})", soc.name, soc.description);
+        println!("  Compatible: {}", soc.compatible.join(", "));
+        println!("  SoC ID: {}", soc.soc_ids.join(", "));
+        let cpu_types: Vec<String> = soc.cpu_types.iter().map(|t| format!("0x{:X}", t)).collect();
+        if soc.min_revision > 0 {
+            println!(
+                "  CPU type: {}, revision 0x{:X} and up",
+                cpu_types.join(", "),
+                soc.min_revision
+            );
+        } else {
+            println!("  CPU type: {}", cpu_types.join(", "));
+        }
+        let bases: Vec<String> = soc
+            .base_addresses
+            .iter()
+            .map(|base| format!("0x{:08X}", base))
+            .collect();
+        println!("  Base addresses: {}", bases.join(", "));
+        println!("  Controller: {:?}", soc.controller);
+        let widths: Vec<String> = soc.bus_widths.iter().map(|w| w.to_string()).collect();
+        println!("  Bus widths: {}", widths.join(", "));
+        let masters: Vec<String> = soc
+            .masters
+            .iter()
+            .map(|master| format!("{} (0x{:08X})", master.name, master.filter))
+            .collect();
+        println!(
+            "  Masters: {}",
+            if masters.is_empty() {
+                "none known, only all masters combined".to_string()
+            } else {
+                masters.join(", ")
+            }
+        );
+    }
 }
 
 fn apply_options(mmdc: &mut MMDC, opt: &Opt) {
-    mmdc.madpcr1 = match opt.madpcr1 {
-        Some(addr) => addr,
-        None => 0,
-    };
+    select_master(mmdc, opt.madpcr1.unwrap_or_default());
+}
+
+fn select_master(mmdc: &mut MMDC, filter: u32) {
+    mmdc.madpcr1 = filter;
     unsafe {
-        let _ = msync(&mut mmdc.madpcr0 as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
+        let _ = msync(&mut mmdc.madpcr1 as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
+    }
+}
+
+/// Names of the masters of all SoCs, for validating and completing --master
+fn master_names() -> &'static [&'static str] {
+    let mut names = vec![ALL_MASTERS.name];
+    for master in soc::SOCS.iter().flat_map(|soc| soc.masters.iter()) {
+        if !names.contains(&master.name) {
+            names.push(master.name);
+        }
+    }
+    Box::leak(names.into_boxed_slice())
+}
+
+/// Filter matching every master
+static ALL_MASTERS: Master = Master {
+    name: "all",
+    filter: AXI_DEFAULT,
+};
+
+/// MADPCR1 bits above the 14-bit AXI ID and mask fields
+const MADPCR1_RESERVED: u32 = 0xC000_C000;
+
+/// Echoes the decoded --madpcr1 filter with the closest known master, panics on reserved bits
+fn check_madpcr1(madpcr1: u32, soc: Option<&'static SocProfile>) {
+    if madpcr1 & MADPCR1_RESERVED != 0 {
+        panic!(
+            "MADPCR1 0x{:08X} sets reserved bits 0x{:08X}",
+            madpcr1,
+            madpcr1 & MADPCR1_RESERVED
+        );
+    }
+    let (id, mask) = (bits(madpcr1, 0, 16), bits(madpcr1, 16, 16));
+    eprintln!(
+        "MADPCR1 0x{:08X}: AXI ID 0x{:04X}, mask 0x{:04X}",
+        madpcr1, id, mask
+    );
+    // only the ID bits selected by the mask are compared
+    if id & !mask != 0 {
+        eprintln!(
+            "WARNING: AXI ID bits 0x{:04X} are outside the mask and never compared",
+            id & !mask
+        );
+    }
+    if madpcr1 == AXI_DEFAULT {
+        eprintln!("MADPCR1 selects all masters");
+        return;
+    }
+    // the IDs differ between the parts, there is no hint without the SoC
+    let soc = match soc {
+        Some(soc) => soc,
+        None => return,
+    };
+    let closest = soc
+        .masters
+        .iter()
+        .min_by_key(|master| (master.filter ^ madpcr1).count_ones());
+    match closest {
+        Some(master) if master.filter == madpcr1 => eprintln!("MADPCR1 selects {}", master.name),
+        Some(master) => eprintln!(
+            "MADPCR1 matches no known master of the {}, closest is {} (0x{:08X})",
+            soc.description, master.name, master.filter
+        ),
+        None => {}
+    }
+}
+
+/// AXI masters of the SoC. The IDs differ between the parts, so an undetected SoC or one
+/// without a master table is an error rather than a reason to assume the i.MX6Q IDs.
+fn soc_masters(soc: Option<&'static SocProfile>) -> (&'static SocProfile, &'static [Master]) {
+    let soc = soc.unwrap_or_else(|| {
+        panic!("The SoC wasn't detected, give it with --soc to select masters by name")
+    });
+    if soc.masters.is_empty() {
+        panic!(
+            "No AXI master IDs are known for the {}, select the filter with --madpcr1",
+            soc.description
+        );
+    }
+    (soc, soc.masters)
+}
+
+/// Masters given with --master followed by the members of the --group groups, looked up
+/// in the SoC profile
+fn resolve_masters(
+    opt: &Opt,
+    soc: Option<&'static SocProfile>,
+    config: &config::Config,
+) -> Vec<&'static Master> {
+    let mut names: Vec<&str> = opt.master.iter().map(String::as_str).collect();
+    for name in opt.group.iter() {
+        let group = config.group(name).unwrap_or_else(|| {
+            let names: Vec<&str> = config
+                .groups
+                .iter()
+                .map(|group| group.name.as_str())
+                .collect();
+            panic!(
+                "Unknown group {} in {}, available: {}",
+                name,
+                opt.config.display(),
+                names.join(", ")
+            )
+        });
+        for master in group.masters.iter() {
+            if !names.contains(&master.as_str()) {
+                names.push(master);
+            }
+        }
     }
+    names
+        .into_iter()
+        .map(|name| match name {
+            "all" => &ALL_MASTERS,
+            name => {
+                let (soc, masters) = soc_masters(soc);
+                masters
+                    .iter()
+                    .find(|master| master.name == name)
+                    .unwrap_or_else(|| {
+                        let names: Vec<&str> = masters.iter().map(|master| master.name).collect();
+                        panic!(
+                            "Unknown master {} on {}, available: all, {}",
+                            name,
+                            soc.description,
+                            names.join(", ")
+                        )
+                    })
+            }
+        })
+        .collect()
+}
+
+/// Parses the command line again with the preset's options in place of the profile subcommand
+fn apply_preset(opt: &Opt, name: &str) -> Opt {
+    let config = config::Config::load(&opt.config)
+        .unwrap_or_else(|e| panic!("Error reading {}: {}", opt.config.display(), e));
+    let preset = config.preset(name).unwrap_or_else(|| {
+        let names: Vec<&str> = config.presets.iter().map(|p| p.name.as_str()).collect();
+        panic!(
+            "Unknown preset {} in {}, available: {}",
+            name,
+            opt.config.display(),
+            names.join(", ")
+        )
+    });
+    let args: Vec<String> = std::env::args().collect();
+    let subcommand = args
+        .iter()
+        .position(|arg| arg == "profile")
+        .unwrap_or(args.len());
+    let given = &args[1..subcommand];
+    let mut preset_opt = Opt::from_iter(
+        args.iter()
+            .take(1)
+            .cloned()
+            .chain(preset.args(given))
+            .chain(given.iter().cloned()),
+    );
+    // the global options may also follow the subcommand, e.g. profile --baseline
+    preset_opt.baseline = preset_opt.baseline.or_else(|| opt.baseline.clone());
+    preset_opt.max_regression = preset_opt.max_regression.or(opt.max_regression);
+    preset_opt.save_baseline = preset_opt
+        .save_baseline
+        .or_else(|| opt.save_baseline.clone());
+    preset_opt
 }
 
 fn main() {
     let opt = Opt::from_args();
-    let mmdc: &mut MMDC;
-    unsafe {
-        let fd = match OpenOptions::new().read(true).write(true).open("/dev/mem") {
-            Err(e) => panic!("couldn't open /dev/mem: {}", e),
-            Ok(file) => file,
-        };
-        match mmap(
-            std::ptr::null_mut(),
-            0x4000,
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-            MapFlags::MAP_SHARED,
-            fd.as_raw_fd(),
-            MMDC_P0_IPS_BASE_ADDR.into(),
-        ) {
-            Ok(p) => mmdc = &mut *(p as *mut MMDC),
-            Err(e) => panic!("Error mapping memory {}", e),
-        };
+    let mut opt = match &opt.cmd {
+        Some(Command::Profile { preset }) => apply_preset(&opt, preset),
+        _ => opt,
+    };
+    if let Some(Command::Completions { shell }) = opt.cmd {
+        return Opt::clap().gen_completions_to("r-mmdc", shell, &mut io::stdout());
+    }
+    if let Some(Command::Record { path }) = &opt.cmd {
+        opt.output = Some(path.clone());
+        opt.format = Some(Format::Jsonl);
+    }
+    if opt.formatted {
+        eprintln!("WARNING: -f is deprecated, use --format csv");
+    }
+    #[cfg(feature = "network")]
+    if let Some(Command::View { port }) = opt.cmd {
+        return view::run(port).unwrap_or_else(|e| panic!("Error viewing samples: {}", e));
+    }
+    #[cfg(feature = "network")]
+    if let Some(Command::Play {
+        path,
+        speed,
+        from,
+        to,
+    }) = &opt.cmd
+    {
+        return view::play(path, *speed, *from, *to)
+            .unwrap_or_else(|e| panic!("Error playing {}: {}", path.display(), e));
+    }
+    #[cfg(feature = "network")]
+    if let Some(Command::Agg { port, output }) = &opt.cmd {
+        return agg::run(*port, output.as_deref())
+            .unwrap_or_else(|e| panic!("Error aggregating samples: {}", e));
+    }
+
+    if let Some(Command::Convert { input, output, to }) = &opt.cmd {
+        let format = to
+            .as_deref()
+            .or_else(|| convert::format_of(output))
+            .unwrap_or_else(|| panic!("No format for {}, use --to", output.display()));
+        let records = convert::run(input, output, format, &opt)
+            .unwrap_or_else(|e| panic!("Error converting {}: {}", input.display(), e));
+        eprintln!("Converted {} records to {}", records, output.display());
+        return;
+    }
+
+    if let Some(Command::ListSocs) = opt.cmd {
+        return print_socs();
+    }
+    if opt.print_schema {
+        let config = config::Config::load_or_default(&opt.config)
+            .unwrap_or_else(|e| panic!("Error reading {}: {}", opt.config.display(), e));
+        let metrics: Vec<String> = config.metrics.into_iter().map(|(name, _)| name).collect();
+        let mut columns = Vec::new();
+        if let Some(cpufreq) = open_cpufreq(&opt) {
+            columns.extend(cpufreq.columns());
+        }
+        if let Some(gpu_load) = open_gpu_load(&opt) {
+            columns.extend(gpu_load.columns());
+        }
+        if let Some(interrupts) = open_interrupts(&opt) {
+            columns.extend(interrupts.columns());
+        }
+        if let Some(memory) = open_memory_pressure(&opt) {
+            columns.extend(memory.columns());
+        }
+        columns.extend(pl310::columns(&opt.l2));
+        #[cfg(feature = "perf")]
+        if opt.pmu {
+            columns.extend(pmu::CpuPmu::columns());
+        }
+        match schema::describe(&opt, &metrics, &columns) {
+            Some(schema) => return println!("{}", schema),
+            None => {
+                eprintln!("The human output has no schema, pick a --format");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let soc = resolve_soc(&opt);
+    if let Some(madpcr1) = opt.madpcr1 {
+        check_madpcr1(madpcr1, soc);
+    }
+    if let Some(soc) = soc.filter(|soc| soc.controller != Controller::Mmdc) {
+        panic!(
+            "The {} has a {:?} DDR controller, whose counters are not supported yet",
+            soc.description, soc.controller
+        );
+    }
+    let mut map = match &opt.regs_file {
+        Some(path) => MmdcMap::open_path(path, 0, opt.map_length).unwrap_or_else(|e| {
+            panic!("couldn't map MMDC registers from {}: {}", path.display(), e)
+        }),
+        None => MmdcMap::open(resolve_base(&opt, soc), opt.map_length)
+            .unwrap_or_else(|e| panic!("couldn't map MMDC registers from /dev/mem: {}", e)),
     };
+    let mmdc: &mut MMDC = &mut map;
+    let bus_width = resolve_bus_width(mmdc, &opt, soc);
 
-    apply_options(mmdc, &opt);
-    for _ in 0..opt.cycles {
-        do_measuring_cylce(mmdc, &opt);
+    match opt.cmd {
+        Some(Command::Calibration) => return calibration::print_calibration(mmdc),
+        Some(Command::Info) => return print_info(mmdc, &opt, soc),
+        #[cfg(feature = "perf")]
+        Some(Command::Crosscheck { rounds, tolerance }) => {
+            apply_options(mmdc, &opt);
+            match crosscheck::run(mmdc, &opt, bus_width, rounds, tolerance) {
+                Ok(true) => return,
+                Ok(false) => std::process::exit(1),
+                Err(e) => panic!("Error opening perf counters: {}", e),
+            }
+        }
+        Some(Command::Sweep {
+            expert,
+            start,
+            end,
+            step,
+            rounds,
+        }) => {
+            if !expert {
+                eprintln!("The sweep disturbs all DDR reads of the system, confirm with --expert");
+                std::process::exit(1);
+            }
+            let sweep = sweep::Sweep {
+                start,
+                end,
+                step,
+                rounds,
+            };
+            return sweep::run(mmdc, bus_width, &sweep)
+                .unwrap_or_else(|e| panic!("Error sweeping the read delay: {}", e));
+        }
+        Some(Command::Top {
+            window,
+            refresh,
+            iterations,
+        }) => {
+            let (_, masters) = soc_masters(soc);
+            let config = config::Config::load_or_default(&opt.config)
+                .unwrap_or_else(|e| panic!("Error reading {}: {}", opt.config.display(), e));
+            return top::run(
+                mmdc,
+                masters,
+                &config.groups,
+                bus_width,
+                Duration::from_millis(window),
+                Duration::from_millis(refresh),
+                iterations,
+            )
+            .unwrap_or_else(|e| panic!("Error writing the ranking: {}", e));
+        }
+        Some(Command::Tune { ref target }) => {
+            apply_options(mmdc, &opt);
+            let result = match target {
+                TuneTarget::Arbitration {
+                    setting,
+                    experiment,
+                } => tune::arbitration(mmdc, &opt, bus_width, setting, &experiment.experiment()),
+                TuneTarget::PowerSaving { state, experiment } => tune::power_saving(
+                    mmdc,
+                    &opt,
+                    bus_width,
+                    state == "on",
+                    &experiment.experiment(),
+                ),
+            };
+            return result.unwrap_or_else(|e| panic!("Error tuning: {}", e));
+        }
+        #[cfg(feature = "compress")]
+        Some(Command::SupportBundle { ref path, samples }) => {
+            bundle::create(path, mmdc, &opt, soc, bus_width, samples)
+                .unwrap_or_else(|e| panic!("Error writing the bundle {}: {}", path.display(), e));
+            return eprintln!("Wrote {}", path.display());
+        }
+        Some(Command::Selftest) => {
+            if !selftest::run(mmdc) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Ptest {
+            samples,
+            min_bandwidth,
+            max_bandwidth,
+            min_utilization,
+            max_utilization,
+        }) => {
+            apply_options(mmdc, &opt);
+            let expectations = ptest::Expectations {
+                samples,
+                min_bandwidth,
+                max_bandwidth,
+                min_utilization,
+                max_utilization,
+            };
+            if !ptest::run(mmdc, &opt, bus_width, &expectations) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let power_saving_off = match opt.no_power_saving {
+        true => Some(power::PowerSavingOff::apply(mmdc)),
+        false => None,
+    };
+    // set up first, the signal mask has to be inherited by all threads
+    let restore_power_saving = power_saving_off
+        .as_ref()
+        .map(power::PowerSavingOff::restorer);
+    control::block_signals(opt.signals)
+        .unwrap_or_else(|e| panic!("couldn't block the signals: {}", e));
+    let termination = control::Termination::catch(move || {
+        if let Some(restore) = restore_power_saving {
+            restore();
+        }
+    });
+    let summary = measure::run(mmdc, &opt, soc, bus_width, &termination);
+    if let Some(power_saving_off) = power_saving_off {
+        power_saving_off.restore();
+    }
+    let current = baseline::Baseline::from_summary(&summary);
+    if let Some(path) = &opt.save_baseline {
+        current
+            .save(path)
+            .unwrap_or_else(|e| panic!("Error saving baseline {}: {}", path.display(), e));
+    }
+    if let Some(path) = &opt.baseline {
+        let baseline = baseline::Baseline::load(path)
+            .unwrap_or_else(|e| panic!("Error reading baseline {}: {}", path.display(), e));
+        let max_regression = opt
+            .max_regression
+            .unwrap_or(baseline::DEFAULT_MAX_REGRESSION);
+        let passed = baseline
+            .compare(&current, max_regression, &mut io::stderr())
+            .unwrap_or_else(|e| panic!("Error writing comparison: {}", e));
+        if !passed {
+            std::process::exit(1);
+        }
+    }
+    if let Some(signal) = termination.signal() {
+        std::process::exit(128 + signal);
     }
 }
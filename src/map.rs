@@ -0,0 +1,101 @@
+//! Owned mapping of an MMDC register block from /dev/mem or a file standing in for it
+
+use mmdc_core::MMDC;
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use nix::unistd::{sysconf, SysconfVar};
+use std::convert::TryInto;
+use std::ffi::c_void;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr::{self, NonNull};
+
+/// Keeps /dev/mem open and the registers mapped for as long as it lives,
+/// unmapping and closing on drop
+pub struct MmdcMap {
+    registers: NonNull<MMDC>,
+    mapping: *mut c_void,
+    mapping_length: usize,
+    _file: File,
+}
+
+fn page_size() -> u64 {
+    match sysconf(SysconfVar::PAGE_SIZE) {
+        Ok(Some(size)) if size > 0 => size as u64,
+        _ => 4096,
+    }
+}
+
+impl MmdcMap {
+    /// Maps `length` bytes of registers at the given physical base address, which
+    /// doesn't need to be page aligned. The length has to cover the MMDC register map.
+    pub fn open(base_address: u64, length: usize) -> io::Result<MmdcMap> {
+        MmdcMap::open_path(Path::new("/dev/mem"), base_address, length)
+    }
+
+    /// Maps `length` bytes at `base_address` of the given file, e.g. a fake register block laid
+    /// out like the MMDC one, see `FakeRegisters`
+    pub fn open_path(path: &Path, base_address: u64, length: usize) -> io::Result<MmdcMap> {
+        if length < size_of::<MMDC>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "mapping length 0x{:X} is shorter than the MMDC register map (0x{:X})",
+                    length,
+                    size_of::<MMDC>()
+                ),
+            ));
+        }
+        let page_offset = base_address % page_size();
+        let page_base = (base_address - page_offset).try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "base address out of range")
+        })?;
+        let mapping_length = length + page_offset as usize;
+
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mapping = unsafe {
+            mmap(
+                ptr::null_mut(),
+                mapping_length,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED,
+                file.as_raw_fd(),
+                page_base,
+            )
+        }
+        .map_err(|e| io::Error::other(e.to_string()))?;
+        let registers = unsafe { (mapping as *mut u8).add(page_offset as usize) } as *mut MMDC;
+        Ok(MmdcMap {
+            registers: NonNull::new(registers)
+                .ok_or_else(|| io::Error::other("mmap returned a null mapping"))?,
+            mapping,
+            mapping_length,
+            _file: file,
+        })
+    }
+}
+
+impl Deref for MmdcMap {
+    type Target = MMDC;
+
+    fn deref(&self) -> &MMDC {
+        unsafe { self.registers.as_ref() }
+    }
+}
+
+impl DerefMut for MmdcMap {
+    fn deref_mut(&mut self) -> &mut MMDC {
+        unsafe { self.registers.as_mut() }
+    }
+}
+
+impl Drop for MmdcMap {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = munmap(self.mapping, self.mapping_length);
+        }
+    }
+}
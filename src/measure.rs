@@ -0,0 +1,388 @@
+//! The sampling loop of a measurement run, which feeds every record to the main output and
+//! the sinks and writes the summaries at the end
+
+use crate::control::{self, Termination};
+#[cfg(feature = "dbus")]
+use crate::dbus;
+#[cfg(feature = "perf")]
+use crate::open_pmu;
+#[cfg(feature = "shm")]
+use crate::shm;
+use crate::sink::{RecordSink, Sink};
+use crate::summary::Summary;
+#[cfg(feature = "syslog")]
+use crate::syslog;
+use crate::{
+    aggregate, alert, apply_options, blame, change, config, ddr, flag_frequency_change,
+    flag_low_bus, get_tick_count, integrity, measure_overhead, measure_window, metadata,
+    open_cpufreq, open_gate, open_gpu_load, open_interrupts, open_l2_events, open_markers,
+    open_memory_pressure, output, resolve_masters, schedule, select_master, sink, soc_masters,
+    statsdir, stop_mmdc_profiling, top, trace, usdt, write_group_rollup, write_marker,
+    write_phase_summaries, write_record, write_segment_summary, write_summary, GapFreeWindows, Opt,
+    Record, FLAG_CONFIG, FLAG_SCAN,
+};
+#[cfg(feature = "network")]
+use crate::{dlt, server, stream};
+use mmdc_core::soc::SocProfile;
+use mmdc_core::MMDC;
+use r_mmdc::SampleSink;
+use std::thread;
+use std::time::Duration;
+
+/// The sinks of the command line options and the config file. The live views get every
+/// record, the logs wrapped in a `Sink` only those written to the main output.
+fn open_sinks<'o>(opt: &'o Opt, config: &config::Config) -> Vec<Box<RecordSink<'o>>> {
+    let mut sinks: Vec<Box<RecordSink<'o>>> = vec![Box::new(usdt::Probe)];
+    if let Some(path) = &opt.stats_dir {
+        let stats_dir = statsdir::StatsDir::create(path)
+            .unwrap_or_else(|e| panic!("couldn't create {}: {}", path.display(), e));
+        sinks.push(Box::new(stats_dir));
+    }
+    #[cfg(feature = "shm")]
+    if let Some(name) = &opt.shm {
+        let segment = shm::ShmSegment::create(name)
+            .unwrap_or_else(|e| panic!("couldn't create shared memory {}: {}", name, e));
+        sinks.push(Box::new(segment));
+    }
+    #[cfg(feature = "syslog")]
+    if let Some(target) = &opt.log {
+        let log = syslog::LogSink::connect(target)
+            .unwrap_or_else(|e| panic!("couldn't connect to {}: {}", target, e));
+        sinks.push(Box::new(Sink::new("log", Box::new(log))));
+    }
+    #[cfg(feature = "network")]
+    if let Some(address) = &opt.dlt {
+        let dlt = dlt::DltSink::connect(address, &opt.dlt_ecu, &opt.dlt_app, &opt.dlt_context)
+            .unwrap_or_else(|e| panic!("couldn't send DLT to {}: {}", address, e));
+        sinks.push(Box::new(Sink::new("dlt", Box::new(dlt))));
+    }
+    #[cfg(feature = "network")]
+    if let Some(address) = &opt.stream {
+        let stream = stream::StreamSink::new(address);
+        sinks.push(Box::new(Sink::new("stream", Box::new(stream))));
+    }
+    if let Some(path) = &opt.trace {
+        let trace = trace::TraceWriter::create(path)
+            .unwrap_or_else(|e| panic!("couldn't create {}: {}", path.display(), e));
+        sinks.push(Box::new(Sink::new("trace", Box::new(trace))));
+    }
+    for config in config.sinks.iter() {
+        let sink = Sink::open(config, opt)
+            .unwrap_or_else(|e| panic!("couldn't open sink {}: {}", config.name, e));
+        sinks.push(Box::new(sink));
+    }
+    sinks
+}
+
+/// Measures `opt.cycles` windows, or fewer if `termination` asks to stop, and returns the
+/// summary of the run. The sinks are finished when it returns.
+pub fn run(
+    mmdc: &mut MMDC,
+    opt: &Opt,
+    soc: Option<&'static SocProfile>,
+    bus_width: u32,
+    termination: &Termination,
+) -> Summary {
+    let gate = open_gate(opt);
+    let mut out = output::Output::open(opt)
+        .unwrap_or_else(|e| panic!("couldn't open output {:?}: {}", opt.output, e));
+    let overhead = measure_overhead(mmdc, bus_width);
+    if overhead * 10 > Duration::from_millis(opt.sleeptime) {
+        eprintln!(
+            "WARNING: register access overhead of {}us exceeds 10% of the sampling window",
+            overhead.as_micros()
+        );
+    }
+    let config = config::Config::load_or_default(&opt.config)
+        .unwrap_or_else(|e| panic!("Error reading {}: {}", opt.config.display(), e));
+    let masters = resolve_masters(opt, soc, &config);
+    let mut run_metadata = metadata::RunMetadata::collect(mmdc, opt, soc, bus_width, overhead);
+    // the members of the groups are rotated as well
+    run_metadata.masters = masters
+        .iter()
+        .map(|master| master.name.to_string())
+        .collect();
+    let metadata_entries = run_metadata.entries();
+    if opt.output_format().has_metadata() {
+        out.begin_batch()
+            .and_then(|_| {
+                sink::FormatEncoder::new(&mut out, opt.output_format(), opt)
+                    .on_metadata(&metadata_entries)
+            })
+            .and_then(|_| out.end_batch())
+            .unwrap_or_else(|e| panic!("Error writing results: {}", e));
+    }
+
+    #[cfg(feature = "dbus")]
+    let dbus_service = opt.dbus.as_ref().map(|bus| {
+        let alert = opt.dbus_threshold.map(|threshold| alert::AlertPolicy {
+            threshold,
+            hysteresis: opt.alert_hysteresis.unwrap_or(0),
+            consecutive: opt.alert_consecutive.unwrap_or(1),
+            min_interval: Duration::from_secs(opt.alert_interval.unwrap_or(0)),
+        });
+        dbus::DbusService::start(bus, alert)
+            .unwrap_or_else(|e| panic!("couldn't register on the {} bus: {}", bus, e))
+    });
+
+    #[cfg(feature = "network")]
+    let server = opt.listen.as_ref().map(|address| {
+        server::Server::start(address, bus_width, opt.history_length)
+            .unwrap_or_else(|e| panic!("couldn't listen on {}: {}", address, e))
+    });
+
+    let mut sinks = open_sinks(opt, &config);
+    #[cfg(feature = "dbus")]
+    if let Some(service) = &dbus_service {
+        sinks.push(Box::new(service.clone()));
+    }
+    #[cfg(feature = "network")]
+    if let Some(server) = &server {
+        sinks.push(Box::new(server.clone()));
+    }
+    for sink in sinks.iter_mut() {
+        sink.on_metadata(&metadata_entries)
+            .unwrap_or_else(|e| panic!("couldn't open sink {}", e));
+    }
+
+    apply_options(mmdc, opt);
+    let mut scheduler = schedule::from_opt(opt);
+    let mut sequence = 0..;
+    for _ in 0..opt.warmup {
+        if termination.signal().is_some() {
+            break;
+        }
+        thread::sleep(scheduler.pause());
+        let (mut results, time) = measure_window(mmdc, bus_width, overhead, scheduler.as_mut());
+        if opt.show_warmup {
+            results.warmup = true;
+            results.sequence = sequence.next().unwrap();
+            write_record(&mut out, &results, time, opt)
+                .unwrap_or_else(|e| panic!("Error writing results: {}", e));
+        }
+    }
+    let mut segment_summary = Summary::new();
+    let mut summary = Summary::new();
+    let mut aggregator = aggregate::Aggregator::new(opt.report_every.unwrap_or(1), bus_width);
+    let mut change_filter = opt.emit_on_change.map(change::ChangeFilter::new);
+    let mut previous_rate = 0_f32;
+    // cycles per millisecond of the nominal DDR clock
+    let mut nominal_rate = opt
+        .ddr_clock
+        .or_else(|| ddr::detect_ddr_clock().ok())
+        .map_or(0_f32, |mhz| mhz as f32 * 1000_f32);
+    // windows of the rotated masters, for the rollup of the groups
+    let mut master_loads: Vec<top::MasterLoad> =
+        masters.iter().copied().map(top::MasterLoad::new).collect();
+    let cpufreq = open_cpufreq(opt);
+    let gpu_load = open_gpu_load(opt);
+    let mut interrupts = open_interrupts(opt);
+    let mut memory = open_memory_pressure(opt);
+    let l2_events = open_l2_events(opt, soc);
+    #[cfg(feature = "perf")]
+    let mut pmu = open_pmu(opt);
+    if opt.gap_free && masters.len() > 1 {
+        panic!("--gap-free can't switch between masters, the filter would change mid-window");
+    }
+    if opt.report_every.unwrap_or(1) > 1 && masters.len() > 1 {
+        panic!("--report-every can't combine the windows of different masters, select only one");
+    }
+    if opt.gap_free && !["fixed", "aligned"].contains(&schedule::name(opt)) {
+        panic!("--gap-free needs windows of a fixed length, use --schedule fixed or aligned");
+    }
+    if opt.gap_free && opt.blame_threshold.is_some() {
+        // the scan stops and clears the counters the gap-free windows keep running
+        panic!(
+            "--blame-threshold can't scan during --gap-free, the next window would be cut short"
+        );
+    }
+    let mut blame = opt.blame_threshold.map(|threshold| {
+        let policy = alert::AlertPolicy {
+            threshold,
+            hysteresis: 0,
+            consecutive: 1,
+            min_interval: Duration::from_secs(opt.blame_interval.unwrap_or(10)),
+        };
+        let (_, masters) = soc_masters(soc);
+        let window = Duration::from_millis(opt.blame_window.unwrap_or(20));
+        blame::Blame::new(policy, masters, window, overhead)
+    });
+    let markers = open_markers(opt, gate.as_ref());
+    // summaries per phase of the markers
+    let mut phases: Vec<(String, Summary)> = Vec::new();
+    let mut last_phase: Option<String> = None;
+    let mut gap_free: Option<GapFreeWindows> = None;
+    let mut register_check = integrity::RegisterCheck::new(mmdc);
+    for cycle in 0..opt.cycles {
+        if termination.signal().is_some() {
+            break;
+        }
+        let master = masters.get(cycle as usize % masters.len().max(1));
+        if let Some(master) = master {
+            select_master(mmdc, master.filter);
+        }
+        if let Some(gate) = &gate {
+            if !gate.is_running() && !segment_summary.is_empty() {
+                write_segment_summary(&mut out, &segment_summary, gate.segment(), opt)
+                    .unwrap_or_else(|e| panic!("Error writing summary: {}", e));
+                segment_summary = Summary::new();
+            }
+            gate.wait_until_running();
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(service) = &dbus_service {
+            service.wait_until_running();
+        }
+        #[cfg(feature = "network")]
+        if let Some(server) = &server {
+            server.wait_until_running();
+        }
+        let reopened = out
+            .reopen_if_needed()
+            .unwrap_or_else(|e| panic!("Error reopening output: {}", e));
+        if reopened && opt.output_format().has_metadata() {
+            sink::FormatEncoder::new(&mut out, opt.output_format(), opt)
+                .on_metadata(&metadata_entries)
+                .unwrap_or_else(|e| panic!("Error writing results: {}", e));
+        }
+        let (mut results, time) = if opt.gap_free {
+            gap_free
+                .get_or_insert_with(|| GapFreeWindows::arm(mmdc, opt))
+                .next(mmdc, opt, bus_width)
+        } else {
+            thread::sleep(scheduler.pause());
+            measure_window(mmdc, bus_width, overhead, scheduler.as_mut())
+        };
+        scheduler.observe(&results, time);
+        results.master = master.map(|master| master.name);
+        flag_frequency_change(&mut results, time, &mut previous_rate);
+        flag_low_bus(&mut results, time, &mut nominal_rate);
+        if opt.check_registers > 0 && (cycle + 1).is_multiple_of(opt.check_registers) {
+            let filter = master.map_or(opt.madpcr1.unwrap_or_default(), |master| master.filter);
+            if register_check.check(mmdc, filter) {
+                results.flags |= FLAG_CONFIG;
+            }
+        }
+        summary.add(&results, time);
+        if let Some(load) = master_loads.get_mut(cycle as usize % masters.len().max(1)) {
+            load.add(&results, time);
+        }
+        // every window, the totals must not skip those folded by --report-every
+        #[cfg(feature = "network")]
+        if let Some(server) = &server {
+            server.count(results.raw_counters, time);
+        }
+        if gate.is_some() {
+            segment_summary.add(&results, time);
+        }
+        let phase = markers.as_ref().and_then(control::Markers::phase);
+        if let Some(phase) = &phase {
+            if last_phase.as_ref() != Some(phase) {
+                write_marker(&mut out, phase, get_tick_count() as u64, opt)
+                    .unwrap_or_else(|e| panic!("Error writing results: {}", e));
+                last_phase = Some(phase.clone());
+            }
+            let index = match phases.iter().position(|(name, _)| name == phase) {
+                Some(index) => index,
+                None => {
+                    phases.push((phase.clone(), Summary::new()));
+                    phases.len() - 1
+                }
+            };
+            phases[index].1.add(&results, time);
+        }
+        let (mut results, time) = match aggregator.add(&results, time) {
+            Some(aggregated) => aggregated,
+            None => continue,
+        };
+        results.phase = phase;
+        results.evaluate_metrics(&config.metrics, time);
+        // read once per record, so the deltas cover all windows of --report-every
+        if let Some(cpufreq) = &cpufreq {
+            results.columns.extend(cpufreq.read());
+        }
+        if let Some(gpu_load) = &gpu_load {
+            results.columns.extend(gpu_load.read());
+        }
+        if let Some(interrupts) = &mut interrupts {
+            results.columns.extend(interrupts.read());
+        }
+        if let Some(memory) = &mut memory {
+            results.columns.extend(memory.read());
+        }
+        if let Some(l2_events) = &l2_events {
+            results.columns.extend(l2_events.read_events());
+        }
+        #[cfg(feature = "perf")]
+        if let Some(pmu) = &mut pmu {
+            results.columns.extend(pmu.read());
+        }
+        results.sequence = sequence.next().unwrap();
+        if let Some(blame) = &mut blame {
+            results.suspects = blame.check(mmdc, bus_width, &results);
+            if let Some(suspects) = &results.suspects {
+                results.flags |= FLAG_SCAN;
+                eprintln!(
+                    "Utilization {}% at or above {}%, top contributors: {}",
+                    results.utilization,
+                    opt.blame_threshold.unwrap_or_default(),
+                    suspects
+                );
+            }
+        }
+        let emitted = change_filter
+            .as_mut()
+            .is_none_or(|filter| filter.should_emit(&results, time));
+        if emitted {
+            write_record(&mut out, &results, time, opt)
+                .unwrap_or_else(|e| panic!("Error writing results: {}", e));
+        }
+        let record = Record {
+            results: &results,
+            time,
+            emitted,
+        };
+        for sink in sinks.iter_mut() {
+            if let Err(e) = sink.on_sample(&record) {
+                eprintln!("Error writing to sink {}", e);
+            }
+        }
+    }
+    if gap_free.is_some() {
+        stop_mmdc_profiling(mmdc);
+    }
+    if let (Some(gate), false) = (&gate, segment_summary.is_empty()) {
+        write_segment_summary(&mut out, &segment_summary, gate.segment(), opt)
+            .unwrap_or_else(|e| panic!("Error writing summary: {}", e));
+    }
+    if !phases.is_empty() {
+        write_phase_summaries(&mut out, &phases, opt)
+            .unwrap_or_else(|e| panic!("Error writing summary: {}", e));
+    }
+    if opt.summary {
+        write_summary(&mut out, &summary, None, opt)
+            .unwrap_or_else(|e| panic!("Error writing summary: {}", e));
+        let groups: Vec<config::MasterGroup> = opt
+            .group
+            .iter()
+            .filter_map(|name| config.group(name).cloned())
+            .collect();
+        if !groups.is_empty() {
+            write_group_rollup(&mut out, &top::rollup(&groups, &master_loads), opt)
+                .unwrap_or_else(|e| panic!("Error writing summary: {}", e));
+        }
+    }
+    // finished explicitly, the exits of the caller skip the destructors
+    out.finish()
+        .unwrap_or_else(|e| panic!("Error writing results: {}", e));
+    for sink in sinks.iter_mut() {
+        let written = match opt.summary {
+            true => sink.on_summary(&summary),
+            false => Ok(()),
+        };
+        if let Err(e) = written.and_then(|_| sink.flush()) {
+            eprintln!("Error writing to sink {}", e);
+        }
+    }
+    summary
+}
@@ -0,0 +1,97 @@
+//! Memory pressure from /proc/vmstat and /proc/meminfo, telling page cache thrashing
+//! apart from application traffic
+
+use crate::schema::Column;
+use std::fs;
+use std::io;
+
+/// vmstat counters reported as per window deltas
+const VMSTAT_KEYS: [(&str, &str, &str); 3] = [
+    (
+        "pgmajfault",
+        "faults",
+        "Major page faults, i.e. pages read back in from storage",
+    ),
+    ("pgpgin", "KiB", "Data paged in from block devices"),
+    ("pgpgout", "KiB", "Data paged out to block devices"),
+];
+
+/// meminfo values reported as the change over the window
+const MEMINFO_KEYS: [(&str, &str, &str); 2] = [
+    ("MemFree", "mem_free_delta_kb", "Change of the free memory"),
+    (
+        "MemAvailable",
+        "mem_available_delta_kb",
+        "Change of the memory available without swapping",
+    ),
+];
+
+/// Value of `key` in a file of `key value` or `key: value kB` lines
+fn value(content: &str, key: &str) -> i64 {
+    content
+        .lines()
+        .find_map(|line| {
+            let mut tokens = line.split_whitespace();
+            match tokens.next()?.trim_end_matches(':') == key {
+                true => tokens.next()?.parse().ok(),
+                false => None,
+            }
+        })
+        .unwrap_or_default()
+}
+
+pub struct MemoryPressure {
+    previous: Vec<i64>,
+}
+
+impl MemoryPressure {
+    pub fn open() -> io::Result<MemoryPressure> {
+        fs::read_to_string("/proc/vmstat")?;
+        fs::read_to_string("/proc/meminfo")?;
+        Ok(MemoryPressure {
+            previous: MemoryPressure::values(),
+        })
+    }
+
+    fn values() -> Vec<i64> {
+        let vmstat = fs::read_to_string("/proc/vmstat").unwrap_or_default();
+        let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+        VMSTAT_KEYS
+            .iter()
+            .map(|(key, _, _)| value(&vmstat, key))
+            .chain(MEMINFO_KEYS.iter().map(|(key, _, _)| value(&meminfo, key)))
+            .collect()
+    }
+
+    fn names() -> impl Iterator<Item = &'static str> {
+        VMSTAT_KEYS
+            .iter()
+            .map(|(name, _, _)| *name)
+            .chain(MEMINFO_KEYS.iter().map(|(_, name, _)| *name))
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        let vmstat = VMSTAT_KEYS.iter().map(|(name, unit, description)| Column {
+            name: name.to_string(),
+            unit,
+            description,
+        });
+        let meminfo = MEMINFO_KEYS.iter().map(|(_, name, description)| Column {
+            name: name.to_string(),
+            unit: "KiB",
+            description,
+        });
+        vmstat.chain(meminfo).collect()
+    }
+
+    /// Changes of all values since the previous read
+    pub fn read(&mut self) -> Vec<(String, i64)> {
+        let values = MemoryPressure::values();
+        let deltas = MemoryPressure::names()
+            .zip(values.iter().zip(self.previous.iter()))
+            .map(|(name, (value, previous))| (name.to_string(), value - previous))
+            .collect();
+        self.previous = values;
+        deltas
+    }
+}
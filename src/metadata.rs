@@ -0,0 +1,115 @@
+use crate::ddr::{DdrGeometry, DdrType};
+use crate::json::JsonObject;
+use crate::schedule;
+use crate::{soc_revision, Opt, MMDC, SCHEMA_VERSION};
+use mmdc_core::soc::SocProfile;
+use std::fs;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Describes a measurement run so recorded outputs remain self-describing
+pub struct RunMetadata {
+    pub version: &'static str,
+    pub board: String,
+    pub soc: Option<&'static str>,
+    pub soc_revision: Option<String>,
+    pub ddr_type: DdrType,
+    pub ddr_size_mb: u64,
+    pub bus_width: u32,
+    pub channel: u32,
+    pub madpcr1: u32,
+    /// Rotated masters, those of --master followed by the members of the groups
+    pub masters: Vec<String>,
+    pub groups: Vec<String>,
+    pub sleeptime: u64,
+    pub cycles: u32,
+    pub warmup: u32,
+    pub schedule: String,
+    pub gap_free: bool,
+    pub overhead_us: u128,
+}
+
+impl RunMetadata {
+    pub fn collect(
+        mmdc: &MMDC,
+        opt: &Opt,
+        soc: Option<&'static SocProfile>,
+        bus_width: u32,
+        overhead: Duration,
+    ) -> RunMetadata {
+        RunMetadata {
+            version: env!("CARGO_PKG_VERSION"),
+            board: opt.board_id.clone().unwrap_or_else(|| {
+                fs::read_to_string("/proc/sys/kernel/hostname")
+                    .map(|hostname| hostname.trim().to_string())
+                    .unwrap_or_default()
+            }),
+            soc: soc.map(|soc| soc.name),
+            soc_revision: soc_revision(),
+            ddr_type: DdrType::from_registers(mmdc),
+            ddr_size_mb: DdrGeometry::from_registers(mmdc).total_size() / (1024 * 1024),
+            bus_width,
+            channel: opt.channel,
+            madpcr1: opt.madpcr1.unwrap_or_default(),
+            masters: opt.master.clone(),
+            groups: opt.group.clone(),
+            sleeptime: opt.sleeptime,
+            cycles: opt.cycles,
+            warmup: opt.warmup,
+            schedule: schedule::name(opt).to_string(),
+            gap_free: opt.gap_free,
+            overhead_us: overhead.as_micros(),
+        }
+    }
+
+    /// Key value pairs of the metadata, shared by all structured encoders
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("version", self.version.to_string()),
+            ("schema_version", SCHEMA_VERSION.to_string()),
+            ("board", self.board.clone()),
+            ("soc", self.soc.unwrap_or("unknown").to_string()),
+            (
+                "soc_revision",
+                self.soc_revision
+                    .as_deref()
+                    .unwrap_or("unknown")
+                    .to_string(),
+            ),
+            ("channel", self.channel.to_string()),
+            ("ddr_type", self.ddr_type.to_string()),
+            ("ddr_size_mb", self.ddr_size_mb.to_string()),
+            ("bus_width", self.bus_width.to_string()),
+            ("madpcr1", format!("0x{:08X}", self.madpcr1)),
+            ("masters", self.masters.join(",")),
+            ("groups", self.groups.join(",")),
+            ("sleeptime_ms", self.sleeptime.to_string()),
+            ("cycles", self.cycles.to_string()),
+            ("warmup", self.warmup.to_string()),
+            ("schedule", self.schedule.clone()),
+            ("gap_free", self.gap_free.to_string()),
+            ("overhead_us", self.overhead_us.to_string()),
+        ]
+    }
+}
+
+/// Metadata record of the JSON encodings
+pub fn json_object(entries: &[(&str, String)]) -> JsonObject {
+    entries.iter().fold(
+        JsonObject::new().string("record", "metadata"),
+        |object, (key, value)| object.string(key, value),
+    )
+}
+
+/// Metadata record of the JSON lines encodings
+pub fn to_json(entries: &[(&str, String)]) -> String {
+    json_object(entries).build()
+}
+
+/// Writes the metadata as CSV comment lines ahead of the records
+pub fn write_csv(entries: &[(&str, String)], out: &mut dyn Write) -> io::Result<()> {
+    for (key, value) in entries {
+        writeln!(out, "# {}={}", key, value)?;
+    }
+    Ok(())
+}
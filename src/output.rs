@@ -0,0 +1,327 @@
+#[cfg(feature = "compress")]
+use crate::gzip::GzipWriter;
+use crate::Opt;
+use nix::fcntl::{fcntl, flock, FcntlArg, FlockArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Writes either plain or gzip compressed to the underlying stream
+pub enum Encoder<W: Write> {
+    Plain(W),
+    #[cfg(feature = "compress")]
+    Gzip(Box<GzipWriter<W>>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(inner: W, compress: bool) -> io::Result<Encoder<W>> {
+        match compress {
+            #[cfg(feature = "compress")]
+            true => Ok(Encoder::Gzip(Box::new(GzipWriter::new(inner)?))),
+            #[cfg(not(feature = "compress"))]
+            true => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "built without the compress feature",
+            )),
+            false => Ok(Encoder::Plain(inner)),
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        match self {
+            Encoder::Plain(inner) => inner,
+            #[cfg(feature = "compress")]
+            Encoder::Gzip(gzip) => gzip.get_ref(),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(inner) => inner.flush(),
+            #[cfg(feature = "compress")]
+            Encoder::Gzip(gzip) => gzip.try_finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(inner) => inner.write(buf),
+            #[cfg(feature = "compress")]
+            Encoder::Gzip(gzip) => gzip.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(inner) => inner.flush(),
+            #[cfg(feature = "compress")]
+            Encoder::Gzip(gzip) => gzip.flush(),
+        }
+    }
+}
+
+/// Output file which is rotated logrotate style once it exceeds its size or age limit
+pub struct RotatingFile {
+    path: PathBuf,
+    file: Encoder<BufWriter<File>>,
+    compress: bool,
+    append: bool,
+    opened: Instant,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: u32,
+}
+
+fn archive_path(path: &Path, index: u32) -> PathBuf {
+    let mut archive = path.as_os_str().to_owned();
+    archive.push(format!(".{}", index));
+    PathBuf::from(archive)
+}
+
+fn open_file(path: &Path, append: bool) -> io::Result<File> {
+    if append {
+        OpenOptions::new().append(true).create(true).open(path)
+    } else {
+        File::create(path)
+    }
+}
+
+pub fn nix_to_io(error: nix::Error) -> io::Error {
+    io::Error::other(error.to_string())
+}
+
+impl RotatingFile {
+    pub fn create(path: &Path, opt: &Opt) -> io::Result<RotatingFile> {
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file: Encoder::new(BufWriter::new(open_file(path, opt.append)?), opt.compress)?,
+            compress: opt.compress,
+            append: opt.append,
+            opened: Instant::now(),
+            max_size: opt.rotate_size,
+            max_age: opt.rotate_age.map(Duration::from_secs),
+            keep: opt.rotate_keep,
+        })
+    }
+
+    fn needs_rotation(&self) -> bool {
+        let written = self
+            .file
+            .get_ref()
+            .get_ref()
+            .metadata()
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        let size_exceeded = self.max_size.is_some_and(|size| written >= size);
+        let age_exceeded = self.max_age.is_some_and(|age| self.opened.elapsed() >= age);
+        size_exceeded || age_exceeded
+    }
+
+    /// Shifts the archives by one, dropping the oldest, and starts a new file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.finish()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                let archive = archive_path(&self.path, index);
+                if archive.exists() {
+                    fs::rename(&archive, archive_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, archive_path(&self.path, 1))?;
+        }
+        self.file = Encoder::new(
+            BufWriter::new(open_file(&self.path, self.append)?),
+            self.compress,
+        )?;
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    /// Takes the advisory write lock shared with other appending invocations
+    fn lock(&mut self) -> io::Result<()> {
+        if self.append {
+            flock(
+                self.file.get_ref().get_ref().as_raw_fd(),
+                FlockArg::LockExclusive,
+            )
+            .map_err(nix_to_io)?;
+        }
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> io::Result<()> {
+        if self.append {
+            self.file.flush()?;
+            flock(self.file.get_ref().get_ref().as_raw_fd(), FlockArg::Unlock)
+                .map_err(nix_to_io)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Creates the named pipe unless it exists already
+pub fn create_fifo(path: &Path) -> io::Result<()> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => Ok(()),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "path exists and is no named pipe",
+        )),
+        Err(_) => mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR).map_err(nix_to_io),
+    }
+}
+
+/// Named pipe sink, records are dropped while no reader is connected
+pub struct FifoSink {
+    path: PathBuf,
+    pipe: Option<File>,
+    record: Vec<u8>,
+}
+
+impl FifoSink {
+    pub fn create(path: &Path) -> io::Result<FifoSink> {
+        create_fifo(path)?;
+        Ok(FifoSink {
+            path: path.to_path_buf(),
+            pipe: None,
+            record: Vec::new(),
+        })
+    }
+
+    /// Tries to connect to a reader without blocking, returns true on a new connection
+    fn connect(&mut self) -> io::Result<bool> {
+        if self.pipe.is_some() {
+            return Ok(false);
+        }
+        match OpenOptions::new()
+            .write(true)
+            .custom_flags(nix::libc::O_NONBLOCK)
+            .open(&self.path)
+        {
+            Ok(pipe) => {
+                // blocking from here on, so records are never written partially
+                fcntl(pipe.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty())).map_err(nix_to_io)?;
+                self.pipe = Some(pipe);
+                Ok(true)
+            }
+            Err(e) if e.raw_os_error() == Some(nix::libc::ENXIO) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Write for FifoSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.record.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = match &mut self.pipe {
+            Some(pipe) => match pipe.write_all(&self.record) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    // reader went away, reconnect before the next record
+                    self.pipe = None;
+                    Ok(())
+                }
+                other => other,
+            },
+            None => Ok(()),
+        };
+        self.record.clear();
+        result
+    }
+}
+
+pub enum Output {
+    Stdout(Encoder<BufWriter<io::Stdout>>),
+    File(RotatingFile),
+    Fifo(FifoSink),
+}
+
+impl Output {
+    pub fn open(opt: &Opt) -> io::Result<Output> {
+        if let Some(path) = &opt.fifo {
+            return Ok(Output::Fifo(FifoSink::create(path)?));
+        }
+        match &opt.output {
+            Some(path) => Ok(Output::File(RotatingFile::create(path, opt)?)),
+            None => Ok(Output::Stdout(Encoder::new(
+                BufWriter::new(io::stdout()),
+                opt.compress,
+            )?)),
+        }
+    }
+
+    /// Starts writing a batch of records which must not interleave with other writers
+    pub fn begin_batch(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(file) => file.lock(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn end_batch(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(file) => file.unlock(),
+            Output::Fifo(fifo) => fifo.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Completes the stream, e.g. writes the gzip trailer, nothing may be written afterwards
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.finish(),
+            Output::File(file) => file.file.finish(),
+            Output::Fifo(fifo) => fifo.flush(),
+        }
+    }
+
+    /// Rotates files or reconnects pipes in between records, returns true if a new
+    /// stream was started which needs its own metadata header
+    pub fn reopen_if_needed(&mut self) -> io::Result<bool> {
+        match self {
+            Output::File(file) if file.needs_rotation() => file.rotate().map(|_| true),
+            Output::Fifo(fifo) => fifo.connect(),
+            _ => Ok(false),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(file) => file.write(buf),
+            Output::Fifo(fifo) => fifo.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.flush(),
+            Output::Fifo(fifo) => fifo.flush(),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Backend reading the MMDC counters through the kernel's mmdc perf PMU
+
+use nix::libc;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+
+const PMU_DEVICES: &str = "/sys/bus/event_source/devices";
+
+/// Perf event names in MADPSR0..MADPSR5 order
+const EVENTS: [&str; 6] = [
+    "total-cycles",
+    "busy-cycles",
+    "read-accesses",
+    "write-accesses",
+    "read-bytes",
+    "write-bytes",
+];
+
+/// perf_event_attr types of the CPU PMU events
+pub const PERF_TYPE_HARDWARE: u32 = 0;
+pub const PERF_TYPE_RAW: u32 = 4;
+
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+const PERF_EVENT_IOC_RESET: libc::c_ulong = 0x2403;
+
+/// `struct perf_event_attr` up to PERF_ATTR_SIZE_VER5
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+/// Sysfs directory of the MMDC PMU, named `mmdc` or `mmdc<N>` depending on the kernel
+fn find_pmu() -> io::Result<PathBuf> {
+    fs::read_dir(PMU_DEVICES)?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("mmdc"))
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no mmdc perf PMU registered"))
+}
+
+/// Parses sysfs values such as `8`, `0-3` or `event=0x04`
+fn read_number(path: &Path) -> io::Result<u64> {
+    let content = fs::read_to_string(path)?;
+    let content = content.trim();
+    let value = content
+        .split(',')
+        .find_map(|term| term.strip_prefix("event="))
+        .unwrap_or(content);
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.split(['-', ',']).next().unwrap_or_default().parse(),
+    };
+    parsed.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Opens an event counting on `cpu` for all processes
+fn open_event(attr: &PerfEventAttr, cpu: libc::c_int) -> io::Result<File> {
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            attr as *const PerfEventAttr,
+            -1 as libc::pid_t,
+            cpu,
+            -1 as libc::c_int,
+            0 as libc::c_ulong,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+fn read_count(event: &mut File) -> io::Result<u64> {
+    let mut value = [0; 8];
+    event.read_exact(&mut value)?;
+    Ok(u64::from_ne_bytes(value))
+}
+
+/// CPUs of a sysfs list such as `0-3` or `0,2-3`
+fn parse_cpu_list(list: &str) -> Vec<libc::c_int> {
+    list.trim()
+        .split(',')
+        .filter_map(|range| match range.split_once('-') {
+            Some((first, last)) => Some((first.parse().ok()?, last.parse().ok()?)),
+            None => range.parse().ok().map(|cpu| (cpu, cpu)),
+        })
+        .flat_map(|(first, last)| first..=last)
+        .collect()
+}
+
+fn ioctl(file: &File, request: libc::c_ulong) -> io::Result<()> {
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The six MMDC counters opened as system wide perf events
+pub struct PerfCounters {
+    events: Vec<File>,
+}
+
+impl PerfCounters {
+    /// Opens the counters filtered to the masters selected by the MADPCR1 `master` value
+    pub fn open(master: u32) -> io::Result<PerfCounters> {
+        let pmu = find_pmu()?;
+        let kind = read_number(&pmu.join("type"))? as u32;
+        // uncore PMUs only accept events on the CPU listed in their cpumask
+        let cpu = read_number(&pmu.join("cpumask")).unwrap_or_default() as libc::c_int;
+
+        let mut events = Vec::new();
+        for (index, name) in EVENTS.iter().enumerate() {
+            let config = read_number(&pmu.join("events").join(name)).unwrap_or(index as u64);
+            let attr = PerfEventAttr {
+                kind,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config,
+                // axi_id format attribute, written to MADPCR1 by the driver
+                config1: master as u64,
+                // disabled until enabled explicitly
+                flags: 1,
+                ..Default::default()
+            };
+            events.push(open_event(&attr, cpu)?);
+        }
+        Ok(PerfCounters { events })
+    }
+
+    pub fn start(&self) -> io::Result<()> {
+        for event in &self.events {
+            ioctl(event, PERF_EVENT_IOC_RESET)?;
+            ioctl(event, PERF_EVENT_IOC_ENABLE)?;
+        }
+        Ok(())
+    }
+
+    pub fn stop(&self) -> io::Result<()> {
+        for event in &self.events {
+            ioctl(event, PERF_EVENT_IOC_DISABLE)?;
+        }
+        Ok(())
+    }
+
+    /// Counts since `start` in MADPSR0..MADPSR5 order
+    pub fn read(&mut self) -> io::Result<[u64; 6]> {
+        let mut counters = [0; 6];
+        for (counter, event) in counters.iter_mut().zip(self.events.iter_mut()) {
+            *counter = read_count(event)?;
+        }
+        Ok(counters)
+    }
+}
+
+/// One CPU PMU event counting on all online CPUs, e.g. the cycles or the L2 refills
+pub struct CpuEvent {
+    cpus: Vec<File>,
+}
+
+impl CpuEvent {
+    /// Opens the event of the given perf type and config, counting from now on
+    pub fn open(kind: u32, config: u64) -> io::Result<CpuEvent> {
+        let online = fs::read_to_string("/sys/devices/system/cpu/online")?;
+        let attr = PerfEventAttr {
+            kind,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config,
+            ..Default::default()
+        };
+        let cpus = parse_cpu_list(&online)
+            .into_iter()
+            .map(|cpu| open_event(&attr, cpu))
+            .collect::<io::Result<Vec<File>>>()?;
+        Ok(CpuEvent { cpus })
+    }
+
+    /// Count of all CPUs since the event was opened
+    pub fn read(&mut self) -> io::Result<u64> {
+        let mut total = 0;
+        for cpu in &mut self.cpus {
+            total += read_count(cpu)?;
+        }
+        Ok(total)
+    }
+}
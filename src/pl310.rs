@@ -0,0 +1,172 @@
+//! Per window event counts of the PL310 L2 cache controller, linking DDR traffic to
+//! linefills and evictions
+
+use crate::schema::Column;
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use std::convert::TryInto;
+use std::ffi::c_void;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+/// Length of the mapped register page
+const MAP_LENGTH: usize = 0x1000;
+
+/// Event counter registers, those of counter 1 precede those of counter 0
+const EV_COUNTER_CTRL: usize = 0x200;
+const EV_COUNTER1_CFG: usize = 0x204;
+const EV_COUNTER0_CFG: usize = 0x208;
+const EV_COUNTER1: usize = 0x20C;
+const EV_COUNTER0: usize = 0x210;
+
+/// EV_COUNTER_CTRL bits
+const COUNTING_ENABLE: u32 = 0x1;
+const COUNTER0_RESET: u32 = 0x2;
+const COUNTER1_RESET: u32 = 0x4;
+
+/// Event sources of the counter configuration, bits 5:2
+const EVENTS: [(&str, u32, &str); 15] = [
+    ("co", 0x1, "Evictions of dirty lines to DDR"),
+    ("drhit", 0x2, "Data read hits"),
+    (
+        "drreq",
+        0x3,
+        "Data read requests, misses are linefills from DDR",
+    ),
+    ("dwhit", 0x4, "Data write hits"),
+    ("dwreq", 0x5, "Data write requests"),
+    ("dwtreq", 0x6, "Data write-through requests"),
+    ("irhit", 0x7, "Instruction read hits"),
+    ("irreq", 0x8, "Instruction read requests"),
+    ("wa", 0x9, "Write allocations, linefills caused by writes"),
+    (
+        "ipfalloc",
+        0xA,
+        "Lines allocated by the internal prefetcher",
+    ),
+    ("epfhit", 0xB, "Hits of prefetch hints"),
+    ("epfalloc", 0xC, "Lines allocated by prefetch hints"),
+    ("srrcvd", 0xD, "Speculative reads received"),
+    ("srconf", 0xE, "Confirmed speculative reads"),
+    ("epfrcvd", 0xF, "Prefetch hints received"),
+];
+
+/// Names of EVENTS, for the command line
+pub const EVENT_NAMES: [&str; 15] = [
+    "co", "drhit", "drreq", "dwhit", "dwreq", "dwtreq", "irhit", "irreq", "wa", "ipfalloc",
+    "epfhit", "epfalloc", "srrcvd", "srconf", "epfrcvd",
+];
+
+/// Number of event counters of the PL310
+pub const COUNTERS: usize = 2;
+
+fn find_event(name: &str) -> io::Result<&'static (&'static str, u32, &'static str)> {
+    EVENTS
+        .iter()
+        .find(|(event, _, _)| *event == name)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown PL310 event {}", name),
+            )
+        })
+}
+
+/// Columns of the named events
+pub fn columns(names: &[String]) -> Vec<Column> {
+    names
+        .iter()
+        .filter_map(|name| find_event(name).ok())
+        .map(|(name, _, description)| Column {
+            name: format!("l2_{}", name),
+            unit: "events",
+            description,
+        })
+        .collect()
+}
+
+/// The two event counters, mapped from /dev/mem and disabled again on drop
+pub struct L2Events {
+    registers: *mut u8,
+    events: Vec<&'static (&'static str, u32, &'static str)>,
+    _file: File,
+}
+
+impl L2Events {
+    /// Counts the named events, at most one per counter, from now on
+    pub fn open(base_address: u64, names: &[String]) -> io::Result<L2Events> {
+        if names.len() > COUNTERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the PL310 has {} event counters", COUNTERS),
+            ));
+        }
+        let events = names
+            .iter()
+            .map(|name| find_event(name))
+            .collect::<io::Result<Vec<_>>>()?;
+        let offset = base_address.try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "base address out of range")
+        })?;
+        let file = OpenOptions::new().read(true).write(true).open("/dev/mem")?;
+        let registers = unsafe {
+            mmap(
+                ptr::null_mut(),
+                MAP_LENGTH,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED,
+                file.as_raw_fd(),
+                offset,
+            )
+        }
+        .map_err(|e| io::Error::other(e.to_string()))? as *mut u8;
+        let l2 = L2Events {
+            registers,
+            events,
+            _file: file,
+        };
+        l2.write(EV_COUNTER_CTRL, 0);
+        for (event, config) in l2.events.iter().zip([EV_COUNTER0_CFG, EV_COUNTER1_CFG]) {
+            l2.write(config, event.1 << 2);
+        }
+        l2.reset();
+        Ok(l2)
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile(self.registers.add(offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.registers.add(offset) as *mut u32, value) }
+    }
+
+    fn reset(&self) {
+        self.write(
+            EV_COUNTER_CTRL,
+            COUNTING_ENABLE | COUNTER0_RESET | COUNTER1_RESET,
+        );
+    }
+
+    /// Counts of each event since the previous read, the counters are reset right away so
+    /// the 32 bits don't saturate
+    pub fn read_events(&self) -> Vec<(String, i64)> {
+        let counts = [self.read(EV_COUNTER0), self.read(EV_COUNTER1)];
+        self.reset();
+        self.events
+            .iter()
+            .zip(counts)
+            .map(|((name, _, _), count)| (format!("l2_{}", name), count as i64))
+            .collect()
+    }
+}
+
+impl Drop for L2Events {
+    fn drop(&mut self) {
+        self.write(EV_COUNTER_CTRL, 0);
+        unsafe {
+            let _ = munmap(self.registers as *mut c_void, MAP_LENGTH);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Per window Cortex-A PMU counts of all CPUs, quantifying the CPU's share of the DDR
+//! traffic
+
+use crate::schema::Column;
+use r_mmdc::perf::{CpuEvent, PERF_TYPE_HARDWARE, PERF_TYPE_RAW};
+use std::io;
+
+/// Column, perf type, config and description of each event. The ARMv7 common event
+/// numbers are used raw, the generic cache events don't name the L2 on every kernel.
+const EVENTS: [(&str, u32, u64, &str); 3] = [
+    (
+        "pmu_cycles",
+        PERF_TYPE_HARDWARE,
+        0,
+        "CPU cycles of all CPUs since the previous record",
+    ),
+    (
+        "pmu_l2_refills",
+        PERF_TYPE_RAW,
+        0x17,
+        "L2 data cache refills since the previous record",
+    ),
+    (
+        "pmu_bus_accesses",
+        PERF_TYPE_RAW,
+        0x19,
+        "CPU bus accesses since the previous record",
+    ),
+];
+
+pub struct CpuPmu {
+    events: Vec<CpuEvent>,
+    previous: Vec<u64>,
+}
+
+impl CpuPmu {
+    pub fn open() -> io::Result<CpuPmu> {
+        let events = EVENTS
+            .iter()
+            .map(|(_, kind, config, _)| CpuEvent::open(*kind, *config))
+            .collect::<io::Result<Vec<CpuEvent>>>()?;
+        Ok(CpuPmu {
+            previous: vec![0; events.len()],
+            events,
+        })
+    }
+
+    pub fn columns() -> Vec<Column> {
+        EVENTS
+            .iter()
+            .map(|(name, _, _, description)| Column {
+                name: name.to_string(),
+                unit: "events",
+                description,
+            })
+            .collect()
+    }
+
+    /// Counts of each event since the previous read
+    pub fn read(&mut self) -> Vec<(String, i64)> {
+        let mut deltas = Vec::new();
+        for ((name, ..), (event, previous)) in EVENTS
+            .iter()
+            .zip(self.events.iter_mut().zip(self.previous.iter_mut()))
+        {
+            let total = event.read().unwrap_or(*previous);
+            deltas.push((name.to_string(), total.saturating_sub(*previous) as i64));
+            *previous = total;
+        }
+        deltas
+    }
+}
@@ -0,0 +1,118 @@
+//! Decoding of MAPSR, the automatic power saving of the MMDC core
+
+use crate::{bits, MMDC};
+use nix::sys::mman::{msync, MsFlags};
+use std::fmt;
+use std::ptr;
+
+/// MAPSR PSD bit, set disables the automatic power saving
+const PSD: u32 = 0x1;
+
+/// MAPSR fields, see the power saving control and status register of the i.MX6 reference
+/// manual
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerSaving {
+    /// PSD cleared, the DDR enters self-refresh after `timer` idle periods
+    pub enabled: bool,
+    /// PST, idle periods of 64 cycles before entering self-refresh
+    pub timer: u32,
+    /// PSS, the DDR is in self-refresh right now
+    pub self_refresh: bool,
+    /// RIS, no read access is pending
+    pub read_idle: bool,
+    /// WIS, no write access is pending
+    pub write_idle: bool,
+    /// LPMD and LPACK, low power mode requested and acknowledged
+    pub low_power_request: bool,
+    pub low_power_ack: bool,
+    /// DVFS and DVACK, self-refresh for a frequency change requested and acknowledged
+    pub dvfs_request: bool,
+    pub dvfs_ack: bool,
+}
+
+impl PowerSaving {
+    pub fn from_register(mapsr: u32) -> PowerSaving {
+        PowerSaving {
+            enabled: mapsr & PSD == 0,
+            timer: bits(mapsr, 8, 8),
+            self_refresh: bits(mapsr, 4, 1) == 1,
+            read_idle: bits(mapsr, 5, 1) == 1,
+            write_idle: bits(mapsr, 6, 1) == 1,
+            low_power_request: bits(mapsr, 16, 1) == 1,
+            low_power_ack: bits(mapsr, 20, 1) == 1,
+            dvfs_request: bits(mapsr, 21, 1) == 1,
+            dvfs_ack: bits(mapsr, 24, 1) == 1,
+        }
+    }
+}
+
+/// MAPSR with the automatic power saving switched on or off
+pub fn with_power_saving(mapsr: u32, enabled: bool) -> u32 {
+    if enabled {
+        mapsr & !PSD
+    } else {
+        mapsr | PSD
+    }
+}
+
+impl fmt::Display for PowerSaving {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.enabled {
+            writeln!(
+                f,
+                "  Automatic power saving: enabled, self-refresh after {} idle cycles",
+                self.timer * 64
+            )?;
+        } else {
+            writeln!(f, "  Automatic power saving: disabled")?;
+        }
+        writeln!(
+            f,
+            "  Self-refresh: {}, read idle: {}, write idle: {}",
+            self.self_refresh, self.read_idle, self.write_idle
+        )?;
+        write!(
+            f,
+            "  Low power mode request: {} (ack {}), DVFS request: {} (ack {})",
+            self.low_power_request, self.low_power_ack, self.dvfs_request, self.dvfs_ack
+        )
+    }
+}
+
+pub fn print_power_saving(mapsr: u32) {
+    println!("Power saving (MAPSR 0x{:08X}):", mapsr);
+    println!("{}", PowerSaving::from_register(mapsr));
+}
+
+fn write_mapsr(mapsr: *mut u32, value: u32) {
+    unsafe {
+        ptr::write_volatile(mapsr, value);
+        let _ = msync(mapsr as *mut _, 4, MsFlags::MS_SYNC);
+    }
+}
+
+/// Keeps the automatic power saving off for a run, restored by `restore`
+pub struct PowerSavingOff {
+    mapsr: *mut u32,
+    original: u32,
+}
+
+impl PowerSavingOff {
+    pub fn apply(mmdc: &mut MMDC) -> PowerSavingOff {
+        let mapsr = &mut mmdc.mapsr as *mut u32;
+        let original = unsafe { ptr::read_volatile(mapsr) };
+        write_mapsr(mapsr, with_power_saving(original, false));
+        PowerSavingOff { mapsr, original }
+    }
+
+    /// Restores the original setting from another thread, e.g. when the process is
+    /// terminated. The mapping outlives all threads, the process exits with it.
+    pub fn restorer(&self) -> impl FnOnce() + Send + 'static {
+        let (address, original) = (self.mapsr as usize, self.original);
+        move || write_mapsr(address as *mut u32, original)
+    }
+
+    pub fn restore(self) {
+        write_mapsr(self.mapsr, self.original);
+    }
+}
@@ -0,0 +1,290 @@
+//! Programmatic profiler configured through `MmdcProfiler::builder()`
+
+use crate::annotation::Annotations;
+use crate::map::MmdcMap;
+#[cfg(feature = "perf")]
+use crate::perf::PerfCounters;
+use crate::sample_sink::{RunTotals, SampleSink};
+use mmdc_core::units::{Bandwidth, Beats, Bytes, Cycles, MegabytesPerSecond, Percent};
+use mmdc_core::{
+    bits, Counters, CYC_OVF, DBG_EN, DBG_RST, MMDC_MAP_LENGTH, MMDC_P0_IPS_BASE_ADDR,
+    MMDC_P1_IPS_BASE_ADDR, PRF_FRZ,
+};
+use std::fmt;
+use std::io;
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// MMDC port to profile, P1 is only in use in 2-channel LPDDR2 configurations
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Channel {
+    P0,
+    P1,
+}
+
+impl Channel {
+    fn base_address(self) -> u64 {
+        match self {
+            Channel::P0 => MMDC_P0_IPS_BASE_ADDR,
+            Channel::P1 => MMDC_P1_IPS_BASE_ADDR,
+        }
+    }
+}
+
+/// Where the counters are read from
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Backend {
+    /// Registers mapped from /dev/mem
+    DevMem,
+    /// The kernel's mmdc perf PMU
+    #[cfg(feature = "perf")]
+    Perf,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    ZeroInterval,
+    UnsupportedChannel(Backend, Channel),
+    Io(io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::ZeroInterval => write!(f, "interval must not be zero"),
+            ConfigError::UnsupportedChannel(backend, channel) => {
+                write!(
+                    f,
+                    "channel {:?} is not supported by the {:?} backend",
+                    channel, backend
+                )
+            }
+            ConfigError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Counters of one measuring interval
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sample {
+    pub counters: Counters,
+    pub time: Duration,
+    pub bus_width: u32,
+    /// Annotations of the profiler at the end of the interval
+    pub annotations: Vec<(String, String)>,
+}
+
+impl Sample {
+    pub fn annotation(&self, key: &str) -> Option<&str> {
+        self.annotations
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn total_cycles(&self) -> Cycles {
+        self.counters.total_cycles
+    }
+
+    pub fn busy_cycles(&self) -> Cycles {
+        self.counters.busy_cycles
+    }
+
+    pub fn read_bytes(&self) -> Bytes {
+        self.counters.read_bytes
+    }
+
+    pub fn write_bytes(&self) -> Bytes {
+        self.counters.write_bytes
+    }
+
+    pub fn read_beats(&self) -> Beats {
+        self.read_bytes().beats(self.bus_width)
+    }
+
+    pub fn write_beats(&self) -> Beats {
+        self.write_bytes().beats(self.bus_width)
+    }
+
+    pub fn bandwidth(&self) -> Bandwidth {
+        let read = self.read_bytes().per(self.time);
+        let write = self.write_bytes().per(self.time);
+        Bandwidth {
+            read,
+            write,
+            total: MegabytesPerSecond(read.get() + write.get()),
+        }
+    }
+
+    /// Transferred bytes relative to what the bus could move in the busy cycles
+    pub fn utilization(&self) -> Percent {
+        self.counters.utilization(self.bus_width)
+    }
+
+    /// Busy cycles relative to all cycles
+    pub fn data_load(&self) -> Percent {
+        self.counters.data_load()
+    }
+}
+
+pub struct ProfilerBuilder {
+    channel: Channel,
+    master: u32,
+    interval: Duration,
+    backend: Backend,
+}
+
+impl ProfilerBuilder {
+    pub fn channel(mut self, channel: Channel) -> ProfilerBuilder {
+        self.channel = channel;
+        self
+    }
+
+    /// MADPCR1 AXI ID filter of the master to profile, e.g. `mmdc_core::AXI_ARM`
+    pub fn master(mut self, master: u32) -> ProfilerBuilder {
+        self.master = master;
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> ProfilerBuilder {
+        self.interval = interval;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> ProfilerBuilder {
+        self.backend = backend;
+        self
+    }
+
+    /// Validates the configuration and opens the backend
+    pub fn build(self) -> Result<MmdcProfiler, ConfigError> {
+        if self.interval == Duration::from_millis(0) {
+            return Err(ConfigError::ZeroInterval);
+        }
+        let (source, bus_width) = match self.backend {
+            Backend::DevMem => {
+                let mut mmdc = MmdcMap::open(self.channel.base_address(), MMDC_MAP_LENGTH)
+                    .map_err(ConfigError::Io)?;
+                let dsiz = unsafe {
+                    ptr::write_volatile(&mut mmdc.madpcr1, self.master);
+                    bits(ptr::read_volatile(&mmdc.mdctl), 16, 2)
+                };
+                (Source::DevMem(mmdc), 16 << dsiz)
+            }
+            #[cfg(feature = "perf")]
+            Backend::Perf => {
+                // the PMU is only registered for the first port
+                if self.channel != Channel::P0 {
+                    return Err(ConfigError::UnsupportedChannel(self.backend, self.channel));
+                }
+                let perf = PerfCounters::open(self.master).map_err(ConfigError::Io)?;
+                // no access to MDCTL, assumes the common 32-bit bus
+                (Source::Perf(perf), 32)
+            }
+        };
+        Ok(MmdcProfiler {
+            source,
+            interval: self.interval,
+            bus_width,
+            annotations: Annotations::default(),
+        })
+    }
+}
+
+enum Source {
+    DevMem(MmdcMap),
+    #[cfg(feature = "perf")]
+    Perf(PerfCounters),
+}
+
+pub struct MmdcProfiler {
+    source: Source,
+    interval: Duration,
+    bus_width: u32,
+    annotations: Annotations,
+}
+
+impl MmdcProfiler {
+    pub fn builder() -> ProfilerBuilder {
+        ProfilerBuilder {
+            channel: Channel::P0,
+            master: 0,
+            interval: Duration::from_secs(1),
+            backend: Backend::DevMem,
+        }
+    }
+
+    /// Annotates the following samples, e.g. `profiler.annotate("test_case", "video_1080p")`
+    pub fn annotate(&self, key: &str, value: impl ToString) {
+        self.annotations.set(key, value);
+    }
+
+    /// Handle to the annotations for other threads, e.g. a render loop setting the frame
+    /// number while `run` blocks
+    pub fn annotations(&self) -> Annotations {
+        self.annotations.clone()
+    }
+
+    /// Counts for one interval, blocking for its duration
+    pub fn sample(&mut self) -> io::Result<Sample> {
+        let start_time = Instant::now();
+        let counters = match &mut self.source {
+            Source::DevMem(mmdc) => unsafe {
+                ptr::write_volatile(&mut mmdc.madpcr0, DBG_RST | CYC_OVF);
+                ptr::write_volatile(&mut mmdc.madpcr0, DBG_EN);
+                thread::sleep(self.interval);
+                ptr::write_volatile(&mut mmdc.madpcr0, DBG_EN | PRF_FRZ);
+                let counters = Counters::from_raw([
+                    ptr::read_volatile(&mmdc.madpsr0),
+                    ptr::read_volatile(&mmdc.madpsr1),
+                    ptr::read_volatile(&mmdc.madpsr2),
+                    ptr::read_volatile(&mmdc.madpsr3),
+                    ptr::read_volatile(&mmdc.madpsr4),
+                    ptr::read_volatile(&mmdc.madpsr5),
+                ]);
+                ptr::write_volatile(&mut mmdc.madpcr0, 0);
+                counters
+            },
+            #[cfg(feature = "perf")]
+            Source::Perf(perf) => {
+                perf.start()?;
+                thread::sleep(self.interval);
+                perf.stop()?;
+                let mut raw = [0; 6];
+                for (target, count) in raw.iter_mut().zip(perf.read()?.iter()) {
+                    *target = *count as u32;
+                }
+                Counters::from_raw(raw)
+            }
+        };
+        Ok(Sample {
+            counters,
+            time: start_time.elapsed(),
+            bus_width: self.bus_width,
+            annotations: self.annotations.snapshot(),
+        })
+    }
+
+    /// Measures `samples` intervals and hands them to `sink`, followed by their totals
+    pub fn run(&mut self, samples: u32, sink: &mut dyn SampleSink) -> io::Result<RunTotals> {
+        sink.on_metadata(&[
+            ("bus_width", self.bus_width.to_string()),
+            ("interval_ms", self.interval.as_millis().to_string()),
+        ])?;
+        let mut totals = RunTotals::default();
+        for _ in 0..samples {
+            let sample = self.sample()?;
+            totals.add(&sample);
+            sink.on_sample(&sample)?;
+        }
+        sink.on_summary(&totals)?;
+        sink.flush()?;
+        Ok(totals)
+    }
+}
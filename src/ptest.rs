@@ -0,0 +1,70 @@
+//! Yocto ptest compatible DDR sanity test
+
+use crate::{do_measuring_cylce, measure_overhead, schedule, Opt, MMDC};
+
+/// Expectations checked against the averaged samples, unset limits are skipped
+pub struct Expectations {
+    pub samples: u32,
+    pub min_bandwidth: Option<f32>,
+    pub max_bandwidth: Option<f32>,
+    pub min_utilization: Option<u32>,
+    pub max_utilization: Option<u32>,
+}
+
+fn report(name: &str, result: Option<bool>) -> bool {
+    match result {
+        Some(true) => println!("PASS: {}", name),
+        Some(false) => println!("FAIL: {}", name),
+        None => println!("SKIP: {}", name),
+    }
+    result != Some(false)
+}
+
+/// Runs the measurement and prints one ptest line per check, returns false if any check failed
+pub fn run(mmdc: &mut MMDC, opt: &Opt, bus_width: u32, expectations: &Expectations) -> bool {
+    let samples = expectations.samples.max(1);
+    let overhead = measure_overhead(mmdc, bus_width);
+    let mut scheduler = schedule::from_opt(opt);
+
+    for _ in 0..opt.warmup {
+        do_measuring_cylce(mmdc, bus_width, overhead, scheduler.as_mut());
+    }
+
+    let mut counting = true;
+    let (mut total_bandwidth, mut total_utilization) = (0_f32, 0_u32);
+    for _ in 0..samples {
+        let (results, time) = do_measuring_cylce(mmdc, bus_width, overhead, scheduler.as_mut());
+        counting &= results.total_cycles > 0;
+        total_bandwidth += results.bandwidth(time).2;
+        total_utilization += results.utilization;
+    }
+    let bandwidth = total_bandwidth / samples as f32;
+    let utilization = total_utilization / samples;
+    println!(
+        "INFO: {} samples, average {:.2}MB/s at {}% utilization",
+        samples, bandwidth, utilization
+    );
+
+    let checks = [
+        ("mmdc-cycle-counter", Some(counting)),
+        (
+            "mmdc-min-bandwidth",
+            expectations.min_bandwidth.map(|min| bandwidth >= min),
+        ),
+        (
+            "mmdc-max-bandwidth",
+            expectations.max_bandwidth.map(|max| bandwidth <= max),
+        ),
+        (
+            "mmdc-min-utilization",
+            expectations.min_utilization.map(|min| utilization >= min),
+        ),
+        (
+            "mmdc-max-utilization",
+            expectations.max_utilization.map(|max| utilization <= max),
+        ),
+    ];
+    checks.iter().fold(true, |passed, (name, result)| {
+        report(name, *result) && passed
+    })
+}
@@ -0,0 +1,66 @@
+//! Extension point for the outputs of a profiling run. The encoders of the r-mmdc tool
+//! implement it for their records, library users implement it for `Sample`s to feed the
+//! measurements into their own telemetry.
+
+use crate::profiler::Sample;
+use mmdc_core::units::{Bandwidth, MegabytesPerSecond};
+use std::io;
+use std::time::Duration;
+
+/// Receives the metadata, every sample and the summary of a run, in that order
+pub trait SampleSink<S = Sample, T = RunTotals> {
+    /// Key value pairs describing the run, before the first sample
+    fn on_metadata(&mut self, _metadata: &[(&str, String)]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_sample(&mut self, sample: &S) -> io::Result<()>;
+
+    /// Statistics over the run, after the last sample
+    fn on_summary(&mut self, _summary: &T) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Counters accumulated over the samples of a run, wide enough for long runs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RunTotals {
+    pub samples: u64,
+    pub time: Duration,
+    pub total_cycles: u64,
+    pub busy_cycles: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+impl RunTotals {
+    pub fn add(&mut self, sample: &Sample) {
+        self.samples += 1;
+        self.time += sample.time;
+        self.total_cycles += sample.counters.total_cycles.get() as u64;
+        self.busy_cycles += sample.counters.busy_cycles.get() as u64;
+        self.read_bytes += sample.counters.read_bytes.get() as u64;
+        self.write_bytes += sample.counters.write_bytes.get() as u64;
+    }
+
+    /// Mean bandwidth over the whole run, zero for an empty one
+    pub fn bandwidth(&self) -> Bandwidth {
+        let seconds = self.time.as_secs_f64();
+        let rate = |bytes: u64| {
+            if seconds == 0_f64 {
+                return MegabytesPerSecond(0_f32);
+            }
+            MegabytesPerSecond((bytes as f64 / (1024_f64 * 1024_f64) / seconds) as f32)
+        };
+        Bandwidth {
+            read: rate(self.read_bytes),
+            write: rate(self.write_bytes),
+            total: rate(self.read_bytes + self.write_bytes),
+        }
+    }
+}
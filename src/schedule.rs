@@ -0,0 +1,224 @@
+//! Policies deciding when the measuring windows of a run start and end
+
+use crate::{until_boundary, MMDCProfileResult, Opt};
+use std::time::Duration;
+
+pub const SCHEDULES: [&str; 4] = ["fixed", "aligned", "duty", "adaptive"];
+
+/// Consecutive quiet windows after which the adaptive schedule slows down again
+const ADAPTIVE_HOLD: u32 = 10;
+
+/// Asked before every window of the sampling loop
+pub trait Scheduler {
+    /// Time to wait before the next window starts
+    fn pause(&mut self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    /// Length of the window starting now
+    fn window(&mut self) -> Duration;
+
+    /// Results of the finished window, for policies following the load
+    fn observe(&mut self, _results: &MMDCProfileResult, _time: u32) {}
+}
+
+/// Back to back windows of the sleep time
+pub struct Fixed {
+    interval: Duration,
+}
+
+impl Fixed {
+    pub fn new(interval: Duration) -> Fixed {
+        Fixed { interval }
+    }
+}
+
+impl Scheduler for Fixed {
+    fn window(&mut self) -> Duration {
+        self.interval
+    }
+}
+
+/// Windows starting and ending on wall-clock multiples of the sleep time
+pub struct Aligned {
+    interval: Duration,
+    started: bool,
+}
+
+impl Scheduler for Aligned {
+    /// Only the first window waits, the following ones each end on the next boundary
+    fn pause(&mut self) -> Duration {
+        if self.started {
+            return Duration::from_millis(0);
+        }
+        self.started = true;
+        until_boundary(self.interval)
+    }
+
+    fn window(&mut self) -> Duration {
+        until_boundary(self.interval)
+    }
+}
+
+/// A window of the sleep time at the start of every period, idle for the rest of it
+pub struct DutyCycle {
+    interval: Duration,
+    period: Duration,
+    started: bool,
+}
+
+impl Scheduler for DutyCycle {
+    fn pause(&mut self) -> Duration {
+        if !self.started {
+            self.started = true;
+            return Duration::from_millis(0);
+        }
+        self.period.saturating_sub(self.interval)
+    }
+
+    fn window(&mut self) -> Duration {
+        self.interval
+    }
+}
+
+/// Long windows while the total bandwidth stays below the threshold, windows of the sleep
+/// time from the first one above it until it was quiet for ADAPTIVE_HOLD windows
+pub struct Adaptive {
+    fast: Duration,
+    slow: Duration,
+    threshold: f32,
+    quiet: u32,
+}
+
+impl Scheduler for Adaptive {
+    fn window(&mut self) -> Duration {
+        if self.quiet >= ADAPTIVE_HOLD {
+            self.slow
+        } else {
+            self.fast
+        }
+    }
+
+    fn observe(&mut self, results: &MMDCProfileResult, time: u32) {
+        if results.bandwidth(time).2 >= self.threshold {
+            self.quiet = 0;
+        } else {
+            self.quiet = self.quiet.saturating_add(1);
+        }
+    }
+}
+
+/// Name of the policy selected by --schedule, or by the older --align
+pub fn name(opt: &Opt) -> &str {
+    match &opt.schedule {
+        Some(schedule) => schedule,
+        None if opt.align => "aligned",
+        None => "fixed",
+    }
+}
+
+pub fn from_opt(opt: &Opt) -> Box<dyn Scheduler> {
+    let interval = Duration::from_millis(opt.sleeptime);
+    match name(opt) {
+        "aligned" => Box::new(Aligned {
+            interval,
+            started: false,
+        }),
+        "duty" => Box::new(DutyCycle {
+            interval,
+            period: Duration::from_millis(opt.duty_period.unwrap_or(opt.sleeptime)),
+            started: false,
+        }),
+        "adaptive" => Box::new(Adaptive {
+            fast: interval,
+            slow: Duration::from_millis(opt.idle_sleeptime),
+            threshold: opt.activity_threshold.unwrap_or_default(),
+            quiet: 0,
+        }),
+        _ => Box::new(Fixed { interval }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(megabytes: u32) -> MMDCProfileResult {
+        MMDCProfileResult {
+            raw_counters: [0, 0, 0, 0, megabytes * 1024 * 1024, 0],
+            ..Default::default()
+        }
+    }
+
+    fn adaptive() -> Adaptive {
+        Adaptive {
+            fast: Duration::from_millis(100),
+            slow: Duration::from_millis(1000),
+            threshold: 50.0,
+            quiet: 0,
+        }
+    }
+
+    #[test]
+    fn adaptive_starts_fast() {
+        assert_eq!(adaptive().window(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn adaptive_slows_down_after_the_quiet_windows() {
+        let mut schedule = adaptive();
+        for _ in 0..ADAPTIVE_HOLD - 1 {
+            schedule.observe(&results(10), 1000);
+            assert_eq!(schedule.window(), Duration::from_millis(100));
+        }
+        schedule.observe(&results(10), 1000);
+        assert_eq!(schedule.window(), Duration::from_millis(1000));
+        schedule.observe(&results(0), 1000);
+        assert_eq!(schedule.window(), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn adaptive_speeds_up_on_one_busy_window() {
+        let mut schedule = adaptive();
+        for _ in 0..ADAPTIVE_HOLD {
+            schedule.observe(&results(0), 1000);
+        }
+        assert_eq!(schedule.window(), Duration::from_millis(1000));
+        schedule.observe(&results(50), 1000);
+        assert_eq!(schedule.window(), Duration::from_millis(100));
+        for _ in 0..ADAPTIVE_HOLD - 1 {
+            schedule.observe(&results(0), 1000);
+        }
+        assert_eq!(schedule.window(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn adaptive_uses_the_bandwidth_of_the_window_length() {
+        let mut schedule = adaptive();
+        schedule.quiet = ADAPTIVE_HOLD;
+        // 10 MB within 100 ms are 100 MB/s
+        schedule.observe(&results(10), 100);
+        assert_eq!(schedule.window(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn duty_cycle_idles_for_the_rest_of_the_period() {
+        let mut schedule = DutyCycle {
+            interval: Duration::from_millis(100),
+            period: Duration::from_millis(1000),
+            started: false,
+        };
+        assert_eq!(schedule.pause(), Duration::from_millis(0));
+        assert_eq!(schedule.window(), Duration::from_millis(100));
+        assert_eq!(schedule.pause(), Duration::from_millis(900));
+        assert_eq!(schedule.window(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn fixed_windows_are_back_to_back() {
+        let mut schedule = Fixed::new(Duration::from_millis(250));
+        schedule.observe(&results(100), 250);
+        assert_eq!(schedule.pause(), Duration::from_millis(0));
+        assert_eq!(schedule.window(), Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,461 @@
+//! Description of the record fields the current options emit, for generating or
+//! validating ingestion pipelines
+
+use crate::json::JsonObject;
+use crate::{Format, Opt, SCHEMA_VERSION};
+
+/// Extra integer column of a system source, e.g. the CPU frequencies
+pub struct Column {
+    pub name: String,
+    pub unit: &'static str,
+    pub description: &'static str,
+}
+
+struct Field {
+    name: String,
+    kind: &'static str,
+    unit: &'static str,
+    description: String,
+}
+
+fn field(name: &str, kind: &'static str, unit: &'static str, description: &str) -> Field {
+    Field {
+        name: name.to_string(),
+        kind,
+        unit,
+        description: description.to_string(),
+    }
+}
+
+/// Sequence number and timestamp fields by name
+fn clock_fields(names: &[&str]) -> Vec<Field> {
+    names
+        .iter()
+        .map(|name| match *name {
+            "seq" => field(
+                name,
+                "integer",
+                "",
+                "Number of the record in the run, gaps are dropped records",
+            ),
+            "timestamp_ms" => field(
+                name,
+                "integer",
+                "ms",
+                "Wall clock time since the Unix epoch",
+            ),
+            _ => field(
+                name,
+                "integer",
+                "ms",
+                "CLOCK_BOOTTIME at the end of the window, including suspended time",
+            ),
+        })
+        .collect()
+}
+
+/// Entries of the metadata record in the order they are emitted in, all values are
+/// strings
+fn metadata_fields() -> Vec<Field> {
+    [
+        ("version", "", "Version of r-mmdc"),
+        ("schema_version", "", "Version of the record fields"),
+        ("board", "", "--board-id or the hostname"),
+        ("soc", "", "SoC profile, unknown if detection failed"),
+        (
+            "soc_revision",
+            "",
+            "Silicon revision as major.minor, unknown where the system doesn't report it",
+        ),
+        ("channel", "", "MMDC port, 0 or 1"),
+        ("ddr_type", "", "DDR type configured in MDMISC"),
+        ("ddr_size_mb", "MiB", "DDR size configured in MDCTL"),
+        ("bus_width", "bit", "DDR bus width"),
+        ("madpcr1", "", "AXI ID filter, hexadecimal"),
+        ("masters", "", "Rotated masters, comma separated"),
+        ("groups", "", "Master groups, comma separated"),
+        ("sleeptime_ms", "ms", "Sleep time, the length of a window"),
+        ("cycles", "", "Windows to measure"),
+        ("warmup", "", "Windows measured ahead of the records"),
+        ("schedule", "", "Policy of the windows"),
+        (
+            "gap_free",
+            "",
+            "true if the counters run on between windows",
+        ),
+        ("overhead_us", "us", "Time to read the counters"),
+    ]
+    .iter()
+    .map(|(name, unit, description)| field(name, "string", unit, description))
+    .collect()
+}
+
+/// Fields of a record in the order they are emitted in
+fn fields(opt: &Opt, format: Format, metrics: &[String], columns: &[Column]) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let masters = !opt.master.is_empty() || !opt.group.is_empty();
+    let markers = opt.marker_fifo.is_some() || opt.control_fifo.is_some();
+    match format {
+        Format::Csv if masters => {
+            fields.push(field("channel", "string", "", "MMDC port, p0 or p1"));
+            fields.push(field(
+                "master",
+                "string",
+                "",
+                "AXI master the record is filtered to",
+            ));
+        }
+        Format::Influx => {
+            fields.push(field("channel", "tag", "", "MMDC port, p0 or p1"));
+            if masters {
+                fields.push(field(
+                    "master",
+                    "tag",
+                    "",
+                    "AXI master the record is filtered to",
+                ));
+            }
+            if opt.show_warmup {
+                fields.push(field(
+                    "warmup",
+                    "tag",
+                    "",
+                    "Present on discarded warm-up records",
+                ));
+            }
+            if markers {
+                fields.push(field("phase", "tag", "", "Phase of the last marker"));
+            }
+            fields.push(field(
+                "schema_version",
+                "integer",
+                "",
+                "Layout version of the record",
+            ));
+            fields.extend(clock_fields(&["seq", "boottime_ms"]));
+        }
+        Format::Json | Format::Jsonl => {
+            fields.push(field(
+                "schema_version",
+                "integer",
+                "",
+                "Layout version of the record",
+            ));
+            if masters {
+                fields.push(field(
+                    "master",
+                    "string",
+                    "",
+                    "AXI master the record is filtered to",
+                ));
+            }
+            if opt.show_warmup {
+                fields.push(field(
+                    "warmup",
+                    "boolean",
+                    "",
+                    "Present on discarded warm-up records",
+                ));
+            }
+            if opt.blame_threshold.is_some() {
+                fields.push(field(
+                    "suspects",
+                    "string",
+                    "",
+                    "Top contributors of a saturation, present on the record raising it",
+                ));
+            }
+            if markers {
+                fields.push(field(
+                    "phase",
+                    "string",
+                    "",
+                    "Phase of the last marker, absent before the first",
+                ));
+            }
+            fields.extend(clock_fields(&["seq", "timestamp_ms", "boottime_ms"]));
+        }
+        Format::Csv | Format::Human => {}
+    }
+
+    fields.push(field("time_ms", "integer", "ms", "Length of the window"));
+    fields.push(field(
+        "total_cycles",
+        "integer",
+        "cycles",
+        "DDR clock cycles in the window",
+    ));
+    fields.push(field(
+        "busy_cycles",
+        "integer",
+        "cycles",
+        "Cycles the controller was busy",
+    ));
+    fields.push(field(
+        "read_accesses",
+        "integer",
+        "accesses",
+        "AXI read bursts",
+    ));
+    fields.push(field(
+        "write_accesses",
+        "integer",
+        "accesses",
+        "AXI write bursts",
+    ));
+    fields.push(field("read_bytes", "integer", "bytes", "Bytes read"));
+    fields.push(field("write_bytes", "integer", "bytes", "Bytes written"));
+    if format != Format::Influx {
+        fields.push(field(
+            "avg_read_burstsize",
+            "integer",
+            "bytes",
+            "Bytes per read access",
+        ));
+        fields.push(field(
+            "avg_write_burstsize",
+            "integer",
+            "bytes",
+            "Bytes per write access",
+        ));
+    }
+    fields.push(field("read_mbps", "number", "MB/s", "Read bandwidth"));
+    fields.push(field("write_mbps", "number", "MB/s", "Write bandwidth"));
+    fields.push(field(
+        "total_mbps",
+        "number",
+        "MB/s",
+        "Read and write bandwidth",
+    ));
+    fields.push(field(
+        "utilization",
+        "integer",
+        "%",
+        "Busy cycles of all cycles",
+    ));
+    fields.push(field(
+        "data_load",
+        "integer",
+        "%",
+        "Bus cycles moving data of busy cycles",
+    ));
+    if format != Format::Influx {
+        fields.push(field(
+            "access_utilization",
+            "integer",
+            "%",
+            "Burst size of the maximum burst size",
+        ));
+    }
+    if opt.beats && format == Format::Csv {
+        fields.push(field(
+            "read_beats",
+            "integer",
+            "beats",
+            "Data bus transfers of reads",
+        ));
+        fields.push(field(
+            "write_beats",
+            "integer",
+            "beats",
+            "Data bus transfers of writes",
+        ));
+    }
+    if opt.raw && format == Format::Csv {
+        for register in 0..6 {
+            fields.push(field(
+                &format!("madpsr{}", register),
+                "integer",
+                "",
+                &format!("Unconverted MADPSR{} register", register),
+            ));
+        }
+    }
+    match format {
+        Format::Influx => fields.push(field(
+            "flags",
+            "integer",
+            "",
+            "Validity bits: 1 overflow, 2 frequency, 4 deadline, 8 freeze, 16 latch, 32 lowbus, 64 config, 128 scan",
+        )),
+        Format::Csv if !opt.flags => {}
+        _ => fields.push(field(
+            "flags",
+            "string",
+            "",
+            "Comma separated validity flags: overflow, frequency, deadline, freeze, latch, lowbus, config, scan",
+        )),
+    }
+    for metric in metrics {
+        fields.push(field(
+            metric,
+            "number",
+            "",
+            "Derived metric of the config file",
+        ));
+    }
+    for column in columns {
+        fields.push(field(
+            &column.name,
+            "integer",
+            column.unit,
+            column.description,
+        ));
+    }
+    if opt.report_every.unwrap_or(1) > 1 && format != Format::Influx {
+        let window = "of the windows in the record";
+        fields.push(field(
+            "min_total_mbps",
+            "number",
+            "MB/s",
+            &format!("Lowest bandwidth {}", window),
+        ));
+        fields.push(field(
+            "max_total_mbps",
+            "number",
+            "MB/s",
+            &format!("Highest bandwidth {}", window),
+        ));
+        fields.push(field(
+            "min_utilization",
+            "integer",
+            "%",
+            &format!("Lowest utilization {}", window),
+        ));
+        fields.push(field(
+            "max_utilization",
+            "integer",
+            "%",
+            &format!("Highest utilization {}", window),
+        ));
+    }
+    if opt.timestamps && format == Format::Csv {
+        fields.extend(clock_fields(&["timestamp_ms", "boottime_ms", "seq"]));
+    }
+    fields
+}
+
+/// JSON document with the format and one object per field, the human output is meant
+/// for reading and has none
+pub fn describe(opt: &Opt, metrics: &[String], columns: &[Column]) -> Option<String> {
+    let format = opt.output_format();
+    if format == Format::Human {
+        return None;
+    }
+    let describe = |fields: Vec<Field>| -> String {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| {
+                JsonObject::new()
+                    .string("name", &field.name)
+                    .string("type", field.kind)
+                    .string("unit", field.unit)
+                    .string("description", &field.description)
+                    .build()
+            })
+            .collect();
+        format!("[\n  {}\n]", fields.join(",\n  "))
+    };
+    let metadata = match format.has_metadata() {
+        true => describe(metadata_fields()),
+        false => "null".to_string(),
+    };
+    Some(format!(
+        "{{\"schema_version\":{},\"format\":\"{}\",\"separator\":{},\"metadata\":{},\"fields\":{}}}",
+        SCHEMA_VERSION,
+        format.name(),
+        match format {
+            Format::Csv => "\";\"",
+            _ => "null",
+        },
+        metadata,
+        describe(fields(opt, format, metrics, columns))
+    ))
+}
+
+/// Version of a parsed record or metadata, those of version 1 predate the field
+pub fn version(fields: &[(String, String)]) -> u32 {
+    fields
+        .iter()
+        .find(|(name, _)| name == "schema_version")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Fails for versions newer than the current one
+pub fn supported(version: u32) -> Result<(), String> {
+    match version > SCHEMA_VERSION {
+        true => Err(format!(
+            "schema version {} is newer than the supported {}",
+            version, SCHEMA_VERSION
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Brings the fields of a parsed record to the current layout. Version 1 samples have no
+/// sequence numbers, they are numbered by `index`, the position of the sample in the
+/// capture. Records of newer versions can't be read.
+pub fn upgrade(fields: &mut Vec<(String, String)>, index: u64) -> Result<(), String> {
+    let version = version(fields);
+    supported(version)?;
+    let sample = !fields.iter().any(|(name, _)| name == "record");
+    if version == 1 && sample && !fields.iter().any(|(name, _)| name == "seq") {
+        fields.push(("seq".to_string(), index.to_string()));
+    }
+    fields.retain(|(name, _)| name != "schema_version");
+    fields.insert(
+        0,
+        ("schema_version".to_string(), SCHEMA_VERSION.to_string()),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn version_1_samples_are_numbered() {
+        let mut sample = fields(&[("timestamp_ms", "10"), ("time_ms", "1000")]);
+        assert_eq!(version(&sample), 1);
+        upgrade(&mut sample, 4).unwrap();
+        assert_eq!(version(&sample), SCHEMA_VERSION);
+        assert!(sample.contains(&("seq".to_string(), "4".to_string())));
+        assert!(sample.contains(&("time_ms".to_string(), "1000".to_string())));
+    }
+
+    #[test]
+    fn version_1_metadata_has_no_sequence() {
+        let mut metadata = fields(&[("record", "metadata"), ("bus_width", "32")]);
+        upgrade(&mut metadata, 0).unwrap();
+        assert_eq!(version(&metadata), SCHEMA_VERSION);
+        assert!(!metadata.iter().any(|(key, _)| key == "seq"));
+    }
+
+    #[test]
+    fn current_records_keep_their_sequence() {
+        let current = SCHEMA_VERSION.to_string();
+        let mut sample = fields(&[("schema_version", &current), ("seq", "7")]);
+        upgrade(&mut sample, 0).unwrap();
+        assert_eq!(
+            sample,
+            fields(&[("schema_version", &current), ("seq", "7")])
+        );
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let newer = (SCHEMA_VERSION + 1).to_string();
+        let mut sample = fields(&[("schema_version", &newer)]);
+        assert!(upgrade(&mut sample, 0).is_err());
+        assert!(supported(SCHEMA_VERSION).is_ok());
+    }
+}
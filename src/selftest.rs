@@ -0,0 +1,100 @@
+//! Sanity checks of the register mapping, run first on new boards or kernels
+
+use crate::ddr::DdrType;
+use crate::{bits, clear_mmdc, load_mmdc_results, start_mmdc_profiling, stop_mmdc_profiling, MMDC};
+use nix::sys::mman::{msync, MsFlags};
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+const COUNT_TIME: Duration = Duration::from_millis(20);
+
+/// MADPCR1 pattern written and read back to verify write access
+const TEST_PATTERN: u32 = 0xA5A5_5A5A;
+
+fn check(name: &str, passed: bool, detail: String) -> bool {
+    println!(
+        "{}: {} ({})",
+        if passed { "PASS" } else { "FAIL" },
+        name,
+        detail
+    );
+    passed
+}
+
+/// Total cycle count of a window with the counters enabled or left disabled
+fn count_cycles(mmdc: &mut MMDC, enable: bool) -> u32 {
+    clear_mmdc(mmdc);
+    if enable {
+        start_mmdc_profiling(mmdc);
+    } else {
+        stop_mmdc_profiling(mmdc);
+    }
+    thread::sleep(COUNT_TIME);
+    load_mmdc_results(mmdc);
+    // the hardware updates the counters behind the compiler's back
+    let cycles = unsafe { ptr::read_volatile(&mmdc.madpsr0) };
+    stop_mmdc_profiling(mmdc);
+    cycles
+}
+
+fn read_back(mmdc: &mut MMDC, value: u32) -> u32 {
+    unsafe {
+        ptr::write_volatile(&mut mmdc.madpcr1, value);
+        let _ = msync(&mut mmdc.madpcr1 as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
+        ptr::read_volatile(&mmdc.madpcr1)
+    }
+}
+
+/// Runs all checks and prints a line per check, returns false if any failed
+pub fn run(mmdc: &mut MMDC) -> bool {
+    let mut passed = true;
+
+    let mdctl = mmdc.mdctl;
+    passed &= check(
+        "register block mapped",
+        mdctl != 0 && mdctl != 0xFFFF_FFFF,
+        format!("MDCTL 0x{:08X}", mdctl),
+    );
+    passed &= check(
+        "chip select enabled",
+        bits(mdctl, 30, 2) != 0,
+        format!("SDE_0 {}, SDE_1 {}", bits(mdctl, 31, 1), bits(mdctl, 30, 1)),
+    );
+    passed &= check(
+        "bus width valid",
+        bits(mdctl, 16, 2) < 3,
+        format!("DSIZ {}", bits(mdctl, 16, 2)),
+    );
+    let ddr_type = DdrType::from_registers(mmdc);
+    passed &= check(
+        "DDR type known",
+        !matches!(ddr_type, DdrType::Unknown(_)),
+        format!("{}", ddr_type),
+    );
+
+    let saved = mmdc.madpcr1;
+    let value = read_back(mmdc, TEST_PATTERN);
+    read_back(mmdc, saved);
+    passed &= check(
+        "MADPCR1 writable",
+        value == TEST_PATTERN,
+        format!("wrote 0x{:08X}, read 0x{:08X}", TEST_PATTERN, value),
+    );
+
+    let enabled = count_cycles(mmdc, true);
+    passed &= check(
+        "counters advance when enabled",
+        enabled > 0,
+        format!("{} cycles in {}ms", enabled, COUNT_TIME.as_millis()),
+    );
+    let disabled = count_cycles(mmdc, false);
+    passed &= check(
+        "counters halt when disabled",
+        disabled == 0,
+        format!("{} cycles in {}ms", disabled, COUNT_TIME.as_millis()),
+    );
+
+    println!("Self-test {}", if passed { "passed" } else { "failed" });
+    passed
+}
@@ -0,0 +1,633 @@
+//! Server mode, serves live samples to network clients
+
+use crate::downsample::{Overview, Point};
+use crate::summary::Summary;
+use crate::{json, websocket, Record};
+use r_mmdc::SampleSink;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Slow clients are dropped instead of stalling the sampling loop
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Clients which don't send their request in time are dropped instead of holding a thread
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Larger request lines with their headers are rejected
+const MAX_HEADER_LENGTH: usize = 8192;
+
+/// Larger request bodies are rejected, control requests are tiny
+const MAX_BODY_LENGTH: usize = 4096;
+
+/// Number of clients GET /delta keeps a cursor for
+const MAX_CURSORS: usize = 64;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    fn read_from(reader: &mut dyn BufRead) -> io::Result<Request> {
+        let mut head = io::Read::take(&mut *reader, MAX_HEADER_LENGTH as u64);
+        let mut read_line = |line: &mut String| match head.read_line(line)? {
+            _ if head.limit() == 0 => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request header too large",
+            )),
+            length => Ok(length),
+        };
+        let mut request_line = String::new();
+        read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let mut request = Request {
+            method,
+            path: path.to_string(),
+            query,
+            headers,
+            body: String::new(),
+        };
+        let length = request
+            .header("content-length")
+            .and_then(|length| length.parse::<usize>().ok())
+            .unwrap_or_default();
+        if length > MAX_BODY_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request body too large",
+            ));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        request.body = String::from_utf8_lossy(&body).to_string();
+        Ok(request)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn respond(
+    out: &mut dyn Write,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    write!(
+        out,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    out.flush()
+}
+
+/// Counters summed over all windows since the server started, 64 bits wide so they
+/// don't wrap like the 32 bit MADPSRx registers. They are updated once per completed
+/// window, time in which the counters didn't run, e.g. in between windows, during blame
+/// scans or while stopped, is summed up separately.
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    counters: [u64; 6],
+    time_ms: u64,
+    unaccounted_ms: u64,
+    /// Wall clock at the end of the last counted window, or at the start of the server
+    timestamp_ms: u64,
+}
+
+impl Totals {
+    fn add(&mut self, counters: [u32; 6], time: u32, timestamp_ms: u64) {
+        for (total, counter) in self.counters.iter_mut().zip(counters) {
+            *total += counter as u64;
+        }
+        self.time_ms += time as u64;
+        let elapsed = timestamp_ms.saturating_sub(self.timestamp_ms);
+        self.unaccounted_ms += elapsed.saturating_sub(time as u64);
+        self.timestamp_ms = timestamp_ms;
+    }
+
+    /// Counters and rates between the `since` cursor and these totals
+    fn delta_json(&self, since: &Totals, client: &str, bus_width: u32) -> String {
+        let mut delta = [0_u64; 6];
+        for (delta, (now, then)) in delta
+            .iter_mut()
+            .zip(self.counters.iter().zip(&since.counters))
+        {
+            *delta = now - then;
+        }
+        let [total_cycles, busy_cycles, read_accesses, write_accesses, read_bytes, write_bytes] =
+            delta;
+        let time_ms = self.time_ms - since.time_ms;
+        let per_second = |bytes: u64| match time_ms {
+            0 => 0_f32,
+            _ => (bytes as f64 * 1000_f64 / (1024_f64 * 1024_f64 * time_ms as f64)) as f32,
+        };
+        let percent = |part: f64, whole: f64| match whole as u64 {
+            0 => 0,
+            _ => (part / whole * 100_f64) as u64,
+        };
+        let bytes_per_cycle = 2_f64 * (bus_width / 8) as f64;
+        json::JsonObject::new()
+            .string("client", client)
+            .number("timestamp_ms", self.timestamp_ms)
+            .number("time_ms", time_ms)
+            .number("unaccounted_ms", self.unaccounted_ms - since.unaccounted_ms)
+            .number("total_cycles", total_cycles)
+            .number("busy_cycles", busy_cycles)
+            .number("read_accesses", read_accesses)
+            .number("write_accesses", write_accesses)
+            .number("read_bytes", read_bytes)
+            .number("write_bytes", write_bytes)
+            .float("read_mbps", per_second(read_bytes))
+            .float("write_mbps", per_second(write_bytes))
+            .float("total_mbps", per_second(read_bytes + write_bytes))
+            .number(
+                "utilization",
+                percent(
+                    (read_bytes + write_bytes) as f64,
+                    busy_cycles as f64 * bytes_per_cycle,
+                ),
+            )
+            .number(
+                "data_load",
+                percent(busy_cycles as f64, total_cycles as f64),
+            )
+            .build()
+    }
+}
+
+struct ApiState {
+    running: bool,
+    /// Latest samples with their timestamp, as many as --history-length
+    history: VecDeque<(u64, String)>,
+    history_length: usize,
+    /// Every record of the run at a resolution falling with its length
+    overview: Overview,
+    websocket_clients: Vec<TcpStream>,
+    bus_width: u32,
+    totals: Totals,
+    /// Totals at the previous GET /delta of each client
+    cursors: HashMap<String, Totals>,
+}
+
+type SharedState = Arc<(Mutex<ApiState>, Condvar)>;
+
+/// Handle of the server, the clones share the state
+#[derive(Clone)]
+pub struct Server {
+    state: SharedState,
+}
+
+/// The latest `count` samples, of those only the ones of the last `seconds` if given
+fn history_json(state: &ApiState, count: usize, seconds: Option<u64>) -> String {
+    let skip = state.history.len().saturating_sub(count);
+    let since = seconds.map_or(0, |seconds| {
+        (crate::get_tick_count() as u64).saturating_sub(seconds * 1000)
+    });
+    let samples: Vec<&str> = state
+        .history
+        .iter()
+        .skip(skip)
+        .filter(|(timestamp, _)| *timestamp >= since)
+        .map(|(_, sample)| sample.as_str())
+        .collect();
+    format!("[{}]", samples.join(","))
+}
+
+/// Handles `{"action":"start"}` and `{"action":"stop"}`
+fn control(request: &Request, state: &SharedState) -> (&'static str, String) {
+    let action = json::parse_flat(&request.body)
+        .into_iter()
+        .find(|(key, _)| key == "action")
+        .map(|(_, value)| value);
+    let (lock, condvar) = &**state;
+    let running = match action.as_deref() {
+        Some("start") => true,
+        Some("stop") => false,
+        _ => {
+            return (
+                "400 Bad Request",
+                "{\"error\":\"action must be start or stop\"}".to_string(),
+            )
+        }
+    };
+    lock.lock().unwrap().running = running;
+    condvar.notify_all();
+    (
+        "200 OK",
+        json::JsonObject::new().boolean("running", running).build(),
+    )
+}
+
+/// Handles GET /delta?client=<name>, the counters since the previous request of the
+/// same client, or since the server started on its first request
+fn delta(request: &Request, state: &SharedState) -> (&'static str, String) {
+    let client = match request.query("client") {
+        Some(client) if !client.is_empty() => client,
+        _ => {
+            return (
+                "400 Bad Request",
+                "{\"error\":\"client is missing\"}".to_string(),
+            )
+        }
+    };
+    let mut state = state.0.lock().unwrap();
+    if !state.cursors.contains_key(client) && state.cursors.len() == MAX_CURSORS {
+        return (
+            "429 Too Many Requests",
+            "{\"error\":\"too many clients\"}".to_string(),
+        );
+    }
+    let totals = state.totals;
+    let since = state
+        .cursors
+        .insert(client.to_string(), totals)
+        .unwrap_or_default();
+    ("200 OK", totals.delta_json(&since, client, state.bus_width))
+}
+
+fn handle_connection(stream: TcpStream, state: &SharedState) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = Request::read_from(&mut reader)?;
+    let mut stream = stream;
+
+    if let (Some(key), "/ws") = (request.header("sec-websocket-key"), request.path.as_str()) {
+        websocket::accept(&mut stream, key)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        state.0.lock().unwrap().websocket_clients.push(stream);
+        return Ok(());
+    }
+
+    let (status, body) = route(&request, state);
+    respond(&mut stream, status, "application/json", &body)
+}
+
+/// Status and JSON body of the REST API for a request other than a WebSocket upgrade
+fn route(request: &Request, state: &SharedState) -> (&'static str, String) {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/sample") => match state.0.lock().unwrap().history.back() {
+            Some((_, sample)) => ("200 OK", sample.clone()),
+            None => (
+                "503 Service Unavailable",
+                "{\"error\":\"no sample yet\"}".to_string(),
+            ),
+        },
+        ("GET", "/history") => {
+            let count = request
+                .query("n")
+                .and_then(|n| n.parse::<usize>().ok())
+                .unwrap_or(usize::MAX);
+            let seconds = request
+                .query("seconds")
+                .and_then(|seconds| seconds.parse::<u64>().ok());
+            (
+                "200 OK",
+                history_json(&state.0.lock().unwrap(), count, seconds),
+            )
+        }
+        ("GET", "/overview") => {
+            let points = request
+                .query("points")
+                .and_then(|points| points.parse::<usize>().ok())
+                .unwrap_or(usize::MAX);
+            ("200 OK", state.0.lock().unwrap().overview.to_json(points))
+        }
+        ("GET", "/delta") => delta(request, state),
+        ("POST", "/control") => control(request, state),
+        (_, "/sample") | (_, "/history") | (_, "/overview") | (_, "/delta") | (_, "/control") => (
+            "405 Method Not Allowed",
+            "{\"error\":\"method not allowed\"}".to_string(),
+        ),
+        _ => ("404 Not Found", "{\"error\":\"not found\"}".to_string()),
+    }
+}
+
+fn shared_state(bus_width: u32, history_length: usize) -> SharedState {
+    Arc::new((
+        Mutex::new(ApiState {
+            running: true,
+            history: VecDeque::with_capacity(history_length),
+            // GET /sample is served from the history as well
+            history_length: history_length.max(1),
+            overview: Overview::new(),
+            websocket_clients: Vec::new(),
+            bus_width,
+            totals: Totals {
+                timestamp_ms: crate::get_tick_count() as u64,
+                ..Totals::default()
+            },
+            cursors: HashMap::new(),
+        }),
+        Condvar::new(),
+    ))
+}
+
+impl Server {
+    pub fn start(address: &str, bus_width: u32, history_length: usize) -> io::Result<Server> {
+        let listener = TcpListener::bind(address)?;
+        let state = shared_state(bus_width, history_length);
+        let thread_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = thread_state.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &state) {
+                        eprintln!("Error serving client: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(Server { state })
+    }
+
+    /// Blocks while a client has stopped the measurement through POST /control
+    pub fn wait_until_running(&self) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        while !state.running {
+            state = condvar.wait(state).unwrap();
+        }
+    }
+
+    /// Adds the counters of a window which just ended to the totals GET /delta is served
+    /// from
+    pub fn count(&self, counters: [u32; 6], time: u32) {
+        let timestamp_ms = crate::get_tick_count() as u64;
+        let totals = &mut self.state.0.lock().unwrap().totals;
+        totals.add(counters, time, timestamp_ms);
+    }
+
+    /// Records the JSON encoded sample for the REST API and pushes it to all
+    /// connected WebSocket clients
+    pub fn publish(&self, json: &str, point: &Point) {
+        let frame = websocket::text_frame(json);
+        let mut state = self.state.0.lock().unwrap();
+        state.overview.add(point);
+        if state.history.len() >= state.history_length {
+            state.history.pop_front();
+        }
+        state
+            .history
+            .push_back((point.timestamp_ms, json.to_string()));
+        state
+            .websocket_clients
+            .retain_mut(|client| client.write_all(&frame).is_ok());
+    }
+}
+
+impl SampleSink<Record<'_>, Summary> for Server {
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        let timestamp = crate::get_tick_count() as u64;
+        self.publish(
+            &record.results.to_json(record.time, timestamp),
+            &Point::from_record(record.results, record.time, timestamp),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn request(text: &str) -> Request {
+        Request::read_from(&mut text.as_bytes()).unwrap()
+    }
+
+    fn get(state: &SharedState, target: &str) -> (&'static str, String) {
+        route(&request(&format!("GET {} HTTP/1.1\r\n\r\n", target)), state)
+    }
+
+    fn point(timestamp_ms: u64) -> Point {
+        Point {
+            timestamp_ms,
+            read_mbps: 1.0,
+            write_mbps: 2.0,
+            total_mbps: 3.0,
+            utilization: 4,
+        }
+    }
+
+    #[test]
+    fn request_is_parsed() {
+        let request = request(
+            "POST /control?client=a&verbose&n=3 HTTP/1.1\r\n\
+             Host: localhost\r\n\
+             Content-Length: 17\r\n\
+             \r\n\
+             {\"action\":\"stop\"}trailing",
+        );
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/control");
+        assert_eq!(request.query("client"), Some("a"));
+        assert_eq!(request.query("n"), Some("3"));
+        assert_eq!(request.query("verbose"), None);
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("content-length"), Some("17"));
+        assert_eq!(request.body, "{\"action\":\"stop\"}");
+    }
+
+    #[test]
+    fn request_without_body_or_headers() {
+        let request = request("GET /sample HTTP/1.1\r\n");
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("GET", "/sample")
+        );
+        assert!(request.headers.is_empty());
+        assert!(request.body.is_empty());
+        let empty = Request::read_from(&mut "".as_bytes()).unwrap();
+        assert_eq!((empty.method.as_str(), empty.path.as_str()), ("", ""));
+    }
+
+    #[test]
+    fn request_body_is_limited() {
+        let text = format!(
+            "POST /control HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LENGTH + 1
+        );
+        let error = Request::read_from(&mut text.as_bytes()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let text = "POST /control HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort";
+        let error = Request::read_from(&mut text.as_bytes()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn request_header_is_limited() {
+        let text = format!(
+            "GET /sample HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_LENGTH)
+        );
+        let error = Request::read_from(&mut text.as_bytes()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // without a line end as well
+        let text = "G".repeat(MAX_HEADER_LENGTH * 2);
+        let error = Request::read_from(&mut text.as_bytes()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn idle_clients_are_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        let error = handle_connection(stream, &shared_state(8, 1))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+        assert!(start.elapsed() >= READ_TIMEOUT);
+        assert!(start.elapsed() < READ_TIMEOUT * 3);
+    }
+
+    #[test]
+    fn samples_are_served_from_the_history() {
+        let state = shared_state(8, 2);
+        assert_eq!(get(&state, "/sample").0, "503 Service Unavailable");
+        assert_eq!(get(&state, "/history"), ("200 OK", "[]".to_string()));
+        let server = Server {
+            state: state.clone(),
+        };
+        for sequence in 0..3 {
+            server.publish(&format!("{{\"seq\":{}}}", sequence), &point(sequence));
+        }
+        assert_eq!(
+            get(&state, "/sample"),
+            ("200 OK", "{\"seq\":2}".to_string())
+        );
+        assert_eq!(
+            get(&state, "/history"),
+            ("200 OK", "[{\"seq\":1},{\"seq\":2}]".to_string())
+        );
+        assert_eq!(
+            get(&state, "/history?n=1"),
+            ("200 OK", "[{\"seq\":2}]".to_string())
+        );
+        let (status, overview) = get(&state, "/overview?points=1");
+        assert_eq!(status, "200 OK");
+        assert_eq!(overview.matches("\"samples\":3").count(), 1);
+    }
+
+    #[test]
+    fn control_starts_and_stops() {
+        let state = shared_state(8, 1);
+        let post = |body: &str| {
+            route(
+                &request(&format!(
+                    "POST /control HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )),
+                &state,
+            )
+        };
+        assert_eq!(
+            post("{\"action\":\"stop\"}"),
+            ("200 OK", "{\"running\":false}".to_string())
+        );
+        assert!(!state.0.lock().unwrap().running);
+        assert_eq!(post("{\"action\":\"start\"}").0, "200 OK");
+        assert!(state.0.lock().unwrap().running);
+        assert_eq!(post("{\"action\":\"pause\"}").0, "400 Bad Request");
+        assert_eq!(post("").0, "400 Bad Request");
+        assert!(state.0.lock().unwrap().running);
+    }
+
+    #[test]
+    fn unknown_routes_and_methods() {
+        let state = shared_state(8, 1);
+        assert_eq!(get(&state, "/control").0, "405 Method Not Allowed");
+        assert_eq!(
+            route(&request("DELETE /history HTTP/1.1\r\n\r\n"), &state).0,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(get(&state, "/samples").0, "404 Not Found");
+        assert_eq!(get(&state, "/").0, "404 Not Found");
+        assert_eq!(get(&state, "/delta").0, "400 Bad Request");
+        assert_eq!(get(&state, "/delta?client=").0, "400 Bad Request");
+        assert_eq!(get(&state, "/delta?client=a").0, "200 OK");
+    }
+
+    #[test]
+    fn totals_sum_up_the_time_without_windows() {
+        let mut totals = Totals {
+            timestamp_ms: 10_000,
+            ..Totals::default()
+        };
+        totals.add([100, 50, 1, 2, 64, 128], 1000, 11_000);
+        // a blame scan of 300 ms ahead of the next window
+        totals.add([100, 50, 1, 2, 64, 128], 1000, 12_300);
+        assert_eq!(totals.counters, [200, 100, 2, 4, 128, 256]);
+        assert_eq!(totals.time_ms, 2000);
+        assert_eq!(totals.unaccounted_ms, 300);
+        assert_eq!(totals.timestamp_ms, 12_300);
+    }
+
+    #[test]
+    fn delta_since_the_cursor() {
+        let mut totals = Totals::default();
+        totals.add([1000, 500, 10, 10, 1 << 20, 0], 1000, 1500);
+        let since = totals;
+        totals.add([1000, 250, 10, 10, 2 << 20, 1 << 20], 1000, 3000);
+        let fields = json::parse_flat(&totals.delta_json(&since, "grafana", 64));
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(field("client"), Some("grafana"));
+        assert_eq!(field("timestamp_ms"), Some("3000"));
+        assert_eq!(field("time_ms"), Some("1000"));
+        assert_eq!(field("unaccounted_ms"), Some("500"));
+        assert_eq!(field("busy_cycles"), Some("250"));
+        assert_eq!(field("data_load"), Some("25"));
+        assert_eq!(field("total_mbps").map(str::parse::<f32>), Some(Ok(3_f32)));
+    }
+}
@@ -0,0 +1,197 @@
+//! Publishes samples into a POSIX shared memory segment for cohabiting processes.
+//!
+//! The segment starts with a `ShmHeader` followed by `RING_SLOTS` `ShmSample` slots,
+//! all fields are native endian and laid out as `#[repr(C)]`:
+//!
+//! | offset | field      | description                                           |
+//! |--------|------------|-------------------------------------------------------|
+//! | 0      | magic      | `0x4D4D4443` ("MMDC")                                  |
+//! | 4      | version    | layout version, currently 1                           |
+//! | 8      | sequence   | sequence lock, odd while the writer updates           |
+//! | 12     | ring_slots | amount of sample slots following the header           |
+//! | 16     | latest     | slot index of the most recent sample                  |
+//! | 20     | reserved   | always 0                                              |
+//! | 24     | published  | total amount of samples published                     |
+//!
+//! The header is 32 bytes long, slot `n` starts at byte `32 + 48 * n`. Each slot holds
+//! one sample, the counters are the raw ones of its window:
+//!
+//! | offset | width | field              | description                                  |
+//! |--------|-------|--------------------|----------------------------------------------|
+//! | 0      | u64   | timestamp_ms       | ms since the epoch at the end of the window  |
+//! | 8      | u32   | duration_ms        | window length in ms                          |
+//! | 12     | u32   | total_cycles       | MADPSR0                                      |
+//! | 16     | u32   | busy_cycles        | MADPSR1                                      |
+//! | 20     | u32   | read_accesses      | MADPSR2                                      |
+//! | 24     | u32   | write_accesses     | MADPSR3                                      |
+//! | 28     | u32   | read_bytes         | MADPSR4                                      |
+//! | 32     | u32   | write_bytes        | MADPSR5                                      |
+//! | 36     | u32   | utilization        | percent of the bus bandwidth                 |
+//! | 40     | u32   | data_load          | percent of busy cycles                       |
+//! | 44     | u32   | access_utilization | average bytes per access                     |
+//!
+//! Samples are written round robin, sample `k` (counting from 0) goes to slot
+//! `k % ring_slots`, so only the last `ring_slots` of `published` samples are present.
+//!
+//! The writer increments `sequence` before and after updating a slot, `latest` and
+//! `published`, it's odd for the duration of the update. Readers follow the seqlock
+//! protocol:
+//!
+//! 1. load `sequence` with acquire ordering, start over while it's odd
+//! 2. copy `latest`, `published` and the slots of interest
+//! 3. issue an acquire fence and load `sequence` again
+//! 4. discard the copies and start over if it differs from the first load
+
+use crate::summary::Summary;
+use crate::{get_tick_count, MMDCProfileResult, Record};
+use nix::fcntl::OFlag;
+use nix::sys::mman::{mmap, munmap, shm_open, shm_unlink, MapFlags, ProtFlags};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, ftruncate};
+use r_mmdc::SampleSink;
+use std::io;
+use std::mem::size_of;
+use std::sync::atomic::{fence, AtomicU32, Ordering};
+
+pub const RING_SLOTS: u32 = 32;
+const MAGIC: u32 = 0x4D4D_4443;
+const LAYOUT_VERSION: u32 = 1;
+
+#[repr(C)]
+pub struct ShmHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub sequence: AtomicU32,
+    pub ring_slots: u32,
+    pub latest: u32,
+    pub reserved: u32,
+    pub published: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ShmSample {
+    pub timestamp_ms: u64,
+    pub duration_ms: u32,
+    pub total_cycles: u32,
+    pub busy_cycles: u32,
+    pub read_accesses: u32,
+    pub write_accesses: u32,
+    pub read_bytes: u32,
+    pub write_bytes: u32,
+    pub utilization: u32,
+    pub data_load: u32,
+    pub access_utilization: u32,
+}
+
+pub struct ShmSegment {
+    name: String,
+    header: *mut ShmHeader,
+    length: usize,
+}
+
+fn nix_to_string(error: nix::Error) -> String {
+    error.to_string()
+}
+
+impl ShmSegment {
+    pub fn create(name: &str) -> Result<ShmSegment, String> {
+        let length = size_of::<ShmHeader>() + RING_SLOTS as usize * size_of::<ShmSample>();
+        let fd = shm_open(
+            name,
+            OFlag::O_CREAT | OFlag::O_RDWR,
+            Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IRGRP | Mode::S_IROTH,
+        )
+        .map_err(nix_to_string)?;
+        let mapping = ftruncate(fd, length as i64).and_then(|_| unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                length,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED,
+                fd,
+                0,
+            )
+        });
+        let _ = close(fd);
+        let header = mapping.map_err(nix_to_string)? as *mut ShmHeader;
+
+        unsafe {
+            (*header).magic = MAGIC;
+            (*header).version = LAYOUT_VERSION;
+            (*header).sequence.store(0, Ordering::Relaxed);
+            (*header).ring_slots = RING_SLOTS;
+            (*header).latest = 0;
+            (*header).reserved = 0;
+            (*header).published = 0;
+        }
+        Ok(ShmSegment {
+            name: name.to_string(),
+            header,
+            length,
+        })
+    }
+
+    fn slot(&mut self, index: u32) -> *mut ShmSample {
+        unsafe { (self.header.add(1) as *mut ShmSample).add(index as usize) }
+    }
+
+    pub fn publish(&mut self, result: &MMDCProfileResult, timestamp_ms: u64, duration_ms: u32) {
+        let sample = ShmSample {
+            timestamp_ms,
+            duration_ms,
+            total_cycles: result.total_cycles,
+            busy_cycles: result.busy_cycles,
+            read_accesses: result.read_accesses,
+            write_accesses: result.write_accesses,
+            read_bytes: result.read_bytes,
+            write_bytes: result.write_bytes,
+            utilization: result.utilization,
+            data_load: result.data_load,
+            access_utilization: result.access_utilization,
+        };
+        unsafe {
+            let header = &mut *self.header;
+            let index = (header.published % RING_SLOTS as u64) as u32;
+            header.sequence.fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::Release);
+            std::ptr::write_volatile(self.slot(index), sample);
+            header.latest = index;
+            header.published += 1;
+            fence(Ordering::Release);
+            header.sequence.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl SampleSink<Record<'_>, Summary> for ShmSegment {
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        self.publish(record.results, get_tick_count() as u64, record.time);
+        Ok(())
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = munmap(self.header as *mut _, self.length);
+        }
+        let _ = shm_unlink(self.name.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::offset_of;
+
+    #[test]
+    fn layout_matches_the_documented_offsets() {
+        assert_eq!(size_of::<ShmHeader>(), 32);
+        assert_eq!(offset_of!(ShmHeader, published), 24);
+        assert_eq!(size_of::<ShmSample>(), 48);
+        assert_eq!(offset_of!(ShmSample, duration_ms), 8);
+        assert_eq!(offset_of!(ShmSample, write_bytes), 32);
+        assert_eq!(offset_of!(ShmSample, access_utilization), 44);
+    }
+}
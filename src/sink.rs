@@ -0,0 +1,284 @@
+//! Additional outputs declared in `[sink.NAME]` sections of the config file, fed from the
+//! sampling loop next to the main output
+//!
+//! ```text
+//! [sink.gpu]
+//! type = file
+//! path = /var/log/mmdc-gpu.csv
+//! format = csv
+//! master = gpu3d
+//!
+//! [sink.journal]
+//! type = journal
+//! filter = total_mbps - 400
+//! ```
+//!
+//! `type` is `file` with `path`, `format` and `append`, `trace` with `path` for an indexed
+//! binary trace, `syslog`, `journal`, `stream` or `dlt` with `address`. `master` keeps only the records of one rotated master, `filter`
+//! keeps the records for which the expression over the counters is positive.
+
+use crate::config::SinkConfig;
+#[cfg(feature = "network")]
+use crate::dlt::DltSink;
+use crate::expr::Expr;
+use crate::json::JsonObject;
+#[cfg(feature = "network")]
+use crate::stream::StreamSink;
+use crate::summary::Summary;
+#[cfg(feature = "syslog")]
+use crate::syslog::LogSink;
+use crate::trace::TraceWriter;
+use crate::{
+    get_tick_count, metadata, print_profiling_results, Format, Opt, Record, METRIC_VARIABLES,
+};
+use r_mmdc::SampleSink;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Any of the built-in encoders of the records
+pub type RecordSink<'o> = dyn for<'a> SampleSink<Record<'a>, Summary> + 'o;
+
+/// Encodes the records in one of the output formats, for the main output as well as the
+/// file sinks
+pub struct FormatEncoder<'o, W: Write> {
+    out: W,
+    format: Format,
+    opt: &'o Opt,
+}
+
+impl<'o, W: Write> FormatEncoder<'o, W> {
+    pub fn new(out: W, format: Format, opt: &'o Opt) -> FormatEncoder<'o, W> {
+        FormatEncoder { out, format, opt }
+    }
+
+    /// Writes the summary, `label` names the segment or phase it covers, e.g.
+    /// `("phase", "boot")`
+    pub fn write_summary(
+        &mut self,
+        summary: &Summary,
+        label: Option<(&str, &str)>,
+    ) -> io::Result<()> {
+        match (self.format, label) {
+            (Format::Csv, Some((key, value))) => writeln!(self.out, "# {}={}", key, value)?,
+            (Format::Human, Some((key, value))) => {
+                let mut key = key.to_string();
+                key[..1].make_ascii_uppercase();
+                writeln!(self.out, "{} {}:", key, value)?
+            }
+            _ => {}
+        }
+        match self.format {
+            Format::Csv => summary.write(&mut self.out, true),
+            Format::Human => summary.write(&mut self.out, false),
+            Format::Json => writeln!(self.out, "{}", summary.json_object(label).build_pretty()),
+            Format::Jsonl => writeln!(self.out, "{}", summary.json_object(label).build()),
+            Format::Influx => {
+                let line = summary.to_influx(self.opt.channel, label, get_tick_count() as u64);
+                writeln!(self.out, "{}", line)
+            }
+        }
+    }
+
+    /// Writes the mean read, write and total bandwidth of each --group
+    pub fn write_group_rollup(&mut self, rollup: &[(&str, (f32, f32, f32))]) -> io::Result<()> {
+        if self.format == Format::Human {
+            writeln!(self.out, "MMDC bandwidth per group:")?;
+            writeln!(self.out, "***********************")?;
+        }
+        for (name, (read, write, total)) in rollup {
+            let object = JsonObject::new()
+                .string("record", "group")
+                .string("group", name)
+                .float("read_mbps", *read)
+                .float("write_mbps", *write)
+                .float("total_mbps", *total);
+            match self.format {
+                Format::Csv => {
+                    writeln!(self.out, "# group_{}_read_mbps={:.2}", name, read)?;
+                    writeln!(self.out, "# group_{}_write_mbps={:.2}", name, write)?;
+                    writeln!(self.out, "# group_{}_total_mbps={:.2}", name, total)?
+                }
+                Format::Human => writeln!(
+                    self.out,
+                    "{}: Read: {:.2} MB/s /  Write: {:.2} MB/s  Total: {:.2} MB/s",
+                    name, read, write, total
+                )?,
+                Format::Json => writeln!(self.out, "{}", object.build_pretty())?,
+                Format::Jsonl => writeln!(self.out, "{}", object.build())?,
+                Format::Influx => writeln!(
+                    self.out,
+                    "mmdc_group,channel=p{},group={} read_mbps={:.2},write_mbps={:.2},total_mbps={:.2} {}",
+                    self.opt.channel,
+                    name,
+                    read,
+                    write,
+                    total,
+                    get_tick_count() * 1_000_000
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> SampleSink<Record<'_>, Summary> for FormatEncoder<'_, W> {
+    /// Only CSV and JSON lines carry the metadata ahead of the records
+    fn on_metadata(&mut self, entries: &[(&str, String)]) -> io::Result<()> {
+        match self.format {
+            Format::Csv => metadata::write_csv(entries, &mut self.out),
+            Format::Json => writeln!(
+                self.out,
+                "{}",
+                metadata::json_object(entries).build_pretty()
+            ),
+            Format::Jsonl => writeln!(self.out, "{}", metadata::to_json(entries)),
+            Format::Human | Format::Influx => Ok(()),
+        }
+    }
+
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        print_profiling_results(
+            &mut self.out,
+            record.results,
+            record.time,
+            self.opt,
+            self.format,
+        )
+    }
+
+    fn on_summary(&mut self, summary: &Summary) -> io::Result<()> {
+        self.write_summary(summary, None)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// A log of the records written to the main output, records held back by --emit-on-change
+/// are skipped. Errors name the sink.
+pub struct Sink<'o> {
+    pub name: String,
+    master: Option<String>,
+    filter: Option<Expr>,
+    target: Box<RecordSink<'o>>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn required<'a>(config: &'a SinkConfig, key: &str) -> io::Result<&'a str> {
+    config
+        .get(key)
+        .ok_or_else(|| invalid(format!("missing {}", key)))
+}
+
+#[cfg(not(all(feature = "syslog", feature = "network")))]
+fn unsupported(kind: &str, feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} sinks need the {} feature", kind, feature),
+    )
+}
+
+impl<'o> Sink<'o> {
+    /// Sink of a command line option, e.g. `--trace`, taking every emitted record
+    pub fn new(name: &str, target: Box<RecordSink<'o>>) -> Sink<'o> {
+        Sink {
+            name: name.to_string(),
+            master: None,
+            filter: None,
+            target,
+        }
+    }
+
+    pub fn open(config: &SinkConfig, opt: &'o Opt) -> io::Result<Sink<'o>> {
+        let format = match config.get("format") {
+            Some(format) => format.parse().map_err(invalid)?,
+            None => Format::Jsonl,
+        };
+        let filter = match config.get("filter") {
+            Some(source) => Some(Expr::parse(source, &METRIC_VARIABLES).map_err(invalid)?),
+            None => None,
+        };
+        let target: Box<RecordSink<'o>> = match required(config, "type")? {
+            "file" => {
+                let path = required(config, "path")?;
+                let file = if config.get("append") == Some("true") {
+                    OpenOptions::new().append(true).create(true).open(path)?
+                } else {
+                    File::create(path)?
+                };
+                Box::new(FormatEncoder::new(BufWriter::new(file), format, opt))
+            }
+            "trace" => Box::new(TraceWriter::create(Path::new(required(config, "path")?))?),
+            #[cfg(feature = "syslog")]
+            kind @ ("syslog" | "journal") => Box::new(LogSink::connect(kind)?),
+            #[cfg(not(feature = "syslog"))]
+            kind @ ("syslog" | "journal") => return Err(unsupported(kind, "syslog")),
+            #[cfg(feature = "network")]
+            "stream" => Box::new(StreamSink::new(required(config, "address")?)),
+            #[cfg(feature = "network")]
+            "dlt" => Box::new(DltSink::connect(
+                required(config, "address")?,
+                &opt.dlt_ecu,
+                &opt.dlt_app,
+                &opt.dlt_context,
+            )?),
+            #[cfg(not(feature = "network"))]
+            kind @ ("stream" | "dlt") => return Err(unsupported(kind, "network")),
+            kind => return Err(invalid(format!("unknown type {}", kind))),
+        };
+        Ok(Sink {
+            name: config.name.clone(),
+            master: config.get("master").map(str::to_string),
+            filter,
+            target,
+        })
+    }
+
+    fn accepts(&self, record: &Record) -> bool {
+        let master = self
+            .master
+            .as_ref()
+            .is_none_or(|master| record.results.master == Some(master.as_str()));
+        let filter = self.filter.as_ref().is_none_or(|filter| {
+            filter.eval(&|variable| record.results.variable(variable, record.time)) > 0.0
+        });
+        record.emitted && master && filter
+    }
+
+    fn named(&self, result: io::Result<()>) -> io::Result<()> {
+        result.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.name, e)))
+    }
+}
+
+impl SampleSink<Record<'_>, Summary> for Sink<'_> {
+    fn on_metadata(&mut self, entries: &[(&str, String)]) -> io::Result<()> {
+        let result = self.target.on_metadata(entries);
+        self.named(result)
+    }
+
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        if !self.accepts(record) {
+            return Ok(());
+        }
+        let result = self
+            .target
+            .on_sample(record)
+            .and_then(|_| self.target.flush());
+        self.named(result)
+    }
+
+    fn on_summary(&mut self, summary: &Summary) -> io::Result<()> {
+        let result = self.target.on_summary(summary);
+        self.named(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.target.flush();
+        self.named(result)
+    }
+}
@@ -0,0 +1,106 @@
+//! Directory of small files with the values of the latest sample, e.g. /run/rmmdc, for
+//! shell scripts and monitoring agents which only `cat` files
+//!
+//! The directory is a FUSE mount generating the files on read. Without the `fuse` feature,
+//! or where mounting fails, the files are rewritten after every window instead, each one
+//! through a rename so readers never see partial values.
+
+#[cfg(feature = "fuse")]
+use crate::fuse::{self, Files, FuseMount};
+use crate::summary::Summary;
+use crate::{MMDCProfileResult, Record};
+use r_mmdc::SampleSink;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "fuse")]
+use std::sync::{Arc, Mutex};
+
+const NAMES: [&str; 7] = [
+    "read_mbps",
+    "write_mbps",
+    "total_mbps",
+    "utilization",
+    "data_load",
+    "flags",
+    "seq",
+];
+
+pub struct StatsDir {
+    path: PathBuf,
+    #[cfg(feature = "fuse")]
+    mount: Option<(FuseMount, Files)>,
+}
+
+impl StatsDir {
+    pub fn create(path: &Path) -> io::Result<StatsDir> {
+        #[cfg(feature = "fuse")]
+        fuse::remove_stale(path);
+        fs::create_dir_all(path)?;
+        #[cfg(feature = "fuse")]
+        {
+            let files: Files = Arc::new(Mutex::new(
+                NAMES
+                    .iter()
+                    .map(|name| (name.to_string(), String::new()))
+                    .collect(),
+            ));
+            match FuseMount::mount(path, files.clone()) {
+                Ok(mount) => {
+                    return Ok(StatsDir {
+                        path: path.to_path_buf(),
+                        mount: Some((mount, files)),
+                    })
+                }
+                Err(e) => eprintln!(
+                    "WARNING: couldn't mount {}: {}, writing the files instead",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(StatsDir {
+            path: path.to_path_buf(),
+            #[cfg(feature = "fuse")]
+            mount: None,
+        })
+    }
+
+    fn write(&self, name: &str, value: &str) -> io::Result<()> {
+        let temporary = self.path.join(format!(".{}", name));
+        fs::write(&temporary, value)?;
+        fs::rename(temporary, self.path.join(name))
+    }
+
+    pub fn update(&self, result: &MMDCProfileResult, time: u32) -> io::Result<()> {
+        let (read, write, total) = result.bandwidth(time);
+        let values = [
+            format!("{:.2}", read),
+            format!("{:.2}", write),
+            format!("{:.2}", total),
+            result.utilization.to_string(),
+            result.data_load.to_string(),
+            result.flag_names(),
+            result.sequence.to_string(),
+        ];
+        #[cfg(feature = "fuse")]
+        if let Some((_, files)) = &self.mount {
+            let mut files = files.lock().unwrap();
+            for ((_, contents), value) in files.iter_mut().zip(values.iter()) {
+                *contents = format!("{}\n", value);
+            }
+            return Ok(());
+        }
+        for (name, value) in NAMES.iter().zip(values.iter()) {
+            self.write(name, &format!("{}\n", value))?;
+        }
+        Ok(())
+    }
+}
+
+impl SampleSink<Record<'_>, Summary> for StatsDir {
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        self.update(record.results, record.time)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.path.display(), e)))
+    }
+}
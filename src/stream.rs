@@ -0,0 +1,66 @@
+//! Streams JSON lines to a remote `r-mmdc view` instance
+
+use crate::summary::Summary;
+use crate::{get_tick_count, metadata, Record};
+use r_mmdc::SampleSink;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Samples are dropped while the viewer is unreachable, the connection is retried per sample
+pub struct StreamSink {
+    address: String,
+    stream: Option<TcpStream>,
+    metadata: String,
+}
+
+impl StreamSink {
+    pub fn new(address: &str) -> StreamSink {
+        StreamSink {
+            address: address.to_string(),
+            stream: None,
+            metadata: String::new(),
+        }
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let address = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not resolvable"))?;
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+        Ok(stream)
+    }
+}
+
+impl SampleSink<Record<'_>, Summary> for StreamSink {
+    fn on_metadata(&mut self, metadata: &[(&str, String)]) -> io::Result<()> {
+        self.metadata = metadata::to_json(metadata);
+        Ok(())
+    }
+
+    /// Sends a sample line, the metadata line is sent ahead of it on every new connection
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        let sample = record.results.to_json(record.time, get_tick_count() as u64);
+        if self.stream.is_none() {
+            match self.connect() {
+                Ok(mut stream) => {
+                    if writeln!(stream, "{}", self.metadata).is_ok() {
+                        self.stream = Some(stream);
+                    }
+                }
+                Err(e) => eprintln!("couldn't connect to {}: {}", self.address, e),
+            }
+        }
+        if let Some(stream) = &mut self.stream {
+            if writeln!(stream, "{}", sample).is_err() {
+                self.stream = None;
+            }
+        }
+        Ok(())
+    }
+}
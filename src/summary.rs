@@ -0,0 +1,289 @@
+//! Statistics over all samples of a run, printed at its end with --summary
+
+use crate::json::JsonObject;
+use crate::MMDCProfileResult;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Utilization buckets of 10% each, 100% and above is counted in the last one
+const BUCKETS: usize = 10;
+const BAR_WIDTH: usize = 40;
+
+/// Relative error of the quantiles reported by `Sketch`
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+const PERCENTILES: [u32; 4] = [50, 90, 95, 99];
+
+/// Quantile sketch with logarithmically sized buckets, its memory only grows with the
+/// range of the values and not with the number of samples
+#[derive(Default)]
+pub struct Sketch {
+    zeros: u64,
+    buckets: BTreeMap<i32, u64>,
+    count: u64,
+}
+
+impl Sketch {
+    fn gamma() -> f64 {
+        (1_f64 + RELATIVE_ACCURACY) / (1_f64 - RELATIVE_ACCURACY)
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        if value <= 0_f64 || !value.is_finite() {
+            self.zeros += 1;
+            return;
+        }
+        let index = (value.ln() / Sketch::gamma().ln()).ceil() as i32;
+        *self.buckets.entry(index).or_insert(0) += 1;
+    }
+
+    /// Value below which `percentile` percent of the values are
+    pub fn quantile(&self, percentile: u32) -> f64 {
+        if self.count == 0 {
+            return 0_f64;
+        }
+        let rank = ((self.count - 1) as f64 * percentile as f64 / 100_f64).round() as u64;
+        if rank < self.zeros {
+            return 0_f64;
+        }
+        let mut seen = self.zeros;
+        let gamma = Sketch::gamma();
+        for (index, count) in self.buckets.iter() {
+            seen += count;
+            if seen > rank {
+                return 2_f64 * gamma.powi(*index) / (gamma + 1_f64);
+            }
+        }
+        0_f64
+    }
+}
+
+#[derive(Default)]
+pub struct Summary {
+    samples: u32,
+    histogram: [u32; BUCKETS],
+    pub total_bandwidth: Sketch,
+    total_bandwidth_sum: f64,
+    utilization_sum: u64,
+}
+
+impl Summary {
+    pub fn new() -> Summary {
+        Summary::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples == 0
+    }
+
+    pub fn add(&mut self, results: &MMDCProfileResult, time: u32) {
+        self.samples += 1;
+        let total = results.bandwidth(time).2 as f64;
+        self.total_bandwidth.add(total);
+        self.total_bandwidth_sum += total;
+        self.utilization_sum += results.utilization as u64;
+        let bucket = (results.utilization as usize / (100 / BUCKETS)).min(BUCKETS - 1);
+        self.histogram[bucket] += 1;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn mean_total_bandwidth(&self) -> f64 {
+        self.total_bandwidth_sum / self.samples.max(1) as f64
+    }
+
+    pub fn mean_utilization(&self) -> f64 {
+        self.utilization_sum as f64 / self.samples.max(1) as f64
+    }
+
+    fn bucket_label(bucket: usize) -> String {
+        let width = 100 / BUCKETS;
+        format!("{}-{}%", bucket * width, (bucket + 1) * width)
+    }
+
+    fn share(&self, count: u32) -> f32 {
+        count as f32 * 100_f32 / self.samples.max(1) as f32
+    }
+
+    /// Field name of the count of a utilization bucket, e.g. utilization_10_20
+    fn bucket_field(bucket: usize) -> String {
+        let width = 100 / BUCKETS;
+        format!("utilization_{}_{}", bucket * width, (bucket + 1) * width)
+    }
+
+    /// Summary record of the JSON encodings, `label` names the segment or phase it covers
+    pub fn json_object(&self, label: Option<(&str, &str)>) -> JsonObject {
+        let object = JsonObject::new().string("record", "summary");
+        let object = match label {
+            Some((key, value)) => object.string(key, value),
+            None => object,
+        };
+        let object = object
+            .number("samples", self.samples)
+            .float("mean_total_mbps", self.mean_total_bandwidth() as f32)
+            .float("mean_utilization", self.mean_utilization() as f32);
+        let object = self
+            .histogram
+            .iter()
+            .enumerate()
+            .fold(object, |object, (bucket, count)| {
+                object.number(&Summary::bucket_field(bucket), count)
+            });
+        PERCENTILES.iter().fold(object, |object, percentile| {
+            object.float(
+                &format!("total_mbps_p{}", percentile),
+                self.total_bandwidth.quantile(*percentile) as f32,
+            )
+        })
+    }
+
+    /// InfluxDB line of the `mmdc_summary` measurement, `label` is added as a tag
+    pub fn to_influx(&self, channel: u32, label: Option<(&str, &str)>, timestamp: u64) -> String {
+        let mut tags = format!("mmdc_summary,channel=p{}", channel);
+        if let Some((key, value)) = label {
+            tags.push_str(&format!(",{}={}", key, value));
+        }
+        let mut fields = vec![
+            format!("samples={}i", self.samples),
+            format!("mean_total_mbps={:.2}", self.mean_total_bandwidth()),
+            format!("mean_utilization={:.2}", self.mean_utilization()),
+        ];
+        for (bucket, count) in self.histogram.iter().enumerate() {
+            fields.push(format!("{}={}i", Summary::bucket_field(bucket), count));
+        }
+        for percentile in PERCENTILES.iter() {
+            fields.push(format!(
+                "total_mbps_p{}={:.2}",
+                percentile,
+                self.total_bandwidth.quantile(*percentile)
+            ));
+        }
+        format!(
+            "{} {} {}",
+            tags,
+            fields.join(","),
+            timestamp as u128 * 1_000_000
+        )
+    }
+
+    pub fn write(&self, out: &mut dyn Write, formatted: bool) -> io::Result<()> {
+        if formatted {
+            let buckets: Vec<String> = self
+                .histogram
+                .iter()
+                .enumerate()
+                .map(|(bucket, count)| format!("{}:{}", Summary::bucket_label(bucket), count))
+                .collect();
+            writeln!(out, "# samples={}", self.samples)?;
+            writeln!(out, "# utilization_histogram={}", buckets.join(","))?;
+            for percentile in PERCENTILES.iter() {
+                writeln!(
+                    out,
+                    "# total_mbps_p{}={:.2}",
+                    percentile,
+                    self.total_bandwidth.quantile(*percentile)
+                )?;
+            }
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "MMDC utilization histogram over {} samples:",
+            self.samples
+        )?;
+        writeln!(out, "***********************")?;
+        for (bucket, count) in self.histogram.iter().enumerate() {
+            let share = self.share(*count);
+            writeln!(
+                out,
+                "{:>8}: {:>6} ({:>5.1}%) {}",
+                Summary::bucket_label(bucket),
+                count,
+                share,
+                "#".repeat((share / 100_f32 * BAR_WIDTH as f32).round() as usize)
+            )?;
+        }
+        writeln!(out, "Total bandwidth percentiles:")?;
+        for percentile in PERCENTILES.iter() {
+            writeln!(
+                out,
+                "p{}: {:.2}MB/s",
+                percentile,
+                self.total_bandwidth.quantile(*percentile)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value at the rank `Sketch::quantile` picks, of the sorted values
+    fn exact(sorted: &[f64], percentile: u32) -> f64 {
+        let rank = ((sorted.len() - 1) as f64 * percentile as f64 / 100_f64).round() as usize;
+        sorted[rank]
+    }
+
+    fn assert_accurate(values: &[f64]) {
+        let mut sketch = Sketch::default();
+        for value in values {
+            sketch.add(*value);
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for percentile in [0, 1, 25, 50, 90, 95, 99, 100] {
+            let expected = exact(&sorted, percentile);
+            let error = (sketch.quantile(percentile) - expected).abs() / expected;
+            assert!(
+                error <= RELATIVE_ACCURACY + 1e-9,
+                "p{}: {} instead of {}",
+                percentile,
+                sketch.quantile(percentile),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn quantiles_of_a_uniform_range() {
+        let values: Vec<f64> = (1..=10_000).map(f64::from).collect();
+        assert_accurate(&values);
+    }
+
+    #[test]
+    fn quantiles_of_a_skewed_distribution() {
+        // mostly idle with rare bursts, spanning six orders of magnitude
+        let values: Vec<f64> = (1..=5_000)
+            .map(|i| 0.01 * (1.003_f64).powi(i % 4_700) + if i % 97 == 0 { 3_000.0 } else { 0.0 })
+            .collect();
+        assert_accurate(&values);
+    }
+
+    #[test]
+    fn zeros_and_empty_sketches() {
+        assert_eq!(Sketch::default().quantile(50), 0_f64);
+        let mut sketch = Sketch::default();
+        for value in [0.0, 0.0, 0.0, f64::NAN, 100.0] {
+            sketch.add(value);
+        }
+        assert_eq!(sketch.quantile(50), 0_f64);
+        assert!((sketch.quantile(100) - 100.0).abs() <= 100.0 * RELATIVE_ACCURACY);
+    }
+
+    #[test]
+    fn memory_grows_with_the_range_only() {
+        let mut sketch = Sketch::default();
+        for i in 0..1_000_000_u32 {
+            sketch.add(1.0 + f64::from(i % 1_000));
+        }
+        // buckets of 2% over three orders of magnitude
+        let limit = (1_000_f64.ln() / Sketch::gamma().ln()).ceil() as usize + 1;
+        assert!(sketch.buckets.len() <= limit);
+        assert_eq!(sketch.count, 1_000_000);
+    }
+}
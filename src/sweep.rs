@@ -0,0 +1,136 @@
+//! Read delay sweep, an automated eye margin scan of the read DQS delay lines
+
+use crate::tune::{self, Restore, MPRDDLCTL};
+use crate::MMDC;
+use nix::sys::mman::{msync, MsFlags};
+use std::ptr;
+
+/// Buffer well beyond the L2 cache, so the verification reads come from DDR
+const BUFFER_WORDS: usize = (32 << 20) / 8;
+
+/// Byte lanes of the delay line register of one PHY
+const BYTE_LANES: usize = 4;
+
+/// MPMUR FRC_MSR, makes the delay lines take over new values
+const FRC_MSR: u32 = 0x800;
+
+/// Range of delay line values stepped through
+pub struct Sweep {
+    pub start: u32,
+    pub end: u32,
+    pub step: u32,
+    /// Verification passes per delay value
+    pub rounds: u32,
+}
+
+fn set_delay(mmdc: &mut MMDC, value: u32) {
+    MPRDDLCTL.write(mmdc, value);
+    unsafe {
+        let mpmur = ptr::read_volatile(&mmdc.mpmur);
+        ptr::write_volatile(&mut mmdc.mpmur, mpmur | FRC_MSR);
+        let _ = msync(&mut mmdc.mpmur as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
+    }
+}
+
+/// Writes and reads back a set of patterns, returns which byte lanes saw a mismatch
+fn verify(buffer: &mut [u64], bytes_per_beat: usize) -> [bool; BYTE_LANES] {
+    let mut failed = [false; BYTE_LANES];
+    let patterns: [fn(usize) -> u64; 5] = [
+        |_| 0,
+        |_| u64::MAX,
+        |_| 0xAAAA_AAAA_AAAA_AAAA,
+        |_| 0x5555_5555_5555_5555,
+        |index| !(index as u64).wrapping_mul(0x0101_0101_0101_0101),
+    ];
+    for pattern in patterns.iter() {
+        for (index, word) in buffer.iter_mut().enumerate() {
+            unsafe { ptr::write_volatile(word, pattern(index)) };
+        }
+        for (index, word) in buffer.iter().enumerate() {
+            let mismatch = unsafe { ptr::read_volatile(word) } ^ pattern(index);
+            if mismatch == 0 {
+                continue;
+            }
+            for (byte, value) in mismatch.to_le_bytes().iter().enumerate() {
+                let lane = (index * 8 + byte) % bytes_per_beat;
+                if *value != 0 && lane < BYTE_LANES {
+                    failed[lane] = true;
+                }
+            }
+        }
+    }
+    failed
+}
+
+/// Longest run of passing delay values, as first and last value
+fn pass_window(results: &[(u32, [bool; BYTE_LANES])], lane: usize) -> Option<(u32, u32)> {
+    let mut best: Option<(u32, u32)> = None;
+    let mut current: Option<(u32, u32)> = None;
+    for (delay, failed) in results {
+        current = match (failed[lane], current) {
+            (true, _) => None,
+            (false, Some((first, _))) => Some((first, *delay)),
+            (false, None) => Some((*delay, *delay)),
+        };
+        if let Some((first, last)) = current {
+            if best.is_none_or(|(best_first, best_last)| last - first > best_last - best_first) {
+                best = current;
+            }
+        }
+    }
+    best
+}
+
+/// Steps all byte lanes of MPRDDLCTL through the range and verifies patterns at every
+/// value. The original setting is restored after each step and on exit, termination
+/// signals are held back meanwhile.
+pub fn run(mmdc: &mut MMDC, bus_width: u32, sweep: &Sweep) -> Result<(), String> {
+    let bytes_per_beat = (bus_width / 8).max(1) as usize;
+    let mut buffer = vec![0_u64; BUFFER_WORDS];
+    let signals = tune::hold_termination()?;
+    let original = MPRDDLCTL.read(mmdc);
+    let mut results = Vec::new();
+    {
+        let restore = Restore {
+            mmdc,
+            register: &MPRDDLCTL,
+            original,
+            persist: false,
+        };
+        println!("Delay  Lane 0  Lane 1  Lane 2  Lane 3");
+        for delay in (sweep.start..=sweep.end.min(0x7F)).step_by(sweep.step.max(1) as usize) {
+            set_delay(restore.mmdc, delay * 0x0101_0101);
+            let mut failed = [false; BYTE_LANES];
+            for _ in 0..sweep.rounds.max(1) {
+                for (failed, round) in failed.iter_mut().zip(verify(&mut buffer, bytes_per_beat)) {
+                    *failed |= round;
+                }
+            }
+            set_delay(restore.mmdc, original);
+            let lanes: Vec<&str> = failed
+                .iter()
+                .map(|failed| if *failed { "FAIL" } else { "pass" })
+                .collect();
+            println!("0x{:02X}   {}", delay, lanes.join("    "));
+            results.push((delay, failed));
+        }
+    }
+    for lane in 0..BYTE_LANES.min(bytes_per_beat) {
+        let current = (original >> (lane * 8)) & 0x7F;
+        match pass_window(&results, lane) {
+            Some((first, last)) => println!(
+                "Byte lane {}: pass window 0x{:02X}..0x{:02X}, center 0x{:02X}, current 0x{:02X}",
+                lane,
+                first,
+                last,
+                (first + last) / 2,
+                current
+            ),
+            None => println!(
+                "Byte lane {}: no passing delay, current 0x{:02X}",
+                lane, current
+            ),
+        }
+    }
+    signals.thread_unblock().map_err(|e| e.to_string())
+}
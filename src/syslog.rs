@@ -0,0 +1,90 @@
+//! Sinks logging one line per sample to syslog or the systemd journal
+
+use crate::summary::Summary;
+use crate::Record;
+use r_mmdc::SampleSink;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+const IDENTIFIER: &str = "r-mmdc";
+
+/// LOG_DAEMON facility
+const FACILITY: u32 = 3;
+/// LOG_INFO priority
+const PRIORITY: u32 = 6;
+
+pub enum LogSink {
+    Syslog(UnixDatagram),
+    Journal(UnixDatagram),
+}
+
+fn connect(path: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+impl LogSink {
+    /// Connects to `syslog` or `journal`
+    pub fn connect(target: &str) -> io::Result<LogSink> {
+        match target {
+            "syslog" => Ok(LogSink::Syslog(connect(SYSLOG_SOCKET)?)),
+            "journal" => Ok(LogSink::Journal(connect(JOURNAL_SOCKET)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "log target must be syslog or journal",
+            )),
+        }
+    }
+}
+
+impl SampleSink<Record<'_>, Summary> for LogSink {
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        let (result, time) = (record.results, record.time);
+        let (read, write, total) = result.bandwidth(time);
+        let message = format!(
+            "read={:.2}MB/s write={:.2}MB/s total={:.2}MB/s utilization={}% data_load={}%",
+            read, write, total, result.utilization, result.data_load
+        );
+        match self {
+            LogSink::Syslog(socket) => {
+                let line = format!(
+                    "<{}>{}[{}]: {}",
+                    FACILITY * 8 + PRIORITY,
+                    IDENTIFIER,
+                    std::process::id(),
+                    message
+                );
+                socket.send(line.as_bytes()).map(|_| ())
+            }
+            LogSink::Journal(socket) => {
+                // native journal protocol, one KEY=value field per line
+                let fields = [
+                    ("MESSAGE", message),
+                    ("PRIORITY", PRIORITY.to_string()),
+                    ("SYSLOG_IDENTIFIER", IDENTIFIER.to_string()),
+                    ("MMDC_TIME_MS", time.to_string()),
+                    ("MMDC_READ_MBPS", format!("{:.2}", read)),
+                    ("MMDC_WRITE_MBPS", format!("{:.2}", write)),
+                    ("MMDC_TOTAL_MBPS", format!("{:.2}", total)),
+                    ("MMDC_UTILIZATION", result.utilization.to_string()),
+                    ("MMDC_DATA_LOAD", result.data_load.to_string()),
+                    (
+                        "MMDC_ACCESS_UTILIZATION",
+                        result.access_utilization.to_string(),
+                    ),
+                    ("MMDC_READ_BYTES", result.read_bytes.to_string()),
+                    ("MMDC_WRITE_BYTES", result.write_bytes.to_string()),
+                ];
+                let entry: String = fields
+                    .iter()
+                    .map(|(key, value)| format!("{}={}\n", key, value))
+                    .collect();
+                socket.send(entry.as_bytes()).map(|_| ())
+            }
+        }
+    }
+}
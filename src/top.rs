@@ -0,0 +1,189 @@
+//! Live ranking of the AXI masters by bandwidth. MADPCR1 selects one master at a time,
+//! so the masters are measured in short windows one after the other and their rates
+//! are estimates of a load that is assumed to be steady over a round. The groups of the
+//! config file are ranked below the masters, with the sum of their members.
+
+use crate::config::MasterGroup;
+use crate::schedule::{Fixed, Scheduler};
+use crate::MMDC;
+use crate::{measure_overhead, measure_window, select_master, MMDCProfileResult, ALL_MASTERS};
+use mmdc_core::soc::Master;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Moves the cursor home and clears the terminal
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+/// Bytes and time of one master accumulated over a refresh period
+pub struct MasterLoad {
+    pub master: &'static Master,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub time_ms: u64,
+    pub max_utilization: u32,
+}
+
+impl MasterLoad {
+    pub fn new(master: &'static Master) -> MasterLoad {
+        MasterLoad {
+            master,
+            read_bytes: 0,
+            write_bytes: 0,
+            time_ms: 0,
+            max_utilization: 0,
+        }
+    }
+
+    pub fn add(&mut self, results: &MMDCProfileResult, time: u32) {
+        self.read_bytes += results.read_bytes as u64;
+        self.write_bytes += results.write_bytes as u64;
+        self.time_ms += time as u64;
+        self.max_utilization = self.max_utilization.max(results.utilization);
+    }
+
+    /// Read, write and total MB/s over the windows of the master
+    pub fn bandwidth(&self) -> (f32, f32, f32) {
+        let per_second = |bytes: u64| {
+            if self.time_ms == 0 {
+                return 0_f32;
+            }
+            (bytes as f64 * 1000_f64 / (1024_f64 * 1024_f64 * self.time_ms as f64)) as f32
+        };
+        let (read, write) = (per_second(self.read_bytes), per_second(self.write_bytes));
+        (read, write, read + write)
+    }
+}
+
+/// Measures one window of each master in turn and adds it to its load
+pub fn scan(
+    mmdc: &mut MMDC,
+    bus_width: u32,
+    overhead: Duration,
+    scheduler: &mut dyn Scheduler,
+    loads: &mut [MasterLoad],
+) {
+    for load in loads.iter_mut() {
+        select_master(mmdc, load.master.filter);
+        let (mut results, time) = measure_window(mmdc, bus_width, overhead, scheduler);
+        results.master = Some(load.master.name);
+        load.add(&results, time);
+    }
+}
+
+/// Read, write and total MB/s of each group, summed over those of its members that
+/// have a load. Sorted by the total, highest first.
+pub fn rollup<'g>(
+    groups: &'g [MasterGroup],
+    loads: &[MasterLoad],
+) -> Vec<(&'g str, (f32, f32, f32))> {
+    let mut totals: Vec<(&str, (f32, f32, f32))> = groups
+        .iter()
+        .map(|group| {
+            let bandwidth = loads
+                .iter()
+                .filter(|load| group.masters.iter().any(|name| name == load.master.name))
+                .map(MasterLoad::bandwidth)
+                .fold((0_f32, 0_f32, 0_f32), |sum, rate| {
+                    (sum.0 + rate.0, sum.1 + rate.1, sum.2 + rate.2)
+                });
+            (group.name.as_str(), bandwidth)
+        })
+        .collect();
+    totals.sort_by(|a, b| (b.1).2.total_cmp(&(a.1).2));
+    totals
+}
+
+/// Ranking table of names with their read, write and total MB/s and share of `all`
+fn write_table(
+    out: &mut impl Write,
+    title: &str,
+    rows: &[(&str, (f32, f32, f32))],
+    all: f32,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:<12} {:>12} {:>12} {:>12} {:>7}",
+        title, "READ MB/s", "WRITE MB/s", "TOTAL MB/s", "SHARE"
+    )?;
+    for (name, (read, write, total)) in rows {
+        let share = if all > 0_f32 {
+            total * 100_f32 / all
+        } else {
+            0_f32
+        };
+        writeln!(
+            out,
+            "{:<12} {:>12.2} {:>12.2} {:>12.2} {:>6.1}%",
+            name, read, write, total, share
+        )?;
+    }
+    Ok(())
+}
+
+/// Masters and groups sorted by their total bandwidth, with the share of the unfiltered
+/// total
+fn render(
+    out: &mut impl Write,
+    loads: &[MasterLoad],
+    groups: &[MasterGroup],
+    reference: &MasterLoad,
+) -> io::Result<()> {
+    let all = reference.bandwidth().2;
+    let mut ranking: Vec<(&str, (f32, f32, f32))> = loads
+        .iter()
+        .map(|load| (load.master.name, load.bandwidth()))
+        .collect();
+    ranking.sort_by(|a, b| (b.1).2.total_cmp(&(a.1).2));
+
+    write!(out, "{}", CLEAR_SCREEN)?;
+    writeln!(
+        out,
+        "all masters: {:.2}MB/s at up to {}% utilization",
+        all, reference.max_utilization
+    )?;
+    writeln!(out)?;
+    write_table(out, "MASTER", &ranking, all)?;
+    if !groups.is_empty() {
+        writeln!(out)?;
+        write_table(out, "GROUP", &rollup(groups, loads), all)?;
+    }
+    out.flush()
+}
+
+/// Cycles through the masters of the SoC, printing the ranking every `refresh`.
+/// Runs `iterations` refreshes or until terminated, the original filter is selected
+/// again after a bounded run.
+pub fn run(
+    mmdc: &mut MMDC,
+    masters: &'static [Master],
+    groups: &[MasterGroup],
+    bus_width: u32,
+    window: Duration,
+    refresh: Duration,
+    iterations: Option<u32>,
+) -> io::Result<()> {
+    let original = mmdc.madpcr1;
+    let overhead = measure_overhead(mmdc, bus_width);
+    let mut scheduler = Fixed::new(window);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let mut refreshes = 0;
+    while iterations.is_none_or(|iterations| refreshes < iterations) {
+        let mut reference = [MasterLoad::new(&ALL_MASTERS)];
+        let mut loads: Vec<MasterLoad> = masters.iter().map(MasterLoad::new).collect();
+        let start = Instant::now();
+        // at least one round, even if a round takes longer than the refresh
+        loop {
+            scan(mmdc, bus_width, overhead, &mut scheduler, &mut reference);
+            scan(mmdc, bus_width, overhead, &mut scheduler, &mut loads);
+            if start.elapsed() >= refresh {
+                break;
+            }
+        }
+        render(&mut out, &loads, groups, &reference[0])?;
+        refreshes += 1;
+    }
+    select_master(mmdc, original);
+    Ok(())
+}
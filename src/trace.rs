@@ -0,0 +1,526 @@
+//! Compact binary trace of the records for long captures, e.g. `--trace run.mtrace`
+//!
+//! A header with the bus width and the run metadata as JSON is followed by the records.
+//! After every INDEX_INTERVAL records an index block gives the time range and offset of
+//! the records before it, and the footer lists the offsets of all index blocks, so a
+//! reader seeks straight to the records of a time range. A trace without a footer, e.g.
+//! of a killed run, is read from the start instead.
+//!
+//! ```text
+//! header  "MMDCTRC\x01" bus_width:u32 length:u32 metadata
+//! record  0x01 seq:u64 timestamp_ms:u64 time_ms:u32 raw:[u32; 6] flags:u32 length:u8 master
+//! index   0x02 first_ms:u64 last_ms:u64 offset:u64 records:u32
+//! footer  0x03 offsets:[u64] blocks:u32 "MMDCIDX\x01"
+//! ```
+//!
+//! All numbers are little endian.
+
+use crate::summary::Summary;
+use crate::{get_tick_count, master_names, metadata, MMDCProfileResult, Record};
+use mmdc_core::Counters;
+use r_mmdc::SampleSink;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const MAGIC: &[u8; 8] = b"MMDCTRC\x01";
+const FOOTER_MAGIC: &[u8; 8] = b"MMDCIDX\x01";
+
+const RECORD: u8 = 1;
+const INDEX: u8 = 2;
+const FOOTER: u8 = 3;
+
+/// Records covered by one index block
+const INDEX_INTERVAL: u32 = 1024;
+
+/// Time range and position of the records before an index block
+#[derive(Clone, Copy)]
+struct Chunk {
+    first_ms: u64,
+    last_ms: u64,
+    offset: u64,
+    records: u32,
+}
+
+/// Record of a trace, the counters as read from the registers
+pub struct TraceRecord {
+    pub sequence: u64,
+    pub timestamp_ms: u64,
+    pub time: u32,
+    pub raw: [u32; 6],
+    pub flags: u32,
+    pub master: Option<String>,
+}
+
+impl TraceRecord {
+    pub fn from_record(record: &Record, timestamp_ms: u64) -> TraceRecord {
+        TraceRecord {
+            sequence: record.results.sequence,
+            timestamp_ms,
+            time: record.time,
+            raw: record.results.raw_counters,
+            flags: record.results.flags,
+            master: record.results.master.map(str::to_string),
+        }
+    }
+
+    /// Results as derived by the sampling loop, the metrics and columns are not traced
+    pub fn results(&self, bus_width: u32) -> MMDCProfileResult {
+        let mut results =
+            MMDCProfileResult::from_counters(&Counters::from_raw(self.raw), bus_width);
+        results.sequence = self.sequence;
+        results.flags = self.flags;
+        results.master = self
+            .master
+            .as_ref()
+            .and_then(|master| master_names().iter().find(|name| **name == master))
+            .copied();
+        results
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let master = self.master.as_deref().unwrap_or("");
+        out.write_all(&[RECORD])?;
+        out.write_all(&self.sequence.to_le_bytes())?;
+        out.write_all(&self.timestamp_ms.to_le_bytes())?;
+        out.write_all(&self.time.to_le_bytes())?;
+        for counter in self.raw.iter() {
+            out.write_all(&counter.to_le_bytes())?;
+        }
+        out.write_all(&self.flags.to_le_bytes())?;
+        out.write_all(&[master.len().min(u8::MAX as usize) as u8])?;
+        out.write_all(&master.as_bytes()[..master.len().min(u8::MAX as usize)])
+    }
+
+    /// Reads the record following its tag
+    fn read_from(input: &mut impl Read) -> io::Result<TraceRecord> {
+        let sequence = read_u64(input)?;
+        let timestamp_ms = read_u64(input)?;
+        let time = read_u32(input)?;
+        let mut raw = [0; 6];
+        for counter in raw.iter_mut() {
+            *counter = read_u32(input)?;
+        }
+        let flags = read_u32(input)?;
+        let mut length = [0; 1];
+        input.read_exact(&mut length)?;
+        let mut master = vec![0; length[0] as usize];
+        input.read_exact(&mut master)?;
+        Ok(TraceRecord {
+            sequence,
+            timestamp_ms,
+            time,
+            raw,
+            flags,
+            master: match master.is_empty() {
+                true => None,
+                false => Some(String::from_utf8_lossy(&master).into_owned()),
+            },
+        })
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Writes a trace, the last index block and the footer are written by `finish` or on drop
+pub struct TraceWriter<W: Write> {
+    out: W,
+    metadata: String,
+    started: bool,
+    position: u64,
+    chunk: Option<Chunk>,
+    index_offsets: Vec<u64>,
+    finished: bool,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<TraceWriter<BufWriter<File>>> {
+        Ok(TraceWriter::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W) -> TraceWriter<W> {
+        TraceWriter {
+            out,
+            metadata: "{}".to_string(),
+            started: false,
+            position: 0,
+            chunk: None,
+            index_offsets: Vec::new(),
+            finished: false,
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Header ahead of the first record, which gives the bus width
+    fn start(&mut self, bus_width: u32) -> io::Result<()> {
+        let metadata = std::mem::take(&mut self.metadata);
+        self.write_bytes(MAGIC)?;
+        self.write_bytes(&bus_width.to_le_bytes())?;
+        self.write_bytes(&(metadata.len() as u32).to_le_bytes())?;
+        self.write_bytes(metadata.as_bytes())?;
+        self.started = true;
+        Ok(())
+    }
+
+    pub fn append(&mut self, record: &TraceRecord, bus_width: u32) -> io::Result<()> {
+        if !self.started {
+            self.start(bus_width)?;
+        }
+        let mut bytes = Vec::new();
+        record.write_to(&mut bytes)?;
+        let offset = self.position;
+        self.write_bytes(&bytes)?;
+        let chunk = self.chunk.get_or_insert(Chunk {
+            first_ms: record.timestamp_ms,
+            last_ms: record.timestamp_ms,
+            offset,
+            records: 0,
+        });
+        chunk.last_ms = record.timestamp_ms;
+        chunk.records += 1;
+        if chunk.records >= INDEX_INTERVAL {
+            self.write_index()?;
+        }
+        Ok(())
+    }
+
+    fn write_index(&mut self) -> io::Result<()> {
+        let chunk = match self.chunk.take() {
+            Some(chunk) => chunk,
+            None => return Ok(()),
+        };
+        self.index_offsets.push(self.position);
+        let mut bytes = vec![INDEX];
+        bytes.extend_from_slice(&chunk.first_ms.to_le_bytes());
+        bytes.extend_from_slice(&chunk.last_ms.to_le_bytes());
+        bytes.extend_from_slice(&chunk.offset.to_le_bytes());
+        bytes.extend_from_slice(&chunk.records.to_le_bytes());
+        self.write_bytes(&bytes)
+    }
+
+    /// Indexes the last records and writes the footer, nothing may be appended afterwards
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished || !self.started {
+            return Ok(());
+        }
+        self.finished = true;
+        self.write_index()?;
+        let mut footer = vec![FOOTER];
+        for offset in self.index_offsets.iter() {
+            footer.extend_from_slice(&offset.to_le_bytes());
+        }
+        footer.extend_from_slice(&(self.index_offsets.len() as u32).to_le_bytes());
+        footer.extend_from_slice(FOOTER_MAGIC);
+        self.write_bytes(&footer)?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Drop for TraceWriter<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("Error finishing the trace: {}", e);
+        }
+    }
+}
+
+impl<W: Write> SampleSink<Record<'_>, Summary> for TraceWriter<W> {
+    fn on_metadata(&mut self, entries: &[(&str, String)]) -> io::Result<()> {
+        self.metadata = metadata::to_json(entries);
+        Ok(())
+    }
+
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        let trace_record = TraceRecord::from_record(record, get_tick_count() as u64);
+        self.append(&trace_record, record.results.bus_width)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Whether the file starts like a trace, other session files are JSON lines
+pub fn is_trace(path: &Path) -> io::Result<bool> {
+    let mut magic = [0; 8];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub struct TraceReader {
+    input: BufReader<File>,
+    pub bus_width: u32,
+    /// Metadata record of the run as JSON
+    pub metadata: String,
+    /// Offset of the first record
+    records_offset: u64,
+    /// Time ranges of the records from the footer, None without a footer
+    chunks: Option<Vec<Chunk>>,
+}
+
+impl TraceReader {
+    pub fn open(path: &Path) -> io::Result<TraceReader> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an r-mmdc trace"));
+        }
+        let bus_width = read_u32(&mut input)?;
+        let length = read_u32(&mut input)?;
+        let mut metadata = vec![0; length as usize];
+        input.read_exact(&mut metadata)?;
+        let mut reader = TraceReader {
+            input,
+            bus_width,
+            metadata: String::from_utf8_lossy(&metadata).into_owned(),
+            records_offset: 16 + length as u64,
+            chunks: None,
+        };
+        reader.chunks = reader.read_footer()?;
+        Ok(reader)
+    }
+
+    fn read_footer(&mut self) -> io::Result<Option<Vec<Chunk>>> {
+        let length = self.input.seek(SeekFrom::End(0))?;
+        if length < self.records_offset + 12 {
+            return Ok(None);
+        }
+        self.input.seek(SeekFrom::End(-12))?;
+        let blocks = read_u32(&mut self.input)? as u64;
+        let mut magic = [0; 8];
+        self.input.read_exact(&mut magic)?;
+        if &magic != FOOTER_MAGIC || length < self.records_offset + 12 + blocks * 8 {
+            return Ok(None);
+        }
+        self.input.seek(SeekFrom::End(-12 - blocks as i64 * 8))?;
+        let mut offsets = Vec::new();
+        for _ in 0..blocks {
+            offsets.push(read_u64(&mut self.input)?);
+        }
+        let mut chunks = Vec::new();
+        for offset in offsets {
+            self.input.seek(SeekFrom::Start(offset))?;
+            let mut tag = [0; 1];
+            self.input.read_exact(&mut tag)?;
+            if tag[0] != INDEX {
+                return Err(invalid("footer points to no index block"));
+            }
+            chunks.push(Chunk {
+                first_ms: read_u64(&mut self.input)?,
+                last_ms: read_u64(&mut self.input)?,
+                offset: read_u64(&mut self.input)?,
+                records: read_u32(&mut self.input)?,
+            });
+        }
+        Ok(Some(chunks))
+    }
+
+    /// Timestamp of the first record, None for an empty trace
+    #[cfg(feature = "network")]
+    pub fn start_ms(&mut self) -> io::Result<Option<u64>> {
+        if let Some(chunks) = &self.chunks {
+            return Ok(chunks.first().map(|chunk| chunk.first_ms));
+        }
+        let mut first = None;
+        self.scan(self.records_offset, None, &mut |record| {
+            first = Some(record.timestamp_ms);
+            false
+        })?;
+        Ok(first)
+    }
+
+    /// Calls `visit` with the records from `from_ms` to `to_ms` in order, only reading the
+    /// indexed chunks overlapping the range
+    pub fn for_each_between(
+        &mut self,
+        from_ms: u64,
+        to_ms: u64,
+        visit: &mut dyn FnMut(&TraceRecord),
+    ) -> io::Result<()> {
+        let mut in_range = |record: &TraceRecord| {
+            if record.timestamp_ms >= from_ms && record.timestamp_ms <= to_ms {
+                visit(record);
+            }
+            record.timestamp_ms <= to_ms
+        };
+        match self.chunks.clone() {
+            Some(chunks) => {
+                for chunk in chunks
+                    .iter()
+                    .filter(|chunk| chunk.last_ms >= from_ms && chunk.first_ms <= to_ms)
+                {
+                    self.scan(chunk.offset, Some(chunk.records), &mut in_range)?;
+                }
+                Ok(())
+            }
+            None => self.scan(self.records_offset, None, &mut in_range),
+        }
+    }
+
+    /// Reads records from `offset` until `records` were read, `visit` returns false or the
+    /// trace ends. A record cut off at the end of an unfinished trace ends it as well.
+    fn scan(
+        &mut self,
+        offset: u64,
+        records: Option<u32>,
+        visit: &mut dyn FnMut(&TraceRecord) -> bool,
+    ) -> io::Result<()> {
+        self.input.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while records.is_none_or(|records| read < records) {
+            let mut tag = [0; 1];
+            match self.input.read_exact(&mut tag) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            match tag[0] {
+                RECORD => {}
+                INDEX => {
+                    self.input.seek(SeekFrom::Current(28))?;
+                    continue;
+                }
+                // the footer of a finished trace
+                _ => return Ok(()),
+            }
+            let record = match TraceRecord::read_from(&mut self.input) {
+                Ok(record) => record,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            read += 1;
+            if !visit(&record) {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::path::PathBuf;
+
+    /// Trace of `records` records 10 ms apart, without its footer unless `finished`
+    fn write_trace(name: &str, records: u64, finished: bool) -> PathBuf {
+        let path = env::temp_dir().join(format!("r-mmdc-{}-{}.mtrace", name, std::process::id()));
+        let mut writer = TraceWriter::create(&path).unwrap();
+        for sequence in 0..records {
+            let record = TraceRecord {
+                sequence,
+                timestamp_ms: sequence * 10,
+                time: 10,
+                raw: [sequence as u32; 6],
+                flags: 0,
+                master: None,
+            };
+            writer.append(&record, 4).unwrap();
+        }
+        writer.finish().unwrap();
+        drop(writer);
+        if !finished {
+            let blocks = records.div_ceil(INDEX_INTERVAL as u64);
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            let length = file.metadata().unwrap().len();
+            file.set_len(length - (1 + blocks * 8 + 12)).unwrap();
+        }
+        path
+    }
+
+    fn between(path: &Path, from_ms: u64, to_ms: u64) -> Vec<u64> {
+        let mut reader = TraceReader::open(path).unwrap();
+        let mut sequences = Vec::new();
+        reader
+            .for_each_between(from_ms, to_ms, &mut |record| {
+                sequences.push(record.sequence)
+            })
+            .unwrap();
+        sequences
+    }
+
+    #[test]
+    fn footer_indexes_the_chunks() {
+        let path = write_trace("indexed", 2500, true);
+        let reader = TraceReader::open(&path).unwrap();
+        let chunks = reader.chunks.unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!((chunks[1].first_ms, chunks[1].last_ms), (10240, 20470));
+        assert_eq!(chunks[2].records, 2500 - 2 * INDEX_INTERVAL);
+        assert_eq!(
+            between(&path, 10235, 20485),
+            (1024..=2048).collect::<Vec<_>>()
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn footerless_trace_is_read_from_the_start() {
+        let path = write_trace("footerless", 2500, false);
+        let reader = TraceReader::open(&path).unwrap();
+        assert!(reader.chunks.is_none());
+        assert_eq!(
+            between(&path, 10235, 20485),
+            (1024..=2048).collect::<Vec<_>>()
+        );
+        assert_eq!(between(&path, 0, u64::MAX), (0..2500).collect::<Vec<_>>());
+        assert_eq!(between(&path, 30000, 40000), Vec::<u64>::new());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn record_cut_off_ends_the_trace() {
+        let path = write_trace("cut-off", 1500, false);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let length = file.metadata().unwrap().len();
+        // the last index block and half of the last record
+        file.set_len(length - 29 - 20).unwrap();
+        assert_eq!(between(&path, 0, u64::MAX), (0..1499).collect::<Vec<_>>());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn records_keep_their_counters() {
+        let path = write_trace("counters", 3, true);
+        let mut reader = TraceReader::open(&path).unwrap();
+        assert_eq!(reader.bus_width, 4);
+        let mut records = Vec::new();
+        reader
+            .for_each_between(0, u64::MAX, &mut |record| {
+                records.push((record.timestamp_ms, record.time, record.raw))
+            })
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![(0, 10, [0; 6]), (10, 10, [1; 6]), (20, 10, [2; 6])]
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+//! Experiments with MMDC settings, measuring the bandwidth before and after a change
+
+use crate::arbitration::{self, Arbitration};
+use crate::power::{self, PowerSaving};
+use crate::schedule::{self, Scheduler};
+use crate::{do_measuring_cylce, measure_overhead, Opt, MMDC};
+use nix::sys::mman::{msync, MsFlags};
+use nix::sys::signal::{SigSet, Signal};
+use std::ptr;
+
+/// Options shared by all tune targets
+pub struct Experiment {
+    /// Number of windows measured before and after the change
+    pub samples: u32,
+    /// Only shows the decoded current and new setting
+    pub dry_run: bool,
+    /// Keeps the new setting after exiting
+    pub persist: bool,
+}
+
+/// Register changed by an experiment
+pub struct Register {
+    name: &'static str,
+    field: fn(&mut MMDC) -> &mut u32,
+    decode: fn(u32) -> String,
+}
+
+const MAARCR: Register = Register {
+    name: "MAARCR",
+    field: |mmdc| &mut mmdc.maarcr,
+    decode: |value| Arbitration::from_register(value).to_string(),
+};
+
+const MAPSR: Register = Register {
+    name: "MAPSR",
+    field: |mmdc| &mut mmdc.mapsr,
+    decode: |value| PowerSaving::from_register(value).to_string(),
+};
+
+pub const MPRDDLCTL: Register = Register {
+    name: "MPRDDLCTL",
+    field: |mmdc| &mut mmdc.mprddlctl,
+    decode: |value| format!("  Read delay 0x{:08X}", value),
+};
+
+impl Register {
+    pub fn read(&self, mmdc: &mut MMDC) -> u32 {
+        unsafe { ptr::read_volatile((self.field)(mmdc)) }
+    }
+
+    pub fn write(&self, mmdc: &mut MMDC, value: u32) {
+        let register = (self.field)(mmdc);
+        unsafe {
+            ptr::write_volatile(register, value);
+            let _ = msync(register as *mut _ as *mut _, 4, MsFlags::MS_SYNC);
+        }
+    }
+}
+
+/// Puts the original register value back when dropped, also when a panic unwinds
+pub struct Restore<'a> {
+    pub mmdc: &'a mut MMDC,
+    pub register: &'a Register,
+    pub original: u32,
+    pub persist: bool,
+}
+
+impl Drop for Restore<'_> {
+    fn drop(&mut self) {
+        if !self.persist {
+            self.register.write(self.mmdc, self.original);
+            println!("Restored {} 0x{:08X}", self.register.name, self.original);
+        }
+    }
+}
+
+/// Blocks the termination signals, they are delivered once unblocked again
+pub fn hold_termination() -> Result<SigSet, String> {
+    let mut signals = SigSet::empty();
+    for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+        signals.add(signal);
+    }
+    signals.thread_block().map_err(|e| e.to_string())?;
+    Ok(signals)
+}
+
+/// Average total bandwidth and utilization of `samples` windows
+fn measure(
+    mmdc: &mut MMDC,
+    scheduler: &mut dyn Scheduler,
+    bus_width: u32,
+    samples: u32,
+) -> (f32, u32) {
+    let overhead = measure_overhead(mmdc, bus_width);
+    let (mut bandwidth, mut utilization) = (0_f32, 0_u32);
+    for _ in 0..samples {
+        let (results, time) = do_measuring_cylce(mmdc, bus_width, overhead, scheduler);
+        bandwidth += results.bandwidth(time).2;
+        utilization += results.utilization;
+    }
+    (bandwidth / samples as f32, utilization / samples)
+}
+
+/// Writes `value` to the register and measures before and after. Termination signals
+/// are held back until the original value is restored.
+fn run(
+    mmdc: &mut MMDC,
+    opt: &Opt,
+    bus_width: u32,
+    register: &Register,
+    value: impl Fn(u32) -> Result<u32, String>,
+    experiment: &Experiment,
+) -> Result<(), String> {
+    let original = register.read(mmdc);
+    let value = value(original)?;
+    println!("Current {} 0x{:08X}:", register.name, original);
+    println!("{}", (register.decode)(original));
+    println!("New {} 0x{:08X}:", register.name, value);
+    println!("{}", (register.decode)(value));
+    if experiment.dry_run {
+        return Ok(());
+    }
+
+    let samples = experiment.samples.max(1);
+    let signals = hold_termination()?;
+    let mut scheduler = schedule::from_opt(opt);
+    {
+        let (before, before_utilization) = measure(mmdc, scheduler.as_mut(), bus_width, samples);
+        let restore = Restore {
+            mmdc,
+            register,
+            original,
+            persist: experiment.persist,
+        };
+        register.write(restore.mmdc, value);
+        let (after, after_utilization) =
+            measure(restore.mmdc, scheduler.as_mut(), bus_width, samples);
+        println!(
+            "Before: {:.2}MB/s at {}% utilization",
+            before, before_utilization
+        );
+        println!(
+            "After: {:.2}MB/s at {}% utilization",
+            after, after_utilization
+        );
+        if before > 0_f32 {
+            println!("Change: {:+.1}%", (after - before) / before * 100_f32);
+        }
+    }
+    signals.thread_unblock().map_err(|e| e.to_string())
+}
+
+/// Applies a named or explicit MAARCR setting
+pub fn arbitration(
+    mmdc: &mut MMDC,
+    opt: &Opt,
+    bus_width: u32,
+    setting: &str,
+    experiment: &Experiment,
+) -> Result<(), String> {
+    if let Some((name, _, description)) = arbitration::POLICIES
+        .iter()
+        .find(|(name, _, _)| *name == setting)
+    {
+        println!("Policy {}: {}", name, description);
+    }
+    run(
+        mmdc,
+        opt,
+        bus_width,
+        &MAARCR,
+        |current| arbitration::resolve(setting, current),
+        experiment,
+    )
+}
+
+/// Switches the automatic power saving of MAPSR on or off
+pub fn power_saving(
+    mmdc: &mut MMDC,
+    opt: &Opt,
+    bus_width: u32,
+    enabled: bool,
+    experiment: &Experiment,
+) -> Result<(), String> {
+    run(
+        mmdc,
+        opt,
+        bus_width,
+        &MAPSR,
+        |current| Ok(power::with_power_saving(current, enabled)),
+        experiment,
+    )
+}
@@ -0,0 +1,131 @@
+//! Static userspace tracepoint (USDT) `r_mmdc:sample` at every window boundary, so
+//! system-wide tracing sessions pick up the DDR load without configuring a sink, e.g.
+//!
+//! ```text
+//! bpftrace -e 'usdt:/usr/bin/r-mmdc:r_mmdc:sample { printf("%d MB/s\n", arg2); }'
+//! ```
+//!
+//! Arguments: read, write and total bandwidth in MB/s, utilization and data load in
+//! percent and the sequence number of the record. The probe is a single nop described by
+//! a SystemTap SDT note, it costs nothing while no tracer is attached.
+
+use crate::summary::Summary;
+use crate::{MMDCProfileResult, Record};
+use r_mmdc::SampleSink;
+use std::io;
+
+/// Emits the SDT note and the probe site, `$address` is the directive for an address,
+/// `$size` the byte width of the register arguments and `$option`s are added to the asm
+/// options, `att_syntax` on x86 only. The base section is marked R(etain) so --gc-sections
+/// keeps it, tracers relocate the probe address against it.
+#[cfg(any(target_arch = "x86_64", target_arch = "arm", target_arch = "aarch64"))]
+macro_rules! probe {
+    ($address:literal, $size:literal, [$($option:ident),*], $($arg:expr),* $(,)?) => {
+        unsafe {
+            std::arch::asm!(
+                "990: nop",
+                ".pushsection .note.stapsdt,\"?\",\"note\"",
+                ".balign 4",
+                ".4byte 992f-991f, 994f-993f, 3",
+                "991: .asciz \"stapsdt\"",
+                "992: .balign 4",
+                concat!("993: ", $address, " 990b"),
+                concat!($address, " _.stapsdt.base"),
+                concat!($address, " 0"),
+                ".asciz \"r_mmdc\"",
+                ".asciz \"sample\"",
+                concat!(
+                    ".asciz \"",
+                    $size, "@{0} ", $size, "@{1} ", $size, "@{2} ",
+                    $size, "@{3} ", $size, "@{4} ", $size, "@{5}\""
+                ),
+                "994: .balign 4",
+                ".popsection",
+                ".ifndef _.stapsdt.base",
+                ".pushsection .stapsdt.base,\"aGR\",\"progbits\",.stapsdt.base,comdat",
+                ".weak _.stapsdt.base",
+                ".hidden _.stapsdt.base",
+                "_.stapsdt.base: .space 1",
+                ".size _.stapsdt.base, 1",
+                ".popsection",
+                ".endif",
+                $(in(reg) $arg,)*
+                options($($option,)* nomem, nostack, preserves_flags)
+            )
+        }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+fn fire(args: [u64; 6]) {
+    probe!(
+        ".8byte",
+        "8",
+        [att_syntax],
+        args[0],
+        args[1],
+        args[2],
+        args[3],
+        args[4],
+        args[5]
+    );
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline(never)]
+fn fire(args: [u64; 6]) {
+    probe!(
+        ".8byte",
+        "8",
+        [],
+        args[0],
+        args[1],
+        args[2],
+        args[3],
+        args[4],
+        args[5]
+    );
+}
+
+#[cfg(target_arch = "arm")]
+#[inline(never)]
+fn fire(args: [u64; 6]) {
+    let args = args.map(|arg| arg as u32);
+    probe!(
+        ".4byte",
+        "4",
+        [],
+        args[0],
+        args[1],
+        args[2],
+        args[3],
+        args[4],
+        args[5]
+    );
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "arm", target_arch = "aarch64")))]
+fn fire(_args: [u64; 6]) {}
+
+pub fn sample(result: &MMDCProfileResult, time: u32) {
+    let (read, write, total) = result.bandwidth(time);
+    fire([
+        read as u64,
+        write as u64,
+        total as u64,
+        result.utilization as u64,
+        result.data_load as u64,
+        result.sequence,
+    ]);
+}
+
+/// Fires the probe for every record
+pub struct Probe;
+
+impl SampleSink<Record<'_>, Summary> for Probe {
+    fn on_sample(&mut self, record: &Record) -> io::Result<()> {
+        sample(record.results, record.time);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Host side viewer for samples streamed with `--stream`
+
+use crate::trace::{self, TraceReader};
+use crate::{json, schema, SCHEMA_VERSION};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+const BAR_WIDTH: usize = 40;
+
+fn field(fields: &[(String, String)], key: &str) -> f32 {
+    fields
+        .iter()
+        .find(|(name, _)| name == key)
+        .and_then(|(_, value)| value.parse::<f32>().ok())
+        .unwrap_or_default()
+}
+
+fn bar(value: f32, max: f32) -> String {
+    let filled = if max > 0_f32 {
+        ((value / max) * BAR_WIDTH as f32).round() as usize
+    } else {
+        0
+    };
+    format!(
+        "{:<width$}",
+        "#".repeat(filled.min(BAR_WIDTH)),
+        width = BAR_WIDTH
+    )
+}
+
+/// Renders the lines of one source, a streaming target or a recorded session
+struct Display {
+    label: String,
+    max_total: f32,
+    warned: bool,
+}
+
+impl Display {
+    fn new(label: String) -> Display {
+        Display {
+            label,
+            max_total: 0_f32,
+            warned: false,
+        }
+    }
+
+    /// Prints a metadata, summary, marker or sample line
+    fn show(&mut self, line: &str) {
+        self.show_fields(&json::parse_flat(line));
+    }
+
+    fn show_fields(&mut self, fields: &[(String, String)]) {
+        let version = schema::version(fields);
+        if version > SCHEMA_VERSION && !self.warned {
+            eprintln!(
+                "WARNING: {} sends schema version {}, this viewer knows up to {}",
+                self.label, version, SCHEMA_VERSION
+            );
+            self.warned = true;
+        }
+        let record = fields
+            .iter()
+            .find(|(key, _)| key == "record")
+            .map(|(_, value)| value.as_str());
+        match record {
+            Some("metadata") => {
+                for (key, value) in fields.iter().filter(|(key, _)| key != "record") {
+                    println!("{} {}: {}", self.label, key, value);
+                }
+                return;
+            }
+            // summary and marker records on one line
+            Some(record) => {
+                let values: Vec<String> = fields
+                    .iter()
+                    .filter(|(key, _)| key != "record")
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                println!("{} {}: {}", self.label, record, values.join(" "));
+                return;
+            }
+            None => {}
+        }
+        let total = field(fields, "total_mbps");
+        self.max_total = self.max_total.max(total);
+        println!(
+            "{} R {:8.2} W {:8.2} T {:8.2} MB/s |{}| util {:3}%",
+            self.label,
+            field(fields, "read_mbps"),
+            field(fields, "write_mbps"),
+            total,
+            bar(total, self.max_total),
+            field(fields, "utilization")
+        );
+    }
+}
+
+fn show_stream(stream: TcpStream) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    println!("{} connected", peer);
+    let mut display = Display::new(peer.to_string());
+    for line in BufReader::new(stream).lines() {
+        display.show(&line?);
+    }
+    println!("{} disconnected", peer);
+    Ok(())
+}
+
+/// Sleeps between replayed records for the time that passed between them
+struct Pacer {
+    speed: f64,
+    previous: Option<u64>,
+}
+
+impl Pacer {
+    fn wait(&mut self, timestamp: u64) {
+        if let Some(previous) = self.previous {
+            let delay = timestamp.saturating_sub(previous) as f64 / 1000_f64 / self.speed;
+            thread::sleep(Duration::from_secs_f64(delay));
+        }
+        self.previous = Some(timestamp);
+    }
+}
+
+/// Seconds from the start of a capture as a range of timestamps
+fn time_range(start: u64, from: Option<f64>, to: Option<f64>) -> (u64, u64) {
+    let offset = |seconds: f64| start + (seconds.max(0_f64) * 1000_f64) as u64;
+    (from.map_or(start, offset), to.map_or(u64::MAX, offset))
+}
+
+/// Replays a session of `r-mmdc record` or a `--trace`, `speed` times faster than it was
+/// recorded. `from` and `to` limit it to seconds from the start of the capture, a trace
+/// only has the indexed chunks of records overlapping the range read.
+pub fn play(path: &Path, speed: f64, from: Option<f64>, to: Option<f64>) -> io::Result<()> {
+    let label = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut display = Display::new(label);
+    let mut pacer = Pacer {
+        speed,
+        previous: None,
+    };
+    if trace::is_trace(path)? {
+        let mut reader = TraceReader::open(path)?;
+        display.show(&reader.metadata.clone());
+        let start = match reader.start_ms()? {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        let (from_ms, to_ms) = time_range(start, from, to);
+        let bus_width = reader.bus_width;
+        return reader.for_each_between(from_ms, to_ms, &mut |record| {
+            pacer.wait(record.timestamp_ms);
+            let results = record.results(bus_width);
+            display.show(&results.to_json(record.time, record.timestamp_ms));
+        });
+    }
+
+    // version 1 sessions are brought to the current layout, newer ones can't be played
+    let mut range: Option<(u64, u64)> = None;
+    let mut samples = 0;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let mut fields = json::parse_flat(&line?);
+        schema::upgrade(&mut fields, samples)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !fields.iter().any(|(key, _)| key == "record") {
+            samples += 1;
+        }
+        let timestamp = fields
+            .iter()
+            .find(|(key, _)| key == "timestamp_ms")
+            .and_then(|(_, value)| value.parse::<u64>().ok());
+        if let Some(timestamp) = timestamp {
+            let (from_ms, to_ms) = *range.get_or_insert_with(|| time_range(timestamp, from, to));
+            if timestamp < from_ms {
+                continue;
+            }
+            if timestamp > to_ms {
+                break;
+            }
+            pacer.wait(timestamp);
+        }
+        display.show_fields(&fields);
+    }
+    Ok(())
+}
+
+/// Accepts streaming targets on the given port and prints their samples as they arrive
+pub fn run(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    println!("Waiting for targets on port {}", port);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        thread::spawn(move || {
+            if let Err(e) = show_stream(stream) {
+                eprintln!("Error receiving samples: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,28 @@
+//! Annotations shared between the profiler and other threads of an embedder
+
+use r_mmdc::Annotations;
+use std::thread;
+
+#[test]
+fn annotations_are_shared_between_clones() {
+    let annotations = Annotations::default();
+    let handle = annotations.clone();
+    thread::spawn(move || {
+        handle.set("test_case", "video_1080p");
+        handle.set("frame", 1);
+        handle.set("frame", 2);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(
+        annotations.snapshot(),
+        vec![
+            ("test_case".to_string(), "video_1080p".to_string()),
+            ("frame".to_string(), "2".to_string()),
+        ]
+    );
+    annotations.remove("test_case");
+    assert_eq!(annotations.snapshot().len(), 1);
+    annotations.clear();
+    assert!(annotations.snapshot().is_empty());
+}
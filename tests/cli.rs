@@ -0,0 +1,524 @@
+//! End-to-end runs of the CLI against a fake register block
+
+mod common;
+
+use common::{fake_registers, output, run};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use r_mmdc::{Accesses, Bytes, Counters, Cycles, FakeRegisters};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn poked_registers(name: &str) -> FakeRegisters {
+    let mut registers = fake_registers(name);
+    registers.set_counters(&Counters {
+        total_cycles: Cycles(528_000),
+        busy_cycles: Cycles(264_000),
+        read_accesses: Accesses(1_000),
+        write_accesses: Accesses(500),
+        read_bytes: Bytes(32_000),
+        write_bytes: Bytes(16_000),
+    });
+    registers
+}
+
+#[test]
+fn csv_record_has_the_poked_counters() {
+    let registers = poked_registers("csv");
+    let stdout = run(
+        &registers,
+        &[
+            "--bus-width",
+            "32",
+            "--format",
+            "csv",
+            "-s",
+            "10",
+            "-c",
+            "2",
+        ],
+    );
+    let records: Vec<&str> = stdout.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(records.len(), 2);
+    for record in records {
+        let fields: Vec<&str> = record.split(';').collect();
+        assert_eq!(
+            &fields[1..7],
+            &["528000", "264000", "1000", "500", "32000", "16000"]
+        );
+        // avg read and write burst size
+        assert_eq!(&fields[7..9], &["32", "32"]);
+    }
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn human_output_and_master_tags() {
+    let registers = poked_registers("human");
+    let stdout = run(
+        &registers,
+        &[
+            "--bus-width",
+            "32",
+            "-s",
+            "10",
+            "-c",
+            "2",
+            "--soc",
+            "imx6q",
+            "--master",
+            "arm",
+            "--master",
+            "vpu",
+        ],
+    );
+    assert!(stdout.contains("Master: arm"));
+    assert!(stdout.contains("Master: vpu"));
+    assert!(stdout.contains("Read bytes count: 32000"));
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn jsonl_and_influx_records() {
+    let registers = poked_registers("formats");
+    let stdout = run(
+        &registers,
+        &["--bus-width", "32", "--format", "jsonl", "-s", "10"],
+    );
+    let mut lines = stdout.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("{\"record\":\"metadata\","));
+    assert!(lines.next().unwrap().starts_with("{\"schema_version\":2,"));
+    assert!(stdout.contains("\"read_bytes\":32000"));
+    let stdout = run(
+        &registers,
+        &["--bus-width", "32", "--format", "influx", "-s", "10"],
+    );
+    assert!(stdout.starts_with("mmdc,channel=p0 "));
+    assert!(stdout.contains("read_bytes=32000i"));
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn schema_matches_csv_columns() {
+    let registers = poked_registers("schema");
+    let args = [
+        "--bus-width",
+        "32",
+        "--format",
+        "csv",
+        "--beats",
+        "--raw",
+        "--flags",
+    ];
+    let schema = run(&registers, &[&args[..], &["--print-schema"]].concat());
+    let (metadata, fields) = schema.split_once("\"fields\":").unwrap();
+    let names = |part: &str| -> Vec<String> {
+        part.lines()
+            .filter_map(|l| l.split("\"name\":\"").nth(1))
+            .map(|l| l.split('"').next().unwrap().to_string())
+            .collect()
+    };
+    let stdout = run(&registers, &[&args[..], &["-s", "10"]].concat());
+    let record = stdout.lines().find(|l| !l.starts_with('#')).unwrap();
+    assert_eq!(record.split(';').count(), names(fields).len());
+    let header: Vec<String> = stdout
+        .lines()
+        .take_while(|l| l.starts_with('#'))
+        .filter_map(|l| l[2..].split_once('='))
+        .map(|(key, _)| key.to_string())
+        .collect();
+    assert_eq!(header, names(metadata));
+    assert!(header.contains(&"soc_revision".to_string()));
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn baseline_gate_fails_on_more_traffic() {
+    let mut registers = poked_registers("baseline");
+    let baseline = registers.path().with_extension("json");
+    let baseline = baseline.to_str().unwrap();
+    run(
+        &registers,
+        &["--bus-width", "32", "-s", "10", "--save-baseline", baseline],
+    );
+    let args = ["--bus-width", "32", "-s", "10", "--baseline", baseline];
+    assert!(output(&registers, &args).status.success());
+    registers.set_counters(&Counters {
+        total_cycles: Cycles(528_000),
+        busy_cycles: Cycles(264_000),
+        read_accesses: Accesses(1_000),
+        write_accesses: Accesses(500),
+        read_bytes: Bytes(64_000),
+        write_bytes: Bytes(16_000),
+    });
+    let failed = output(&registers, &args);
+    assert!(!failed.status.success());
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("REGRESSION"));
+    fs::remove_file(baseline).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn record_writes_metadata_and_samples() {
+    let registers = poked_registers("record");
+    let session = registers.path().with_extension("jsonl");
+    run(
+        &registers,
+        &["-s", "10", "-c", "2", "record", session.to_str().unwrap()],
+    );
+    let content = fs::read_to_string(&session).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("{\"record\":\"metadata\""));
+    assert!(lines[2].contains("\"seq\":1"));
+    fs::remove_file(session).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
+
+/// Runs tar, failing on a non-zero exit, and returns its stdout
+#[cfg(feature = "compress")]
+fn tar(args: &[&str]) -> String {
+    let output = Command::new("tar").args(args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[cfg(feature = "compress")]
+#[test]
+fn support_bundle_is_a_gzip_archive() {
+    let registers = poked_registers("bundle");
+    let bundle = registers.path().with_extension("tar.gz");
+    run(
+        &registers,
+        &[
+            "-s",
+            "10",
+            "support-bundle",
+            bundle.to_str().unwrap(),
+            "--samples",
+            "2",
+        ],
+    );
+    let content = fs::read(&bundle).unwrap();
+    assert_eq!(&content[..2], &[0x1F, 0x8B]);
+    let archive = bundle.to_str().unwrap();
+    let listing = tar(&["-tzf", archive]);
+    assert_eq!(
+        listing.lines().collect::<Vec<_>>(),
+        &[
+            "r-mmdc-support/metadata.txt",
+            "r-mmdc-support/cpuinfo.txt",
+            "r-mmdc-support/kernel.txt",
+            "r-mmdc-support/cmdline.txt",
+            "r-mmdc-support/device-tree.txt",
+            "r-mmdc-support/registers.txt",
+            "r-mmdc-support/samples.jsonl",
+        ]
+    );
+    let file = |name: &str| tar(&["-xzOf", archive, &format!("r-mmdc-support/{}", name)]);
+    let metadata = file("metadata.txt");
+    assert!(metadata.contains("command_line="), "{}", metadata);
+    assert!(metadata.contains(" support-bundle "), "{}", metadata);
+    assert!(metadata.contains("\nfeatures="), "{}", metadata);
+    // dumped ahead of the measurement, MADPSR0 holds the poked total cycles
+    let registers_dump = file("registers.txt");
+    assert!(
+        registers_dump.contains("0x418: 0x00080E80\n"),
+        "{}",
+        registers_dump
+    );
+    let samples = file("samples.jsonl");
+    assert_eq!(samples.lines().count(), 2);
+    for sample in samples.lines() {
+        assert!(sample.starts_with('{'), "{}", sample);
+        assert!(sample.contains("\"read_bytes\":32000"), "{}", sample);
+    }
+    fs::remove_file(bundle).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[cfg(feature = "network")]
+#[test]
+fn trace_replays_the_recorded_counters() {
+    let registers = poked_registers("trace");
+    let trace = registers.path().with_extension("mtrace");
+    run(
+        &registers,
+        &["-s", "10", "-c", "3", "--trace", trace.to_str().unwrap()],
+    );
+    let stdout = run(
+        &registers,
+        &["play", trace.to_str().unwrap(), "--speed", "100"],
+    );
+    let samples: Vec<&str> = stdout.lines().filter(|l| l.contains(" R ")).collect();
+    assert_eq!(samples.len(), 3);
+    fs::remove_file(trace).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn convert_writes_the_counters_of_a_trace_as_csv() {
+    let registers = poked_registers("convert");
+    let trace = registers.path().with_extension("mtrace");
+    let csv = registers.path().with_extension("csv");
+    run(
+        &registers,
+        &["-s", "10", "-c", "2", "--trace", trace.to_str().unwrap()],
+    );
+    run(
+        &registers,
+        &["convert", trace.to_str().unwrap(), csv.to_str().unwrap()],
+    );
+    let converted = fs::read_to_string(&csv).unwrap();
+    assert!(converted.contains("# bus_width="));
+    let records: Vec<&str> = converted.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(records.len(), 2);
+    for record in records {
+        let fields: Vec<&str> = record.split(';').collect();
+        assert_eq!(
+            &fields[1..7],
+            &["528000", "264000", "1000", "500", "32000", "16000"]
+        );
+    }
+    fs::remove_file(trace).unwrap();
+    fs::remove_file(csv).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn convert_reads_version_1_sessions() {
+    let registers = fake_registers("convert-v1");
+    let session = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/session-v1.jsonl");
+    let jsonl = registers.path().with_extension("jsonl");
+    run(
+        &registers,
+        &[
+            "convert",
+            session.to_str().unwrap(),
+            jsonl.to_str().unwrap(),
+        ],
+    );
+    let converted = fs::read_to_string(&jsonl).unwrap();
+    let lines: Vec<&str> = converted.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].contains("\"schema_version\":\"2\""));
+    for (seq, line) in lines[1..].iter().enumerate() {
+        assert!(line.starts_with("{\"schema_version\":2,"));
+        assert!(line.contains(&format!("\"seq\":{}", seq)));
+        assert!(line.contains("\"total_cycles\":528000"));
+    }
+    assert!(lines[2].contains("\"read_bytes\":64000"));
+    fs::remove_file(jsonl).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn convert_rejects_newer_schema_versions() {
+    let registers = fake_registers("convert-v3");
+    let session = registers.path().with_extension("v3.jsonl");
+    fs::write(
+        &session,
+        "{\"schema_version\":3,\"timestamp_ms\":1,\"time_ms\":1000}\n",
+    )
+    .unwrap();
+    let csv = registers.path().with_extension("csv");
+    let failed = output(
+        &registers,
+        &["convert", session.to_str().unwrap(), csv.to_str().unwrap()],
+    );
+    assert!(!failed.status.success());
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("schema version 3"));
+    fs::remove_file(session).unwrap();
+    let _ = fs::remove_file(csv);
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[cfg(feature = "network")]
+#[test]
+fn play_shows_version_1_sessions() {
+    let registers = fake_registers("play-v1");
+    let session = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/session-v1.jsonl");
+    let stdout = run(
+        &registers,
+        &["play", session.to_str().unwrap(), "--speed", "100"],
+    );
+    assert!(stdout.contains("bus_width: 32"));
+    let samples: Vec<&str> = stdout.lines().filter(|l| l.contains(" R ")).collect();
+    assert_eq!(samples.len(), 2);
+    fs::remove_file(registers.path()).unwrap();
+}
+
+/// Polls `path` until `done` holds for its contents, failing after 10 seconds
+fn wait_for(path: &Path, done: impl Fn(&str) -> bool) -> String {
+    let start = Instant::now();
+    loop {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        if done(&contents) {
+            return contents;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "{}", contents);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn records(contents: &str) -> usize {
+    contents.lines().filter(|l| !l.starts_with('#')).count()
+}
+
+#[test]
+fn signals_start_and_stop_the_measurement() {
+    let registers = poked_registers("signals");
+    let path = registers.path().with_extension("csv");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_r-mmdc"))
+        .arg("--regs-file")
+        .arg(registers.path())
+        .args(["--format", "csv", "-s", "20", "-c", "10", "--signals"])
+        .stdout(fs::File::create(&path).unwrap())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let pid = Pid::from_raw(child.id() as i32);
+    // idles until the first start
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(records(&fs::read_to_string(&path).unwrap()), 0);
+
+    kill(pid, Signal::SIGUSR1).unwrap();
+    wait_for(&path, |contents| records(contents) > 0);
+    kill(pid, Signal::SIGUSR2).unwrap();
+    let paused = wait_for(&path, |contents| contents.contains("# segment=1\n"));
+    thread::sleep(Duration::from_millis(200));
+    assert!(child.try_wait().unwrap().is_none());
+    assert_eq!(
+        records(&fs::read_to_string(&path).unwrap()),
+        records(&paused)
+    );
+    assert!(records(&paused) < 10);
+
+    kill(pid, Signal::SIGUSR1).unwrap();
+    assert!(child.wait().unwrap().success());
+    let contents = fs::read_to_string(&path).unwrap();
+    assert_eq!(records(&contents), 10);
+    assert!(contents.contains("# segment=2\n"), "{}", contents);
+    fs::remove_file(path).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn report_every_combines_the_windows_of_one_master() {
+    let registers = poked_registers("report-every");
+    let args = [
+        "--format",
+        "csv",
+        "-s",
+        "10",
+        "-c",
+        "4",
+        "--soc",
+        "imx6q",
+        "--report-every",
+        "2",
+    ];
+    let stdout = run(&registers, &[&args[..], &["--master", "arm"]].concat());
+    let records: Vec<&str> = stdout.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|record| record.contains(";arm;")));
+
+    let rotated = [&args[..], &["--master", "arm", "--master", "vpu"]].concat();
+    let failed = output(&registers, &rotated);
+    assert!(!failed.status.success());
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("--report-every"));
+
+    let failed = output(&registers, &["--report-every", "0"]);
+    assert!(!failed.status.success());
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("at least 1"));
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[test]
+fn groups_are_recorded_and_rolled_up_in_the_format() {
+    let registers = poked_registers("groups");
+    let config = registers.path().with_extension("conf");
+    fs::write(&config, "[groups]\ngraphics = gpu3d + gpu2d\n").unwrap();
+    let args = [
+        "-s",
+        "10",
+        "-c",
+        "2",
+        "--soc",
+        "imx6q",
+        "--config",
+        config.to_str().unwrap(),
+        "--group",
+        "graphics",
+    ];
+    let output_of = |extra: &[&str]| output(&registers, &[&args[..], extra].concat());
+
+    let jsonl = output_of(&["--format", "jsonl", "--summary"]);
+    assert!(jsonl.status.success());
+    let stdout = String::from_utf8(jsonl.stdout).unwrap();
+    assert!(stdout.contains("\"masters\":\"gpu3d,gpu2d\""), "{}", stdout);
+    let rollup = stdout.lines().last().unwrap();
+    assert!(
+        rollup.starts_with("{\"record\":\"group\",\"group\":\"graphics\","),
+        "{}",
+        rollup
+    );
+    assert!(!String::from_utf8_lossy(&jsonl.stderr).contains("per group"));
+
+    let failed = output_of(&["--report-every", "2"]);
+    assert!(!failed.status.success());
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("--report-every"));
+    fs::remove_file(config).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
+
+#[cfg(feature = "compress")]
+#[test]
+fn support_bundle_follows_the_adaptive_schedule() {
+    let registers = poked_registers("bundle-adaptive");
+    let bundle = registers.path().with_extension("tar.gz");
+    run(
+        &registers,
+        &[
+            "-s",
+            "10",
+            "--schedule",
+            "adaptive",
+            "--idle-sleeptime",
+            "50",
+            "--activity-threshold",
+            "1000",
+            "support-bundle",
+            bundle.to_str().unwrap(),
+            "--samples",
+            "12",
+        ],
+    );
+    let samples = tar(&[
+        "-xzOf",
+        bundle.to_str().unwrap(),
+        "r-mmdc-support/samples.jsonl",
+    ]);
+    let times: Vec<u32> = samples
+        .lines()
+        .map(|sample| {
+            let time = sample.split("\"time_ms\":").nth(1).unwrap();
+            time.split(',').next().unwrap().parse().unwrap()
+        })
+        .collect();
+    // slows down after 10 quiet windows
+    assert!(times[..10].iter().all(|time| *time < 40), "{:?}", times);
+    assert!(times[10..].iter().all(|time| *time >= 40), "{:?}", times);
+    fs::remove_file(bundle).unwrap();
+    fs::remove_file(registers.path()).unwrap();
+}
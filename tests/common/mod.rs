@@ -0,0 +1,33 @@
+//! Helpers shared by the integration tests
+
+use r_mmdc::FakeRegisters;
+use std::env;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Zeroed fake register block in the temp directory, unique per test process and `name`
+pub fn fake_registers(name: &str) -> FakeRegisters {
+    let path: PathBuf = env::temp_dir().join(format!("r-mmdc-{}-{}", name, std::process::id()));
+    FakeRegisters::create(&path).unwrap()
+}
+
+/// Runs the CLI on the fake registers, whatever its exit status
+pub fn output(registers: &FakeRegisters, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_r-mmdc"))
+        .arg("--regs-file")
+        .arg(registers.path())
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs the CLI on the fake registers and returns its stdout, failing on a non-zero exit
+pub fn run(registers: &FakeRegisters, args: &[&str]) -> String {
+    let output = output(registers, args);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
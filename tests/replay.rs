@@ -0,0 +1,162 @@
+//! Replays the recorded counter sequences of tests/corpus through the derived metrics and
+//! the CLI, so formula changes show up against known results
+
+mod common;
+
+use common::{fake_registers, run};
+use r_mmdc::Counters;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Metric columns of a dump and of the CLI's CSV records, by name
+const METRICS: [(&str, usize); 5] = [
+    ("avg_read_burstsize", 7),
+    ("avg_write_burstsize", 8),
+    ("utilization", 12),
+    ("data_load", 13),
+    ("access_utilization", 14),
+];
+
+struct Record {
+    time: u32,
+    raw: [u32; 6],
+    expected: Vec<(String, String)>,
+}
+
+struct Dump {
+    bus_width: u32,
+    records: Vec<Record>,
+}
+
+/// Parses a dump: `#` comments with a `bus_width=` line, a header naming the columns, then
+/// time, MADPSR0..5 and the expected metrics per record
+fn parse(path: &Path) -> Dump {
+    let content = fs::read_to_string(path).unwrap();
+    let mut bus_width = 32;
+    let mut header: Vec<String> = Vec::new();
+    let mut records = Vec::new();
+    for line in content.lines() {
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some(width) = comment.trim().strip_prefix("bus_width=") {
+                bus_width = width.parse().unwrap();
+            }
+            continue;
+        }
+        let fields: Vec<&str> = line.split(';').collect();
+        if header.is_empty() {
+            header = fields.iter().map(|field| field.to_string()).collect();
+            continue;
+        }
+        let mut raw = [0; 6];
+        for (counter, field) in raw.iter_mut().zip(fields[1..7].iter()) {
+            *counter = field.parse().unwrap();
+        }
+        records.push(Record {
+            time: fields[0].parse().unwrap(),
+            raw,
+            expected: header[7..]
+                .iter()
+                .cloned()
+                .zip(fields[7..].iter().map(|field| field.to_string()))
+                .collect(),
+        });
+    }
+    Dump { bus_width, records }
+}
+
+fn dumps() -> Vec<(String, Dump)> {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut dumps: Vec<(String, Dump)> = fs::read_dir(corpus)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "dump")
+        })
+        .map(|path| (path.display().to_string(), parse(&path)))
+        .collect();
+    dumps.sort_by(|a, b| a.0.cmp(&b.0));
+    assert!(!dumps.is_empty());
+    dumps
+}
+
+fn derived(counters: &Counters, bus_width: u32, time: u32, metric: &str) -> String {
+    match metric {
+        "utilization" => counters.utilization(bus_width).get().to_string(),
+        "data_load" => counters.data_load().get().to_string(),
+        "access_utilization" => counters.access_utilization().get().to_string(),
+        "avg_read_burstsize" => counters.avg_read_burstsize().get().to_string(),
+        "avg_write_burstsize" => counters.avg_write_burstsize().get().to_string(),
+        "total_mbps" => format!(
+            "{:.2}",
+            counters
+                .bandwidth(Duration::from_millis(time as u64))
+                .total
+                .get()
+        ),
+        metric => panic!("unknown metric {}", metric),
+    }
+}
+
+#[test]
+fn derived_metrics_match_the_corpus() {
+    for (name, dump) in dumps() {
+        for (index, record) in dump.records.iter().enumerate() {
+            let counters = Counters::from_raw(record.raw);
+            for (metric, expected) in record.expected.iter() {
+                assert_eq!(
+                    &derived(&counters, dump.bus_width, record.time, metric),
+                    expected,
+                    "{} record {}: {}",
+                    name,
+                    index + 1,
+                    metric
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn cli_records_match_the_corpus() {
+    let mut registers = fake_registers("replay");
+    for (name, dump) in dumps() {
+        let bus_width = dump.bus_width.to_string();
+        for (index, record) in dump.records.iter().enumerate() {
+            registers.set_counters(&Counters::from_raw(record.raw));
+            let stdout = run(
+                &registers,
+                &[
+                    "--bus-width",
+                    &bus_width,
+                    "--format",
+                    "csv",
+                    "-s",
+                    "1",
+                    "-c",
+                    "1",
+                ],
+            );
+            let csv = stdout.lines().find(|line| !line.starts_with('#')).unwrap();
+            let fields: Vec<&str> = csv.split(';').collect();
+            // the CLI measures its own time, so only the time independent metrics compare
+            for (metric, column) in METRICS.iter() {
+                let expected = record
+                    .expected
+                    .iter()
+                    .find(|(name, _)| name == metric)
+                    .map(|(_, value)| value.as_str());
+                assert_eq!(
+                    Some(fields[*column]),
+                    expected,
+                    "{} record {}: {}",
+                    name,
+                    index + 1,
+                    metric
+                );
+            }
+        }
+    }
+    fs::remove_file(registers.path()).unwrap();
+}
@@ -0,0 +1,64 @@
+//! Conversions of the typed units of the library API
+
+use r_mmdc::{Accesses, Beats, Bytes, Counters, Cycles, Percent};
+use std::time::Duration;
+
+#[test]
+fn conversions_take_the_bus_width_and_time() {
+    let bytes = Bytes(64 * 1024 * 1024);
+    assert_eq!(bytes.beats(32), Beats(16 * 1024 * 1024));
+    assert_eq!(bytes.beats(32).bytes(32), Some(bytes));
+    assert_eq!(Beats(u32::MAX).bytes(64), None);
+    assert_eq!(Beats(10).cycles(), Cycles(5));
+    assert_eq!(bytes.per(Duration::from_secs(2)).get(), 32_f32);
+    assert_eq!(bytes.per(Duration::default()).get(), 0_f32);
+    assert_eq!(Percent::of(Cycles(1), Cycles(4)), Percent(25));
+    assert_eq!(Percent::of(Cycles(1), Cycles(0)), Percent(0));
+}
+
+#[test]
+fn run_totals_accumulate_beyond_one_window() {
+    let sample = r_mmdc::Sample {
+        counters: r_mmdc::Counters::from_raw([u32::MAX, 0, 0, 0, 3 << 20, 1 << 20]),
+        time: Duration::from_millis(500),
+        bus_width: 32,
+        annotations: Vec::new(),
+    };
+    let mut totals = r_mmdc::RunTotals::default();
+    totals.add(&sample);
+    totals.add(&sample);
+    assert_eq!(totals.samples, 2);
+    assert_eq!(totals.total_cycles, 2 * u32::MAX as u64);
+    let bandwidth = totals.bandwidth();
+    assert_eq!(
+        (bandwidth.read.get(), bandwidth.write.get()),
+        (6_f32, 2_f32)
+    );
+    assert_eq!(r_mmdc::RunTotals::default().bandwidth().total.get(), 0_f32);
+}
+
+#[test]
+fn counters_report_typed_quantities() {
+    let counters = Counters::from_raw([1_000_000, 250_000, 4, 2, 1_200_000, 400_000]);
+    assert_eq!(counters.busy_cycles, Cycles(250_000));
+    assert_eq!(counters.read_accesses, Accesses(4));
+    assert_eq!(counters.data_load(), Percent(25));
+    // 1.6 MB in 250k busy cycles moving 16 bytes each on a 64 bit bus
+    assert_eq!(counters.utilization(64), Percent(40));
+    assert_eq!(counters.avg_read_burstsize(), Bytes(300_000));
+    assert_eq!(counters.access_utilization(), Bytes(266_666));
+    assert_eq!(Counters::from_raw(counters.raw()), counters);
+
+    let counters = Counters::from_raw([0, 0, 0, 0, 3 << 20, 1 << 20]);
+    let bandwidth = counters.bandwidth(Duration::from_millis(500));
+    assert_eq!(
+        (
+            bandwidth.read.get(),
+            bandwidth.write.get(),
+            bandwidth.total.get()
+        ),
+        (6_f32, 2_f32, 8_f32)
+    );
+    assert_eq!(counters.utilization(64), Percent(0));
+    assert_eq!(counters.avg_write_burstsize(), Bytes(0));
+}